@@ -0,0 +1,372 @@
+//! CupruxOS ABI — общие константы ядра и userspace
+//! CupruxOS ABI — constants shared by the kernel and userspace
+//!
+//! Ядро и libcuprum обязаны совпадать в этих значениях, поэтому они
+//! определены ровно в одном месте.
+//! The kernel and libcuprum must agree on these values, so they are
+//! defined in exactly one place.
+
+#![no_std]
+
+/// Максимальный размер inline payload сообщения (байт).
+/// Maximum inline message payload size (bytes).
+///
+/// Больше — только через shared memory (MemoryCap).
+/// Anything larger must go through shared memory (MemoryCap).
+pub const MAX_INLINE_PAYLOAD: usize = 512;
+
+/// Слотов capability в сообщении: при доставке они переезжают из таблицы
+/// отправителя в таблицу получателя.
+/// Capability slots in a message: on delivery they move from the sender's
+/// table into the receiver's.
+pub const MAX_MSG_CAPS: usize = 4;
+
+/// Пустой слот capability в сообщении / An empty capability slot in a message
+pub const NO_CAP: u64 = u64::MAX;
+
+/// Биты прав capability — маска `cap_grant` и `Rights` ядра.
+/// Capability right bits — the `cap_grant` mask and the kernel's `Rights`.
+pub mod rights {
+    pub const READ:   u32 = 1 << 0;
+    pub const WRITE:  u32 = 1 << 1;
+    /// Передать другому / Transfer to another
+    pub const GRANT:  u32 = 1 << 2;
+    /// Отозвать производные / Revoke derived copies
+    pub const REVOKE: u32 = 1 << 3;
+    /// Копия в своей таблице / A copy in one's own table
+    pub const DUP:    u32 = 1 << 4;
+    /// Слать в порт — запись / Sending to a port is writing
+    pub const SEND:   u32 = WRITE;
+    /// Принимать из порта — чтение / Receiving from a port is reading
+    pub const RECV:   u32 = READ;
+}
+
+/// Длина имени задачи в байтах; длиннее — обрезается по границе символа.
+/// A task name's length in bytes; longer ones are cut at a char boundary.
+pub const TASK_NAME_LEN: usize = 32;
+
+/// Номера syscall'ов — одна таблица для диспетчера ядра и обёрток libcuprum.
+/// Syscall numbers — one table for the kernel dispatcher and libcuprum's wrappers.
+///
+/// Номер — часть ABI: существующие значения не меняются, новые — в конец.
+/// A number is part of the ABI: existing values never change, new ones go last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Syscall {
+    IpcCall         = 0,
+    IpcSend         = 1,
+    IpcRecv         = 2,
+    IpcReply        = 3,
+    CapCreatePort   = 4,
+    CapGrant        = 5,
+    CapRevoke       = 6,
+    MemMap          = 7,
+    MemUnmap        = 8,
+    MemAlloc        = 9,
+    TaskSpawn       = 10,
+    TaskExit        = 11,
+    TaskYield       = 12,
+    TimeNow         = 13,
+    TimeSleep       = 14,
+    SyscallStats    = 15,
+    IpcRecvTimeout  = 16,
+    TaskSetPriority = 17,
+    IpcCallBuf      = 18,
+    ProfileDump     = 19,
+    IoGrant         = 20,
+    EvqCreate       = 21,
+    EvqAttach       = 22,
+    EvqWait         = 23,
+    IpcSendBatch    = 24,
+    TaskSetName     = 25,
+    Notify          = 26,
+    NotifyWait      = 27,
+    NotifyCreate    = 28,
+    MemShare        = 29,
+}
+
+impl Syscall {
+    /// Все syscall'ы в порядке номеров / Every syscall in number order
+    pub const ALL: [Self; 30] = [
+        Self::IpcCall, Self::IpcSend, Self::IpcRecv, Self::IpcReply,
+        Self::CapCreatePort, Self::CapGrant, Self::CapRevoke,
+        Self::MemMap, Self::MemUnmap, Self::MemAlloc,
+        Self::TaskSpawn, Self::TaskExit, Self::TaskYield,
+        Self::TimeNow, Self::TimeSleep, Self::SyscallStats,
+        Self::IpcRecvTimeout, Self::TaskSetPriority, Self::IpcCallBuf,
+        Self::ProfileDump, Self::IoGrant,
+        Self::EvqCreate, Self::EvqAttach, Self::EvqWait, Self::IpcSendBatch,
+        Self::TaskSetName, Self::Notify, Self::NotifyWait, Self::NotifyCreate,
+        Self::MemShare,
+    ];
+
+    /// Из номера в rax; неизвестный — `None` (ENOSYS).
+    /// From the number in rax; an unknown one — `None` (ENOSYS).
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        if raw < Self::ALL.len() { Some(Self::ALL[raw]) } else { None }
+    }
+}
+
+// Номер syscall'а — его индекс в `ALL`, без дыр; 0–14 — исходная таблица.
+// A syscall's number is its index in `ALL`, with no gaps; 0–14 is the original table.
+const _: () = {
+    let mut i = 0;
+    while i < Syscall::ALL.len() {
+        assert!(Syscall::ALL[i] as usize == i);
+        i += 1;
+    }
+    assert!(Syscall::IpcCall as usize == 0 && Syscall::IpcReply as usize == 3);
+    assert!(Syscall::MemMap as usize == 7 && Syscall::MemAlloc as usize == 9);
+    assert!(Syscall::TaskSpawn as usize == 10 && Syscall::TimeSleep as usize == 14);
+};
+
+/// Коды ошибок syscall'ов: ядро возвращает их отрицательными в rax,
+/// libcuprum превращает в `Error` того же имени. Неотрицательный rax — успех.
+/// Syscall error codes: the kernel returns them negative in rax, libcuprum
+/// turns them into the `Error` of the same name. A non-negative rax is success.
+///
+/// Код — часть ABI, как и номер syscall'а: новые — в конец.
+/// A code is part of the ABI, like a syscall number: new ones go last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    /// Нет такой capability или она другого типа / No such capability, or the wrong kind
+    InvalidCap   = -1,
+    NoPermission = -2,
+    InvalidArg   = -3,
+    NoMemory     = -4,
+    NotFound     = -5,
+    /// Порт уже занят / The port is already taken
+    AddrInUse    = -6,
+    /// Данных пока нет / No data yet
+    WouldBlock   = -7,
+    /// Syscall не реализован или выключен / The syscall isn't implemented or is disabled
+    NotSupported = -8,
+    /// Дедлайн прошёл / The deadline passed
+    TimedOut     = -9,
+}
+
+impl Errno {
+    /// Все коды по убыванию: `ALL[i]` — это `-(i + 1)` / Every code, descending: `ALL[i]` is `-(i + 1)`
+    pub const ALL: [Self; 9] = [
+        Self::InvalidCap, Self::NoPermission, Self::InvalidArg, Self::NoMemory,
+        Self::NotFound, Self::AddrInUse, Self::WouldBlock, Self::NotSupported,
+        Self::TimedOut,
+    ];
+
+    /// Из возврата syscall'а; успех или незнакомый код — `None`.
+    /// From a syscall return; success or an unfamiliar code — `None`.
+    pub const fn from_raw(raw: isize) -> Option<Self> {
+        if raw < 0 && raw.unsigned_abs() <= Self::ALL.len() {
+            Some(Self::ALL[raw.unsigned_abs() - 1])
+        } else {
+            None
+        }
+    }
+}
+
+const _: () = {
+    let mut i = 0;
+    while i < Errno::ALL.len() {
+        assert!(Errno::ALL[i] as isize == -(i as isize + 1));
+        i += 1;
+    }
+};
+
+/// Класс планирования, который задача может запросить у ядра.
+/// Scheduling class a task can request from the kernel.
+///
+/// Выше `Normal` — только с capability на управление планировщиком.
+/// Above `Normal` requires a scheduler-control capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+    Interactive = 0,
+    Normal      = 1,
+    Background  = 2,
+}
+
+impl Priority {
+    /// Из аргумента syscall / From a syscall argument
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Interactive),
+            1 => Some(Self::Normal),
+            2 => Some(Self::Background),
+            _ => None,
+        }
+    }
+}
+
+/// Аргументы `ipc_call_buf`: ответ пишется прямо в буфер вызывающего.
+/// Arguments of `ipc_call_buf`: the reply is written straight into the
+/// caller's buffer.
+///
+/// Ядро возвращает полную длину ответа; если она больше `reply_cap`,
+/// в буфере только префикс.
+/// The kernel returns the full reply length; if it exceeds `reply_cap`
+/// the buffer holds only a prefix.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CallBufArgs {
+    /// Адрес запроса (`Message`) / Request address (`Message`)
+    pub request:     usize,
+    pub request_len: usize,
+    /// Буфер ответа / Reply buffer
+    pub reply:       usize,
+    pub reply_cap:   usize,
+}
+
+/// Бит `token` у `evq_attach`: источник edge-triggered, а не level.
+/// The `evq_attach` token bit: the source is edge-triggered, not level.
+pub const EVQ_EDGE: u64 = 1 << 63;
+
+/// Запись, которую `evq_wait` пишет на каждый готовый источник.
+/// The entry `evq_wait` writes for each ready source.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvqEvent {
+    /// Идентификатор порта в ядре / The port's kernel identifier
+    pub port:  u64,
+    /// Метка из `evq_attach` без `EVQ_EDGE` / The `evq_attach` token without `EVQ_EDGE`
+    pub token: u64,
+}
+
+/// Протокол loopback-сокетов между libcuprum и сервером `netd`.
+/// Loopback socket protocol between libcuprum and the `netd` server.
+///
+/// Каждое сообщение начинается с `Header`; у `SendTo` за ним датаграмма,
+/// у ответа на `RecvFrom` — полученная датаграмма.
+/// Every message starts with a `Header`; a `SendTo` is followed by the
+/// datagram, a reply to `RecvFrom` by the received datagram.
+pub mod net {
+    use super::MAX_INLINE_PAYLOAD;
+
+    pub const HEADER_LEN: usize = 8;
+
+    /// Больше в одно сообщение не влезает / Anything larger doesn't fit one message
+    pub const MAX_DATAGRAM: usize = MAX_INLINE_PAYLOAD - HEADER_LEN;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(u8)]
+    pub enum Op {
+        /// Новый сокет; id — в ответе / New socket; the id is in the reply
+        Socket   = 0,
+        Bind     = 1,
+        /// `port` — получатель / `port` is the destination
+        SendTo   = 2,
+        /// `port` в ответе — отправитель / `port` in the reply is the sender
+        RecvFrom = 3,
+        Close    = 4,
+    }
+
+    impl Op {
+        pub const fn from_raw(raw: u8) -> Option<Self> {
+            match raw {
+                0 => Some(Self::Socket),
+                1 => Some(Self::Bind),
+                2 => Some(Self::SendTo),
+                3 => Some(Self::RecvFrom),
+                4 => Some(Self::Close),
+                _ => None,
+            }
+        }
+    }
+
+    /// Код ответа `netd` / `netd` reply status
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(u8)]
+    pub enum Status {
+        Ok          = 0,
+        /// Нет такого сокета / No such socket
+        BadSocket   = 1,
+        /// Отправка с непривязанного сокета / Sending from an unbound socket
+        NotBound    = 2,
+        /// Порт занят или сокет уже привязан / Port taken or socket already bound
+        AddrInUse   = 3,
+        /// Никто не слушает порт / Nobody listens on the port
+        NoRoute     = 4,
+        /// Датаграмма больше `MAX_DATAGRAM` / Datagram larger than `MAX_DATAGRAM`
+        TooBig      = 5,
+        /// Очередь пуста (recv) / Queue empty (recv)
+        WouldBlock  = 6,
+        /// Очередь получателя или таблица сокетов полна / Receiver queue or socket table full
+        NoBuffers   = 7,
+        Invalid     = 8,
+    }
+
+    impl Status {
+        pub const fn from_raw(raw: u8) -> Self {
+            match raw {
+                0 => Self::Ok,
+                1 => Self::BadSocket,
+                2 => Self::NotBound,
+                3 => Self::AddrInUse,
+                4 => Self::NoRoute,
+                5 => Self::TooBig,
+                6 => Self::WouldBlock,
+                7 => Self::NoBuffers,
+                _ => Self::Invalid,
+            }
+        }
+    }
+
+    /// op (1) | status (1) | socket (2, LE) | port (2, LE) | len (2, LE)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Header {
+        pub op:     u8,
+        pub status: u8,
+        pub socket: u16,
+        pub port:   u16,
+        /// Длина датаграммы за заголовком / Length of the datagram after the header
+        pub len:    u16,
+    }
+
+    impl Header {
+        pub const fn request(op: Op, socket: u16, port: u16, len: u16) -> Self {
+            Self { op: op as u8, status: Status::Ok as u8, socket, port, len }
+        }
+
+        pub fn encode(&self) -> [u8; HEADER_LEN] {
+            let [s0, s1] = self.socket.to_le_bytes();
+            let [p0, p1] = self.port.to_le_bytes();
+            let [l0, l1] = self.len.to_le_bytes();
+            [self.op, self.status, s0, s1, p0, p1, l0, l1]
+        }
+
+        pub fn decode(bytes: &[u8]) -> Option<Self> {
+            let b: &[u8; HEADER_LEN] = bytes.get(..HEADER_LEN)?.try_into().ok()?;
+            Some(Self {
+                op:     b[0],
+                status: b[1],
+                socket: u16::from_le_bytes([b[2], b[3]]),
+                port:   u16::from_le_bytes([b[4], b[5]]),
+                len:    u16::from_le_bytes([b[6], b[7]]),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errno_round_trips() {
+        for errno in Errno::ALL {
+            assert_eq!(Errno::from_raw(errno as isize), Some(errno));
+        }
+        for raw in [0, 1, isize::MAX, -(Errno::ALL.len() as isize) - 1, isize::MIN] {
+            assert_eq!(Errno::from_raw(raw), None, "{raw}");
+        }
+    }
+
+    #[test]
+    fn syscall_numbers_round_trip() {
+        for call in Syscall::ALL {
+            assert_eq!(Syscall::from_raw(call as usize), Some(call));
+        }
+        assert_eq!(Syscall::from_raw(Syscall::ALL.len()), None);
+        assert_eq!(Syscall::from_raw(usize::MAX), None);
+    }
+}
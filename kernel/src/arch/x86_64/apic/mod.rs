@@ -0,0 +1,182 @@
+//! Local APIC + IO-APIC — замена 8259 PIC
+//! Local APIC + IO-APIC — the 8259 PIC's replacement
+//!
+//! До VMM прерывания идут через PIC (`idt::init`): MMIO APIC ещё негде
+//! замапить. `init` после VMM гасит PIC (все линии закрыты, IMCR —
+//! на APIC), включает Local APIC и переносит ISA IRQ в IO-APIC на те же
+//! векторы `0x20 + irq`. Линии, открытые в PIC, остаются открытыми.
+//! Until the VMM is up interrupts go through the PIC (`idt::init`): there
+//! is nowhere to map the APIC MMIO yet. `init` after the VMM silences the
+//! PIC (every line masked, IMCR to the APIC), enables the Local APIC and
+//! moves the ISA IRQs to the IO-APIC on the same `0x20 + irq` vectors.
+//! Lines that were open in the PIC stay open.
+//!
+//! MADT пока не разбирается: Local APIC — из IA32_APIC_BASE, IO-APIC — по
+//! стандартному адресу, подмена ISA — только типичная IRQ 0 → GSI 2.
+//! The MADT isn't parsed yet: the Local APIC comes from IA32_APIC_BASE,
+//! the IO-APIC from the standard address, and the only ISA override is the
+//! usual IRQ 0 → GSI 2.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::mm::pmm::{PhysAddr, PAGE_SIZE};
+use crate::mm::vmm::{self, PageFlags};
+use crate::mm::MmError;
+use crate::sync::IrqMutex;
+use super::{idt, interrupts, msr};
+
+/// Вектор ложных прерываний Local APIC; EOI на него не нужен.
+/// The Local APIC's spurious vector; it takes no EOI.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Вектор ISA IRQ 0 — как у PIC после ремапа / ISA IRQ 0's vector — as with the remapped PIC
+const IRQ_BASE: u8 = 0x20;
+/// ISA IRQ, которые переносятся в IO-APIC / ISA IRQs moved to the IO-APIC
+const ISA_IRQS: u8 = 16;
+
+/// Бит глобального включения в IA32_APIC_BASE / The global enable bit in IA32_APIC_BASE
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR:   u64 = 0x000F_FFFF_FFFF_F000;
+/// Где IO-APIC почти всегда; точный адрес знает MADT.
+/// Where the IO-APIC almost always is; the MADT knows the exact address.
+const IOAPIC_DEFAULT_BASE: u64 = 0xFEC0_0000;
+
+// Регистры Local APIC (смещения MMIO) / Local APIC registers (MMIO offsets)
+const LAPIC_ID:  usize = 0x20;
+const LAPIC_TPR: usize = 0x80;
+const LAPIC_EOI: usize = 0xB0;
+const LAPIC_SVR: usize = 0xF0;
+const SVR_ENABLE: u32 = 1 << 8;
+
+// Регистры IO-APIC: окно выбора и данных / IO-APIC registers: select and data windows
+const IOREGSEL:  usize = 0x00;
+const IOWIN:     usize = 0x10;
+const IOAPICVER: u32   = 0x01;
+const IOREDTBL:  u32   = 0x10;
+const REDIR_MASKED: u32 = 1 << 16;
+
+/// Порты IMCR — переключают линию INTR с PIC на APIC.
+/// IMCR ports — switch the INTR line from the PIC to the APIC.
+const IMCR_SELECT: u16 = 0x22;
+const IMCR_DATA:   u16 = 0x23;
+
+/// Виртуальные адреса MMIO; 0 — APIC не включён.
+/// MMIO virtual addresses; 0 — the APIC isn't enabled.
+static LAPIC:  AtomicU64 = AtomicU64::new(0);
+static IOAPIC: AtomicU64 = AtomicU64::new(0);
+
+/// IOREGSEL и IOWIN — пара, между ними никто не должен влезть.
+/// IOREGSEL and IOWIN are a pair; nobody may get in between.
+static IOAPIC_LOCK: IrqMutex<()> = IrqMutex::new(());
+
+/// Идут ли прерывания уже через APIC / Whether interrupts go through the APIC yet
+pub fn enabled() -> bool {
+    LAPIC.load(Ordering::Acquire) != 0
+}
+
+/// Конец обработки прерывания / End of interrupt
+pub fn eoi() {
+    unsafe { lapic_write(LAPIC_EOI, 0); }
+}
+
+/// Открыть ISA IRQ в IO-APIC / Unmask an ISA IRQ in the IO-APIC
+pub fn unmask_irq(irq: u8) {
+    if irq >= ISA_IRQS || !enabled() { return; }
+    let reg = IOREDTBL + 2 * isa_gsi(irq);
+    let _lock = IOAPIC_LOCK.lock();
+    unsafe { ioapic_write(reg, ioapic_read(reg) & !REDIR_MASKED); }
+}
+
+/// Перейти с PIC на APIC. Без APIC в CPUID остаёмся на PIC.
+/// Switch from the PIC to the APIC. Without an APIC in CPUID we stay on the PIC.
+pub fn init() -> Result<(), MmError> {
+    if !has_apic() {
+        crate::kprintln!("[apic] no Local APIC — staying on the 8259 PIC");
+        return Ok(());
+    }
+    let base = unsafe { msr::read(msr::IA32_APIC_BASE) };
+    let lapic_phys  = PhysAddr::new(base & APIC_BASE_ADDR);
+    let ioapic_phys = PhysAddr::new(IOAPIC_DEFAULT_BASE);
+    let lapic  = map_mmio(lapic_phys)?;
+    let ioapic = map_mmio(ioapic_phys)?;
+
+    let were_enabled = interrupts::are_enabled();
+    interrupts::disable();
+    unsafe {
+        msr::write(msr::IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+        let pic_masked = idt::pic_mask_all();
+        idt::outb(IMCR_SELECT, 0x70);
+        idt::outb(IMCR_DATA, 0x01);
+
+        LAPIC.store(lapic, Ordering::Release);
+        IOAPIC.store(ioapic, Ordering::Release);
+        lapic_write(LAPIC_TPR, 0);
+        lapic_write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+
+        // Все ISA IRQ — на BSP, фронт, активный высокий уровень
+        // Every ISA IRQ — to the BSP, edge-triggered, active high
+        let dest = lapic_read(LAPIC_ID) >> 24;
+        let _lock = IOAPIC_LOCK.lock();
+        for irq in 0..ISA_IRQS {
+            let reg = IOREDTBL + 2 * isa_gsi(irq);
+            let masked = if pic_masked & (1 << irq) != 0 { REDIR_MASKED } else { 0 };
+            ioapic_write(reg + 1, dest << 24);
+            ioapic_write(reg, (IRQ_BASE + irq) as u32 | masked);
+        }
+    }
+    if were_enabled { interrupts::enable(); }
+
+    let pins = unsafe {
+        let _lock = IOAPIC_LOCK.lock();
+        (ioapic_read(IOAPICVER) >> 16 & 0xFF) + 1
+    };
+    crate::kprintln!("[apic] LAPIC at {:#x}, IO-APIC at {:#x} ({} pins)",
+                     lapic_phys.as_u64(), ioapic_phys.as_u64(), pins);
+    Ok(())
+}
+
+/// ISA IRQ → GSI. Без MADT — только подмена, которая есть почти везде.
+/// ISA IRQ → GSI. Without the MADT — only the override nearly everyone has.
+fn isa_gsi(irq: u8) -> u32 {
+    if irq == 0 { 2 } else { irq as u32 }
+}
+
+/// Поддерживает ли CPU APIC (CPUID.1:EDX[9]).
+/// Whether the CPU supports the APIC (CPUID.1:EDX[9]).
+fn has_apic() -> bool {
+    let leaf = core::arch::x86_64::__cpuid(1);
+    leaf.edx & (1 << 9) != 0
+}
+
+/// Страница MMIO — некэшируемая, по её HHDM-адресу / An MMIO page — uncached, at its HHDM address
+fn map_mmio(phys: PhysAddr) -> Result<u64, MmError> {
+    let virt = vmm::phys_to_virt(phys);
+    let flags = PageFlags::KERNEL_RW | PageFlags::NO_CACHE | PageFlags::WRITE_THROUGH;
+    vmm::map_kernel_range(virt, phys, PAGE_SIZE as u64, flags)?;
+    Ok(virt.as_u64())
+}
+
+unsafe fn lapic_read(reg: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((LAPIC.load(Ordering::Acquire) as usize + reg) as *const u32) }
+}
+
+unsafe fn lapic_write(reg: usize, value: u32) {
+    unsafe { core::ptr::write_volatile((LAPIC.load(Ordering::Acquire) as usize + reg) as *mut u32, value); }
+}
+
+/// Под `IOAPIC_LOCK` / Under `IOAPIC_LOCK`
+unsafe fn ioapic_read(reg: u32) -> u32 {
+    let base = IOAPIC.load(Ordering::Acquire) as usize;
+    unsafe {
+        core::ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+        core::ptr::read_volatile((base + IOWIN) as *const u32)
+    }
+}
+
+/// Под `IOAPIC_LOCK` / Under `IOAPIC_LOCK`
+unsafe fn ioapic_write(reg: u32, value: u32) {
+    let base = IOAPIC.load(Ordering::Acquire) as usize;
+    unsafe {
+        core::ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+        core::ptr::write_volatile((base + IOWIN) as *mut u32, value);
+    }
+}
@@ -0,0 +1,36 @@
+//! Backtrace по цепочке RBP / RBP-chain backtrace
+//!
+//! Ядро собирается с `force-frame-pointers`, так что каждый кадр начинается
+//! с `[rbp] = предыдущий rbp, [rbp + 8] = адрес возврата`. Символов нет —
+//! печатаем адреса, их разрешает `addr2line` по ELF ядра.
+//! The kernel is built with `force-frame-pointers`, so every frame starts
+//! with `[rbp] = previous rbp, [rbp + 8] = return address`. There are no
+//! symbols — we print addresses for `addr2line` against the kernel ELF.
+
+use crate::mm::vmm::VirtAddr;
+
+/// Сколько кадров печатать, не больше / At most this many frames
+const MAX_FRAMES: usize = 16;
+
+/// Напечатать `rip`, затем адреса возврата по цепочке от `rbp`.
+/// Цепочка может быть порчена (нас зовут из паники) — идём, пока указатель
+/// выглядит как кадр ядра и растёт вверх по стеку.
+/// Print `rip`, then return addresses along the chain from `rbp`. The chain
+/// may be corrupt (we are called on the way to a panic) — walk only while the
+/// pointer looks like a kernel frame and moves up the stack.
+pub fn print(rip: u64, mut rbp: u64) {
+    crate::kprintln!("backtrace:");
+    crate::kprintln!("  #0  {:#018x}", rip);
+    for depth in 1..MAX_FRAMES {
+        if rbp == 0 || rbp % 8 != 0 || !VirtAddr::new(rbp).is_canonical() || VirtAddr::new(rbp).is_user() {
+            break;
+        }
+        // Выровнен и в верхней половине — кадр ядра, читаем.
+        // Aligned and in the upper half — a kernel frame, read it.
+        let (next, ret) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if ret == 0 { break; }
+        crate::kprintln!("  #{:<2} {:#018x}", depth, ret);
+        if next <= rbp { break; }
+        rbp = next;
+    }
+}
@@ -2,10 +2,23 @@
 //!
 //! Limine jumps here in 64-bit long mode with interrupts disabled.
 //! We set up the boot stack, zero BSS, then call kernel_main.
+//!
+//! Весь boot-стек заполняется канарейкой до первого push, так что после
+//! init видно, докуда он дорос и не переполнен ли.
+//! The whole boot stack is filled with a canary before the first push, so
+//! after init we can see how deep it grew and whether it overflowed.
+
+use limine::memory_map::Entry;
+use limine::request::{ExecutableAddressRequest, MemoryMapRequest, ModuleRequest};
 
-use core::arch::global_asm;
+/// Размер boot-стека / Boot stack size
+pub const BOOT_STACK_SIZE: usize = 64 * 1024;
 
-global_asm!(
+/// Узор незатронутого стека / Pattern of untouched stack
+pub const BOOT_STACK_CANARY: u64 = 0xC0FF_EE57_ACC5_CA9E;
+
+#[cfg(not(test))]
+core::arch::global_asm!(
     r#"
 .section .text
 .global _start
@@ -23,6 +36,12 @@ _start:
     xorl %eax, %eax
     rep stosb
 
+    /* Fill the boot stack with the canary (BSS zeroing wiped it) */
+    leaq boot_stack_bottom(%rip), %rdi
+    movq ${words}, %rcx
+    movabsq ${canary}, %rax
+    rep stosq
+
     callq kernel_main
 
     /* kernel_main never returns — halt just in case */
@@ -33,9 +52,104 @@ _start:
 
 .section .bss
 .balign 16
+.global boot_stack_bottom
 boot_stack_bottom:
-    .skip 65536
+    .skip {size}
 boot_stack_top:
 "#,
+    size   = const BOOT_STACK_SIZE,
+    words  = const BOOT_STACK_SIZE / 8,
+    canary = const BOOT_STACK_CANARY,
     options(att_syntax)
 );
+
+#[used]
+static MEMMAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
+
+/// Карта физической памяти от Limine (по возрастанию адресов); `None` —
+/// загрузчик не ответил.
+/// The physical memory map from Limine (in ascending address order); `None` —
+/// the bootloader did not answer.
+pub fn memory_map() -> Option<&'static [&'static Entry]> {
+    MEMMAP_REQUEST.get_response().map(|response| response.entries())
+}
+
+#[used]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+/// Модули из `module_path:` в limine.conf: `(путь, содержимое)`. Путь не
+/// UTF-8 — модуль пропускается.
+/// The modules from `module_path:` in limine.conf: `(path, contents)`. A
+/// non-UTF-8 path skips the module.
+pub fn modules() -> impl Iterator<Item = (&'static str, &'static [u8])> {
+    let files = MODULE_REQUEST.get_response().map_or(&[][..], |response| response.modules());
+    files.iter().filter_map(|file| {
+        let path = file.path().to_str().ok()?;
+        let data = unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) };
+        Some((path, data))
+    })
+}
+
+#[used]
+static EXECUTABLE_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressRequest::new();
+
+extern "C" {
+    static boot_stack_bottom: [u64; BOOT_STACK_SIZE / 8];
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+/// Образ ядра в физической памяти: `(начало, размер)`. Границы — из
+/// линкер-скрипта, физическая база — от Limine.
+/// The kernel image in physical memory: `(start, size)`. The bounds come
+/// from the linker script, the physical base from Limine.
+pub fn kernel_phys_range() -> Option<(u64, u64)> {
+    let response = EXECUTABLE_ADDRESS_REQUEST.get_response()?;
+    let start = core::ptr::addr_of!(__kernel_start) as u64;
+    let end   = core::ptr::addr_of!(__kernel_end) as u64;
+    Some((response.physical_base(), end - start))
+}
+
+/// Сколько байт стека было тронуто: от первого слова без канарейки
+/// (снизу) до вершины.
+/// How many bytes of the stack were touched: from the first non-canary
+/// word (from the bottom) up to the top.
+pub fn high_water_mark(stack: &[u64]) -> usize {
+    let untouched = stack.iter().take_while(|&&w| w == BOOT_STACK_CANARY).count();
+    (stack.len() - untouched) * 8
+}
+
+/// Проверить boot-стек после init и напечатать, сколько использовано.
+/// `false` — нижнее слово затёрто: стек переполнялся.
+/// Check the boot stack after init and log how much was used.
+/// `false` — the bottom word is clobbered: the stack overflowed.
+pub fn check_boot_stack() -> bool {
+    let stack = unsafe { &*core::ptr::addr_of!(boot_stack_bottom) };
+    let used = high_water_mark(stack);
+    let intact = stack[0] == BOOT_STACK_CANARY;
+    if intact {
+        crate::kprintln!("[boot] stack: {} / {} KB used", used / 1024, BOOT_STACK_SIZE / 1024);
+    } else {
+        crate::kprintln!("[boot] stack OVERFLOW: canary at bottom clobbered ({} KB)", BOOT_STACK_SIZE / 1024);
+    }
+    intact
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_water_mark_counts_from_the_lowest_touched_word() {
+        let mut stack = [BOOT_STACK_CANARY; 64];
+        assert_eq!(high_water_mark(&stack), 0);
+        // Стек растёт вниз: тронуты верхние 10 слов, и одно глубже — с дырой
+        // The stack grows down: the top 10 words were touched, and one deeper — with a gap
+        stack[54..].fill(0);
+        assert_eq!(high_water_mark(&stack), 10 * 8);
+        stack[40] = 0x1234;
+        assert_eq!(high_water_mark(&stack), 24 * 8);
+        stack[0] = 0;
+        assert_eq!(high_water_mark(&stack), 64 * 8);
+    }
+}
@@ -0,0 +1,73 @@
+//! Управляющие регистры (CR0/CR4) — все чтения/записи здесь
+//! Control registers (CR0/CR4) — all reads/writes live here
+
+use bitflags::bitflags;
+use core::arch::asm;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct Cr0: u64 {
+        const PROTECTED_MODE = 1 << 0;
+        const MONITOR_COPROC = 1 << 1;
+        const EMULATE_COPROC = 1 << 2;
+        const TASK_SWITCHED  = 1 << 3;
+        const NUMERIC_ERROR  = 1 << 5;
+        /// Запрет записи в RO-страницы даже из ring 0
+        /// Forbid writes to RO pages even from ring 0
+        const WRITE_PROTECT  = 1 << 16;
+        const ALIGNMENT_MASK = 1 << 18;
+        const NOT_WRITE_THROUGH = 1 << 29;
+        const CACHE_DISABLE  = 1 << 30;
+        const PAGING         = 1 << 31;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct Cr4: u64 {
+        const PAE        = 1 << 5;
+        const PGE        = 1 << 7;
+        const OSFXSR     = 1 << 9;
+        const OSXMMEXCPT = 1 << 10;
+        const FSGSBASE   = 1 << 16;
+        const SMEP       = 1 << 20;
+        const SMAP       = 1 << 21;
+    }
+}
+
+pub fn read_cr0() -> Cr0 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags)); }
+    Cr0::from_bits_retain(value)
+}
+
+/// # Safety
+/// Сброс PG/PE или WP меняет смысл всех маппингов.
+/// Clearing PG/PE or WP changes the meaning of every mapping.
+pub unsafe fn write_cr0(value: Cr0) {
+    unsafe { asm!("mov cr0, {}", in(reg) value.bits(), options(nostack, preserves_flags)); }
+}
+
+pub fn read_cr4() -> Cr4 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags)); }
+    Cr4::from_bits_retain(value)
+}
+
+/// # Safety
+/// Включение неподдерживаемого бита — #GP.
+/// Setting an unsupported bit raises #GP.
+pub unsafe fn write_cr4(value: Cr4) {
+    unsafe { asm!("mov cr4, {}", in(reg) value.bits(), options(nostack, preserves_flags)); }
+}
+
+/// Включить CR0.WP: без него ring 0 пишет в `KERNEL_RO`/`KERNEL_EX`
+/// страницы, и W^X ядра — только видимость.
+/// Enable CR0.WP: without it ring 0 writes straight through
+/// `KERNEL_RO`/`KERNEL_EX` pages and kernel W^X is cosmetic.
+pub fn enable_write_protect() {
+    let cr0 = read_cr0();
+    if !cr0.contains(Cr0::WRITE_PROTECT) {
+        unsafe { write_cr0(cr0 | Cr0::WRITE_PROTECT); }
+    }
+}
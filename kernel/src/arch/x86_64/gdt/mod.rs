@@ -1,11 +1,14 @@
 //! Global Descriptor Table (GDT) — x86_64
 
-use core::mem::size_of;
+use core::mem::{offset_of, size_of};
+use super::msr;
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::vmm::{self, VirtAddr};
 
 pub const KERNEL_CODE: u16 = 0x08;
 pub const KERNEL_DATA: u16 = 0x10;
-pub const USER_CODE:   u16 = 0x1B;
-pub const USER_DATA:   u16 = 0x23;
+pub const USER_DATA:   u16 = 0x1B;
+pub const USER_CODE:   u16 = 0x23;
 pub const TSS_SEL:     u16 = 0x28;
 
 #[derive(Clone, Copy)]
@@ -59,6 +62,14 @@ impl TssEntry {
     }
 }
 
+/// Байт в битмапе I/O портов: по биту на каждый из 65536 портов.
+/// Bytes in the I/O permission bitmap: one bit for each of 65536 ports.
+pub const IO_BITMAP_BYTES: usize = 65536 / 8;
+
+/// Битмап прав на порты задачи: бит 0 — доступ разрешён, 1 — #GP.
+/// A task's port permission bitmap: bit 0 — access allowed, 1 — #GP.
+pub type IoBitmap = [u8; IO_BITMAP_BYTES];
+
 #[repr(C, packed)]
 pub struct Tss {
     reserved0:      u32,
@@ -70,6 +81,9 @@ pub struct Tss {
     reserved2:      u64,
     reserved3:      u16,
     pub iomap_base: u16,
+    /// Битмап текущей задачи + завершающий байт 0xFF (требование CPU).
+    /// The current task's bitmap + the trailing 0xFF byte the CPU requires.
+    io_bitmap:      [u8; IO_BITMAP_BYTES + 1],
 }
 
 impl Tss {
@@ -77,7 +91,8 @@ impl Tss {
         Self {
             reserved0: 0, rsp0: 0, rsp1: 0, rsp2: 0,
             reserved1: 0, ist: [0; 7], reserved2: 0,
-            reserved3: 0, iomap_base: size_of::<Tss>() as u16,
+            reserved3: 0, iomap_base: offset_of!(Tss, io_bitmap) as u16,
+            io_bitmap: [0xFF; IO_BITMAP_BYTES + 1],
         }
     }
 }
@@ -87,46 +102,160 @@ struct GdtBase {
     null:        GdtEntry,
     kernel_code: GdtEntry,
     kernel_data: GdtEntry,
-    user_code:   GdtEntry,
     user_data:   GdtEntry,
+    user_code:   GdtEntry,
     tss:         TssEntry,
 }
 
+impl GdtBase {
+    const fn new() -> Self {
+        Self {
+            null:        GdtEntry::null(),
+            kernel_code: GdtEntry::new(0x9A, 0xA0),
+            kernel_data: GdtEntry::new(0x92, 0xC0),
+            user_data:   GdtEntry::new(0xF2, 0xC0),
+            user_code:   GdtEntry::new(0xFA, 0xA0),
+            tss: TssEntry {
+                limit_low: 0, base_low: 0, base_mid: 0,
+                access: 0, granularity: 0, base_high: 0,
+                base_upper: 0, reserved: 0,
+            },
+        }
+    }
+}
+
+// RPL селектора обязан совпадать с DPL дескриптора, иначе iretq в ring 3 — #GP.
+// The selector RPL must match the descriptor DPL, or iretq to ring 3 #GPs.
+const _: () = assert!(USER_CODE & 3 == 3 && USER_DATA & 3 == 3);
+const _: () = assert!(KERNEL_CODE & 3 == 0 && KERNEL_DATA & 3 == 0 && TSS_SEL & 3 == 0);
+// Индекс селектора (биты 3..) — смещение своего слота в GDT, TI = 0.
+// The selector index (bits 3..) is its slot's offset in the GDT, TI = 0.
+const _: () = assert!(
+    (KERNEL_CODE & !7) as usize == offset_of!(GdtBase, kernel_code)
+        && (KERNEL_DATA & !7) as usize == offset_of!(GdtBase, kernel_data)
+        && (USER_CODE & !7) as usize == offset_of!(GdtBase, user_code)
+        && (USER_DATA & !7) as usize == offset_of!(GdtBase, user_data)
+        && (TSS_SEL & !7) as usize == offset_of!(GdtBase, tss)
+);
+// `sysret` берёт SS = база + 8, CS = база + 16, а `syscall` — SS = CS ядра + 8:
+// данные пользователя строго перед его кодом, данные ядра — после кода.
+// `sysret` takes SS = base + 8 and CS = base + 16, and `syscall` takes
+// SS = kernel CS + 8: user data sits right before user code, kernel data
+// right after kernel code.
+const _: () = assert!(
+    (USER_CODE & !7) == (USER_DATA & !7) + 8 && KERNEL_DATA == KERNEL_CODE + 8
+);
+
 #[repr(C, packed)]
 struct GdtDescriptor {
     size:   u16,
     offset: u64,
 }
 
-static mut KERNEL_STACK: [u8; 16 * 1024] = [0; 16 * 1024];
-static mut TSS: Tss = Tss::new();
-static mut GDT: GdtBase = GdtBase {
-    null:        GdtEntry::null(),
-    kernel_code: GdtEntry::new(0x9A, 0xA0),
-    kernel_data: GdtEntry::new(0x92, 0xC0),
-    user_code:   GdtEntry::new(0xFA, 0xA0),
-    user_data:   GdtEntry::new(0xF2, 0xC0),
-    tss: TssEntry {
-        limit_low: 0, base_low: 0, base_mid: 0,
-        access: 0, granularity: 0, base_high: 0,
-        base_upper: 0, reserved: 0,
-    },
-};
-
-pub fn init() {
+/// Индексы IST (1-based, как в IDT) / IST indices (1-based, as in the IDT)
+pub const IST_DOUBLE_FAULT: u8 = 1;
+pub const IST_PAGE_FAULT:   u8 = 2;
+pub const IST_NMI:          u8 = 3;
+
+/// IST-стеков на CPU / IST stacks per CPU
+const IST_COUNT: usize = 3;
+
+/// Размер каждого IST-стека / Size of each IST stack
+const IST_STACK_SIZE: usize = 16 * 1024;
+
+/// IST-стек со страницей под ним: после `unmap_ist_guards` она не
+/// замаплена, и переполнение — #PF/#DF, а не порча соседнего стека.
+/// An IST stack with a page beneath it: after `unmap_ist_guards` it isn't
+/// mapped, so an overflow is a #PF/#DF rather than a trampled neighbour.
+#[repr(C, align(4096))]
+struct IstStack {
+    guard: [u8; PAGE_SIZE],
+    stack: [u8; IST_STACK_SIZE],
+}
+
+// #PF, #DF и NMI на своих стеках: переполнение стека ядра упирается в guard-
+// страницу, и обработчику нужен стек, который ещё есть. Отдельно от `CPUS`,
+// чтобы лечь в .bss.
+// #PF, #DF and NMI run on their own stacks: a kernel stack overflow hits the
+// guard page, and the handler needs a stack that still exists. Kept apart
+// from `CPUS` so they land in .bss.
+static mut IST_STACKS: [[IstStack; IST_COUNT]; MAX_CPUS] =
+    [const { [const { IstStack { guard: [0; PAGE_SIZE], stack: [0; IST_STACK_SIZE] } }; IST_COUNT] }; MAX_CPUS];
+
+/// Сколько CPU умеем поднять / How many CPUs we can bring up
+pub const MAX_CPUS: usize = 8;
+
+/// Всё, что у CPU своё, кроме IST-стеков: TSS (rsp0, IST, битмап портов).
+/// GS base ядра указывает сюда.
+/// Everything a CPU owns but the IST stacks: the TSS (rsp0, IST, port
+/// bitmap). The kernel GS base points here.
+#[repr(C)]
+struct PerCpu {
+    /// Адрес самой структуры — `gs:[0]` даёт его без `rdmsr`
+    /// The structure's own address — `gs:[0]` yields it without `rdmsr`
+    this:  u64,
+    index: usize,
+    /// Открыт ли сейчас в TSS чей-то битмап (иначе — всё запрещено).
+    /// Whether the TSS currently holds someone's bitmap (otherwise all denied).
+    io_bitmap_open: bool,
+    /// RSP пользователя, пока вход `syscall` не перешёл на стек ядра
+    /// The user RSP while the `syscall` entry hasn't reached the kernel stack yet
+    user_rsp: u64,
+    tss:   Tss,
+}
+
+const _: () = assert!(offset_of!(PerCpu, this) == 0);
+
+/// Смещения в per-CPU данных для `gs:[...]` во входе `syscall`.
+/// Per-CPU data offsets for `gs:[...]` in the `syscall` entry.
+pub const PERCPU_USER_RSP: usize = offset_of!(PerCpu, user_rsp);
+pub const PERCPU_KERNEL_RSP: usize = offset_of!(PerCpu, tss) + offset_of!(Tss, rsp0);
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            this: 0, index: 0, io_bitmap_open: false, user_rsp: 0, tss: Tss::new(),
+        }
+    }
+}
+
+static mut CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+// После init не меняется — lockdown() делает страницу RO. `ltr` пишет бит
+// busy в дескриптор TSS, поэтому все CPU обязаны пройти `init` до lockdown.
+// Never changes after init — lockdown() makes its page RO. `ltr` writes the
+// busy bit into the TSS descriptor, so every CPU must go through `init`
+// before lockdown.
+#[link_section = ".data.ro_after_init"]
+static mut GDTS: [GdtBase; MAX_CPUS] = [const { GdtBase::new() }; MAX_CPUS];
+
+/// Загрузить GDT и TSS CPU `cpu` и направить на них GS base ядра.
+/// Вызывается на каждом CPU один раз: BSP — 0, AP — по порядку.
+/// Load CPU `cpu`'s GDT and TSS and point the kernel GS base at them.
+/// Called once on each CPU: the BSP is 0, APs follow in order.
+pub fn init(cpu: usize) {
+    assert!(cpu < MAX_CPUS, "CPU {} beyond MAX_CPUS", cpu);
     unsafe {
-        // &raw const — безопасный способ получить указатель на static mut
-        // &raw const — safe way to get pointer to static mut
-        let stack_top = (&raw const KERNEL_STACK) as *const u8;
-        let stack_top = stack_top.add(KERNEL_STACK.len()) as u64;
-        TSS.rsp0 = stack_top;
+        // TSS.rsp0 выставляет планировщик при переключении на задачу —
+        // у каждой задачи свой стек ядра (sched::task::KernelStack).
+        // TSS.rsp0 is set by the scheduler when switching to a task —
+        // every task has its own kernel stack (sched::task::KernelStack).
 
-        let tss_addr = (&raw const TSS) as u64;
-        GDT.tss = TssEntry::from_tss(tss_addr, size_of::<Tss>() as u64);
+        // &raw mut — безопасный способ получить указатель на static mut
+        // &raw mut — safe way to get pointer to static mut
+        let this = &raw mut CPUS[cpu];
+        (*this).this = this as u64;
+        (*this).index = cpu;
+        let tss_addr = (&raw const (*this).tss) as u64;
+        for (i, ist) in [IST_DOUBLE_FAULT, IST_PAGE_FAULT, IST_NMI].into_iter().enumerate() {
+            let stack = (&raw const IST_STACKS[cpu][i].stack) as u64;
+            (*this).tss.ist[ist as usize - 1] = stack + IST_STACK_SIZE as u64;
+        }
+        let gdt = &raw mut GDTS[cpu];
+        (*gdt).tss = TssEntry::from_tss(tss_addr, size_of::<Tss>() as u64);
 
         let descriptor = GdtDescriptor {
             size:   (size_of::<GdtBase>() - 1) as u16,
-            offset: (&raw const GDT) as u64,
+            offset: gdt as u64,
         };
 
         core::arch::asm!(
@@ -149,9 +278,82 @@ pub fn init() {
             tss   = in(reg) TSS_SEL,
             out("rax") _,
         );
+        // Загрузка GS обнулила базу — ставим после неё
+        // Loading GS zeroed its base — set it afterwards
+        msr::write(msr::IA32_GS_BASE, this as u64);
     }
 }
 
+/// Снять маппинг guard-страниц IST-стеков всех CPU — нужен VMM. Вернуть,
+/// сколько снято: ядро на 2MB-страницах их не отдаст.
+/// Unmap the guard pages of every CPU's IST stacks — needs the VMM. Return
+/// how many were unmapped: a kernel on 2MB pages won't give them up.
+pub fn unmap_ist_guards() -> usize {
+    // Только адреса — без ссылок на `static mut`
+    // Addresses only — no references to the `static mut`
+    let stacks = &raw const IST_STACKS;
+    let mut unmapped = 0;
+    for cpu in 0..MAX_CPUS {
+        for i in 0..IST_COUNT {
+            let guard = unsafe { &raw const (*stacks)[cpu][i].guard } as u64;
+            if vmm::unmap_kernel_guard(VirtAddr::new(guard)) { unmapped += 1; }
+        }
+    }
+    unmapped
+}
+
+/// Таблицы этого CPU — через GS base / This CPU's tables — through the GS base
+#[cfg(not(test))]
+fn this_cpu() -> *mut PerCpu {
+    let this: u64;
+    unsafe { core::arch::asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags)); }
+    this as *mut PerCpu
+}
+
+// На хосте GS ядра нет: тесты идут на одном CPU — нулевом. Его TSS только
+// пишется, ни один CPU его не читает.
+// On the host there is no kernel GS: tests run on a single CPU — CPU 0. Its
+// TSS is only written to, no CPU ever reads it.
+#[cfg(test)]
+fn this_cpu() -> *mut PerCpu {
+    unsafe { &raw mut CPUS[0] }
+}
+
+/// Номер этого CPU / This CPU's index
+#[allow(dead_code)] // понадобится с запуском AP / needed once APs are brought up
+pub fn cpu_index() -> usize {
+    unsafe { (*this_cpu()).index }
+}
+
+/// Поставить в TSS битмап портов задачи; `None` — все порты запрещены.
+/// Задачи без портов (почти все) не платят за копирование 8KB.
+/// Load a task's port bitmap into the TSS; `None` — every port denied.
+/// Tasks without ports (almost all of them) don't pay for the 8KB copy.
+pub fn load_io_bitmap(bitmap: Option<&IoBitmap>) {
+    unsafe {
+        let cpu = this_cpu();
+        let dst = &raw mut (*cpu).tss.io_bitmap as *mut u8;
+        match bitmap {
+            Some(bitmap) => {
+                core::ptr::copy_nonoverlapping(bitmap.as_ptr(), dst, IO_BITMAP_BYTES);
+                (*cpu).io_bitmap_open = true;
+            }
+            None if core::mem::replace(&mut (*cpu).io_bitmap_open, false) => {
+                core::ptr::write_bytes(dst, 0xFF, IO_BITMAP_BYTES);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Выставить TSS.rsp0 этого CPU — стек ядра для прерываний из ring 3.
+/// Set this CPU's TSS.rsp0 — the kernel stack used for interrupts from ring 3.
 pub fn set_kernel_stack(stack_top: u64) {
-    unsafe { TSS.rsp0 = stack_top; }
+    unsafe { (*this_cpu()).tss.rsp0 = stack_top; }
+}
+
+/// TSS.rsp0 этого CPU — для тестов / This CPU's TSS.rsp0 — for tests
+#[cfg(test)]
+pub fn kernel_stack() -> u64 {
+    unsafe { (*this_cpu()).tss.rsp0 }
 }
@@ -1,6 +1,8 @@
 //! Interrupt Descriptor Table (IDT) — x86_64
 
 use core::arch::{asm, naked_asm};
+use bitflags::bitflags;
+use crate::sync::IrqMutex;
 
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
@@ -41,6 +43,9 @@ struct IdtDescriptor {
 }
 
 const IDT_SIZE: usize = 256;
+// После init не меняется — lockdown() делает страницу RO
+// Never changes after init — lockdown() makes its page RO
+#[link_section = ".data.ro_after_init"]
 static mut IDT: [IdtEntry; IDT_SIZE] = [IdtEntry::missing(); IDT_SIZE];
 
 #[repr(C)]
@@ -52,16 +57,71 @@ pub struct InterruptFrame {
     pub ss:     u64,
 }
 
+/// Всё состояние прерванного кода: GPR в порядке `push_gprs!` (r15 —
+/// ниже всех) и кадр прерывания над ними. Тот же формат сохраняет
+/// таймер для планировщика.
+/// The interrupted code's whole state: GPRs in `push_gprs!` order (r15
+/// lowest) and the interrupt frame above them. The timer saves the same
+/// layout for the scheduler.
+#[repr(C)]
+pub struct FullContext {
+    pub r15: u64, pub r14: u64, pub r13: u64, pub r12: u64,
+    pub r11: u64, pub r10: u64, pub r9:  u64, pub r8:  u64,
+    pub rbp: u64, pub rdi: u64, pub rsi: u64, pub rdx: u64,
+    pub rcx: u64, pub rbx: u64, pub rax: u64,
+    pub frame: InterruptFrame,
+}
+
+const _: () = assert!(core::mem::size_of::<FullContext>() == (SAVED_REGS as usize + 5) * 8);
+
 // ── Макросы для обработчиков / Handler macros ─────────────────────────────────
 
+/// Все 15 GPR — порядок, который ждёт `pop_gprs!` и планировщик.
+/// All 15 GPRs — the order `pop_gprs!` and the scheduler expect.
+macro_rules! push_gprs {
+    () => {
+        "push rax; push rbx; push rcx; push rdx; push rsi; push rdi; push rbp;
+         push r8; push r9; push r10; push r11; push r12; push r13; push r14; push r15"
+    };
+}
+
+macro_rules! pop_gprs {
+    () => {
+        "pop r15; pop r14; pop r13; pop r12; pop r11; pop r10; pop r9; pop r8;
+         pop rbp; pop rdi; pop rsi; pop rdx; pop rcx; pop rbx; pop rax"
+    };
+}
+
+/// Из ring 3 — `swapgs`: в ядре GS base указывает на per-CPU данные
+/// (`gdt::init`), у пользователя — свой. `$cs` — смещение CS кадра от RSP.
+/// From ring 3 — `swapgs`: in the kernel GS base points at the per-CPU data
+/// (`gdt::init`), in user mode it is the user's own. `$cs` is the frame's
+/// CS offset from RSP.
+macro_rules! swapgs_if_user {
+    ($cs:literal) => {
+        concat!("test byte ptr [rsp + ", $cs, "], 3; jz 3f; swapgs; 3:")
+    };
+}
+
+/// Обработчик получает `&mut FullContext` и error code (без него — 0);
+/// правки регистров в контексте восстанавливаются при выходе. Стек после
+/// сохранения выровнен: 20 qword'ов — кадр и 15 GPR, error code вынут.
+/// The handler gets `&mut FullContext` and the error code (0 without one);
+/// register edits in the context are restored on the way out. The stack is
+/// aligned once saved: 20 qwords — the frame and 15 GPRs, the error code
+/// taken out.
 macro_rules! isr_handler {
     ($name:ident, $handler:expr) => {
         #[unsafe(naked)]
         unsafe extern "C" fn $name() {
             naked_asm!(
-                "push 0",
+                swapgs_if_user!(8),
+                push_gprs!(),
+                "mov rdi, rsp",
+                "xor esi, esi",
                 "call {handler}",
-                "add rsp, 8",
+                pop_gprs!(),
+                swapgs_if_user!(8),
                 "iretq",
                 handler = sym $handler,
             );
@@ -74,8 +134,17 @@ macro_rules! isr_handler_err {
         #[unsafe(naked)]
         unsafe extern "C" fn $name() {
             naked_asm!(
+                swapgs_if_user!(16),
+                // Error code → rax, rax — на его место: дальше как в push_gprs
+                // Error code → rax, rax into its slot: the rest as in push_gprs
+                "xchg rax, [rsp]",
+                "push rbx; push rcx; push rdx; push rsi; push rdi; push rbp;
+                 push r8; push r9; push r10; push r11; push r12; push r13; push r14; push r15",
+                "mov rdi, rsp",
+                "mov rsi, rax",
                 "call {handler}",
-                "add rsp, 8",
+                pop_gprs!(),
+                swapgs_if_user!(8),
                 "iretq",
                 handler = sym $handler,
             );
@@ -83,45 +152,456 @@ macro_rules! isr_handler_err {
     };
 }
 
+/// Исключение, которое из ring 3 убивает задачу. Обработчик получает RSP
+/// (регистры, error code, кадр) и либо паникует (ring 0), либо возвращает
+/// RSP следующей задачи в формате таймера. В упавший кадр возвращается
+/// только #PF, и то через `resume_fault`. 21 qword'а на стеке —
+/// выравниваем до вызова.
+/// An exception that kills the task when it comes from ring 3. The handler
+/// gets the RSP (registers, error code, frame) and either panics (ring 0)
+/// or returns the next task's RSP in the timer layout. Only a #PF returns
+/// into the faulting frame, and only through `resume_fault`. 21 qwords on
+/// the stack — realign before the call.
+macro_rules! isr_user_fault {
+    ($name:ident, $handler:expr, no_error) => {
+        #[unsafe(naked)]
+        unsafe extern "C" fn $name() {
+            naked_asm!(swapgs_if_user!(8), "push 0", push_gprs!(), "mov rdi, rsp", "sub rsp, 8",
+                       "call {handler}", "mov rsp, rax", pop_gprs!(), swapgs_if_user!(8), "iretq",
+                       handler = sym $handler);
+        }
+    };
+    ($name:ident, $handler:expr) => {
+        #[unsafe(naked)]
+        unsafe extern "C" fn $name() {
+            naked_asm!(swapgs_if_user!(16), push_gprs!(), "mov rdi, rsp", "sub rsp, 8",
+                       "call {handler}", "mov rsp, rax", pop_gprs!(), swapgs_if_user!(8), "iretq",
+                       handler = sym $handler);
+        }
+    };
+}
+
 // ── Обработчики / Handlers ────────────────────────────────────────────────────
 
-extern "C" fn handle_divide_error(frame: &InterruptFrame, _e: u64) {
-    panic!("Division Error at RIP={:#x}", frame.rip);
+/// Error code и кадр над сохранёнными регистрами / Error code and frame above the saved registers
+unsafe fn fault_frame<'a>(rsp: u64) -> (&'a InterruptFrame, u64) {
+    let error = rsp + SAVED_REGS * 8;
+    unsafe { (&*((error + 8) as *const InterruptFrame), *(error as *const u64)) }
+}
+
+/// Ring 0 — паника; ring 3 — диагностика и смерть только этой задачи.
+/// Ring 0 — panic; ring 3 — a diagnostic and death of that task alone.
+fn fatal_fault(name: &str, rsp: u64) -> u64 {
+    let (frame, e) = unsafe { fault_frame(rsp) };
+    if frame.cs & 3 != 3 {
+        panic!("{} (err={:#x}) at RIP={:#x}", name, e, frame.rip);
+    }
+    match crate::sched::current() {
+        Some(task) => crate::kprintln!("[fault] {} in task {} ({}) (err={:#x}) at RIP={:#x} RSP={:#x}",
+                                       name, task.id.0, task.name(), e, frame.rip, frame.rsp),
+        None => crate::kprintln!("[fault] {} in userspace (err={:#x}) at RIP={:#x} RSP={:#x}",
+                                 name, e, frame.rip, frame.rsp),
+    }
+    crate::sched::kill_current()
+}
+
+extern "C" fn handle_divide_error(rsp: u64) -> u64 {
+    fatal_fault("Division Error (#DE)", rsp)
 }
 
-extern "C" fn handle_invalid_opcode(frame: &InterruptFrame, _e: u64) {
-    panic!("Invalid Opcode at RIP={:#x}", frame.rip);
+extern "C" fn handle_invalid_opcode(rsp: u64) -> u64 {
+    fatal_fault("Invalid Opcode (#UD)", rsp)
 }
 
-extern "C" fn handle_double_fault(frame: &InterruptFrame, e: u64) {
-    panic!("Double Fault (err={:#x}) at RIP={:#x}", e, frame.rip);
+extern "C" fn handle_double_fault(ctx: &mut FullContext, e: u64) {
+    panic!("Double Fault (#DF) (err={:#x}) at RIP={:#x}", e, ctx.frame.rip);
 }
 
-extern "C" fn handle_general_protection(frame: &InterruptFrame, e: u64) {
-    panic!("General Protection Fault (err={:#x}) at RIP={:#x}", e, frame.rip);
+extern "C" fn handle_general_protection(rsp: u64) -> u64 {
+    fatal_fault("General Protection Fault (#GP)", rsp)
 }
 
-extern "C" fn handle_page_fault(frame: &InterruptFrame, e: u64) {
+extern "C" fn handle_debug(rsp: u64) -> u64 {
+    fatal_fault("Debug (#DB)", rsp)
+}
+
+extern "C" fn handle_breakpoint(rsp: u64) -> u64 {
+    fatal_fault("Breakpoint (#BP)", rsp)
+}
+
+extern "C" fn handle_overflow(rsp: u64) -> u64 {
+    fatal_fault("Overflow (#OF)", rsp)
+}
+
+extern "C" fn handle_bound_range(rsp: u64) -> u64 {
+    fatal_fault("Bound Range Exceeded (#BR)", rsp)
+}
+
+extern "C" fn handle_device_not_available(rsp: u64) -> u64 {
+    fatal_fault("Device Not Available (#NM)", rsp)
+}
+
+extern "C" fn handle_invalid_tss(rsp: u64) -> u64 {
+    fatal_fault("Invalid TSS (#TS)", rsp)
+}
+
+extern "C" fn handle_segment_not_present(rsp: u64) -> u64 {
+    fatal_fault("Segment Not Present (#NP)", rsp)
+}
+
+extern "C" fn handle_stack_segment(rsp: u64) -> u64 {
+    fatal_fault("Stack-Segment Fault (#SS)", rsp)
+}
+
+extern "C" fn handle_x87_fp(rsp: u64) -> u64 {
+    fatal_fault("x87 Floating-Point Exception (#MF)", rsp)
+}
+
+extern "C" fn handle_alignment_check(rsp: u64) -> u64 {
+    fatal_fault("Alignment Check (#AC)", rsp)
+}
+
+extern "C" fn handle_simd_fp(rsp: u64) -> u64 {
+    fatal_fault("SIMD Floating-Point Exception (#XM)", rsp)
+}
+
+extern "C" fn handle_virtualization(rsp: u64) -> u64 {
+    fatal_fault("Virtualization Exception (#VE)", rsp)
+}
+
+extern "C" fn handle_control_protection(rsp: u64) -> u64 {
+    fatal_fault("Control Protection Exception (#CP)", rsp)
+}
+
+// Не от кода задачи — в ring 3 задачу не убиваем, а падаем
+// Not caused by the task's code — no killing the task in ring 3, we go down
+
+extern "C" fn handle_nmi(ctx: &mut FullContext, _e: u64) {
+    panic!("Non-Maskable Interrupt (NMI) at RIP={:#x}", ctx.frame.rip);
+}
+
+extern "C" fn handle_machine_check(ctx: &mut FullContext, _e: u64) {
+    panic!("Machine Check (#MC) at RIP={:#x}", ctx.frame.rip);
+}
+
+extern "C" fn handle_hypervisor_injection(ctx: &mut FullContext, _e: u64) {
+    panic!("Hypervisor Injection Exception (#HV) at RIP={:#x}", ctx.frame.rip);
+}
+
+extern "C" fn handle_vmm_communication(ctx: &mut FullContext, e: u64) {
+    panic!("VMM Communication Exception (#VC) (err={:#x}) at RIP={:#x}", e, ctx.frame.rip);
+}
+
+extern "C" fn handle_security(ctx: &mut FullContext, e: u64) {
+    panic!("Security Exception (#SX) (err={:#x}) at RIP={:#x}", e, ctx.frame.rip);
+}
+
+/// #PF уходит в VMM: пространство текущей задачи, без задачи — ядра.
+/// Обработан — возвращаемся в упавший кадр. Нет — ring 0 паникует,
+/// ring 3 теряет задачу.
+/// A #PF goes to the VMM: the current task's space, or the kernel's without
+/// a task. Handled — back into the faulting frame. Not handled — ring 0
+/// panics, ring 3 loses the task.
+extern "C" fn handle_page_fault(rsp: u64) -> u64 {
+    let (frame, e) = unsafe { fault_frame(rsp) };
     let cr2: u64;
     unsafe { asm!("mov {}, cr2", out(reg) cr2) };
-    panic!("Page Fault at RIP={:#x} addr={:#x} err={:#x}", frame.rip, cr2, e);
+    let addr = crate::mm::vmm::VirtAddr::new(cr2);
+    let user = frame.cs & 3 == 3;
+    // Ring 0 в guard-странице — переполнение стека ядра, а не случайный адрес.
+    // Ring 0 in a guard page — a kernel stack overflow, not a stray address.
+    if !user {
+        if let Some(task) = crate::sched::task::guard_owner(addr) {
+            let rbp: u64;
+            unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+            super::backtrace::print(frame.rip, rbp);
+            panic!("kernel stack overflow in task {}", task.0);
+        }
+    }
+
+    let handled = match crate::sched::current_space() {
+        Some(space) => crate::mm::vmm::handle_page_fault(&space, addr, e),
+        None        => crate::mm::vmm::handle_kernel_fault(addr, e),
+    };
+    if handled { return unsafe { resume_fault(rsp) }; }
+    if !user {
+        panic!("Page Fault in kernel mode at RIP={:#x} addr={:#x} err={:#x}", frame.rip, cr2, e);
+    }
+    crate::kprintln!("[fault] Page Fault in user mode at addr={:#x}", cr2);
+    fatal_fault("Page Fault (#PF)", rsp)
+}
+
+/// Вернуться в кадр `isr_user_fault`: регистры сдвигаются на место error
+/// code, `pop_gprs` + `iretq` снимут ровно кадр прерывания.
+/// Return into an `isr_user_fault` frame: the registers move up over the
+/// error code, so `pop_gprs` + `iretq` leave exactly the interrupt frame.
+unsafe fn resume_fault(rsp: u64) -> u64 {
+    unsafe {
+        let regs = rsp as *mut u64;
+        core::ptr::copy(regs, regs.add(1), SAVED_REGS as usize);
+    }
+    rsp + 8
+}
+
+/// Таймер сохраняет все регистры, чтобы планировщик мог вернуться в
+/// другую задачу: `handle_timer` получает RSP и отдаёт RSP, с которого
+/// восстановиться.
+/// The timer saves every register so the scheduler can return into another
+/// task: `handle_timer` takes an RSP and hands back the RSP to restore from.
+#[unsafe(naked)]
+unsafe extern "C" fn isr_timer() {
+    naked_asm!(
+        swapgs_if_user!(8),
+        push_gprs!(),
+        "mov rdi, rsp",
+        "call {handler}",
+        "mov rsp, rax",
+        pop_gprs!(),
+        swapgs_if_user!(8),
+        "iretq",
+        handler = sym handle_timer,
+    );
 }
 
-extern "C" fn handle_timer(_frame: &InterruptFrame, _e: u64) {
-    unsafe { pic_eoi(0x20); }
-    // TODO: sched::tick()
+/// Сохранённых регистров над кадром прерывания / Saved registers above the interrupt frame
+const SAVED_REGS: u64 = 15;
+
+/// Переключить стек ядра: сохранить себя в формате `FullContext` — как
+/// если бы таймер прервал ядро на адресе возврата — записать RSP в
+/// `*prev_rsp` и уйти в `next_rsp` тем же выходом, что у `isr_timer`.
+/// Поэтому продолжить можно любой контекст: снятый таймером, этой функцией
+/// или собранный `FullContext::user_entry` для новой задачи. Вызывать с
+/// выключенными прерываниями: IF сохраняется и вернётся при возобновлении.
+/// Switch kernel stacks: save ourselves in the `FullContext` layout — as
+/// if the timer had interrupted the kernel at the return address — write
+/// RSP to `*prev_rsp` and leave into `next_rsp` through the same exit as
+/// `isr_timer`. So any context can be resumed: one taken by the timer, by
+/// this function or built by `FullContext::user_entry` for a new task. Call
+/// with interrupts off: IF is saved and comes back on resumption.
+#[cfg(not(test))]
+#[unsafe(naked)]
+pub unsafe extern "C" fn switch_context(prev_rsp: *mut u64, next_rsp: u64) {
+    naked_asm!(
+        // Кадр iretq в ядро: SS, RSP после `ret`, RFLAGS, CS, RIP возврата
+        // An iretq frame into the kernel: SS, RSP after `ret`, RFLAGS, CS, return RIP
+        "pop rax",
+        "mov rcx, rsp",
+        "push {kernel_data}",
+        "push rcx",
+        "pushfq",
+        "push {kernel_code}",
+        "push rax",
+        push_gprs!(),
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        pop_gprs!(),
+        swapgs_if_user!(8),
+        "iretq",
+        kernel_data = const crate::arch::x86_64::gdt::KERNEL_DATA,
+        kernel_code = const crate::arch::x86_64::gdt::KERNEL_CODE,
+    );
 }
 
-extern "C" fn handle_spurious(_frame: &InterruptFrame, _e: u64) {}
+// На хосте стек не сменить — `iretq` в ring 0 из тестов не выйдет.
+// «Переключение» запоминает, куда ушли бы, и пишет в `*prev_rsp` вместо
+// контекста адрес самого слота: ненулевой, как настоящий, а возобновлять
+// его никто не станет. Вызвавший продолжает, будто его тут же выбрали снова.
+// On the host stacks can't be switched — tests can't `iretq` into ring 0.
+// The "switch" remembers where it would have gone and writes the slot's
+// own address into `*prev_rsp` in place of a context: nonzero like a real
+// one, and nobody will ever resume it. The caller goes on as if it had
+// been picked again right away.
+#[cfg(test)]
+std::thread_local! {
+    static SWITCHED_TO: core::cell::Cell<u64> = const { core::cell::Cell::new(0) };
+}
 
-isr_handler!(isr_divide_error,   handle_divide_error);
-isr_handler!(isr_invalid_opcode, handle_invalid_opcode);
-isr_handler_err!(isr_double_fault,  handle_double_fault);
-isr_handler_err!(isr_gp_fault,      handle_general_protection);
-isr_handler_err!(isr_page_fault,    handle_page_fault);
-isr_handler!(isr_timer,    handle_timer);
+#[cfg(test)]
+pub unsafe fn switch_context(prev_rsp: *mut u64, next_rsp: u64) {
+    unsafe { *prev_rsp = prev_rsp as u64; }
+    SWITCHED_TO.with(|to| to.set(next_rsp));
+}
+
+/// Контекст, в который ушёл последний `switch_context`, — для тестов
+/// The context the last `switch_context` left into — for tests
+#[cfg(test)]
+pub fn switched_to() -> u64 {
+    SWITCHED_TO.with(|to| to.get())
+}
+
+impl FullContext {
+    /// Контекст новой задачи: выход `isr_timer` из него делает `iretq` в
+    /// ring 3 на `entry` со стеком `user_rsp` и нулевыми регистрами.
+    /// A new task's context: the `isr_timer` exit from it `iretq`s into
+    /// ring 3 at `entry` with the `user_rsp` stack and zeroed registers.
+    pub const fn user_entry(entry: u64, user_rsp: u64, rflags: u64) -> Self {
+        use crate::arch::x86_64::gdt::{USER_CODE, USER_DATA};
+        Self {
+            r15: 0, r14: 0, r13: 0, r12: 0, r11: 0, r10: 0, r9: 0, r8: 0,
+            rbp: 0, rdi: 0, rsi: 0, rdx: 0, rcx: 0, rbx: 0, rax: 0,
+            frame: InterruptFrame {
+                rip:    entry,
+                cs:     USER_CODE as u64,
+                rflags,
+                rsp:    user_rsp,
+                ss:     USER_DATA as u64,
+            },
+        }
+    }
+
+    /// Контекст задачи ядра: `iretq` в ring 0 на `entry` со стеком `rsp`.
+    /// A kernel task's context: `iretq` into ring 0 at `entry` with the `rsp` stack.
+    pub const fn kernel_entry(entry: u64, rsp: u64, rflags: u64) -> Self {
+        use crate::arch::x86_64::gdt::{KERNEL_CODE, KERNEL_DATA};
+        Self {
+            frame: InterruptFrame {
+                rip:    entry,
+                cs:     KERNEL_CODE as u64,
+                rflags,
+                rsp,
+                ss:     KERNEL_DATA as u64,
+            },
+            ..Self::user_entry(0, 0, 0)
+        }
+    }
+}
+
+extern "C" fn handle_timer(rsp: u64) -> u64 {
+    let frame = unsafe { &(*(rsp as *const FullContext)).frame };
+    crate::profile::sample(frame.rip, frame.cs);
+    irq_eoi(0);
+    crate::sched::on_timer(rsp, frame)
+}
+
+extern "C" fn handle_spurious(_ctx: &mut FullContext, _e: u64) {}
+
+isr_user_fault!(isr_divide_error,         handle_divide_error, no_error);
+isr_user_fault!(isr_debug,                handle_debug, no_error);
+isr_handler!(isr_nmi,                     handle_nmi);
+isr_user_fault!(isr_breakpoint,           handle_breakpoint, no_error);
+isr_user_fault!(isr_overflow,             handle_overflow, no_error);
+isr_user_fault!(isr_bound_range,          handle_bound_range, no_error);
+isr_user_fault!(isr_invalid_opcode,       handle_invalid_opcode, no_error);
+isr_user_fault!(isr_device_not_available, handle_device_not_available, no_error);
+isr_handler_err!(isr_double_fault,        handle_double_fault);
+isr_user_fault!(isr_invalid_tss,          handle_invalid_tss);
+isr_user_fault!(isr_segment_not_present,  handle_segment_not_present);
+isr_user_fault!(isr_stack_segment,        handle_stack_segment);
+isr_user_fault!(isr_gp_fault,             handle_general_protection);
+isr_user_fault!(isr_page_fault,           handle_page_fault);
+isr_user_fault!(isr_x87_fp,               handle_x87_fp, no_error);
+isr_user_fault!(isr_alignment_check,      handle_alignment_check);
+isr_handler!(isr_machine_check,           handle_machine_check);
+isr_user_fault!(isr_simd_fp,              handle_simd_fp, no_error);
+isr_user_fault!(isr_virtualization,       handle_virtualization, no_error);
+isr_user_fault!(isr_control_protection,   handle_control_protection);
+isr_handler!(isr_hypervisor_injection,    handle_hypervisor_injection);
+isr_handler_err!(isr_vmm_communication,   handle_vmm_communication);
+isr_handler_err!(isr_security,            handle_security);
 isr_handler!(isr_spurious, handle_spurious);
 
+// ── Динамические обработчики / Dynamic handlers ───────────────────────────────
+
+/// Обработчик, ставящийся после `init` / A handler installed after `init`
+pub type DynHandler = extern "C" fn(&mut FullContext);
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct IdtFlags: u8 {
+        /// Линия PIC: открыть её при регистрации, EOI после обработчика
+        /// A PIC line: unmask it on registration, EOI after the handler
+        const IRQ = 1 << 0;
+    }
+}
+
+/// Первый вектор, который можно занять / The first vector that can be taken
+pub const DYNAMIC_FIRST: u8 = 0x21;
+/// Вектор IRQ 0 после ремапа PIC / IRQ 0's vector after the PIC remap
+const PIC_BASE: u8 = 0x20;
+/// Заняты статически — `register_handler` их не трогает
+/// Taken statically — `register_handler` leaves them alone
+const SPURIOUS_VECTOR: u8 = 0x27;
+
+/// Байт на заглушку: `push imm32` + `jmp rel32` и выравнивание
+/// Bytes per stub: `push imm32` + `jmp rel32` and padding
+const STUB_SIZE: u64 = 16;
+const DYNAMIC_COUNT: usize = IDT_SIZE - DYNAMIC_FIRST as usize;
+
+static HANDLERS: IrqMutex<[Option<(DynHandler, IdtFlags)>; IDT_SIZE]> = IrqMutex::new([None; IDT_SIZE]);
+
+/// Поставить `handler` на `vector`, заменив прежний. IDT после `init` только
+/// для чтения, поэтому меняется таблица, а не шлюз: все свободные векторы
+/// ведут в `isr_dynamic`. Исключения, таймер, spurious и shootdown TLB — `false`.
+/// Install `handler` on `vector`, replacing the old one. The IDT is
+/// read-only after `init`, so the table changes rather than the gate: every
+/// free vector leads into `isr_dynamic`. Exceptions, the timer, spurious and
+/// the TLB shootdown — `false`.
+#[allow(dead_code)] // драйверов с IRQ пока нет / no IRQ-driven drivers yet
+pub fn register_handler(vector: u8, handler: DynHandler, flags: IdtFlags) -> bool {
+    if !is_dynamic(vector) { return false; }
+    HANDLERS.lock()[vector as usize] = Some((handler, flags));
+    if flags.contains(IdtFlags::IRQ) {
+        if let Some(irq) = pic_irq(vector) { irq_unmask(irq); }
+    }
+    true
+}
+
+/// Снять обработчик; вектор снова ничего не делает. Линия PIC остаётся открытой.
+/// Remove the handler; the vector does nothing again. The PIC line stays unmasked.
+#[allow(dead_code)] // пара к `register_handler` / the pair of `register_handler`
+pub fn unregister_handler(vector: u8) -> bool {
+    if !is_dynamic(vector) { return false; }
+    HANDLERS.lock()[vector as usize].take().is_some()
+}
+
+fn is_dynamic(vector: u8) -> bool {
+    vector >= DYNAMIC_FIRST
+        && vector != SPURIOUS_VECTOR
+        && vector != super::apic::SPURIOUS_VECTOR
+        && vector != crate::mm::vmm::tlb::SHOOTDOWN_VECTOR
+}
+
+/// Линия PIC вектора / The PIC line of a vector
+fn pic_irq(vector: u8) -> Option<u8> {
+    vector.checked_sub(PIC_BASE).filter(|&irq| irq < 16)
+}
+
+/// Заглушки векторов `DYNAMIC_FIRST..`: кладут номер вектора туда, где у
+/// исключений error code, и прыгают в `isr_dynamic`.
+/// Stubs for vectors `DYNAMIC_FIRST..`: they put the vector number where
+/// exceptions have their error code and jump to `isr_dynamic`.
+#[unsafe(naked)]
+unsafe extern "C" fn dynamic_stubs() {
+    naked_asm!(
+        ".set vector, {first}",
+        ".rept {count}",
+        "2:",
+        ".byte 0x68",
+        ".long vector",
+        "jmp {common}",
+        ".skip {size} - (. - 2b), 0xCC",
+        ".set vector, vector + 1",
+        ".endr",
+        first  = const DYNAMIC_FIRST,
+        count  = const DYNAMIC_COUNT,
+        size   = const STUB_SIZE,
+        common = sym isr_dynamic,
+    );
+}
+
+isr_handler_err!(isr_dynamic, dispatch_dynamic);
+
+extern "C" fn dispatch_dynamic(ctx: &mut FullContext, vector: u64) {
+    // Копия из-под lock'а: обработчик может перерегистрироваться сам
+    // A copy from under the lock: the handler may re-register itself
+    let entry = HANDLERS.lock()[vector as usize];
+    let Some((handler, flags)) = entry else { return };
+    handler(ctx);
+    if flags.contains(IdtFlags::IRQ) {
+        if let Some(irq) = pic_irq(vector as u8) { irq_eoi(irq); }
+    }
+}
+
 // ── PIC ───────────────────────────────────────────────────────────────────────
 
 const PIC1_CMD:  u16 = 0x20;
@@ -129,7 +609,7 @@ const PIC1_DATA: u16 = 0x21;
 const PIC2_CMD:  u16 = 0xA0;
 const PIC2_DATA: u16 = 0xA1;
 
-unsafe fn outb(port: u16, val: u8) {
+pub(super) unsafe fn outb(port: u16, val: u8) {
     unsafe { asm!("out dx, al", in("dx") port, in("al") val); }
 }
 
@@ -140,6 +620,46 @@ unsafe fn pic_eoi(irq: u8) {
     }
 }
 
+unsafe fn inb(port: u16) -> u8 {
+    let val: u8;
+    unsafe { asm!("in al, dx", in("dx") port, out("al") val); }
+    val
+}
+
+/// Открыть линию; для ведомого PIC — и каскад (IRQ 2).
+/// Unmask a line; for the slave PIC the cascade (IRQ 2) as well.
+unsafe fn pic_unmask(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(PIC2_DATA, inb(PIC2_DATA) & !(1 << (irq - 8)));
+            outb(PIC1_DATA, inb(PIC1_DATA) & !(1 << 2));
+        } else {
+            outb(PIC1_DATA, inb(PIC1_DATA) & !(1 << irq));
+        }
+    }
+}
+
+/// Закрыть все линии PIC; вернуть прежнюю маску (бит N — IRQ N закрыт).
+/// Mask every PIC line; return the old mask (bit N — IRQ N masked).
+pub(super) unsafe fn pic_mask_all() -> u16 {
+    unsafe {
+        let old = inb(PIC1_DATA) as u16 | (inb(PIC2_DATA) as u16) << 8;
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+        old
+    }
+}
+
+/// EOI тому контроллеру, что сейчас доставляет IRQ.
+/// EOI to whichever controller delivers IRQs now.
+fn irq_eoi(irq: u8) {
+    if super::apic::enabled() { super::apic::eoi(); } else { unsafe { pic_eoi(irq); } }
+}
+
+fn irq_unmask(irq: u8) {
+    if super::apic::enabled() { super::apic::unmask_irq(irq); } else { unsafe { pic_unmask(irq); } }
+}
+
 unsafe fn pic_init() {
     unsafe {
         outb(PIC1_CMD,  0x11);
@@ -158,20 +678,46 @@ unsafe fn pic_init() {
 // ── Init ──────────────────────────────────────────────────────────────────────
 
 pub fn init() {
-    use super::gdt::KERNEL_CODE;
+    use super::gdt::{IST_DOUBLE_FAULT, IST_NMI, IST_PAGE_FAULT, KERNEL_CODE};
 
     unsafe {
         let set = |vec: usize, handler: u64, ist: u8, attr: u8| {
             IDT[vec] = IdtEntry::new(handler, KERNEL_CODE, ist, attr);
         };
 
-        set(0x00, isr_divide_error   as *const () as u64, 0, 0x8E);
-        set(0x06, isr_invalid_opcode as *const () as u64, 0, 0x8E);
-        set(0x08, isr_double_fault   as *const () as u64, 1, 0x8E);
-        set(0x0D, isr_gp_fault       as *const () as u64, 0, 0x8E);
-        set(0x0E, isr_page_fault     as *const () as u64, 0, 0x8E);
-        set(0x20, isr_timer          as *const () as u64, 0, 0x8E);
-        set(0x27, isr_spurious       as *const () as u64, 0, 0x8E);
+        // Сначала все свободные векторы — в диспетчер, фиксированные поверх
+        // Every free vector into the dispatcher first, the fixed ones on top
+        let stubs = dynamic_stubs as *const () as u64;
+        for i in 0..DYNAMIC_COUNT {
+            set(DYNAMIC_FIRST as usize + i, stubs + i as u64 * STUB_SIZE, 0, 0x8E);
+        }
+
+        // 0x09, 0x0F, 0x16–0x1B, 0x1F зарезервированы / 0x09, 0x0F, 0x16–0x1B, 0x1F are reserved
+        set(0x00, isr_divide_error         as *const () as u64, 0, 0x8E);
+        set(0x01, isr_debug                as *const () as u64, 0, 0x8E);
+        set(0x02, isr_nmi                  as *const () as u64, IST_NMI, 0x8E);
+        set(0x03, isr_breakpoint           as *const () as u64, 0, 0x8E);
+        set(0x04, isr_overflow             as *const () as u64, 0, 0x8E);
+        set(0x05, isr_bound_range          as *const () as u64, 0, 0x8E);
+        set(0x06, isr_invalid_opcode       as *const () as u64, 0, 0x8E);
+        set(0x07, isr_device_not_available as *const () as u64, 0, 0x8E);
+        set(0x08, isr_double_fault         as *const () as u64, IST_DOUBLE_FAULT, 0x8E);
+        set(0x0A, isr_invalid_tss          as *const () as u64, 0, 0x8E);
+        set(0x0B, isr_segment_not_present  as *const () as u64, 0, 0x8E);
+        set(0x0C, isr_stack_segment        as *const () as u64, 0, 0x8E);
+        set(0x0D, isr_gp_fault             as *const () as u64, 0, 0x8E);
+        set(0x0E, isr_page_fault           as *const () as u64, IST_PAGE_FAULT, 0x8E);
+        set(0x10, isr_x87_fp               as *const () as u64, 0, 0x8E);
+        set(0x11, isr_alignment_check      as *const () as u64, 0, 0x8E);
+        set(0x12, isr_machine_check        as *const () as u64, 0, 0x8E);
+        set(0x13, isr_simd_fp              as *const () as u64, 0, 0x8E);
+        set(0x14, isr_virtualization       as *const () as u64, 0, 0x8E);
+        set(0x15, isr_control_protection   as *const () as u64, 0, 0x8E);
+        set(0x1C, isr_hypervisor_injection as *const () as u64, 0, 0x8E);
+        set(0x1D, isr_vmm_communication    as *const () as u64, 0, 0x8E);
+        set(0x1E, isr_security             as *const () as u64, 0, 0x8E);
+        set(0x20, isr_timer                as *const () as u64, 0, 0x8E);
+        set(0x27, isr_spurious             as *const () as u64, 0, 0x8E);
 
         pic_init();
 
@@ -183,3 +729,48 @@ pub fn init() {
         asm!("sti");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::gdt;
+    use crate::mm::pmm;
+    use crate::sched;
+    use crate::testing;
+
+    /// Стек обработчика исключения: регистры, error code и кадр из `cs:rip`
+    /// An exception handler's stack: the registers, the error code and a frame from `cs:rip`
+    fn fault_stack(cs: u16, ss: u16) -> [u64; SAVED_REGS as usize + 6] {
+        let mut stack = [0; SAVED_REGS as usize + 6];
+        stack[SAVED_REGS as usize..].copy_from_slice(&[0, 0x40_1000, cs as u64, 0x202, 0x7FFF_F000, ss as u64]);
+        stack
+    }
+
+    #[test]
+    fn a_ring3_gp_terminates_only_the_task() {
+        let _kernel = testing::setup();
+        let victim = testing::user_task();
+        let next = sched::spawn_ready().unwrap();
+        let mut stack = fault_stack(gdt::USER_CODE, gdt::USER_DATA);
+
+        let rsp = fatal_fault("General Protection Fault (#GP)", stack.as_mut_ptr() as u64);
+        assert_eq!(sched::current_id(), Some(next));
+        assert_eq!(rsp, sched::current().unwrap().saved_rsp);
+        assert_eq!(gdt::kernel_stack(), sched::current().unwrap().kernel_stack.top().as_u64());
+        assert_eq!(sched::task_row(victim), None);
+
+        // Стек и пространство погибшей отпускает только `reap`
+        // Only `reap` lets go of the dead task's stack and space
+        let free = pmm::free_memory();
+        sched::reap();
+        assert!(pmm::free_memory() > free);
+        testing::end_task(next);
+    }
+
+    #[test]
+    #[should_panic(expected = "General Protection Fault (#GP)")]
+    fn a_ring0_gp_panics() {
+        let mut stack = fault_stack(gdt::KERNEL_CODE, gdt::KERNEL_DATA);
+        fatal_fault("General Protection Fault (#GP)", stack.as_mut_ptr() as u64);
+    }
+}
@@ -0,0 +1,47 @@
+//! Управление прерываниями (RFLAGS.IF) / Interrupt control (RFLAGS.IF)
+
+#[cfg(not(test))]
+use core::arch::asm;
+
+const RFLAGS_IF: u64 = 1 << 9;
+
+/// Включены ли прерывания / Whether interrupts are enabled
+#[cfg(not(test))]
+#[inline]
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+    unsafe { asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags)); }
+    rflags & RFLAGS_IF != 0
+}
+
+#[cfg(not(test))]
+#[inline]
+pub fn enable() {
+    unsafe { asm!("sti", options(nomem, nostack)); }
+}
+
+#[cfg(not(test))]
+#[inline]
+pub fn disable() {
+    unsafe { asm!("cli", options(nomem, nostack)); }
+}
+
+// На хосте cli/sti — #GP: тесты идут в пользовательском режиме, где
+// прерываний ядра просто нет. IF здесь — флаг потока теста, изначально
+// сброшенный: его можно поднять, но таймер от этого не придёт.
+// On the host cli/sti are a #GP: tests run in user mode, where there are
+// no kernel interrupts at all. IF here is a per-test-thread flag, clear to
+// begin with: it can be raised, but no timer will fire because of it.
+#[cfg(test)]
+std::thread_local! {
+    static IF: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+}
+
+#[cfg(test)]
+pub fn are_enabled() -> bool { IF.with(|f| f.get()) }
+
+#[cfg(test)]
+pub fn enable() { IF.with(|f| f.set(true)) }
+
+#[cfg(test)]
+pub fn disable() { IF.with(|f| f.set(false)) }
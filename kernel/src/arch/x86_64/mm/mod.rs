@@ -5,9 +5,117 @@
 //!
 //! Реализует трейт PageTableImpl из mm::vmm.
 //! Implements the PageTableImpl trait from mm::vmm.
+//!
+//! Здесь же — PAT (Page Attribute Table): тип кэширования страницы задают
+//! биты PWT (3), PCD (4) и PAT (7) в PTE как индекс 0..7 в MSR IA32_PAT.
+//! Also PAT (Page Attribute Table): a page's cache type is selected by the
+//! PWT (3), PCD (4) and PAT (7) PTE bits as an index 0..7 into MSR IA32_PAT.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::msr;
+
+// ── PAT ───────────────────────────────────────────────────────────────────────
+
+// Типы памяти / Memory types
+const PAT_UC:       u64 = 0x00;
+const PAT_WC:       u64 = 0x01;
+const PAT_WT:       u64 = 0x04;
+const PAT_WP:       u64 = 0x05;
+const PAT_WB:       u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+
+/// Индекс PAT для write-combining / PAT index used for write-combining.
+///
+/// Раскладка совпадает с той, что оставляет Limine (PA5 = WC), поэтому
+/// уже существующие маппинги не меняют смысл.
+/// Same layout Limine leaves behind (PA5 = WC), so existing mappings keep
+/// their meaning.
+pub const PAT_WC_INDEX: u8 = 5;
+
+const PAT_LAYOUT: u64 = PAT_WB
+    | PAT_WT       << 8
+    | PAT_UC_MINUS << 16
+    | PAT_UC       << 24
+    | PAT_WP       << 32
+    | PAT_WC       << 40
+    | PAT_UC_MINUS << 48
+    | PAT_UC       << 56;
+
+/// Биты PTE (4KB страница), выбирающие индекс PAT.
+/// PTE bits (4KB page) selecting a PAT index.
+pub const fn pat_index_bits(index: u8) -> u64 {
+    ((index as u64 & 0b001) << 3)        // PWT
+        | ((index as u64 & 0b010) << 3)  // PCD (bit 4)
+        | ((index as u64 & 0b100) << 5)  // PAT (bit 7)
+}
+
+const _: () = assert!(pat_index_bits(PAT_WC_INDEX) == (1 << 7) | (1 << 3));
+const _: () = assert!((PAT_LAYOUT >> (PAT_WC_INDEX as u64 * 8)) & 0xFF == PAT_WC);
+
+static PAT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Поддерживает ли CPU PAT (CPUID.1:EDX[16]).
+/// Whether the CPU supports PAT (CPUID.1:EDX[16]).
+fn has_pat() -> bool {
+    let leaf = core::arch::x86_64::__cpuid(1);
+    leaf.edx & (1 << 16) != 0
+}
+
+/// Запрограммировать IA32_PAT нашей раскладкой.
+/// Program IA32_PAT with our layout.
+fn init_pat() {
+    if !has_pat() {
+        crate::kprintln!("[pat] PAT not supported — write-combining falls back to uncached");
+        return;
+    }
+    unsafe {
+        msr::write(msr::IA32_PAT, PAT_LAYOUT);
+        // Сбросить TLB, чтобы старые атрибуты не остались закэшированы
+        // Flush the TLB so stale attributes don't linger
+        core::arch::asm!(
+            "mov {tmp}, cr3",
+            "mov cr3, {tmp}",
+            tmp = out(reg) _,
+            options(nostack),
+        );
+    }
+    PAT_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Включён ли PAT (иначе WC недоступен).
+/// Whether PAT is enabled (otherwise WC is unavailable).
+pub fn pat_enabled() -> bool {
+    PAT_ENABLED.load(Ordering::Relaxed)
+}
 
-// TODO: Этап 3 — реализация page tables
-// TODO: Phase 3 — page tables implementation
 pub fn init() {
-    // stub
+    init_pat();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PWT: u64 = 1 << 3;
+    const PCD: u64 = 1 << 4;
+    const PAT: u64 = 1 << 7;
+
+    #[test]
+    fn pat_index_bits_cover_every_index() {
+        for index in 0..8u8 {
+            let bits = pat_index_bits(index);
+            assert_eq!(bits & !(PWT | PCD | PAT), 0);
+            let back = (bits & PWT != 0) as u8 | ((bits & PCD != 0) as u8) << 1 | ((bits & PAT != 0) as u8) << 2;
+            assert_eq!(back, index);
+        }
+        assert_eq!(pat_index_bits(0), 0);
+    }
+
+    #[test]
+    fn layout_keeps_limine_defaults() {
+        let entry = |i: u64| (PAT_LAYOUT >> (i * 8)) & 0xFF;
+        // PA0..PA3 — как после сброса / as after reset
+        assert_eq!([entry(0), entry(1), entry(2), entry(3)], [PAT_WB, PAT_WT, PAT_UC_MINUS, PAT_UC]);
+        assert_eq!(entry(PAT_WC_INDEX as u64), PAT_WC);
+    }
 }
@@ -2,13 +2,33 @@
 
 mod boot; // entry point: _start via global_asm! (no NASM required)
 
+#[cfg_attr(test, allow(unused_imports))]
+pub use boot::{check_boot_stack, kernel_phys_range, memory_map, modules};
+
+pub mod apic;
+pub mod backtrace;
+pub mod control;
 pub mod gdt;
 pub mod idt;
+pub mod interrupts;
 pub mod mm;
+pub mod msr;
+pub mod segbase;
+pub mod syscall;
+pub mod tsc;
 
 /// x86_64 init sequence
 pub fn init() {
-    gdt::init();   // Global Descriptor Table
+    // Limine отдаёт управление уже в long mode и с NXE — без него NO_EXEC
+    // в PTE был бы зарезервированным битом
+    // Limine hands over in long mode and with NXE — without it NO_EXEC in a
+    // PTE would be a reserved bit
+    let efer = unsafe { msr::read(msr::IA32_EFER) };
+    debug_assert!(efer & (msr::EFER_LME | msr::EFER_LMA) == msr::EFER_LME | msr::EFER_LMA);
+    debug_assert!(efer & msr::EFER_NXE != 0);
+    gdt::init(0);  // Global Descriptor Table (BSP)
     idt::init();   // Interrupt Descriptor Table
     mm::init();    // Page tables (identity map kernel)
+    control::enable_write_protect(); // CR0.WP — RO страницы и для ring 0 / RO pages for ring 0 too
+    segbase::init(); // CR4.FSGSBASE, если есть / if available
 }
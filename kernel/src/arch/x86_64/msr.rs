@@ -0,0 +1,54 @@
+//! MSR — model-specific registers
+//!
+//! Все `rdmsr`/`wrmsr` ядра идут через этот модуль.
+//! Every `rdmsr`/`wrmsr` in the kernel goes through this module.
+
+use core::arch::asm;
+
+/// EFER — расширенные возможности (SCE, LME, LMA, NXE)
+/// EFER — extended features (SCE, LME, LMA, NXE)
+pub const IA32_EFER:           u32 = 0xC000_0080;
+/// CS/SS для `syscall`/`sysret` / CS/SS for `syscall`/`sysret`
+pub const IA32_STAR:           u32 = 0xC000_0081;
+/// Точка входа `syscall` / `syscall` entry point
+pub const IA32_LSTAR:          u32 = 0xC000_0082;
+/// Биты RFLAGS, сбрасываемые на `syscall` / RFLAGS bits cleared on `syscall`
+pub const IA32_FMASK:          u32 = 0xC000_0084;
+pub const IA32_FS_BASE:        u32 = 0xC000_0100;
+pub const IA32_GS_BASE:        u32 = 0xC000_0101;
+/// GS base, который `swapgs` меняет местами с активным
+/// The GS base `swapgs` exchanges with the active one
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+pub const IA32_APIC_BASE:      u32 = 0x1B;
+pub const IA32_PAT:            u32 = 0x277;
+
+/// Биты EFER / EFER bits
+pub const EFER_SCE: u64 = 1 << 0;
+pub const EFER_LME: u64 = 1 << 8;
+pub const EFER_LMA: u64 = 1 << 10;
+pub const EFER_NXE: u64 = 1 << 11;
+
+/// Прочитать MSR.
+///
+/// # Safety
+/// Несуществующий MSR — #GP.
+/// A nonexistent MSR raises #GP.
+pub unsafe fn read(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe { asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi, options(nomem, nostack, preserves_flags)); }
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Записать MSR.
+///
+/// # Safety
+/// Несуществующий MSR или недопустимое значение — #GP; многие MSR
+/// (EFER, LSTAR, PAT) меняют поведение всего CPU.
+/// A nonexistent MSR or an invalid value raises #GP; many MSRs (EFER,
+/// LSTAR, PAT) change how the whole CPU behaves.
+pub unsafe fn write(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32,
+             options(nostack, preserves_flags));
+    }
+}
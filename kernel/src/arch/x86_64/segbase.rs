@@ -0,0 +1,76 @@
+//! FS/GS base — TLS задачи и per-CPU данные ядра
+//! FS/GS base — task TLS and kernel per-CPU data
+//!
+//! FS base — thread pointer задачи. GS base в ring 0 — per-CPU данные
+//! ядра, а GS base пользователя после `swapgs` лежит в IA32_KERNEL_GS_BASE;
+//! поэтому пользовательский GS сохраняем и восстанавливаем только через
+//! этот MSR и никогда не трогаем активный GS ядра.
+//! FS base is the task's thread pointer. In ring 0 GS base holds the
+//! kernel's per-CPU data, and after `swapgs` the user's GS base sits in
+//! IA32_KERNEL_GS_BASE; so the user GS is saved and restored only through
+//! that MSR and the kernel's active GS is never touched.
+
+#[cfg(not(test))]
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::control::{self, Cr4};
+#[cfg(not(test))]
+use super::msr::{self, IA32_FS_BASE, IA32_KERNEL_GS_BASE};
+
+static FSGSBASE: AtomicBool = AtomicBool::new(false);
+
+/// Включить `rdfsbase`/`wrfsbase`, если CPU умеет (CPUID.7.0:EBX[0]).
+/// Enable `rdfsbase`/`wrfsbase` if the CPU supports them (CPUID.7.0:EBX[0]).
+pub fn init() {
+    let leaf = core::arch::x86_64::__cpuid_count(7, 0);
+    if leaf.ebx & 1 == 0 { return; }
+    unsafe { control::write_cr4(control::read_cr4() | Cr4::FSGSBASE); }
+    FSGSBASE.store(true, Ordering::Relaxed);
+}
+
+#[cfg(not(test))]
+pub fn read_fs_base() -> u64 {
+    if !FSGSBASE.load(Ordering::Relaxed) { return unsafe { msr::read(IA32_FS_BASE) }; }
+    let value: u64;
+    unsafe { asm!("rdfsbase {}", out(reg) value, options(nomem, nostack)); }
+    value
+}
+
+#[cfg(not(test))]
+pub fn write_fs_base(value: u64) {
+    if !FSGSBASE.load(Ordering::Relaxed) { return unsafe { msr::write(IA32_FS_BASE, value) }; }
+    unsafe { asm!("wrfsbase {}", in(reg) value, options(nostack)); }
+}
+
+/// GS base пользователя (пока мы в ядре — спрятан `swapgs`).
+/// The user's GS base (stashed by `swapgs` while we're in the kernel).
+#[cfg(not(test))]
+pub fn read_user_gs_base() -> u64 {
+    unsafe { msr::read(IA32_KERNEL_GS_BASE) }
+}
+
+#[cfg(not(test))]
+pub fn write_user_gs_base(value: u64) {
+    unsafe { msr::write(IA32_KERNEL_GS_BASE, value) }
+}
+
+// На хосте MSR и `wrfsbase` недоступны: базы — пара значений потока теста,
+// как будто это регистры его CPU.
+// On the host MSRs and `wrfsbase` are off limits: the bases are a pair of
+// values on the test thread, as if they were its CPU's registers.
+#[cfg(test)]
+std::thread_local! {
+    static BASES: core::cell::Cell<(u64, u64)> = const { core::cell::Cell::new((0, 0)) };
+}
+
+#[cfg(test)]
+pub fn read_fs_base() -> u64 { BASES.with(|b| b.get().0) }
+
+#[cfg(test)]
+pub fn write_fs_base(value: u64) { BASES.with(|b| b.set((value, b.get().1))) }
+
+#[cfg(test)]
+pub fn read_user_gs_base() -> u64 { BASES.with(|b| b.get().1) }
+
+#[cfg(test)]
+pub fn write_user_gs_base(value: u64) { BASES.with(|b| b.set((b.get().0, value))) }
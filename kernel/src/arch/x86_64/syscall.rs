@@ -0,0 +1,90 @@
+//! Вход в ядро по `syscall` и выход по `sysretq`
+//! Kernel entry via `syscall` and exit via `sysretq`
+//!
+//! ABI (libcuprum `arch::syscall`): номер — rax, аргументы — rdi, rsi, rdx,
+//! результат — rax. `syscall` сам портит rcx (RIP) и r11 (RFLAGS), всё
+//! остальное вызывающий ждёт нетронутым.
+//! ABI (libcuprum `arch::syscall`): the number in rax, arguments in rdi,
+//! rsi, rdx, the result in rax. `syscall` itself clobbers rcx (RIP) and r11
+//! (RFLAGS); the caller expects everything else untouched.
+
+use core::arch::naked_asm;
+use super::gdt::{self, KERNEL_CODE, USER_DATA};
+use super::msr;
+
+/// RFLAGS, сбрасываемые на входе: IF — до перехода на стек ядра прерываний
+/// быть не должно; DF — ABI; TF и AC — не наследуем от пользователя.
+/// RFLAGS cleared on entry: IF — no interrupts before we are on the kernel
+/// stack; DF — the ABI; TF and AC — not inherited from user mode.
+const FMASK: u64 = 1 << 8 | 1 << 9 | 1 << 10 | 1 << 18;
+
+/// Длина инструкции `syscall` (0F 05) / Length of the `syscall` instruction (0F 05)
+const SYSCALL_LEN: u64 = 2;
+
+/// Включить `syscall`/`sysret` и направить вход в `syscall_entry`.
+/// На каждом CPU, после `gdt::init`.
+/// Enable `syscall`/`sysret` and point the entry at `syscall_entry`.
+/// On every CPU, after `gdt::init`.
+pub fn init() {
+    // sysret: SS = база + 8, CS = база + 16 (RPL 3 CPU ставит сам)
+    // sysret: SS = base + 8, CS = base + 16 (the CPU forces RPL 3)
+    let sysret_base = (USER_DATA & !7) as u64 - 8;
+    let star = sysret_base << 48 | (KERNEL_CODE as u64) << 32;
+    unsafe {
+        msr::write(msr::IA32_STAR, star);
+        msr::write(msr::IA32_LSTAR, syscall_entry as *const () as u64);
+        msr::write(msr::IA32_FMASK, FMASK);
+        msr::write(msr::IA32_EFER, msr::read(msr::IA32_EFER) | msr::EFER_SCE);
+    }
+}
+
+/// Точка входа `syscall`. RSP ещё пользовательский: `swapgs` даёт per-CPU
+/// данные, через них — стек ядра задачи (TSS.rsp0). На стеке ядра —
+/// RSP/RIP/RFLAGS пользователя и его caller-saved регистры; callee-saved
+/// сохранит сам `syscall_handler`. На `syscall::RESTART` RIP откатывается
+/// на инструкцию `syscall`, а rax снова номер — задача повторит вызов.
+/// The `syscall` entry point. RSP is still the user's: `swapgs` yields the
+/// per-CPU data, and through it the task's kernel stack (TSS.rsp0). The
+/// kernel stack holds the user RSP/RIP/RFLAGS and caller-saved registers;
+/// `syscall_handler` preserves the callee-saved ones itself. On
+/// `syscall::RESTART` RIP is wound back onto the `syscall` instruction and
+/// rax is the number again — the task will repeat the call.
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "swapgs",
+        "mov gs:[{user_rsp}], rsp",
+        "mov rsp, gs:[{kernel_rsp}]",
+        "push qword ptr gs:[{user_rsp}]",
+        "push rcx",
+        "push r11",
+        "push rax; push rdi; push rsi; push rdx; push r8; push r9; push r10",
+        "sti",
+        // (rax, rdi, rsi, rdx) → (number, arg0, arg1, arg2) по SysV
+        // (rax, rdi, rsi, rdx) → (number, arg0, arg1, arg2) per SysV
+        "mov rcx, rdx",
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "call {handler}",
+        "cli",
+        "cmp rax, {restart}",
+        "jne 2f",
+        "mov rax, [rsp + 6 * 8]",
+        "sub qword ptr [rsp + 8 * 8], {syscall_len}",
+        "2:",
+        "pop r10; pop r9; pop r8; pop rdx; pop rsi; pop rdi",
+        // Сохранённый номер / The saved number
+        "add rsp, 8",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "swapgs",
+        "sysretq",
+        user_rsp   = const gdt::PERCPU_USER_RSP,
+        kernel_rsp = const gdt::PERCPU_KERNEL_RSP,
+        restart    = const crate::syscall::RESTART,
+        syscall_len = const SYSCALL_LEN,
+        handler    = sym crate::syscall::syscall_handler,
+    );
+}
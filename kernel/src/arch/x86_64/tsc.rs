@@ -0,0 +1,36 @@
+//! TSC — счётчик тактов, единственные часы до таймера
+//! TSC — the cycle counter, the only clock before the timer
+//!
+//! Частоту берём из CPUID: 0x15 (отношение TSC к кварцу), иначе 0x16
+//! (базовая частота в МГц). Ни того ни другого — частота неизвестна, и
+//! пользователи TSC показывают сырые такты.
+//! The frequency comes from CPUID: 0x15 (TSC to crystal ratio), else 0x16
+//! (base frequency in MHz). With neither the frequency is unknown and TSC
+//! users show raw cycles.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Частота TSC в Гц, если CPU её сообщает / TSC frequency in Hz if the CPU reports it
+pub fn frequency_hz() -> Option<u64> {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf >= 0x15 {
+        let leaf = __cpuid(0x15);
+        // eax — знаменатель, ebx — числитель, ecx — частота кварца
+        // eax — denominator, ebx — numerator, ecx — crystal frequency
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+        }
+    }
+    if max_leaf >= 0x16 {
+        let mhz = __cpuid(0x16).eax & 0xFFFF;
+        if mhz != 0 {
+            return Some(mhz as u64 * 1_000_000);
+        }
+    }
+    None
+}
@@ -0,0 +1,142 @@
+//! Время загрузки по фазам / Per-phase boot timing
+//!
+//! `kernel_main` отмечает начало каждой фазы TSC-меткой, `finish` ставит
+//! последнюю и печатает таблицу. Часов ещё нет (таймер запускается позже),
+//! поэтому всё в тактах TSC; в микросекунды переводим один раз в конце,
+//! когда частота уже известна. Без частоты таблица остаётся в тактах.
+//! `kernel_main` marks the start of each phase with a TSC stamp, `finish`
+//! adds the last one and prints the table. There is no clock yet (the timer
+//! starts later), so everything is in TSC cycles; they are converted to
+//! microseconds once at the end, when the frequency is known. Without a
+//! frequency the table stays in cycles.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::current::tsc;
+
+/// Фазы в порядке `kernel_main` / Phases in `kernel_main` order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Phase {
+    Arch    = 0,
+    Pmm     = 1,
+    Vmm     = 2,
+    Heap    = 3,
+    Ipc     = 4,
+    Sched   = 5,
+    Syscall = 6,
+}
+
+pub const PHASES: usize = 7;
+
+impl Phase {
+    pub const ALL: [Phase; PHASES] = [
+        Self::Arch, Self::Pmm, Self::Vmm, Self::Heap, Self::Ipc, Self::Sched, Self::Syscall,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Arch    => "arch",
+            Self::Pmm     => "pmm",
+            Self::Vmm     => "vmm",
+            Self::Heap    => "heap",
+            Self::Ipc     => "ipc",
+            Self::Sched   => "sched",
+            Self::Syscall => "syscall",
+        }
+    }
+}
+
+/// Метки начала фаз + конец последней; 0 — фаза не отмечена.
+/// Phase start stamps + the end of the last one; 0 — phase not marked.
+static MARKS: [AtomicU64; PHASES + 1] = [const { AtomicU64::new(0) }; PHASES + 1];
+
+/// Фаза началась / A phase has started
+pub fn start(phase: Phase) {
+    MARKS[phase as usize].store(tsc::read(), Ordering::Relaxed);
+}
+
+/// Такты фазы: до следующей отмеченной метки. Не отмечена — `None`.
+/// A phase's cycles: up to the next marked stamp. Not marked — `None`.
+pub fn phase_cycles(marks: &[u64; PHASES + 1], phase: Phase) -> Option<u64> {
+    let begin = marks[phase as usize];
+    if begin == 0 { return None; }
+    let end = marks[phase as usize + 1..].iter().copied().find(|&m| m != 0)?;
+    Some(end.saturating_sub(begin))
+}
+
+/// Такты → микросекунды без переполнения / Cycles → microseconds without overflow
+pub const fn cycles_to_us(cycles: u64, hz: u64) -> u64 {
+    (cycles as u128 * 1_000_000 / hz as u128) as u64
+}
+
+/// Init закончен — поставить последнюю метку и напечатать таблицу.
+/// Init is done — stamp the end and print the table.
+pub fn finish() {
+    MARKS[PHASES].store(tsc::read(), Ordering::Relaxed);
+    let marks: [u64; PHASES + 1] = core::array::from_fn(|i| MARKS[i].load(Ordering::Relaxed));
+    let hz = tsc::frequency_hz().filter(|&hz| hz != 0);
+    let _ = write_table(&mut crate::drivers::console::Console, &marks, hz);
+}
+
+/// Таблица фаз в любой приёмник / The phase table into any sink
+fn write_table(out: &mut impl fmt::Write, marks: &[u64; PHASES + 1], hz: Option<u64>) -> fmt::Result {
+    let total = phase_cycles_total(marks);
+    writeln!(out, "[boot] phase timing ({}):", if hz.is_some() { "us" } else { "TSC cycles" })?;
+    for phase in Phase::ALL {
+        let Some(cycles) = phase_cycles(marks, phase) else { continue };
+        let value = hz.map_or(cycles, |hz| cycles_to_us(cycles, hz));
+        let percent = (cycles * 100).checked_div(total).unwrap_or(0);
+        writeln!(out, "  {:<8} {:>12} {:>3}%", phase.name(), value, percent)?;
+    }
+    writeln!(out, "  {:<8} {:>12}", "total", hz.map_or(total, |hz| cycles_to_us(total, hz)))
+}
+
+/// От первой отмеченной фазы до конца / From the first marked phase to the end
+fn phase_cycles_total(marks: &[u64; PHASES + 1]) -> u64 {
+    let first = marks[..PHASES].iter().copied().find(|&m| m != 0).unwrap_or(marks[PHASES]);
+    marks[PHASES].saturating_sub(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn table_skips_unmarked_phases_and_sums_the_rest() {
+        // arch 0..100, pmm не отмечена, vmm 100..400, конец 400
+        let mut marks = [0u64; PHASES + 1];
+        marks[Phase::Arch as usize] = 1000;
+        marks[Phase::Vmm as usize] = 1100;
+        marks[PHASES] = 1400;
+
+        let mut out = String::new();
+        write_table(&mut out, &marks, None).unwrap();
+        assert_eq!(out, "[boot] phase timing (TSC cycles):\n\
+                         \x20 arch              100  25%\n\
+                         \x20 vmm               300  75%\n\
+                         \x20 total             400\n");
+    }
+
+    #[test]
+    fn table_converts_to_microseconds_with_a_frequency() {
+        let mut marks = [0u64; PHASES + 1];
+        marks[Phase::Arch as usize] = 1;
+        marks[PHASES] = 2_000_001;
+
+        let mut out = String::new();
+        write_table(&mut out, &marks, Some(1_000_000_000)).unwrap();
+        assert!(out.starts_with("[boot] phase timing (us):\n"));
+        assert!(out.contains("  arch             2000 100%\n"));
+        assert!(out.ends_with("  total            2000\n"));
+    }
+
+    #[test]
+    fn empty_marks_print_a_zero_total_without_dividing() {
+        let mut out = String::new();
+        write_table(&mut out, &[0; PHASES + 1], None).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.ends_with("  total               0\n"));
+    }
+}
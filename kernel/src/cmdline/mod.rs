@@ -0,0 +1,54 @@
+//! Командная строка ядра / Kernel command line
+//!
+//! Формат: `key=value` или просто `key`, через пробел.
+//! Format: `key=value` or bare `key`, separated by spaces.
+//!
+//!   syscall.bench=1   — замер латентности syscall / syscall latency counter
+//!   rngseed=<u64>     — детерминированный PRNG, только для тестов / deterministic PRNG, testing only
+//!   sched.quanta=a,b,c,d — кванты MLFQ в мс / MLFQ quanta in ms
+//!   profile=1         — сэмплировать RIP на тиках / sample RIP on ticks
+
+use limine::request::ExecutableCmdlineRequest;
+use spin::Once;
+
+#[used]
+static CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
+static CMDLINE: Once<&'static str> = Once::new();
+
+/// Прочитать командную строку от Limine. Нет — пустая строка.
+/// Read the command line from Limine. Missing — empty string.
+pub fn init() {
+    CMDLINE.call_once(|| {
+        CMDLINE_REQUEST.get_response()
+            .and_then(|r| r.cmdline().to_str().ok())
+            .unwrap_or("")
+    });
+    crate::kprintln!("[cmdline] \"{}\"", raw());
+}
+
+/// Вся строка / The whole line
+pub fn raw() -> &'static str {
+    CMDLINE.get().copied().unwrap_or("")
+}
+
+/// Найти значение `key` в строке `line` (`key` без `=` даёт `""`).
+/// Find the value of `key` in `line` (a bare `key` yields `""`).
+pub fn find<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_ascii_whitespace().find_map(|arg| match arg.split_once('=') {
+        Some((k, v)) if k == key => Some(v),
+        None if arg == key       => Some(""),
+        _                        => None,
+    })
+}
+
+/// Значение параметра / Parameter value
+pub fn get(key: &str) -> Option<&'static str> {
+    find(raw(), key)
+}
+
+/// Булев флаг: `key`, `key=1`, `key=true`, `key=on`.
+/// Boolean flag: `key`, `key=1`, `key=true`, `key=on`.
+pub fn flag(key: &str) -> bool {
+    matches!(get(key), Some("" | "1" | "true" | "on"))
+}
@@ -0,0 +1,112 @@
+//! Console — мультиплексор вывода ядра / kernel output multiplexer
+//!
+//! `kprint!` пишет сюда; текст уходит в UART всегда и на выбранный
+//! framebuffer, если он есть.
+//! `kprint!` writes here; text always goes to the UART and to the selected
+//! framebuffer when there is one.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+#[cfg_attr(test, allow(unused_imports))]
+use super::{fb, font, uart};
+
+const GLYPH_W: u64 = 8;
+const GLYPH_H: u64 = 8;
+const FG: u32 = 0x00D4_956A; // медь / copper
+const BG: u32 = 0x0000_0000;
+
+/// Текстовый курсор на framebuffer / Text cursor on the framebuffer
+struct FbConsole {
+    col: u64,
+    row: u64,
+}
+
+impl FbConsole {
+    fn newline(&mut self, fb: &fb::Framebuffer) {
+        self.col = 0;
+        self.row += 1;
+        // Прокрутка читала бы WC память — медленно; вместо неё переходим наверх
+        // Scrolling would read WC memory — slow; wrap to the top instead
+        if (self.row + 1) * GLYPH_H > fb.height { self.row = 0; }
+        self.clear_row(fb);
+    }
+
+    fn clear_row(&self, fb: &fb::Framebuffer) {
+        for y in 0..GLYPH_H {
+            for x in 0..fb.width {
+                fb.put_pixel(x, self.row * GLYPH_H + y, BG);
+            }
+        }
+    }
+
+    fn put_char(&mut self, fb: &fb::Framebuffer, c: char) {
+        if c == '\n' { self.newline(fb); return; }
+        if (self.col + 1) * GLYPH_W > fb.width { self.newline(fb); }
+
+        let glyph = font::glyph(c);
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..GLYPH_W {
+                let color = if bits & (1 << dx) != 0 { FG } else { BG };
+                fb.put_pixel(self.col * GLYPH_W + dx, self.row * GLYPH_H + dy as u64, color);
+            }
+        }
+        self.col += 1;
+    }
+}
+
+impl fmt::Write for FbConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        fb::with_selected(|fb| {
+            for c in s.chars() { self.put_char(fb, c); }
+        });
+        Ok(())
+    }
+}
+
+static FB_CONSOLE: Mutex<FbConsole> = Mutex::new(FbConsole { col: 0, row: 0 });
+
+/// Есть ли fb-приёмник. Пока нет (до fb init или headless) — `_print`
+/// вообще не трогает код framebuffer'а.
+/// Whether there is an fb sink. Until there is (before fb init, or
+/// headless) `_print` doesn't touch framebuffer code at all.
+static FB_SINK: AtomicBool = AtomicBool::new(false);
+
+/// Переключить консоль на framebuffer `index`. `false`, если его нет.
+/// Switch the console to framebuffer `index`. `false` if it doesn't exist.
+pub fn set_target(index: usize) -> bool {
+    if !fb::select(index) { return false; }
+    let mut console = FB_CONSOLE.lock();
+    console.col = 0;
+    console.row = 0;
+    fb::with_selected(|fb| fb.fill(BG));
+    FB_SINK.store(true, Ordering::Release);
+    true
+}
+
+#[cfg(not(test))]
+pub fn _print(args: fmt::Arguments) {
+    use fmt::Write;
+    uart::_print(args);
+    if FB_SINK.load(Ordering::Acquire) {
+        FB_CONSOLE.lock().write_fmt(args).ok();
+    }
+}
+
+/// Консоль как `fmt::Write` — для тех, кто пишет в любой приёмник
+/// The console as a `fmt::Write` — for code that writes to any sink
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{s}"));
+        Ok(())
+    }
+}
+
+/// В тестах — в stdout: порта UART у процесса нет
+/// In tests — to stdout: the process has no UART port
+#[cfg(test)]
+pub fn _print(args: fmt::Arguments) {
+    std::print!("{args}");
+}
@@ -0,0 +1,132 @@
+//! Framebuffer driver — линейный framebuffer от Limine
+//! Framebuffer driver — linear framebuffer from Limine
+//!
+//! Framebuffer маппится как write-combining: записи пикселей копятся в
+//! буферах WC и уходят пачками, не загрязняя кэш. Без PAT — uncached.
+//! The framebuffer is mapped write-combining: pixel writes are gathered in
+//! WC buffers and flushed in bursts without polluting the cache. Without
+//! PAT — uncached.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use limine::request::FramebufferRequest;
+use crate::sync::RwLock;
+use crate::mm::pmm::PhysAddr;
+use crate::mm::vmm::{self, PageFlags, VirtAddr};
+
+#[used]
+static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
+
+/// Описание framebuffer / Framebuffer description
+pub struct Framebuffer {
+    pub addr:   VirtAddr,
+    pub phys:   PhysAddr,
+    pub width:  u64,
+    pub height: u64,
+    pub pitch:  u64,
+    pub bpp:    u16,
+}
+
+impl Framebuffer {
+    /// Размер в байтах / Size in bytes
+    pub fn size(&self) -> u64 { self.pitch * self.height }
+
+    /// Записать пиксель (32bpp) / Write a pixel (32bpp)
+    pub fn put_pixel(&self, x: u64, y: u64, color: u32) {
+        if x >= self.width || y >= self.height || self.bpp != 32 { return; }
+        let offset = y * self.pitch + x * 4;
+        unsafe {
+            let ptr = (self.addr.as_u64() + offset) as *mut u32;
+            ptr.write_volatile(color);
+        }
+    }
+
+    /// Залить весь экран цветом / Fill the whole screen with a color
+    pub fn fill(&self, color: u32) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+/// Все framebuffer'ы от загрузчика / All framebuffers reported by the bootloader
+///
+/// Пишется один раз в `init`; `kprintln!` из обработчиков только читает.
+/// Written once in `init`; `kprintln!` from handlers only reads it.
+static FRAMEBUFFERS: RwLock<Vec<Framebuffer>> = RwLock::new(Vec::new());
+
+/// Индекс framebuffer'а для консоли / Framebuffer index used by the console
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Собрать описания всех framebuffer'ов из ответа Limine.
+/// Collect descriptions of every framebuffer in the Limine response.
+fn enumerate<'a>(raw: impl Iterator<Item = limine::framebuffer::Framebuffer<'a>>) -> Vec<Framebuffer> {
+    raw.map(|fb| {
+        let addr = VirtAddr::new(fb.addr() as u64);
+        Framebuffer {
+            addr,
+            phys:   vmm::virt_to_phys(addr),
+            width:  fb.width(),
+            height: fb.height(),
+            pitch:  fb.pitch(),
+            bpp:    fb.bpp(),
+        }
+    }).collect()
+}
+
+/// Инициализировать framebuffer'ы — после heap (нужен Vec) и VMM (маппинг).
+/// Initialize framebuffers — after the heap (needs Vec) and VMM (mapping).
+///
+/// Возвращает число framebuffer'ов; `None` — headless (нет ответа или
+/// ни одного fb): консоль остаётся только на serial.
+/// Returns the number of framebuffers; `None` — headless (no response or
+/// zero framebuffers): the console stays serial-only.
+pub fn init() -> Option<usize> {
+    let fbs = match FRAMEBUFFER_REQUEST.get_response() {
+        Some(response) => enumerate(response.framebuffers()),
+        None           => Vec::new(),
+    };
+    if fbs.is_empty() {
+        crate::kprintln!("[fb] No framebuffer from bootloader — serial only");
+        return None;
+    }
+
+    for (i, fb) in fbs.iter().enumerate() {
+        // Наш PML4 мапит только первые 16MB — framebuffer нужно замаппить явно
+        // Our PML4 only maps the first 16MB — the framebuffer must be mapped explicitly
+        if let Err(err) = vmm::map_kernel_range(fb.addr, fb.phys, fb.size(), PageFlags::KERNEL_RW | PageFlags::write_combining()) {
+            crate::kprintln!("[fb] #{}: mapping failed ({:?}) — serial only", i, err);
+            return None;
+        }
+        crate::kprintln!(
+            "[fb] #{}: {}x{} {}bpp at {:#x} ({})",
+            i, fb.width, fb.height, fb.bpp, fb.phys.as_u64(),
+            if crate::arch::x86_64::mm::pat_enabled() { "write-combining" } else { "uncached" },
+        );
+    }
+    let count = fbs.len();
+    *FRAMEBUFFERS.write() = fbs;
+    Some(count)
+}
+
+/// Число framebuffer'ов / Number of framebuffers
+pub fn count() -> usize {
+    FRAMEBUFFERS.read().len()
+}
+
+/// Выбрать framebuffer для консоли. `false`, если индекса нет.
+/// Select the framebuffer used by the console. `false` if the index doesn't exist.
+pub fn select(index: usize) -> bool {
+    if index >= count() { return false; }
+    SELECTED.store(index, Ordering::Relaxed);
+    true
+}
+
+/// Выполнить `f` над выбранным framebuffer'ом, если он есть.
+/// Run `f` on the selected framebuffer, if there is one.
+pub fn with_selected<R>(f: impl FnOnce(&Framebuffer) -> R) -> Option<R> {
+    let fbs = FRAMEBUFFERS.read();
+    fbs.get(SELECTED.load(Ordering::Relaxed)).map(f)
+}
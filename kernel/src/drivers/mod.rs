@@ -2,9 +2,12 @@
 //!
 //! Минимально необходимые для отладки / Minimum required for debugging:
 //!   - UART/Serial  — отладочный вывод в терминал QEMU
-//!   - Framebuffer  — вывод на экран (TODO: Этап 2)
+//!   - Framebuffer  — вывод на экран / screen output (write-combining)
 
 pub mod uart;
+pub mod fb;
+pub mod console;
+mod font;
 
 /// Вывести строку в UART (для отладки).
 /// Print string to UART (for debugging).
@@ -17,7 +20,7 @@ pub fn print(s: &str) {
 #[macro_export]
 macro_rules! kprint {
     ($($arg:tt)*) => {
-        $crate::drivers::uart::_print(format_args!($($arg)*))
+        $crate::drivers::console::_print(format_args!($($arg)*))
     };
 }
 
@@ -0,0 +1,128 @@
+//! Синхронный вызов: `call` → `recv` → `reply`
+//! Synchronous call: `call` → `recv` → `reply`
+//!
+//! Вызывающий ставит сообщение с пометкой своего `TaskId` и паркуется.
+//! Сервер, приняв такое сообщение, запоминает, кому должен ответ; `reply`
+//! кладёт ответ в слот вызывающего и будит его в очередь 0. Пока задача не
+//! ушла с CPU, syscall повторяется — поэтому `call` идемпотентен: повтор
+//! забирает готовый ответ, а не шлёт запрос второй раз.
+//! The caller queues a message tagged with its `TaskId` and parks. The
+//! server, on receiving such a message, remembers whom it owes a reply;
+//! `reply` puts the reply into the caller's slot and wakes it into queue 0.
+//! Until the task is off the CPU the syscall is retried — so `call` is
+//! idempotent: a retry collects the finished reply instead of sending the
+//! request again.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use super::message::{Envelope, ReplyBody};
+use super::{port, IpcError, Message, PortId, TaskId};
+
+enum CallState {
+    /// Запрос в очереди или у сервера / The request is queued or with the server
+    Waiting,
+    /// Capability ответа ещё вне таблиц — ставятся при повторе `call`
+    /// The reply's capabilities are still outside any table — installed on the `call` retry
+    Replied(Box<Envelope>),
+    /// Ответа не будет: порт закрыт, сервер умер или взял следующий вызов
+    /// No reply is coming: the port closed, the server died or took the next call
+    Failed,
+}
+
+/// Незавершённые вызовы по вызывающему / Unfinished calls by caller
+static CALLS: Mutex<BTreeMap<TaskId, CallState>> = Mutex::new(BTreeMap::new());
+/// Сервер → вызывающий, которому он должен ответ / Server → the caller it owes a reply
+static REPLY_TO: Mutex<BTreeMap<TaskId, TaskId>> = Mutex::new(BTreeMap::new());
+
+/// Отправить `msg` и ждать ответа. Первый вход ставит запрос и паркует
+/// `caller` (`WouldBlock`); вход после пробуждения отдаёт ответ.
+/// Send `msg` and wait for the reply. The first entry queues the request
+/// and parks `caller` (`WouldBlock`); the entry after wakeup returns the reply.
+pub fn call(id: PortId, msg: &Message, caller: TaskId) -> Result<Message, IpcError> {
+    wait_reply(id, msg, caller).map(|reply| reply.deliver(Some(caller)).0)
+}
+
+/// `call`, но ответ — байтами для буфера вызывающего (`ipc_call_buf`).
+/// Capability ответа вызывающему не достаются, кроме буфера сервера.
+/// `call`, but the reply comes as bytes for the caller's buffer
+/// (`ipc_call_buf`). The reply's capabilities don't reach the caller, except
+/// for the server's buffer.
+pub fn call_buf(id: PortId, msg: &Message, caller: TaskId) -> Result<ReplyBody, IpcError> {
+    wait_reply(id, msg, caller).map(Envelope::into_body)
+}
+
+fn wait_reply(id: PortId, msg: &Message, caller: TaskId) -> Result<Envelope, IpcError> {
+    {
+        let mut calls = CALLS.lock();
+        match calls.get(&caller) {
+            // Ещё не сняты с CPU — ответа нет, ждём дальше
+            // Not off the CPU yet — no reply, keep waiting
+            Some(CallState::Waiting) => return Err(IpcError::WouldBlock),
+            Some(_) => return match calls.remove(&caller) {
+                Some(CallState::Replied(reply)) => Ok(*reply),
+                _                               => Err(IpcError::CallFailed),
+            },
+            None => {}
+        }
+    }
+    let port = port(id).ok_or(IpcError::NoSuchPort)?;
+    let env = Envelope::take(Some(caller), msg, Some(caller))?;
+    // Парковка до постановки: ответ, пришедший сразу, застанет нас ждущими
+    // Park before queueing: a reply arriving at once finds us waiting
+    crate::sched::block_on_ipc(caller);
+    CALLS.lock().insert(caller, CallState::Waiting);
+    if port.send(env).is_err() {
+        CALLS.lock().remove(&caller);
+        crate::sched::wake_ipc(caller);
+        return Err(IpcError::QueueFull);
+    }
+    Err(IpcError::WouldBlock)
+}
+
+/// Сервер `server` принял сообщение `call` от `caller`. Прежний
+/// неотвеченный вызов проваливается — ответ у сервера один.
+/// Server `server` received a `call` message from `caller`. A previous
+/// unanswered call fails — the server has a single reply.
+pub(super) fn accepted(server: TaskId, caller: TaskId) {
+    let old = REPLY_TO.lock().insert(server, caller);
+    if let Some(old) = old { fail(old); }
+}
+
+/// Ответить на последний принятый вызов; capability ответа уходят копиями
+/// (нужен GRANT). Вызывающий уже мёртв — ответ выбрасывается;
+/// неотвеченного вызова нет — `NoCaller`.
+/// Reply to the last call received; the reply's capabilities go as copies
+/// (GRANT required). If the caller is already dead the reply is dropped;
+/// with no unanswered call — `NoCaller`.
+pub fn reply(server: TaskId, msg: &Message) -> Result<(), IpcError> {
+    if !REPLY_TO.lock().contains_key(&server) { return Err(IpcError::NoCaller); }
+    let env = Envelope::take(Some(server), msg, None)?;
+    let caller = REPLY_TO.lock().remove(&server).ok_or(IpcError::NoCaller)?;
+    finish(caller, CallState::Replied(Box::new(env)));
+    Ok(())
+}
+
+/// Вызов `caller` не получит ответа / `caller`'s call will get no reply
+pub(super) fn fail(caller: TaskId) {
+    finish(caller, CallState::Failed);
+}
+
+fn finish(caller: TaskId, state: CallState) {
+    let mut calls = CALLS.lock();
+    // Нет записи — вызывающий умер, будить некого
+    // No entry — the caller died, there is nobody to wake
+    let Some(slot) = calls.get_mut(&caller) else { return };
+    *slot = state;
+    drop(calls);
+    crate::sched::wake_ipc(caller);
+}
+
+/// Задача уходит: её вызов забыт, а кто ждал её ответа — получает отказ.
+/// A task is going away: its call is forgotten, and whoever awaited its
+/// reply gets a failure.
+pub fn forget_task(task: TaskId) {
+    CALLS.lock().remove(&task);
+    let caller = REPLY_TO.lock().remove(&task);
+    if let Some(caller) = caller { fail(caller); }
+}
@@ -0,0 +1,458 @@
+//! Capability tables — по одной на задачу / one per task
+//!
+//! Capability — unforgeable токен: задача знает только `CapId` (индекс
+//! в своей таблице), а сам объект и права лежат в ядре.
+//! A capability is an unforgeable token: a task only knows a `CapId`
+//! (an index into its own table); the object and rights live in the kernel.
+//!
+//! Каждая запись помнит родителя (от кого получена) — по этим ссылкам
+//! строится дерево деривации для отладки и отзыва.
+//! Every entry remembers its parent (where it was derived from) — these links
+//! form the derivation tree used for debugging and revocation.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use cupruxos_abi::rights;
+use spin::Mutex;
+use crate::mm::pmm::PhysAddr;
+use crate::mm::shared::{MemoryCap, SharedMemObject};
+use crate::mm::vmm::PageFlags;
+use super::{CapId, PortId, TaskId};
+use super::event::EventQueue;
+use super::notify::Notification;
+use super::object::Port;
+
+bitflags! {
+    /// Права capability / Capability rights
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Rights: u32 {
+        const READ   = rights::READ;
+        const WRITE  = rights::WRITE;
+        const GRANT  = rights::GRANT;    // передать другому · transfer to another
+        const REVOKE = rights::REVOKE;   // отозвать производные · revoke derived copies
+        const DUP    = rights::DUP;      // копия в своей таблице · a copy in one's own table
+
+        // Для порта: слать — запись, принимать — чтение
+        // For a port: sending is writing, receiving is reading
+        const SEND = rights::SEND;
+        const RECV = rights::RECV;
+    }
+}
+
+/// На что указывает capability — держит ссылку на объект.
+/// What a capability refers to — holds a reference to the object.
+#[derive(Clone)]
+pub enum CapKind {
+    Port(Arc<Port>),            // право писать/читать порт · port read/write
+    Shared(MemoryCap),          // общая память и её флаги · shared memory and its flags
+    EventQueue(Arc<EventQueue>), // ждать готовности портов · wait for port readiness
+    Notification(Arc<Notification>), // слово сигналов · signal word
+    Task(TaskId),               // право управлять задачей · control task
+    SchedControl,               // поднимать приоритет выше Normal · raise priority above Normal
+    /// Порты `[base, base + count)` для userspace драйвера
+    /// Ports `[base, base + count)` for a userspace driver
+    IoPort { base: u16, count: u16 },
+}
+
+impl CapKind {
+    /// Идентичность объекта без ссылки на него / Object identity without a reference
+    pub fn object(&self) -> CapObject {
+        match self {
+            Self::Port(port)  => CapObject::Port(port.id),
+            Self::Shared(mem) => CapObject::Shared(mem.object.base(), mem.object.order()),
+            Self::EventQueue(q) => CapObject::EventQueue(q.id),
+            Self::Notification(n) => CapObject::Notification(n.id),
+            Self::Task(task)  => CapObject::Task(*task),
+            Self::SchedControl => CapObject::SchedControl,
+            Self::IoPort { base, count } => CapObject::IoPort(*base, *count),
+        }
+    }
+}
+
+/// Идентичность объекта для снимков / Object identity for snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapObject {
+    Port(PortId),
+    Shared(PhysAddr, usize),
+    EventQueue(u64),
+    Notification(u64),
+    Task(TaskId),
+    SchedControl,
+    IoPort(u16, u16),
+}
+
+/// Ссылка на capability в чужой таблице / Reference to a capability in some task's table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapRef {
+    pub task: TaskId,
+    pub cap:  CapId,
+}
+
+/// Запись таблицы / Table entry
+#[derive(Clone)]
+pub struct CapEntry {
+    pub kind:   CapKind,
+    pub rights: Rights,
+    /// Метка, которую видит получатель сообщения / Tag seen by message receivers
+    pub badge:  u64,
+    pub parent: Option<CapRef>,
+}
+
+/// Таблица capability одной задачи / Capability table of one task
+#[derive(Default)]
+pub struct CapTable {
+    slots: Vec<Option<CapEntry>>,
+}
+
+impl CapTable {
+    /// Положить запись в первый свободный слот / Put an entry into the first free slot
+    pub fn install(&mut self, entry: CapEntry) -> CapId {
+        let idx = match self.slots.iter().position(|s| s.is_none()) {
+            Some(idx) => { self.slots[idx] = Some(entry); idx }
+            None      => { self.slots.push(Some(entry)); self.slots.len() - 1 }
+        };
+        CapId(idx as u64)
+    }
+
+    pub fn get(&self, cap: CapId) -> Option<&CapEntry> {
+        self.slots.get(cap.0 as usize)?.as_ref()
+    }
+
+    pub fn remove(&mut self, cap: CapId) -> Option<CapEntry> {
+        self.slots.get_mut(cap.0 as usize)?.take()
+    }
+
+    /// Все живые записи по порядку `CapId` / All live entries in `CapId` order
+    pub fn iter(&self) -> impl Iterator<Item = (CapId, &CapEntry)> {
+        self.slots.iter().enumerate()
+            .filter_map(|(i, s)| s.as_ref().map(|e| (CapId(i as u64), e)))
+    }
+}
+
+static TABLES: Mutex<BTreeMap<TaskId, CapTable>> = Mutex::new(BTreeMap::new());
+
+/// Выдать задаче capability / Give a task a capability
+pub fn install(task: TaskId, entry: CapEntry) -> CapId {
+    TABLES.lock().entry(task).or_default().install(entry)
+}
+
+/// Поставить копию, пришедшую в сообщении. Пока она стояла в очереди,
+/// родителя могли отозвать (или его слот занять другим объектом) — такая
+/// копия `revoke` уже не найдёт, и она выбрасывается: `None`.
+/// Install a copy that came in a message. While it sat in the queue its
+/// parent may have been revoked (or its slot taken by another object) —
+/// `revoke` can no longer find such a copy, so it is dropped: `None`.
+pub fn install_received(task: TaskId, entry: CapEntry) -> Option<CapId> {
+    let mut tables = TABLES.lock();
+    if let Some(parent) = entry.parent {
+        let live = tables.get(&parent.task).and_then(|t| t.get(parent.cap))
+            .is_some_and(|p| p.kind.object() == entry.kind.object());
+        if !live {
+            // Объект отпускается после lock'а таблиц / The object is released after the tables lock
+            drop(tables);
+            return None;
+        }
+    }
+    Some(tables.entry(task).or_default().install(entry))
+}
+
+/// Получить копию записи (со своей ссылкой на объект).
+/// Get a copy of an entry (with its own reference to the object).
+pub fn lookup(task: TaskId, cap: CapId) -> Option<CapEntry> {
+    TABLES.lock().get(&task)?.get(cap).cloned()
+}
+
+/// Есть ли у задачи capability, подходящая под `pred`.
+/// Whether the task holds a capability matching `pred`.
+pub fn holds(task: TaskId, pred: impl Fn(&CapKind) -> bool) -> bool {
+    TABLES.lock().get(&task).is_some_and(|t| t.iter().any(|(_, e)| pred(&e.kind)))
+}
+
+/// Shared memory за capability и флаги, с которыми её можно замапить
+/// (для `map_shared`). Без права WRITE — только чтение.
+/// The shared memory behind a capability and the flags it may be mapped
+/// with (for `map_shared`). Without the WRITE right — read-only.
+pub fn shared_object(task: TaskId, cap: CapId) -> Option<(Arc<SharedMemObject>, PageFlags)> {
+    let entry = lookup(task, cap)?;
+    match entry.kind {
+        CapKind::Shared(mem) => {
+            let flags = mem.map_flags(entry.rights.contains(Rights::WRITE));
+            Some((mem.object, flags))
+        }
+        _ => None,
+    }
+}
+
+/// Удалить capability. Если это была последняя ссылка на объект, объект
+/// освобождается здесь же — уже после того, как отпущен lock таблиц.
+/// Remove a capability. If it was the last reference to the object, the
+/// object is freed right here — after the table lock is released.
+/// Пока только для тестов: задачи теряют capability через `revoke` и
+/// `drop_table`.
+/// Tests only for now: tasks lose capabilities through `revoke` and
+/// `drop_table`.
+#[cfg(test)]
+pub fn remove(task: TaskId, cap: CapId) -> bool {
+    let mut tables = TABLES.lock();
+    let entry = tables.get_mut(&task).and_then(|t| t.remove(cap));
+    if entry.is_some() { orphan(&mut tables, |r| r == CapRef { task, cap }); }
+    drop(tables);
+    entry.is_some()
+}
+
+/// Задача ушла — её таблица выбрасывается, а с ней и ссылки на объекты.
+/// Объекты освобождаются уже после lock'а таблиц.
+/// The task is gone — its table is thrown away, and with it the references
+/// to objects. The objects are freed after the tables lock is released.
+pub fn drop_table(task: TaskId) {
+    let mut tables = TABLES.lock();
+    let table = tables.remove(&task);
+    orphan(&mut tables, |r| r.task == task);
+    drop(tables);
+    drop(table);
+}
+
+/// Производные удалённой записи становятся корнями: её слот может занять
+/// чужая запись, и `revoke` не должен принять её за родителя.
+/// The derived copies of a removed entry become roots: its slot may be
+/// reused by an unrelated entry, and `revoke` must not take that for the parent.
+fn orphan(tables: &mut BTreeMap<TaskId, CapTable>, gone: impl Fn(CapRef) -> bool) {
+    for table in tables.values_mut() {
+        for entry in table.slots.iter_mut().flatten() {
+            if entry.parent.is_some_and(&gone) { entry.parent = None; }
+        }
+    }
+}
+
+/// Почему `revoke` отказал / Why `revoke` refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevokeError {
+    NoSuchCap,
+    /// У capability нет права REVOKE / The capability lacks the REVOKE right
+    Denied,
+}
+
+/// Отозвать `task:cap` и всё, что от неё произведено, во всех таблицах.
+/// Задачи, запаркованные на порту через отозванную capability, будятся;
+/// порт, на который не осталось capability, закрывается. Возвращает,
+/// сколько записей удалено.
+/// Revoke `task:cap` and everything derived from it, in every table. Tasks
+/// parked on a port through a revoked capability are woken; a port with no
+/// capabilities left is closed. Returns how many entries were removed.
+pub fn revoke(task: TaskId, cap: CapId) -> Result<usize, RevokeError> {
+    let mut tables = TABLES.lock();
+    let root = tables.get(&task).and_then(|t| t.get(cap)).ok_or(RevokeError::NoSuchCap)?;
+    if !root.rights.contains(Rights::REVOKE) { return Err(RevokeError::Denied); }
+
+    // Обход дерева в ширину: родитель удаляется раньше, чем ищутся его дети
+    // Breadth-first over the tree: a parent goes before its children are sought
+    let mut pending = alloc::collections::VecDeque::from([CapRef { task, cap }]);
+    let mut removed = Vec::new();
+    while let Some(node) = pending.pop_front() {
+        let Some(entry) = tables.get_mut(&node.task).and_then(|t| t.remove(node.cap)) else { continue };
+        for (&owner, table) in tables.iter() {
+            pending.extend(table.iter()
+                .filter(|(_, e)| e.parent == Some(node))
+                .map(|(id, _)| CapRef { task: owner, cap: id }));
+        }
+        removed.push((node.task, entry));
+    }
+
+    // Порт жив, пока на него есть хоть одна capability
+    // A port lives while at least one capability refers to it
+    let ports: Vec<_> = removed.iter()
+        .filter_map(|(owner, entry)| match &entry.kind {
+            CapKind::Port(port) => Some((*owner, port.clone())),
+            _                   => None,
+        })
+        .map(|(owner, port)| {
+            let alive = tables.values().any(|t| t.iter().any(|(_, e)| {
+                matches!(&e.kind, CapKind::Port(p) if p.id == port.id)
+            }));
+            (owner, port, alive)
+        })
+        .collect();
+    drop(tables);
+
+    // Очередь порта и TASKS — только без lock'а таблиц
+    // The port queue and TASKS — only without the tables lock
+    for (owner, port, alive) in ports {
+        if !alive {
+            super::destroy_port(port.id);
+        } else if port.cancel_wait(owner) {
+            // Повтор `recv` увидит, что capability больше нет
+            // The `recv` retry will find the capability gone
+            crate::sched::wake_ipc(owner);
+        }
+    }
+    // Ссылки на объекты уходят после всех lock'ов / Object references go after every lock
+    let count = removed.len();
+    drop(removed);
+    Ok(count)
+}
+
+/// Почему `grant` отказал / Why `grant` refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantError {
+    /// У `from` нет такой capability / `from` holds no such capability
+    NoSuchCap,
+    /// У исходной нет права GRANT / The source lacks the GRANT right
+    Denied,
+}
+
+/// Выдать `to` производную от `from:cap` с правами `исходные & mask` —
+/// ослабить можно, усилить нельзя. Без GRANT у исходной — отказ целиком.
+/// Give `to` a capability derived from `from:cap` with rights
+/// `source & mask` — attenuating is possible, amplifying is not. Without
+/// GRANT on the source the grant is refused outright.
+pub fn grant(from: TaskId, cap: CapId, to: TaskId, mask: Rights) -> Result<CapId, GrantError> {
+    let mut tables = TABLES.lock();
+    let entry = derived(&tables, from, cap, mask)?;
+    Ok(tables.entry(to).or_default().install(entry))
+}
+
+/// Как `grant`, но запись возвращается, а не ставится: для задачи, которой
+/// ещё нет (`task_spawn`).
+/// Like `grant`, but the entry is returned rather than installed: for a
+/// task that doesn't exist yet (`task_spawn`).
+pub fn derive(from: TaskId, cap: CapId, mask: Rights) -> Result<CapEntry, GrantError> {
+    derived(&TABLES.lock(), from, cap, mask)
+}
+
+fn derived(tables: &BTreeMap<TaskId, CapTable>, from: TaskId, cap: CapId, mask: Rights) -> Result<CapEntry, GrantError> {
+    let src = tables.get(&from).and_then(|t| t.get(cap)).ok_or(GrantError::NoSuchCap)?;
+    if !src.rights.contains(Rights::GRANT) { return Err(GrantError::Denied); }
+    Ok(CapEntry {
+        kind:   src.kind.clone(),
+        rights: src.rights & mask,
+        badge:  src.badge,
+        parent: Some(CapRef { task: from, cap }),
+    })
+}
+
+// Снимки ждут команды `caps <pid>` — оболочки, которая их напечатает, пока нет
+// Snapshots await the `caps <pid>` command — there is no shell to print them yet
+
+/// Снимок одной capability для отладки / Debug snapshot of one capability
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct CapInfo {
+    pub id:     CapId,
+    pub kind:   CapObject,
+    pub rights: Rights,
+    pub badge:  u64,
+    pub parent: Option<CapRef>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl CapInfo {
+    pub const EMPTY: Self = Self {
+        id:     CapId(0),
+        kind:   CapObject::Port(PortId(0)),
+        rights: Rights::empty(),
+        badge:  0,
+        parent: None,
+    };
+}
+
+/// Перечислить capability задачи в `buf` (привилегированная операция —
+/// для `caps <pid>`). Возвращает сколько их всего; если больше
+/// `buf.len()`, заполнен только префикс — вызывающий повторяет с буфером
+/// нужного размера.
+/// List a task's capabilities into `buf` (privileged — backs `caps <pid>`).
+/// Returns how many there are in total; if that exceeds `buf.len()` only a
+/// prefix is filled and the caller retries with a large enough buffer.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn list(task: TaskId, buf: &mut [CapInfo]) -> usize {
+    let tables = TABLES.lock();
+    let Some(table) = tables.get(&task) else { return 0 };
+
+    let mut count = 0;
+    for (id, entry) in table.iter() {
+        if let Some(slot) = buf.get_mut(count) {
+            *slot = CapInfo {
+                id,
+                kind:   entry.kind.object(),
+                rights: entry.rights,
+                badge:  entry.badge,
+                parent: entry.parent,
+            };
+        }
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const A: TaskId = TaskId(0x1454_0001);
+    const B: TaskId = TaskId(0x1454_0002);
+
+    fn root(target: TaskId, badge: u64) -> CapEntry {
+        CapEntry { kind: CapKind::Task(target), rights: Rights::all(), badge, parent: None }
+    }
+
+    #[test]
+    fn list_reports_parent_links_and_rights() {
+        let _kernel = testing::setup();
+        let top = install(A, root(B, 7));
+        let child = grant(A, top, B, Rights::READ | Rights::GRANT).unwrap();
+        let grandchild = grant(B, child, B, Rights::READ | Rights::WRITE).unwrap();
+
+        let mut buf = [CapInfo::EMPTY; 4];
+        assert_eq!(list(B, &mut buf), 2);
+        let [first, second, ..] = buf;
+        assert_eq!((first.id, first.kind, first.badge), (child, CapObject::Task(B), 7));
+        assert_eq!(first.parent, Some(CapRef { task: A, cap: top }));
+        assert_eq!(first.rights, Rights::READ | Rights::GRANT);
+        assert_eq!(second.id, grandchild);
+        assert_eq!(second.parent, Some(CapRef { task: B, cap: child }));
+        // WRITE не было у родителя — не появилось / WRITE wasn't on the parent — it didn't appear
+        assert_eq!(second.rights, Rights::READ);
+
+        // Буфер мал — заполнен префикс, а вернулось сколько нужно
+        // The buffer is short — a prefix is filled and the needed count comes back
+        let mut short = [CapInfo::EMPTY; 1];
+        assert_eq!(list(B, &mut short), 2);
+        assert_eq!(short[0].id, child);
+        drop_table(A);
+        drop_table(B);
+    }
+
+    #[test]
+    fn a_cap_id_resolves_only_in_its_own_table() {
+        let _kernel = testing::setup();
+        let mine = install(A, root(A, 1));
+        assert!(lookup(B, mine).is_none());
+
+        // Тот же номер у B — своя запись, не запись A
+        // The same number in B — B's own entry, not A's
+        let theirs = install(B, root(B, 2));
+        assert_eq!(theirs, mine);
+        assert_eq!(lookup(B, theirs).map(|e| e.badge), Some(2));
+        assert_eq!(lookup(A, mine).map(|e| e.badge), Some(1));
+        drop_table(A);
+        drop_table(B);
+    }
+
+    #[test]
+    fn a_shared_object_lives_until_its_last_cap_goes() {
+        let _kernel = testing::setup();
+        let free = crate::mm::pmm::free_memory();
+        let object = SharedMemObject::alloc(0).unwrap();
+        let kind = CapKind::Shared(MemoryCap::new(object, PageFlags::USER_RW));
+        let top = install(A, CapEntry { kind, rights: Rights::all(), badge: 0, parent: None });
+        let copy = grant(A, top, B, Rights::all()).unwrap();
+
+        assert!(remove(A, top));
+        // Копия у B ещё держит фреймы / B's copy still holds the frames
+        assert!(crate::mm::pmm::free_memory() < free);
+        assert!(remove(B, copy));
+        assert_eq!(crate::mm::pmm::free_memory(), free);
+    }
+}
@@ -0,0 +1,186 @@
+//! EventQueue — готовность многих источников за O(1)
+//! EventQueue — readiness of many sources in O(1)
+//!
+//! Источник (порт) подключается к очереди один раз; дальше он сам сообщает
+//! очереди о смене своей готовности, и `wait` (на нём стоит блокирующий
+//! `evq_wait`) просто забирает список готовых — без перебора всех
+//! источников на каждом ожидании.
+//! A source (port) is attached to the queue once; from then on it reports
+//! its own readiness changes to the queue, and `wait` (the blocking
+//! `evq_wait` sits on top of it) just collects the ready list — no scan
+//! over every source on each wait.
+//!
+//! Level (по умолчанию): источник остаётся в списке, пока он готов.
+//! Edge: источник сообщается один раз на каждый переход в «готов».
+//! Level (default): a source stays listed for as long as it is ready.
+//! Edge: a source is reported once per transition to ready.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use super::cap::{self, CapKind, Rights};
+use super::{CapId, IpcError, PortId, TaskId};
+
+static NEXT_QUEUE_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Trigger {
+    #[default]
+    Level,
+    Edge,
+}
+
+/// Готовый источник, как его видит сервер / A ready source as the server sees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Event {
+    pub port:  PortId,
+    /// Метка, заданная при подключении / Token given at attach time
+    pub token: u64,
+}
+
+impl Event {
+    pub const EMPTY: Self = Self { port: PortId(0), token: 0 };
+
+    /// Байты `cupruxos_abi::EvqEvent` для userspace.
+    /// The bytes of a `cupruxos_abi::EvqEvent` for user space.
+    pub fn to_bytes(self) -> [u8; core::mem::size_of::<cupruxos_abi::EvqEvent>()] {
+        let mut bytes = [0; core::mem::size_of::<cupruxos_abi::EvqEvent>()];
+        bytes[..8].copy_from_slice(&self.port.0.to_ne_bytes());
+        bytes[8..].copy_from_slice(&self.token.to_ne_bytes());
+        bytes
+    }
+}
+
+struct Source {
+    token:   u64,
+    trigger: Trigger,
+    ready:   bool,
+    /// Уже стоит в `pending` / Already queued in `pending`
+    queued:  bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    sources: BTreeMap<PortId, Source>,
+    pending: VecDeque<PortId>,
+    /// Запаркованные в `wait` / Parked in `wait`
+    waiters: Vec<TaskId>,
+}
+
+pub struct EventQueue {
+    pub id: u64,
+    inner:  Mutex<Inner>,
+}
+
+impl EventQueue {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            id:    NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed),
+            inner: Mutex::new(Inner::default()),
+        })
+    }
+
+    /// Подключить порт. `ready` — его готовность сейчас. Повторно — `false`.
+    /// Attach a port. `ready` — its readiness right now. Already attached — `false`.
+    pub fn attach(&self, port: PortId, token: u64, trigger: Trigger, ready: bool) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.sources.contains_key(&port) { return false; }
+        inner.sources.insert(port, Source { token, trigger, ready, queued: ready });
+        if ready { inner.pending.push_back(port); }
+        true
+    }
+
+    pub fn detach(&self, port: PortId) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.sources.remove(&port).is_none() { return false; }
+        inner.pending.retain(|p| *p != port);
+        true
+    }
+
+    /// Источник сменил готовность — O(1) относительно числа источников
+    /// (плюс поиск в дереве).
+    /// A source changed readiness — O(1) in the number of sources (plus the
+    /// tree lookup).
+    pub fn notify(&self, port: PortId, ready: bool) {
+        let woken = {
+            let mut inner = self.inner.lock();
+            let Some(src) = inner.sources.get_mut(&port) else { return };
+            let rising = ready && !src.ready;
+            src.ready = ready;
+            let enqueue = !src.queued && match src.trigger {
+                Trigger::Level => ready,
+                Trigger::Edge  => rising,
+            };
+            if !enqueue { return; }
+            src.queued = true;
+            inner.pending.push_back(port);
+            core::mem::take(&mut inner.waiters)
+        };
+        for task in woken { crate::sched::wake_ipc(task); }
+    }
+
+    /// Забрать готовые источники в `out`, вернуть сколько записано.
+    /// Level-источники, которые всё ещё готовы, остаются в очереди. Без
+    /// готовых — запарковать `waiter` до следующего `notify` и вернуть 0.
+    /// Collect ready sources into `out`, return how many were written.
+    /// Level sources that are still ready stay queued. With nothing ready,
+    /// park `waiter` until the next `notify` and return 0.
+    pub fn wait(&self, out: &mut [Event], waiter: Option<TaskId>) -> usize {
+        let mut inner = self.inner.lock();
+        let n = collect(&mut inner, out);
+        // Парковка под lock'ом — `notify` увидит нас в списке
+        // Parking under the lock — `notify` will see us listed
+        if n == 0 {
+            if let Some(task) = waiter {
+                if crate::sched::block_on_ipc(task) { inner.waiters.push(task); }
+            }
+        }
+        n
+    }
+}
+
+fn collect(inner: &mut Inner, out: &mut [Event]) -> usize {
+    let Inner { sources, pending, .. } = inner;
+    let mut n = 0;
+    let mut requeue = 0;
+    while n < out.len() {
+        let Some(port) = pending.pop_front() else { break };
+        let Some(src) = sources.get_mut(&port) else { continue };
+        let still = src.trigger == Trigger::Level && src.ready;
+        if src.trigger == Trigger::Edge || src.ready {
+            out[n] = Event { port, token: src.token };
+            n += 1;
+        }
+        src.queued = still;
+        if still { pending.push_back(port); requeue += 1; }
+        // Всё оставшееся — уже перенесённые в хвост / Everything left was requeued
+        if requeue == pending.len() { break; }
+    }
+    n
+}
+
+/// Очередь за capability текущей задачи; ждать и подключать — право RECV.
+/// The queue behind the current task's capability; waiting and attaching take RECV.
+pub fn lookup(cap: CapId) -> Result<Arc<EventQueue>, IpcError> {
+    let task = crate::sched::current_id().ok_or(IpcError::InvalidCap)?;
+    let entry = cap::lookup(task, cap).ok_or(IpcError::InvalidCap)?;
+    let CapKind::EventQueue(queue) = entry.kind else { return Err(IpcError::InvalidCap) };
+    if !entry.rights.contains(Rights::RECV) { return Err(IpcError::NoPermission); }
+    Ok(queue)
+}
+
+/// Дождаться готовых источников очереди за `cap`. Их нет — текущая задача
+/// паркуется, а syscall повторяет `wait_events` после пробуждения.
+/// Wait for ready sources of the queue behind `cap`. With none the current
+/// task parks, and the syscall retries `wait_events` once it wakes.
+pub fn wait_events(cap: CapId, out: &mut [Event]) -> Result<usize, IpcError> {
+    let queue = lookup(cap)?;
+    let task = crate::sched::current_id();
+    let n = queue.wait(out, task);
+    if n == 0 { return Err(IpcError::WouldBlock); }
+    if let Some(task) = task { crate::sched::unpark(task); }
+    Ok(n)
+}
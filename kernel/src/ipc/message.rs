@@ -0,0 +1,262 @@
+//! Сообщение IPC — тот же формат, что `libcuprum::ipc::Message`
+//! IPC message — the same layout as `libcuprum::ipc::Message`
+//!
+//! Capability из слотов `caps` передаются копиями: отправка требует права
+//! GRANT и снимает копию, чей родитель — запись отправителя (она остаётся
+//! на месте), сообщение везёт копии, а получение ставит их в таблицу
+//! получателя и переписывает слоты на его `CapId`.
+//! Capabilities in the `caps` slots travel as copies: sending requires the
+//! GRANT right and takes a copy whose parent is the sender's entry (which
+//! stays where it is), the message carries the copies, and receiving
+//! installs them in the receiver's table and rewrites the slots with its
+//! `CapId`s.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use super::cap::{self, CapEntry, CapKind, CapRef, Rights};
+use crate::mm::shared::SharedMemObject;
+use crate::mm::vmm::phys_to_virt;
+use super::{CapId, IpcError, TaskId, MAX_INLINE_PAYLOAD};
+pub use cupruxos_abi::{MAX_MSG_CAPS, NO_CAP};
+
+/// Inline payload, его длина и слоты capability. `repr(C)`, как в
+/// userspace: ядро копирует сообщения из пользовательской памяти как есть.
+/// The inline payload, its length and the capability slots. `repr(C)`, as
+/// in userspace: the kernel copies messages out of user memory as they are.
+#[derive(Clone)]
+#[repr(C)]
+pub struct Message {
+    pub payload:     [u8; MAX_INLINE_PAYLOAD],
+    pub payload_len: usize,
+    /// `CapId` в таблице отправителя (у принятого — получателя); `NO_CAP` — пусто
+    /// `CapId`s in the sender's table (the receiver's once received); `NO_CAP` — empty
+    pub caps:        [u64; MAX_MSG_CAPS],
+}
+
+const _: () = assert!(
+    core::mem::size_of::<Message>() == MAX_INLINE_PAYLOAD + core::mem::size_of::<usize>() + MAX_MSG_CAPS * 8
+);
+
+impl Message {
+    pub const fn empty() -> Self {
+        Self { payload: [0; MAX_INLINE_PAYLOAD], payload_len: 0, caps: [NO_CAP; MAX_MSG_CAPS] }
+    }
+
+    /// Больше `MAX_INLINE_PAYLOAD` — `None`. Сообщения ядру приходят из
+    /// userspace готовыми, так что собирают их только тесты.
+    /// Longer than `MAX_INLINE_PAYLOAD` — `None`. The kernel's messages come
+    /// ready-made from userspace, so only tests assemble them.
+    #[cfg(test)]
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > MAX_INLINE_PAYLOAD { return None; }
+        let mut msg = Self::empty();
+        msg.payload[..bytes.len()].copy_from_slice(bytes);
+        msg.payload_len = bytes.len();
+        Some(msg)
+    }
+
+    /// Полезная часть payload; длина от пользователя обрезается.
+    /// The used part of the payload; a user-supplied length is clamped.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.payload[..self.payload_len.min(MAX_INLINE_PAYLOAD)]
+    }
+}
+
+/// Тело ответа для `call_buf`: inline payload или буфер сервера — shared
+/// memory в первом слоте ответа, длина тела — `u64` в его payload.
+/// The reply body for `call_buf`: the inline payload or the server's buffer —
+/// shared memory in the reply's first slot, the body length a `u64` in its
+/// payload.
+pub enum ReplyBody {
+    Inline(Box<Message>),
+    Shared(Arc<SharedMemObject>, usize),
+}
+
+impl ReplyBody {
+    /// Байты тела; длина из payload обрезается по размеру буфера.
+    /// The body bytes; a length from the payload is clamped to the buffer size.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Inline(msg) => msg.as_bytes(),
+            Self::Shared(object, len) => {
+                let len = (*len).min(object.size() as usize);
+                let at = phys_to_virt(object.base()).as_u64() as *const u8;
+                // Объект держим — память жива, пока жив `self`
+                // We hold the object — the memory lives as long as `self` does
+                unsafe { core::slice::from_raw_parts(at, len) }
+            }
+        }
+    }
+}
+
+/// Сообщение в пути: копии capability ещё ни в чьей таблице.
+/// A message in flight: the capability copies aren't in any table yet.
+pub struct Envelope {
+    pub msg:    Message,
+    /// Кто ждёт ответа (`call`) / Who awaits the reply (`call`)
+    pub caller: Option<TaskId>,
+    caps:       [Option<CapEntry>; MAX_MSG_CAPS],
+}
+
+impl Envelope {
+    /// Без capability — слоты сообщения очищаются / Without capabilities — the message's slots are cleared
+    pub fn plain(msg: &Message, caller: Option<TaskId>) -> Self {
+        let mut msg = msg.clone();
+        msg.caps = [NO_CAP; MAX_MSG_CAPS];
+        Self { msg, caller, caps: Default::default() }
+    }
+
+    /// Снять копии capability из слотов `msg` у `sender`, родитель каждой —
+    /// исходная запись. Слот не capability (или повтор) — `InvalidCap`, без
+    /// права GRANT — `NoPermission`; в обоих случаях не передаётся ничего.
+    /// Take copies of the capabilities in `msg`'s slots from `sender`, each
+    /// one's parent being the source entry. A slot that isn't a capability
+    /// (or a repeat) — `InvalidCap`, one without the GRANT right —
+    /// `NoPermission`; either way nothing is transferred.
+    pub fn take(sender: Option<TaskId>, msg: &Message, caller: Option<TaskId>) -> Result<Self, IpcError> {
+        let mut env = Self::plain(msg, caller);
+        let slots = msg.caps.iter().enumerate().filter(|(_, &c)| c != NO_CAP);
+        if slots.clone().next().is_none() { return Ok(env); }
+        let sender = sender.ok_or(IpcError::InvalidCap)?;
+        for (i, &raw) in slots {
+            if msg.caps[..i].contains(&raw) { return Err(IpcError::InvalidCap); }
+            let id = CapId(raw);
+            let entry = cap::lookup(sender, id).ok_or(IpcError::InvalidCap)?;
+            if !entry.rights.contains(Rights::GRANT) { return Err(IpcError::NoPermission); }
+            env.caps[i] = Some(CapEntry { parent: Some(CapRef { task: sender, cap: id }), ..entry });
+        }
+        Ok(env)
+    }
+
+    /// Доставить: записи — в таблицу `receiver`, слоты — его `CapId`. Без
+    /// получателя-задачи capability просто отпускаются; копия, чей родитель
+    /// отозван в пути, тоже — её слот остаётся `NO_CAP`.
+    /// Deliver: the entries go into `receiver`'s table, the slots get its
+    /// `CapId`s. Without a receiving task the capabilities are just dropped;
+    /// so is a copy whose parent was revoked in flight — its slot stays
+    /// `NO_CAP`.
+    pub fn deliver(self, receiver: Option<TaskId>) -> (Message, Option<TaskId>) {
+        let Self { mut msg, caller, caps, .. } = self;
+        if let Some(receiver) = receiver {
+            for (slot, entry) in msg.caps.iter_mut().zip(caps) {
+                let Some(entry) = entry else { continue };
+                if let Some(id) = cap::install_received(receiver, entry) { *slot = id.0; }
+            }
+        }
+        (msg, caller)
+    }
+
+    /// Тело ответа для `call_buf`; остальные capability отпускаются.
+    /// The reply body for `call_buf`; the other capabilities are let go.
+    pub fn into_body(self) -> ReplyBody {
+        let Self { msg, caps: [first, ..], .. } = self;
+        match first.map(|entry| entry.kind) {
+            Some(CapKind::Shared(mem)) if msg.payload_len == 8 => {
+                let len = u64::from_le_bytes(*msg.payload.first_chunk().unwrap());
+                ReplyBody::Shared(mem.object, usize::try_from(len).unwrap_or(usize::MAX))
+            }
+            _ => ReplyBody::Inline(Box::new(msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::cap::CapKind;
+    use crate::ipc::object::Port;
+    use crate::testing;
+
+    const A: TaskId = TaskId(0xA000);
+    const B: TaskId = TaskId(0xB000);
+    const C: TaskId = TaskId(0xC000);
+
+    fn port_cap(task: TaskId, rights: Rights) -> CapId {
+        cap::install(task, CapEntry { kind: CapKind::Port(Port::new()), rights, badge: 7, parent: None })
+    }
+
+    fn carrying(cap: CapId) -> Message {
+        let mut msg = Message::from_bytes(b"cap").unwrap();
+        msg.caps[0] = cap.0;
+        msg
+    }
+
+    #[test]
+    fn from_bytes_round_trips_up_to_the_limit() {
+        let bytes = [0xA5u8; MAX_INLINE_PAYLOAD];
+        let msg = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(msg.as_bytes(), &bytes[..]);
+        assert_eq!(msg.caps, [NO_CAP; MAX_MSG_CAPS]);
+        assert_eq!(Message::from_bytes(b"ping").unwrap().as_bytes(), b"ping");
+    }
+
+    #[test]
+    fn from_bytes_rejects_oversize_payload() {
+        assert!(Message::from_bytes(&[0; MAX_INLINE_PAYLOAD + 1]).is_none());
+    }
+
+    #[test]
+    fn as_bytes_clamps_a_user_length() {
+        let mut msg = Message::empty();
+        msg.payload_len = usize::MAX;
+        assert_eq!(msg.as_bytes().len(), MAX_INLINE_PAYLOAD);
+    }
+
+    #[test]
+    fn grant_less_capability_is_not_delegated() {
+        let _kernel = testing::setup();
+        let plain = port_cap(A, Rights::SEND | Rights::RECV);
+        let grantable = port_cap(A, Rights::SEND | Rights::GRANT);
+        assert_eq!(Envelope::take(Some(A), &carrying(plain), None).err(), Some(IpcError::NoPermission));
+        // Один негодный слот срывает всё сообщение / One bad slot sinks the whole message
+        let mut both = carrying(grantable);
+        both.caps[1] = plain.0;
+        assert_eq!(Envelope::take(Some(A), &both, None).err(), Some(IpcError::NoPermission));
+        // Отправитель ничего не потерял / The sender lost nothing
+        assert!(cap::lookup(A, plain).is_some());
+        assert!(cap::lookup(A, grantable).is_some());
+        cap::drop_table(A);
+    }
+
+    #[test]
+    fn delivered_copy_keeps_the_attenuated_rights() {
+        let _kernel = testing::setup();
+        let root = port_cap(A, Rights::SEND | Rights::RECV | Rights::GRANT | Rights::REVOKE);
+        let weak = cap::grant(A, root, B, Rights::SEND | Rights::GRANT).unwrap();
+
+        let env = Envelope::take(Some(B), &carrying(weak), None).unwrap();
+        let (msg, _) = env.deliver(Some(C));
+        let copy = cap::lookup(C, CapId(msg.caps[0])).unwrap();
+        // Не больше, чем было у B, и от записи B / No more than B had, and derived from B's entry
+        assert_eq!(copy.rights, Rights::SEND | Rights::GRANT);
+        assert_eq!(copy.badge, 7);
+        assert_eq!(copy.parent, Some(CapRef { task: B, cap: weak }));
+        assert!(cap::lookup(B, weak).is_some());
+        for task in [A, B, C] { cap::drop_table(task); }
+    }
+
+    #[test]
+    fn revoke_reaches_a_copy_still_in_the_queue() {
+        let _kernel = testing::setup();
+        let root = port_cap(A, Rights::SEND | Rights::GRANT | Rights::REVOKE);
+        let granted = cap::grant(A, root, B, Rights::SEND | Rights::GRANT).unwrap();
+        // B шлёт копию C, и пока она в очереди, A отзывает всю цепочку
+        // B sends a copy to C, and while it is queued A revokes the whole chain
+        let env = Envelope::take(Some(B), &carrying(granted), None).unwrap();
+        assert_eq!(cap::revoke(A, root), Ok(2));
+
+        let (msg, _) = env.deliver(Some(C));
+        assert_eq!(msg.caps, [NO_CAP; MAX_MSG_CAPS]);
+        assert!(cap::lookup(C, CapId(0)).is_none());
+
+        // Слот B занят другим объектом — копия всё равно не проходит
+        // B's slot is taken by another object — the copy still doesn't get through
+        let root = port_cap(A, Rights::SEND | Rights::GRANT | Rights::REVOKE);
+        let granted = cap::grant(A, root, B, Rights::SEND | Rights::GRANT).unwrap();
+        let env = Envelope::take(Some(B), &carrying(granted), None).unwrap();
+        cap::remove(B, granted);
+        assert_eq!(port_cap(B, Rights::SEND), granted);
+        assert_eq!(env.deliver(Some(C)).0.caps, [NO_CAP; MAX_MSG_CAPS]);
+        for task in [A, B, C] { cap::drop_table(task); }
+    }
+}
@@ -4,22 +4,242 @@
 //!   Port       — очередь сообщений / message queue
 //!   Capability — unforgeable токен доступа / unforgeable access token
 //!   Message    — сообщение (inline + capability transfer) / message
+//!   EventQueue — готовность многих портов / readiness of many ports
+//!   Notification — слово сигналов для IRQ / signal word for IRQs
 
-// TODO: Этап 6 — реализация IPC
-// TODO: Phase 6 — IPC implementation
+pub mod call;
+pub mod cap;
+pub mod event;
+pub mod message;
+pub mod notify;
+pub mod object;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::Mutex;
+pub use message::Message;
+use message::Envelope;
+use object::Port;
+pub use call::{call, call_buf, reply};
+pub use notify::{notify, wait_notify};
+
+/// Предел inline payload — общий с libcuprum через cupruxos-abi.
+/// Inline payload limit — shared with libcuprum via cupruxos-abi.
+pub use cupruxos_abi::MAX_INLINE_PAYLOAD;
 
 /// Идентификатор порта / Port identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PortId(pub u64);
 
 /// Идентификатор capability / Capability identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CapId(pub u64);
 
 /// Идентификатор задачи / Task identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(pub u64);
 
+/// Почему операция с портом не прошла / Why a port operation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    NoSuchPort,
+    /// Очередь порта полна — повторить позже / The port queue is full — retry later
+    QueueFull,
+    /// Сообщений (или ответа) нет; если был вызывающий, он запаркован.
+    /// No messages (or reply) yet; if there was a caller, it is parked.
+    WouldBlock,
+    /// `reply` без принятого вызова / `reply` with no call received
+    NoCaller,
+    /// Вызов не получит ответа / The call will get no reply
+    CallFailed,
+    /// Слот `caps` сообщения — не capability отправителя (или повтор)
+    /// A message `caps` slot isn't a capability of the sender's (or a repeat)
+    InvalidCap,
+    /// У capability нет нужного права / The capability lacks the needed right
+    NoPermission,
+    /// Дедлайн `recv_timeout` прошёл раньше сообщения
+    /// `recv_timeout`'s deadline passed before a message came
+    TimedOut,
+}
+
+/// Порты по id — от `create_port` до `destroy_port`. Capability и
+/// идущие операции держат свои ссылки поверх этой.
+/// Ports by id — from `create_port` until `destroy_port`. Capabilities and
+/// operations in flight hold their own references on top of this one.
+static PORTS: Mutex<BTreeMap<PortId, Arc<Port>>> = Mutex::new(BTreeMap::new());
+
+/// Создать порт / Create a port
+pub fn create_port() -> PortId {
+    let port = Port::new();
+    let id = port.id;
+    PORTS.lock().insert(id, port);
+    id
+}
+
+/// Убрать порт из реестра; запаркованные на нём просыпаются.
+/// Drop a port from the registry; whoever is parked on it wakes up.
+pub fn destroy_port(id: PortId) -> bool {
+    let Some(port) = PORTS.lock().remove(&id) else { return false };
+    for caller in port.close() { call::fail(caller); }
+    true
+}
+
+/// Порт по id / A port by id
+pub fn port(id: PortId) -> Option<Arc<Port>> {
+    PORTS.lock().get(&id).cloned()
+}
+
+/// Поставить сообщение в очередь порта и разбудить одного получателя.
+/// Capability из слотов уходят копиями — у текущей задачи они остаются.
+/// Queue a message on a port and wake one receiver. The capabilities in
+/// the slots go as copies — the current task keeps its own.
+pub fn send(id: PortId, msg: &Message) -> Result<(), IpcError> {
+    let port = port(id).ok_or(IpcError::NoSuchPort)?;
+    let env = Envelope::take(crate::sched::current_id(), msg, None)?;
+    port.send(env).map_err(|_| IpcError::QueueFull)
+}
+
+/// Забрать старейшее сообщение. Очередь пуста — текущая задача паркуется
+/// (BlockedOnIpc) до следующего `send`, а syscall повторяет `recv` после
+/// пробуждения.
+/// Take the oldest message. With the queue empty the current task is
+/// parked (BlockedOnIpc) until the next `send`, and the syscall retries
+/// `recv` once it wakes.
+///
+/// Сообщение `call` делает получателя должником ответа (`reply`).
+/// A `call` message makes the receiver owe a reply (`reply`).
+pub fn recv(id: PortId) -> Result<Message, IpcError> {
+    let port = port(id).ok_or(IpcError::NoSuchPort)?;
+    let receiver = crate::sched::current_id();
+    let env = port.recv_or_park(receiver).ok_or(IpcError::WouldBlock)?;
+    Ok(accept(env, receiver))
+}
+
+/// `recv`, но не дольше `deadline_ns` (часы `time::now`). Запаркованную
+/// задачу будит либо `send`, либо тот же таймер, что и `sched::sleep_until`;
+/// повтор после дедлайна забирает сообщение, если оно всё-таки пришло, а
+/// иначе уходит из ожидающих порта — следующее сообщение достанется
+/// другому получателю.
+/// `recv`, but no longer than `deadline_ns` (the `time::now` clock). A
+/// parked task is woken either by `send` or by the same timer as
+/// `sched::sleep_until`; the retry after the deadline takes the message if
+/// one did arrive, and otherwise leaves the port's waiters — the next
+/// message goes to another receiver.
+pub fn recv_timeout(id: PortId, deadline_ns: u64) -> Result<Message, IpcError> {
+    let port = port(id).ok_or(IpcError::NoSuchPort)?;
+    let receiver = crate::sched::current_id();
+    if crate::time::now() < deadline_ns {
+        let Some(env) = port.recv_or_park(receiver) else {
+            if let Some(task) = receiver { crate::sched::ipc_deadline(task, deadline_ns); }
+            return Err(IpcError::WouldBlock);
+        };
+        if let Some(task) = receiver { crate::sched::cancel_deadline(task, deadline_ns); }
+        return Ok(accept(env, receiver));
+    }
+    // Дедлайн прошёл: сообщение, пришедшее одновременно с ним, — наше
+    // The deadline passed: a message that arrived along with it is ours
+    let env = port.try_recv();
+    if let Some(task) = receiver {
+        port.cancel_wait(task);
+        crate::sched::cancel_deadline(task, deadline_ns);
+        crate::sched::unpark(task);
+    }
+    env.map(|env| accept(env, receiver)).ok_or(IpcError::TimedOut)
+}
+
+/// Отдать принятое получателю: capability — в его таблицу, `call` —
+/// в долг ответа.
+/// Hand a received message to the receiver: capabilities into its table,
+/// a `call` into a reply owed.
+fn accept(env: Envelope, receiver: Option<TaskId>) -> Message {
+    if let Some(task) = receiver { crate::sched::unpark(task); }
+    let (msg, caller) = env.deliver(receiver);
+    match (receiver, caller) {
+        (Some(server), Some(caller)) => call::accepted(server, caller),
+        // Ответить некому — вызывающий не дождётся / Nobody to reply — the caller won't get one
+        (None, Some(caller))         => call::fail(caller),
+        _                            => {}
+    }
+    msg
+}
+
 pub fn init() {
-    // stub
+    // Порты, очереди и таблицы capability создаются по требованию — поднимать нечего
+    // Ports, queues and capability tables are created on demand — nothing to bring up
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, CLOCK};
+    use crate::time;
+
+    const TIMEOUT: u64 = 1_000_000;
+
+    fn expired() -> alloc::vec::Vec<TaskId> {
+        let mut woken = alloc::vec::Vec::new();
+        crate::sched::wake_expired(time::now(), |id| woken.push(id));
+        woken
+    }
+
+    #[test]
+    fn message_before_the_timeout_cancels_the_deadline() {
+        let _kernel = testing::setup();
+        let task = testing::user_task();
+        let id = create_port();
+        let deadline = time::now() + TIMEOUT;
+        assert_eq!(recv_timeout(id, deadline).err(), Some(IpcError::WouldBlock));
+
+        send(id, &Message::from_bytes(b"ping").unwrap()).unwrap();
+        assert_eq!(recv_timeout(id, deadline).unwrap().as_bytes(), b"ping");
+        // Дедлайн снят — таймер задачу больше не тронет
+        // The deadline is gone — the timer won't touch the task again
+        CLOCK.advance(TIMEOUT);
+        assert!(expired().is_empty());
+
+        destroy_port(id);
+        testing::end_task(task);
+    }
+
+    #[test]
+    fn timeout_before_the_message_leaves_the_port() {
+        let _kernel = testing::setup();
+        let task = testing::user_task();
+        let id = create_port();
+        let deadline = time::now() + TIMEOUT;
+        assert_eq!(recv_timeout(id, deadline).err(), Some(IpcError::WouldBlock));
+
+        CLOCK.advance(TIMEOUT);
+        assert_eq!(expired(), [task]);
+        assert_eq!(recv_timeout(id, deadline).err(), Some(IpcError::TimedOut));
+        // Опоздавшее сообщение ждёт следующего получателя в очереди
+        // The late message waits in the queue for the next receiver
+        send(id, &Message::from_bytes(b"late").unwrap()).unwrap();
+        assert!(!port(id).unwrap().cancel_wait(task));
+        assert_eq!(port(id).unwrap().try_recv().unwrap().msg.as_bytes(), b"late");
+
+        destroy_port(id);
+        testing::end_task(task);
+    }
+
+    #[test]
+    fn message_arriving_with_the_deadline_is_delivered() {
+        let _kernel = testing::setup();
+        let task = testing::user_task();
+        let id = create_port();
+        let deadline = time::now() + TIMEOUT;
+        assert_eq!(recv_timeout(id, deadline).err(), Some(IpcError::WouldBlock));
+
+        // Тот же тик: таймер будит задачу, и тут же приходит сообщение
+        // The same tick: the timer wakes the task and a message comes right away
+        CLOCK.advance(TIMEOUT);
+        assert_eq!(expired(), [task]);
+        send(id, &Message::from_bytes(b"tie").unwrap()).unwrap();
+        assert_eq!(recv_timeout(id, deadline).unwrap().as_bytes(), b"tie");
+        assert!(!port(id).unwrap().cancel_wait(task));
+        assert!(expired().is_empty());
+
+        destroy_port(id);
+        testing::end_task(task);
+    }
 }
@@ -0,0 +1,134 @@
+//! Notification — слово сигналов вместо сообщения
+//! Notification — a signal word instead of a message
+//!
+//! Для сигналов «у устройства X есть данные» 512-байтное сообщение
+//! избыточно. `signal` ORит биты в слово и будит ждущих; `wait` забирает
+//! накопленные биты и обнуляет слово. Сколько бы сигналов ни пришло до
+//! `wait`, ждущий проснётся один раз и увидит их объединение. `signal` не
+//! выделяет память и не ждёт — его зовёт обработчик IRQ драйвера.
+//! For "device X has data" signals a 512-byte message is overkill.
+//! `signal` ORs bits into the word and wakes the waiters; `wait` takes the
+//! accumulated bits and clears the word. However many signals arrive before
+//! `wait`, the waiter wakes once and sees their union. `signal` neither
+//! allocates nor waits — a driver's IRQ handler calls it.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::IrqMutex;
+use super::cap::{self, CapKind, Rights};
+use super::{CapId, IpcError, TaskId};
+
+static NEXT_NOTIFICATION_ID: AtomicU64 = AtomicU64::new(1);
+
+pub struct Notification {
+    pub id:  u64,
+    word:    AtomicU64,
+    /// Запаркованные в `wait`; IrqMutex — `signal` приходит из IRQ
+    /// Parked in `wait`; an IrqMutex — `signal` comes from IRQs
+    waiters: IrqMutex<Vec<TaskId>>,
+}
+
+impl Notification {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            id:      NEXT_NOTIFICATION_ID.fetch_add(1, Ordering::Relaxed),
+            word:    AtomicU64::new(0),
+            waiters: IrqMutex::new(Vec::new()),
+        })
+    }
+
+    /// Добавить `bits` в слово и разбудить всех ждущих.
+    /// Add `bits` to the word and wake every waiter.
+    pub fn signal(&self, bits: u64) {
+        if bits == 0 { return; }
+        self.word.fetch_or(bits, Ordering::AcqRel);
+        // После OR: ждущий либо уже увидел биты, либо стоит в списке.
+        // `drain` — без освобождения памяти, в IRQ его не нужно.
+        // After the OR: a waiter has either seen the bits or is listed.
+        // `drain` — no freeing memory, not needed in an IRQ.
+        for task in self.waiters.lock().drain(..) { crate::sched::wake_ipc(task); }
+    }
+
+    /// Забрать накопленные биты, обнулив слово. Их нет — запарковать
+    /// `waiter` до `signal` и вернуть `None`.
+    /// Take the accumulated bits, clearing the word. With none, park
+    /// `waiter` until `signal` and return `None`.
+    pub fn wait(&self, waiter: Option<TaskId>) -> Option<u64> {
+        let mut waiters = self.waiters.lock();
+        let bits = self.word.swap(0, Ordering::AcqRel);
+        if bits != 0 { return Some(bits); }
+        // Парковка под lock'ом — `signal` увидит нас в списке
+        // Parking under the lock — `signal` will see us listed
+        if let Some(task) = waiter {
+            if crate::sched::block_on_ipc(task) { waiters.push(task); }
+        }
+        None
+    }
+}
+
+/// Notification за capability текущей задачи с правом `need`.
+/// The notification behind the current task's capability, with the `need` right.
+fn lookup(cap: CapId, need: Rights) -> Result<Arc<Notification>, IpcError> {
+    let task = crate::sched::current_id().ok_or(IpcError::InvalidCap)?;
+    let entry = cap::lookup(task, cap).ok_or(IpcError::InvalidCap)?;
+    let CapKind::Notification(n) = entry.kind else { return Err(IpcError::InvalidCap) };
+    if !entry.rights.contains(need) { return Err(IpcError::NoPermission); }
+    Ok(n)
+}
+
+/// OR `bits` в слово notification за `cap` и разбудить ждущих.
+/// OR `bits` into the word of the notification behind `cap` and wake the waiters.
+pub fn notify(cap: CapId, bits: u64) -> Result<(), IpcError> {
+    lookup(cap, Rights::SEND)?.signal(bits);
+    Ok(())
+}
+
+/// Дождаться ненулевого слова и забрать его. Слово пусто — текущая задача
+/// паркуется, а syscall повторяет `wait_notify` после пробуждения.
+/// Wait for a nonzero word and take it. With the word empty the current
+/// task parks, and the syscall retries `wait_notify` once it wakes.
+pub fn wait_notify(cap: CapId) -> Result<u64, IpcError> {
+    let notification = lookup(cap, Rights::RECV)?;
+    let task = crate::sched::current_id();
+    let bits = notification.wait(task).ok_or(IpcError::WouldBlock)?;
+    if let Some(task) = task { crate::sched::unpark(task); }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::cap::CapEntry;
+    use crate::testing;
+
+    #[test]
+    fn signals_before_the_wakeup_coalesce_into_one() {
+        let _kernel = testing::setup();
+        let task = testing::user_task();
+        let notification = Notification::new();
+        assert_eq!(notification.wait(Some(task)), None);
+        assert_eq!(notification.waiters.lock().len(), 1);
+
+        // Первый сигнал будит и снимает ждущего, второй лишь добавляет биты
+        // The first signal wakes and unlists the waiter, the second only adds bits
+        notification.signal(0b001);
+        assert!(notification.waiters.lock().is_empty());
+        notification.signal(0b100);
+        assert_eq!(notification.wait(Some(task)), Some(0b101));
+        testing::end_task(task);
+    }
+
+    #[test]
+    fn reading_clears_the_word() {
+        let _kernel = testing::setup();
+        let task = testing::user_task();
+        let kind = CapKind::Notification(Notification::new());
+        let cap = cap::install(task, CapEntry { kind, rights: Rights::all(), badge: 0, parent: None });
+        notify(cap, 0x10).unwrap();
+        notify(cap, 0x10).unwrap();
+        assert_eq!(wait_notify(cap), Ok(0x10));
+        assert_eq!(wait_notify(cap), Err(IpcError::WouldBlock));
+        testing::end_task(task);
+    }
+}
@@ -0,0 +1,214 @@
+//! Объекты ядра, на которые ссылаются capability
+//! Kernel objects referenced by capabilities
+//!
+//! Capability держит `Arc` на объект: счётчик атомарный, поэтому его
+//! можно трогать и из обработчика прерывания. Объект освобождается ровно
+//! тогда, когда исчезает последняя ссылка — последняя capability или
+//! временная ссылка операции, которая ещё идёт (например, `send`, который
+//! успел взять порт до отзыва).
+//! A capability holds an `Arc` to its object: the count is atomic, so it is
+//! safe to touch from interrupt context. The object is freed exactly when
+//! the last reference goes away — the last capability, or a temporary
+//! reference held by an operation still in flight (e.g. a `send` that
+//! grabbed the port before it was revoked).
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use super::event::{EventQueue, Trigger};
+use super::message::{Envelope, Message};
+use super::{PortId, TaskId};
+
+static NEXT_PORT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Сообщений в очереди порта, дальше отправка отказывает.
+/// Messages a port queues before sends are refused.
+pub const PORT_QUEUE_LEN: usize = 16;
+
+/// Сообщения и те, кто их ждёт, — под одним lock'ом: `send` не может
+/// проскочить между пустой проверкой `recv` и парковкой получателя.
+/// Messages and whoever waits for them — under one lock: a `send` can't
+/// slip in between `recv`'s empty check and the receiver being parked.
+#[derive(Default)]
+struct PortQueue {
+    messages: VecDeque<Envelope>,
+    /// Запаркованные в `recv`, в порядке прихода / Parked in `recv`, in arrival order
+    waiters:  VecDeque<TaskId>,
+}
+
+/// Порт / Port
+pub struct Port {
+    pub id: PortId,
+    queue: Mutex<PortQueue>,
+    /// Очереди событий, куда подключён порт / Event queues the port is attached to
+    watchers: Mutex<Vec<Weak<EventQueue>>>,
+}
+
+impl Port {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            id:       PortId(NEXT_PORT_ID.fetch_add(1, Ordering::Relaxed)),
+            queue:    Mutex::new(PortQueue::default()),
+            watchers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Поставить сообщение в очередь (у `call` — с пометкой, кто ждёт
+    /// ответа). Очередь полна — конверт возвращается нетронутым.
+    /// Queue a message (for a `call` — tagged with who awaits the reply).
+    /// The queue is full — the envelope comes back untouched.
+    pub fn send(&self, env: Envelope) -> Result<(), Box<Envelope>> {
+        let mut env = Some(env);
+        self.enqueue(core::iter::from_fn(|| env.take()));
+        env.map_or(Ok(()), |env| Err(Box::new(env)))
+    }
+
+    /// Поставить сообщения по порядку под одним lock'ом, остановившись на
+    /// первом, которое не влезло. Возвращает, сколько принято — префикс
+    /// `msgs` ровно такой длины уже в очереди. Будит по получателю на
+    /// каждое принятое сообщение.
+    /// Queue messages in order under a single lock, stopping at the first
+    /// one that doesn't fit. Returns how many were accepted — exactly that
+    /// long a prefix of `msgs` is now queued. Wakes one receiver for every
+    /// message accepted. Capability пакет не несёт / A batch carries no capabilities.
+    pub fn send_batch<'a>(&self, msgs: impl IntoIterator<Item = &'a Message>) -> usize {
+        self.enqueue(msgs.into_iter().map(|msg| Envelope::plain(msg, None)))
+    }
+
+    fn enqueue(&self, mut msgs: impl Iterator<Item = Envelope>) -> usize {
+        let mut woken = Vec::new();
+        let (accepted, was_empty) = {
+            let mut queue = self.queue.lock();
+            let was_empty = queue.messages.is_empty();
+            let mut accepted = 0;
+            // Длина — до `next()`: не влезшее сообщение остаётся у вызывающего
+            // Length before `next()`: a message that doesn't fit stays with the caller
+            while queue.messages.len() < PORT_QUEUE_LEN {
+                let Some(env) = msgs.next() else { break };
+                queue.messages.push_back(env);
+                accepted += 1;
+            }
+            let wake = accepted.min(queue.waiters.len());
+            woken.extend(queue.waiters.drain(..wake));
+            (accepted, was_empty)
+        };
+        for task in woken { crate::sched::wake_ipc(task); }
+        // Одно уведомление на весь пакет / One notification for the whole batch
+        if was_empty && accepted > 0 { self.set_ready(true); }
+        accepted
+    }
+
+    /// Забрать старейшее сообщение и того, кто ждёт на него ответа.
+    /// Take the oldest message and whoever awaits a reply to it.
+    pub fn try_recv(&self) -> Option<Envelope> {
+        self.recv_or_park(None)
+    }
+
+    /// Забрать старейшее сообщение; очередь пуста — запарковать `waiter`
+    /// до следующего `send` и вернуть `None`. Забравший сообщение из
+    /// `waiters` уходит: будить его больше не за чем.
+    /// Take the oldest message; with the queue empty, park `waiter` until
+    /// the next `send` and return `None`. Whoever takes a message leaves
+    /// `waiters`: there is nothing left to wake it for.
+    pub fn recv_or_park(&self, waiter: Option<TaskId>) -> Option<Envelope> {
+        let (msg, drained) = {
+            let mut queue = self.queue.lock();
+            let Some(msg) = queue.messages.pop_front() else {
+                // Парковка под lock'ом очереди — `send` увидит нас в `waiters`
+                // Parking under the queue lock — `send` will see us in `waiters`
+                if let Some(task) = waiter {
+                    if crate::sched::block_on_ipc(task) { queue.waiters.push_back(task); }
+                }
+                return None;
+            };
+            if let Some(task) = waiter { queue.waiters.retain(|&t| t != task); }
+            (msg, queue.messages.is_empty())
+        };
+        if drained { self.set_ready(false); }
+        Some(msg)
+    }
+
+    /// Перестать ждать (истёк дедлайн, отозвана capability): следующий
+    /// `send` разбудит другого получателя. `true` — задача ждала.
+    /// Stop waiting (the deadline passed, the capability was revoked): the
+    /// next `send` wakes another receiver. `true` — the task was waiting.
+    pub fn cancel_wait(&self, task: TaskId) -> bool {
+        let mut queue = self.queue.lock();
+        let before = queue.waiters.len();
+        queue.waiters.retain(|&t| t != task);
+        queue.waiters.len() != before
+    }
+
+    /// Порт уходит: разбудить запаркованных в `recv`, выбросить очередь и
+    /// вернуть тех, кто ждал ответа на выброшенные `call`.
+    /// The port is going away: wake whoever is parked in `recv`, drop the
+    /// queue and return whoever awaited replies to the dropped `call`s.
+    pub fn close(&self) -> Vec<TaskId> {
+        let PortQueue { messages, waiters } = core::mem::take(&mut *self.queue.lock());
+        for task in waiters { crate::sched::wake_ipc(task); }
+        // Мёртвый источник не должен висеть в очередях событий
+        // A dead source must not linger in event queues
+        for queue in self.watchers.lock().drain(..).filter_map(|w| w.upgrade()) { queue.detach(self.id); }
+        messages.into_iter().filter_map(|env| env.caller).collect()
+    }
+
+    /// Подключить порт к очереди событий. Готовность снимается под lock'ом
+    /// `watchers`: `set_ready` пришедшего между делом сообщения ждёт нас.
+    /// Attach the port to an event queue. Readiness is sampled under the
+    /// `watchers` lock: the `set_ready` of a message arriving meanwhile waits for us.
+    pub fn watch(&self, queue: &Arc<EventQueue>, token: u64, trigger: Trigger) -> bool {
+        let mut watchers = self.watchers.lock();
+        let ready = !self.queue.lock().messages.is_empty();
+        if !queue.attach(self.id, token, trigger, ready) { return false; }
+        watchers.push(Arc::downgrade(queue));
+        true
+    }
+
+    /// Готовность порта изменилась (сообщение пришло / очередь опустела).
+    /// Умершие очереди выбрасываются по пути.
+    /// The port's readiness changed (a message arrived / the queue drained).
+    /// Dead queues are dropped along the way.
+    pub fn set_ready(&self, ready: bool) {
+        self.watchers.lock().retain(|w| match w.upgrade() {
+            Some(queue) => { queue.notify(self.id, ready); true }
+            None        => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn batch(n: usize) -> Vec<Message> {
+        (0..n).map(|i| Message::from_bytes(&[i as u8]).unwrap()).collect()
+    }
+
+    fn drain(port: &Port) -> Vec<u8> {
+        core::iter::from_fn(|| port.try_recv()).map(|env| env.msg.as_bytes()[0]).collect()
+    }
+
+    #[test]
+    fn send_batch_accepts_a_batch_that_fits() {
+        let _kernel = testing::setup();
+        let port = Port::new();
+        assert_eq!(port.send_batch(&batch(PORT_QUEUE_LEN)), PORT_QUEUE_LEN);
+        assert_eq!(drain(&port), (0..PORT_QUEUE_LEN as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn send_batch_stops_when_the_port_fills() {
+        let _kernel = testing::setup();
+        let port = Port::new();
+        assert_eq!(port.send_batch(&batch(5)), 5);
+        // Принят ровно префикс, хвост не тронут / Exactly a prefix is accepted, the tail is untouched
+        assert_eq!(port.send_batch(&batch(PORT_QUEUE_LEN)), PORT_QUEUE_LEN - 5);
+        assert_eq!(port.send_batch(&batch(1)), 0);
+        let expected: Vec<u8> = (0..5).chain(0..(PORT_QUEUE_LEN - 5) as u8).collect();
+        assert_eq!(drain(&port), expected);
+    }
+}
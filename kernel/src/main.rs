@@ -1,7 +1,11 @@
 //! CupruxOS Kernel — точка входа / entry point
 
-#![no_std]
-#![no_main]
+// Тесты — на хосте, с std: `make test` / Tests run on the host, with std: `make test`
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+// Без `kernel_main` почти всё ядро в тестах мёртвый код
+// Without `kernel_main` almost the whole kernel is dead code in tests
+#![cfg_attr(test, allow(dead_code))]
 #![feature(asm_const)]
 #![feature(naked_functions)]
 #![feature(alloc_error_handler)]
@@ -11,42 +15,67 @@
 // Connect standard alloc crate (Box, Vec, Arc, ...)
 extern crate alloc;
 
-use core::panic::PanicInfo;
-
 mod arch;
+mod boottime;
+mod cmdline;
 mod mm;
 mod sched;
 mod ipc;
+mod profile;
+mod rand;
 mod vfs;
 mod drivers;
 mod syscall;
+mod sync;
+mod time;
+#[cfg(test)]
+mod testing;
 
 /// Точка входа ядра — вызывается загрузчиком Limine.
 /// Kernel entry point — called by the Limine bootloader.
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn kernel_main() -> ! {
     // 0. UART — первым делом / first of all
     drivers::uart::init();
     kprintln!("CupruxOS booting...");
+    cmdline::init();
+    time::init();
+    rand::init();
+    profile::init();
 
     // 1. GDT + IDT
+    boottime::start(boottime::Phase::Arch);
     kprintln!("[arch] Initializing GDT + IDT...");
     arch::init();
     kprintln!("[arch] OK — interrupts enabled");
 
     // 2. Physical Memory Manager
+    boottime::start(boottime::Phase::Pmm);
     kprintln!("[mm] Initializing PMM (Buddy)...");
     mm::pmm::init();
 
     // 3. Virtual Memory Manager
+    boottime::start(boottime::Phase::Vmm);
     kprintln!("[mm] Initializing VMM...");
     mm::vmm::init();
+    // APIC — его MMIO мапится через VMM / APIC — its MMIO is mapped through the VMM
+    arch::current::apic::init().expect("APIC: out of memory for MMIO mapping");
+    let guards = arch::current::gdt::unmap_ist_guards();
+    kprintln!("[arch] {} IST guard pages unmapped", guards);
 
     // 4. Kernel Heap — после этого работают Box<T>, Vec<T>!
     //    Kernel Heap — after this Box<T>, Vec<T> work!
+    boottime::start(boottime::Phase::Heap);
     kprintln!("[mm] Initializing heap (Slab)...");
     mm::heap::init();
 
+    // Framebuffer'ы — нужны VMM и heap / need the VMM and heap
+    // Headless — консоль остаётся на serial / Headless — the console stays on serial
+    if drivers::fb::init().is_some() {
+        drivers::console::set_target(0);
+    }
+
     // Тест heap — убедиться что всё работает
     // Heap test — make sure everything works
     {
@@ -58,20 +87,30 @@ pub extern "C" fn kernel_main() -> ! {
         kprintln!("[mm] Heap test OK: vec={:?}, box={}", v, b);
     }
 
+    // tmpfs корень — до диска / tmpfs root — until a disk exists
+    vfs::init();
+
     // 5. IPC + Capability
+    boottime::start(boottime::Phase::Ipc);
     kprintln!("[ipc] Initializing IPC + Capability...");
     ipc::init();
     kprintln!("[ipc] OK");
 
     // 6. Scheduler
+    boottime::start(boottime::Phase::Sched);
     kprintln!("[sched] Initializing scheduler (MLFQ)...");
     sched::init();
     kprintln!("[sched] OK");
 
     // 7. Syscall interface
+    boottime::start(boottime::Phase::Syscall);
     kprintln!("[syscall] Installing handler...");
     syscall::init();
     kprintln!("[syscall] OK");
+    boottime::finish();
+
+    // Не дорос ли init до дна boot-стека / Did init reach the bottom of the boot stack
+    arch::current::check_boot_stack();
 
     kprintln!("");
     kprintln!("  ██████╗██╗   ██╗██████╗ ██████╗ ██╗   ██╗██╗  ██╗ ██████╗ ███████╗");
@@ -83,15 +122,47 @@ pub extern "C" fn kernel_main() -> ! {
     kprintln!("  Kernel ready. Launching init...");
     kprintln!("");
 
+    lockdown();
+
     // 8. Первый userspace процесс / First userspace process
     sched::spawn_init();
     sched::start();
 }
 
-/// Panic handler — выводим в UART и halt.
+#[cfg(not(test))]
+extern "C" {
+    static __ro_after_init_start: u8;
+    static __ro_after_init_end:   u8;
+}
+
+/// Lockdown — сделать read-only данные, которые пишутся только на init.
+/// Lockdown — make data that is only written during init read-only.
+///
+/// Такие данные (GDT, IDT, ...) помечаются
+/// `#[link_section = ".data.ro_after_init"]`: линкер собирает их на
+/// отдельные страницы, а здесь они становятся `KERNEL_RO`. С CR0.WP
+/// запись туда даже из ring 0 — #PF.
+/// Such data (GDT, IDT, ...) is marked
+/// `#[link_section = ".data.ro_after_init"]`: the linker gathers it onto
+/// its own pages, and here they become `KERNEL_RO`. With CR0.WP a write
+/// there faults even from ring 0.
+#[cfg(not(test))]
+fn lockdown() {
+    let (start, end) = (&raw const __ro_after_init_start as u64, &raw const __ro_after_init_end as u64);
+    let pages = mm::vmm::protect_kernel_range(
+        mm::vmm::VirtAddr::new(start),
+        mm::vmm::VirtAddr::new(end),
+        mm::vmm::PageFlags::KERNEL_RO,
+    );
+    kprintln!("[lockdown] {} page(s) of init-then-const data now read-only", pages);
+}
+
+/// Panic handler — выводим в UART сообщение и список задач, потом halt.
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
     kprintln!("\n[KERNEL PANIC] {}", info);
+    sched::dump_tasks();
     loop {
         core::hint::spin_loop();
     }
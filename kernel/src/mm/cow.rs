@@ -0,0 +1,40 @@
+//! Счётчики ссылок на фреймы для copy-on-write
+//! Frame reference counts for copy-on-write
+//!
+//! После `fork` один фрейм мапят несколько пространств. В таблице только
+//! такие фреймы: отсутствие записи — единственный владелец, число —
+//! сколько ещё отображений его держат. Так таблица растёт лишь на fork, а
+//! не на каждую страницу памяти.
+//! After `fork` one frame is mapped by several spaces. Only such frames are
+//! in the table: no entry — a sole owner, a number — how many more mappings
+//! hold it. That way the table grows only on fork, not for every page of
+//! memory.
+
+use alloc::collections::BTreeMap;
+use crate::sync::IrqMutex;
+use super::pmm::{self, PhysAddr};
+
+// IrqMutex — отпускают и из обработчика page fault
+// IrqMutex — frames are also released from the page-fault handler
+static EXTRA_REFS: IrqMutex<BTreeMap<PhysAddr, u32>> = IrqMutex::new(BTreeMap::new());
+
+/// Ещё одно отображение держит `frame` / One more mapping holds `frame`
+pub fn share(frame: PhysAddr) {
+    *EXTRA_REFS.lock().entry(frame).or_insert(0) += 1;
+}
+
+/// Держит ли `frame` кто-то ещё / Whether anyone else holds `frame`
+pub fn is_shared(frame: PhysAddr) -> bool {
+    EXTRA_REFS.lock().contains_key(&frame)
+}
+
+/// Отображение отпускает `frame`; последнее — возвращает его в PMM.
+/// A mapping lets go of `frame`; the last one returns it to the PMM.
+pub fn release(frame: PhysAddr) {
+    let mut refs = EXTRA_REFS.lock();
+    match refs.get_mut(&frame) {
+        Some(1)  => { refs.remove(&frame); }
+        Some(n)  => *n -= 1,
+        None     => { drop(refs); pmm::free_page(frame); }
+    }
+}
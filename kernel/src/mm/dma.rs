@@ -0,0 +1,145 @@
+//! DMA — барьеры, сброс кэша и непрерывные буферы для устройств
+//! DMA — barriers, cache flushing and contiguous buffers for devices
+//!
+//! На x86 DMA в основном когерентен, но для uncached/WC маппингов
+//! (MMIO, кольца virtio) порядок записей нужно фиксировать явно.
+//! On x86 DMA is mostly coherent, but uncached/WC mappings (MMIO,
+//! virtio rings) need write ordering pinned down explicitly.
+//!
+//! Драйверов с DMA в ядре пока нет — API ждёт первого (virtio), а до тех
+//! пор его держат только тесты.
+//! There are no DMA drivers in the kernel yet — the API awaits the first one
+//! (virtio), and until then only the tests hold on to it.
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+use core::arch::asm;
+use super::pmm::{self, PhysAddr, PAGE_SIZE};
+use super::vmm::{self, VirtAddr};
+use super::MmError;
+
+/// Размер строки кэша для CLFLUSH / Cache line size used by CLFLUSH
+pub const CACHE_LINE: u64 = 64;
+
+/// Барьер записи / Write memory barrier
+#[inline]
+pub fn wmb() {
+    unsafe { asm!("sfence", options(nostack, preserves_flags)); }
+}
+
+/// Барьер чтения / Read memory barrier
+#[inline]
+pub fn rmb() {
+    unsafe { asm!("lfence", options(nostack, preserves_flags)); }
+}
+
+/// Полный барьер / Full memory barrier
+#[inline]
+pub fn mb() {
+    unsafe { asm!("mfence", options(nostack, preserves_flags)); }
+}
+
+/// Строки кэша, покрывающие [virt, virt + len): первая и конец (не включая).
+/// Cache lines covering [virt, virt + len): the first one and the exclusive end.
+pub const fn cache_line_bounds(virt: u64, len: u64) -> (u64, u64) {
+    if len == 0 { return (virt, virt); }
+    let start = virt & !(CACHE_LINE - 1);
+    let end = virt.saturating_add(len).saturating_add(CACHE_LINE - 1) & !(CACHE_LINE - 1);
+    (start, end)
+}
+
+/// Сбросить диапазон из кэша в память (CLFLUSH по строкам).
+/// Flush a range from the cache to memory (CLFLUSH per line).
+pub fn flush_cache_range(virt: VirtAddr, len: usize) {
+    let (start, end) = cache_line_bounds(virt.as_u64(), len as u64);
+    let mut line = start;
+    while line < end {
+        unsafe { asm!("clflush [{}]", in(reg) line, options(nostack, preserves_flags)); }
+        line += CACHE_LINE;
+    }
+    // CLFLUSH упорядочен только MFENCE / CLFLUSH is only ordered by MFENCE
+    mb();
+}
+
+/// Физически непрерывный буфер: адрес для устройства и адрес для CPU.
+/// A physically contiguous buffer: an address for the device and one for the CPU.
+pub struct DmaBuffer {
+    phys:  PhysAddr,
+    pages: usize,
+}
+
+impl DmaBuffer {
+    /// Выделить минимум `size` байт (округляется до страниц), обнулённых.
+    /// Больше `MAX_ORDER` тоже можно — через `pmm::alloc_contiguous`.
+    /// Allocate at least `size` bytes (rounded up to pages), zeroed. More
+    /// than `MAX_ORDER` works too — through `pmm::alloc_contiguous`.
+    pub fn alloc(size: usize) -> Result<Self, MmError> {
+        let pages = size.div_ceil(PAGE_SIZE).max(1);
+        let phys = pmm::alloc_contiguous(pages)?;
+        let buf = Self { phys, pages };
+        unsafe { core::ptr::write_bytes(buf.virt().as_mut_ptr::<u8>(), 0, buf.len()); }
+        Ok(buf)
+    }
+
+    /// Адрес для устройства / Device-visible address
+    pub fn phys(&self) -> PhysAddr { self.phys }
+
+    /// Адрес для CPU (HHDM) / CPU address (HHDM)
+    pub fn virt(&self) -> VirtAddr { vmm::phys_to_virt(self.phys) }
+
+    pub fn len(&self) -> usize { self.pages * PAGE_SIZE }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt().as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt().as_mut_ptr(), self.len()) }
+    }
+
+    /// Сбросить весь буфер перед передачей устройству.
+    /// Flush the whole buffer before handing it to the device.
+    pub fn flush(&self) {
+        flush_cache_range(self.virt(), self.len());
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        pmm::free_contiguous(self.phys, self.pages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_line_bounds_cover_partial_lines() {
+        assert_eq!(cache_line_bounds(0x1000, 64), (0x1000, 0x1040));
+        // Начало и конец внутри строк — обе захватываются целиком
+        // Start and end inside lines — both are taken whole
+        assert_eq!(cache_line_bounds(0x1010, 0x40), (0x1000, 0x1080));
+        assert_eq!(cache_line_bounds(0x103F, 1), (0x1000, 0x1040));
+        assert_eq!(cache_line_bounds(0x1010, 0), (0x1010, 0x1010));
+    }
+
+    #[test]
+    fn dma_buffer_pairs_device_and_cpu_addresses() {
+        let _kernel = crate::testing::setup();
+        let free = pmm::free_memory();
+        {
+            let mut buf = DmaBuffer::alloc(3 * PAGE_SIZE + 1).unwrap();
+            assert_eq!(buf.len(), 4 * PAGE_SIZE);
+            assert_eq!(buf.virt(), vmm::phys_to_virt(buf.phys()));
+            assert!(buf.as_slice().iter().all(|&b| b == 0));
+
+            // Запись через CPU-адрес видна по физическому / A write through the CPU address shows at the physical one
+            buf.as_mut_slice()[PAGE_SIZE] = 0x5A;
+            let at = vmm::phys_to_virt(PhysAddr::new(buf.phys().as_u64() + PAGE_SIZE as u64));
+            assert_eq!(unsafe { *at.as_ptr::<u8>() }, 0x5A);
+            assert_eq!(pmm::free_memory(), free - buf.len() as u64);
+        }
+        assert_eq!(pmm::free_memory(), free);
+    }
+}
@@ -1,124 +1,376 @@
 //! Kernel Heap — Slab Allocator
 
+use alloc::collections::BTreeMap;
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::NonNull,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
 };
-use spin::Mutex;
-use super::pmm::{self, PAGE_SIZE};
+use crate::sync::IrqMutex;
+use super::pmm::{self, PhysAddr, PAGE_SIZE};
 use super::vmm::phys_to_virt;
+#[cfg(feature = "slab_debug")]
+use core::sync::atomic::AtomicU8;
 
 const SLAB_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
-const NUM_SLABS:  usize = SLAB_SIZES.len();
+pub const NUM_SLABS: usize = SLAB_SIZES.len();
+
+// Объект i лежит на `page + i * obj_size`: степень двойки, делящая страницу,
+// выровнена по самой себе — значит, и по любому align ≤ obj_size.
+// Object i sits at `page + i * obj_size`: a power of two dividing the page is
+// aligned to itself — and so to any align ≤ obj_size.
+const _: () = {
+    let mut i = 0;
+    while i < NUM_SLABS {
+        assert!(SLAB_SIZES[i].is_power_of_two() && PAGE_SIZE.is_multiple_of(SLAB_SIZES[i]));
+        i += 1;
+    }
+};
+
+/// Байт выдано и не возвращено (по размеру объекта slab / блока buddy).
+/// Растёт только после успешного выделения — неудача его не трогает.
+/// Bytes handed out and not yet returned (by slab object / buddy block
+/// size). Grows only after a successful allocation — a failure leaves it be.
+static HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// Order каждого выданного большого блока по его адресу. `dealloc` берёт
+/// order отсюда, а не пересчитывает из `Layout`. Узлы дерева мелкие и
+/// идут из slab'ов, так что вложенная аллокация сюда не вернётся.
+/// The order of every large block handed out, by address. `dealloc` takes
+/// the order from here rather than recomputing it from the `Layout`. Tree
+/// nodes are small and come from the slabs, so a nested allocation never
+/// comes back here.
+static LARGE_ORDERS: IrqMutex<BTreeMap<u64, usize>> = IrqMutex::new(BTreeMap::new());
+
+/// Занято в heap, байт / Heap bytes in use
+pub fn used() -> usize {
+    HEAP_USED.load(Ordering::Relaxed)
+}
 
 struct FreeNode {
     next: Option<NonNull<FreeNode>>,
 }
 
-// NonNull не Send по умолчанию — оборачиваем
-// NonNull is not Send by default — wrap it
-struct FreeList(Option<NonNull<FreeNode>>);
-unsafe impl Send for FreeList {}
+/// Свободные объекты кэша — стек Трайбера на атомарной голове.
+///
+/// Контракт публикации: узел попадает в список только после того, как его
+/// `next` записан. Публикация — CAS головы с `Release`, чтение — `Acquire`,
+/// так что потребитель, увидевший узел, видит и его `next`. Публиковать
+/// (`push`/`push_chain`) можно из нескольких потоков сразу — это путь
+/// будущих per-CPU магазинов и удалённых free; снимать (`pop`) — только
+/// одному за раз, под lock'ом кэша. С единственным потребителем снятый узел
+/// не вернётся в голову, пока `pop` читает его `next`, — ABA нет.
+/// A cache's free objects — a Treiber stack on an atomic head.
+///
+/// Publication contract: a node lands on the list only after its `next` is
+/// written. Publishing is a `Release` CAS of the head and reading is an
+/// `Acquire` load, so a consumer that sees a node also sees its `next`.
+/// Publishing (`push`/`push_chain`) may happen from several threads at once
+/// — the path of future per-CPU magazines and remote frees; taking (`pop`)
+/// is for one at a time, under the cache's lock. With a single consumer a
+/// taken node can't come back to the head while `pop` reads its `next` —
+/// there is no ABA.
+struct FreeList(AtomicPtr<FreeNode>);
+
+impl FreeList {
+    const fn new() -> Self {
+        Self(AtomicPtr::new(core::ptr::null_mut()))
+    }
+
+    fn head(&self) -> Option<NonNull<FreeNode>> {
+        NonNull::new(self.0.load(Ordering::Acquire))
+    }
+
+    /// Опубликовать один узел / Publish a single node
+    ///
+    /// # Safety
+    /// `node` — свободный объект кэша, больше нигде не используемый.
+    /// `node` is a free object of this cache, not used anywhere else.
+    unsafe fn push(&self, node: NonNull<FreeNode>) {
+        unsafe { self.push_chain(node, node) }
+    }
+
+    /// Опубликовать готовую цепочку `head..=tail`: сначала дописать хвост,
+    /// затем CAS'ом — голову.
+    /// Publish a finished chain `head..=tail`: link the tail first, then
+    /// CAS the head.
+    ///
+    /// # Safety
+    /// `head..=tail` связаны через `next`, и никто, кроме нас, их не видит.
+    /// `head..=tail` are linked via `next`, and nobody but us can see them.
+    unsafe fn push_chain(&self, head: NonNull<FreeNode>, tail: NonNull<FreeNode>) {
+        let mut old = self.0.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*tail.as_ptr()).next = NonNull::new(old); }
+            match self.0.compare_exchange_weak(old, head.as_ptr(), Ordering::Release, Ordering::Relaxed) {
+                Ok(_)    => return,
+                Err(now) => old = now,
+            }
+        }
+    }
+
+    /// Снять голову / Take the head
+    ///
+    /// # Safety
+    /// Других `pop` одновременно нет (их сериализует lock кэша).
+    /// No other `pop` runs at the same time (the cache's lock serialises them).
+    unsafe fn pop(&self) -> Option<NonNull<FreeNode>> {
+        let mut node = self.head()?;
+        loop {
+            // Acquire показал и `next`, записанный до публикации узла
+            // The Acquire load also showed the `next` written before the node was published
+            let next = unsafe { (*node.as_ptr()).next };
+            let next = next.map_or(core::ptr::null_mut(), NonNull::as_ptr);
+            match self.0.compare_exchange_weak(node.as_ptr(), next, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_)    => return Some(node),
+                Err(now) => node = NonNull::new(now)?,
+            }
+        }
+    }
+}
+
+/// Владелец каждой физической страницы (по `pmm::frame_index`):
+/// `log2(obj_size)` кэша или 0.
+/// Страницы slab'а в PMM не возвращаются, так что метка не устаревает.
+/// The owner of each physical page (by `pmm::frame_index`): the cache's
+/// `log2(obj_size)` or 0.
+/// Slab pages never go back to the PMM, so a tag never goes stale.
+#[cfg(feature = "slab_debug")]
+static PAGE_OWNER: [AtomicU8; pmm::MAX_PAGES] = [const { AtomicU8::new(0) }; pmm::MAX_PAGES];
+
+/// Сколько узлов free list проверять на повтор / How many free-list nodes to check for a repeat
+#[cfg(feature = "slab_debug")]
+const DEBUG_WALK: usize = 64;
 
 struct SlabCache {
     obj_size: usize,
     free:     FreeList,
+    /// Объектов нарезано / Objects carved out
+    total:    usize,
+    /// Объектов выдано / Objects handed out
+    used:     usize,
 }
 
 impl SlabCache {
     const fn new(obj_size: usize) -> Self {
-        Self { obj_size, free: FreeList(None) }
+        Self { obj_size, free: FreeList::new(), total: 0, used: 0 }
     }
 
+    /// Нарезать новую страницу на объекты. Цепочка связывается целиком в
+    /// приватной странице и публикуется одним CAS — ни один узел не виден
+    /// раньше своего `next`.
+    /// Carve a new page into objects. The chain is linked entirely inside
+    /// the still-private page and published by a single CAS — no node is
+    /// visible before its `next`.
     fn grow(&mut self) {
-        let phys = match pmm::alloc_page() { Some(p) => p, None => return };
+        let Ok(phys) = pmm::alloc_pages_backoff(0) else { return };
+        #[cfg(feature = "slab_debug")]
+        if let Some(owner) = pmm::frame_index(phys).and_then(|i| PAGE_OWNER.get(i)) {
+            owner.store(self.tag(), Ordering::Relaxed);
+        }
         let virt  = phys_to_virt(phys);
         let start = virt.as_u64() as usize;
         let count = PAGE_SIZE / self.obj_size;
+        let node  = |i: usize| (start + i * self.obj_size) as *mut FreeNode;
 
-        for i in (0..count).rev() {
-            let ptr = (start + i * self.obj_size) as *mut FreeNode;
-            unsafe {
-                (*ptr).next = self.free.0;
-                self.free.0 = NonNull::new(ptr);
-            }
+        for i in 0..count - 1 {
+            unsafe { (*node(i)).next = NonNull::new(node(i + 1)); }
         }
+        let (Some(head), Some(tail)) = (NonNull::new(node(0)), NonNull::new(node(count - 1))) else { return };
+        unsafe { self.free.push_chain(head, tail); }
+        self.total += count;
     }
 
     fn alloc(&mut self) -> Option<*mut u8> {
-        if self.free.0.is_none() { self.grow(); }
-        let node = self.free.0?;
-        unsafe { self.free.0 = (*node.as_ptr()).next; }
+        if self.free.head().is_none() { self.grow(); }
+        let node = unsafe { self.free.pop() }?;
+        self.used += 1;
         Some(node.as_ptr() as *mut u8)
     }
 
     fn free(&mut self, ptr: *mut u8) {
-        let node = ptr as *mut FreeNode;
-        unsafe {
-            (*node).next = self.free.0;
-            self.free.0  = NonNull::new(node);
+        let Some(node) = NonNull::new(ptr as *mut FreeNode) else { return };
+        #[cfg(feature = "slab_debug")]
+        self.check_free(node);
+        self.used = self.used.checked_sub(1)
+            .unwrap_or_else(|| panic!("slab-{}: free of {:p} with nothing allocated", self.obj_size, ptr));
+        unsafe { self.free.push(node); }
+    }
+
+    #[cfg(feature = "slab_debug")]
+    fn tag(&self) -> u8 {
+        self.obj_size.trailing_zeros() as u8
+    }
+
+    /// Указатель — начало объекта на нашей странице и его нет в начале
+    /// free list. Двойное освобождение глубже `DEBUG_WALK` не ловится.
+    /// The pointer is the start of an object on one of our pages and is not
+    /// in the head of the free list. A double free deeper than `DEBUG_WALK`
+    /// goes unnoticed.
+    #[cfg(feature = "slab_debug")]
+    fn check_free(&self, node: NonNull<FreeNode>) {
+        let addr = node.as_ptr() as u64;
+        let frame = pmm::frame_index(super::vmm::virt_to_phys(super::vmm::VirtAddr::new(addr)));
+        let owner = frame.and_then(|i| PAGE_OWNER.get(i)).map_or(0, |o| o.load(Ordering::Relaxed));
+        if owner != self.tag() || !(addr as usize).is_multiple_of(self.obj_size) {
+            panic!("slab-{}: free of {:#x}, not an object of this cache (page owner tag {})",
+                   self.obj_size, addr, owner);
+        }
+        let mut cursor = self.free.head();
+        for _ in 0..DEBUG_WALK {
+            let Some(free) = cursor else { break };
+            if free == node {
+                panic!("slab-{}: double free of {:#x}", self.obj_size, addr);
+            }
+            cursor = unsafe { (*free.as_ptr()).next };
         }
     }
 }
 
 pub struct KernelHeap {
-    slabs: [Mutex<SlabCache>; NUM_SLABS],
+    // IrqMutex: таймер тоже выделяет и освобождает (узлы очередей и спящих)
+    // IrqMutex: the timer allocates and frees too (queue and sleeper nodes)
+    slabs: [IrqMutex<SlabCache>; NUM_SLABS],
 }
 
 impl KernelHeap {
     const fn new() -> Self {
         Self {
             slabs: [
-                Mutex::new(SlabCache::new(SLAB_SIZES[0])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[1])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[2])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[3])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[4])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[5])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[6])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[7])),
-                Mutex::new(SlabCache::new(SLAB_SIZES[8])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[0])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[1])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[2])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[3])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[4])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[5])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[6])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[7])),
+                IrqMutex::new(SlabCache::new(SLAB_SIZES[8])),
             ],
         }
     }
 
-    fn slab_index(size: usize) -> Option<usize> {
-        SLAB_SIZES.iter().position(|&s| s >= size)
+    /// Наименьший кэш, чьи объекты вмещают `size` и выровнены по `align`.
+    /// The smallest cache whose objects hold `size` and are aligned to `align`.
+    fn slab_index(size: usize, align: usize) -> Option<usize> {
+        SLAB_SIZES.iter().position(|&s| s >= size && s % align == 0)
     }
 }
 
+/// Снимок кэшей: `(размер объекта, выдано, нарезано)` на каждый. Каждый
+/// lock берётся только на чтение двух чисел — кэши между собой не
+/// согласованы, но и аллокации надолго не стоят.
+/// A snapshot of the caches: `(object size, used, total)` for each. Each
+/// lock is held only to read two numbers — the caches aren't consistent
+/// with each other, but allocations aren't held up either.
+pub fn slab_stats() -> [(usize, usize, usize); NUM_SLABS] {
+    core::array::from_fn(|i| {
+        let slab = HEAP.slabs[i].lock();
+        (slab.obj_size, slab.used, slab.total)
+    })
+}
+
+/// Порядок buddy для большого объекта. `None` — размер не влезает даже
+/// в максимальный блок PMM (или переполнился бы при округлении).
+/// Buddy order for a large object. `None` — the size doesn't fit even the
+/// largest PMM block (or would overflow while rounding up).
+fn large_order(size: usize) -> Option<usize> {
+    let pages = size.div_ceil(PAGE_SIZE);
+    let order = pages.checked_next_power_of_two()?.trailing_zeros() as usize;
+    (order < pmm::MAX_ORDER).then_some(order)
+}
+
 unsafe impl GlobalAlloc for KernelHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size().max(layout.align());
-        match Self::slab_index(size) {
-            Some(idx) => self.slabs[idx].lock().alloc().unwrap_or(core::ptr::null_mut()),
+        let (ptr, bytes) = match Self::slab_index(size, layout.align()) {
+            Some(idx) => (self.slabs[idx].lock().alloc().unwrap_or(core::ptr::null_mut()), SLAB_SIZES[idx]),
             None => {
-                let order = ((size + PAGE_SIZE - 1) / PAGE_SIZE)
-                    .next_power_of_two().trailing_zeros() as usize;
-                match pmm::alloc_pages(order) {
-                    Some(phys) => phys_to_virt(phys).as_u64() as *mut u8,
-                    None       => core::ptr::null_mut(),
+                let Some(order) = large_order(size) else { return core::ptr::null_mut() };
+                // Блок buddy выровнен лишь по странице относительно начала памяти
+                // A buddy block is only page-aligned relative to the start of memory
+                let block = if layout.align() > PAGE_SIZE {
+                    pmm::alloc_pages_aligned(order, layout.align())
+                } else {
+                    pmm::alloc_pages_backoff(order)
+                };
+                match block {
+                    Ok(phys) => {
+                        let virt = phys_to_virt(phys).as_u64();
+                        LARGE_ORDERS.lock().insert(virt, order);
+                        (virt as *mut u8, PAGE_SIZE << order)
+                    }
+                    Err(_) => (core::ptr::null_mut(), 0),
                 }
             }
-        }
+        };
+        if !ptr.is_null() { HEAP_USED.fetch_add(bytes, Ordering::Relaxed); }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let size = layout.size().max(layout.align());
-        match Self::slab_index(size) {
-            Some(idx) => self.slabs[idx].lock().free(ptr),
+        match Self::slab_index(size, layout.align()) {
+            Some(idx) => {
+                self.slabs[idx].lock().free(ptr);
+                HEAP_USED.fetch_sub(SLAB_SIZES[idx], Ordering::Relaxed);
+            }
             None => {
                 let virt = super::vmm::VirtAddr::new(ptr as u64);
                 let phys = super::vmm::virt_to_phys(virt);
-                let order = ((size + PAGE_SIZE - 1) / PAGE_SIZE)
-                    .next_power_of_two().trailing_zeros() as usize;
+                let order = LARGE_ORDERS.lock().remove(&(ptr as u64))
+                    .unwrap_or_else(|| panic!("heap: dealloc of {:p}, not a large block we handed out", ptr));
                 pmm::free_pages(phys, order);
+                HEAP_USED.fetch_sub(PAGE_SIZE << order, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Тот же кэш slab — тот же указатель. Большой блок, которому хватает
+    /// его order, остаётся на месте, лишний хвост уходит в PMM. Копируем
+    /// только при смене класса.
+    /// The same slab cache — the same pointer. A large block whose order
+    /// still suffices stays put and its spare tail goes back to the PMM. We
+    /// copy only when the class changes.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let align = layout.align();
+        let old = layout.size().max(align);
+        let new = new_size.max(align);
+        match (Self::slab_index(old, align), Self::slab_index(new, align)) {
+            (Some(from), Some(to)) if from == to => return ptr,
+            (None, None) => {
+                if let Some(new_order) = large_order(new) {
+                    let mut orders = LARGE_ORDERS.lock();
+                    let order = *orders.get(&(ptr as u64))
+                        .unwrap_or_else(|| panic!("heap: realloc of {:p}, not a large block we handed out", ptr));
+                    if new_order <= order {
+                        let phys = super::vmm::virt_to_phys(super::vmm::VirtAddr::new(ptr as u64));
+                        let spare = (1 << order) - (1 << new_order);
+                        if spare != 0 {
+                            pmm::free_contiguous(PhysAddr::new(phys.as_u64() + (PAGE_SIZE << new_order) as u64), spare);
+                            orders.insert(ptr as u64, new_order);
+                            HEAP_USED.fetch_sub(spare * PAGE_SIZE, Ordering::Relaxed);
+                        }
+                        return ptr;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, align) };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
             }
         }
+        new_ptr
     }
 }
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static HEAP: KernelHeap = KernelHeap::new();
 
 pub fn init() {
@@ -126,7 +378,224 @@ pub fn init() {
     crate::kprintln!("[heap] Slab allocator ready ({} caches)", NUM_SLABS);
 }
 
+/// OOM: сначала — сколько heap уже держит и в каких кэшах, потом паника.
+/// OOM: first — how much the heap already holds and in which caches, then
+/// the panic.
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error(layout: Layout) -> ! {
+    crate::kprintln!("[heap] out of memory with {} KB in use", used() / 1024);
+    for (size, used, total) in slab_stats() {
+        crate::kprintln!("[heap]   slab-{:<4} {:>6} of {:>6} objects", size, used, total);
+    }
     panic!("Kernel OOM: size={} align={}", layout.size(), layout.align());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use crate::testing;
+
+    #[test]
+    fn failed_allocation_leaves_used_unchanged() {
+        let _kernel = testing::setup();
+        let before = used();
+        let too_big = Layout::from_size_align(PAGE_SIZE << pmm::MAX_ORDER, 8).unwrap();
+        assert!(unsafe { HEAP.alloc(too_big) }.is_null());
+        assert_eq!(used(), before);
+
+        // Размер допустим, но блоков такого order не осталось
+        // The size is fine, but no block of that order is left
+        let top = pmm::MAX_ORDER - 1;
+        let held: Vec<_> = core::iter::from_fn(|| pmm::alloc_pages(top).ok()).collect();
+        let largest = Layout::from_size_align(PAGE_SIZE << top, 8).unwrap();
+        assert!(unsafe { HEAP.alloc(largest) }.is_null());
+        assert_eq!(used(), before);
+        for block in held { pmm::free_pages(block, top); }
+    }
+
+    #[test]
+    fn slab_stats_count_objects_in_use() {
+        let _kernel = testing::setup();
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let idx = KernelHeap::slab_index(48, 8).unwrap();
+        let (size, before, _) = slab_stats()[idx];
+        assert_eq!(size, 64);
+
+        let objects: Vec<_> = (0..3).map(|_| unsafe { HEAP.alloc(layout) }).collect();
+        let (_, during, total) = slab_stats()[idx];
+        assert_eq!(during, before + 3);
+        assert!(total >= during);
+        for ptr in objects { unsafe { HEAP.dealloc(ptr, layout) }; }
+        assert_eq!(slab_stats()[idx].1, before);
+    }
+
+    #[test]
+    fn sizes_near_the_ceiling_fail_instead_of_wrapping() {
+        assert_eq!(large_order(usize::MAX), None);
+        assert_eq!(large_order(usize::MAX - PAGE_SIZE), None);
+        assert_eq!(large_order(PAGE_SIZE << (pmm::MAX_ORDER - 1)), Some(pmm::MAX_ORDER - 1));
+
+        let _kernel = testing::setup();
+        let before = used();
+        for (size, align) in [(isize::MAX as usize, 1), (isize::MAX as usize - 2 * PAGE_SIZE + 1, PAGE_SIZE)] {
+            let huge = Layout::from_size_align(size, align).unwrap();
+            assert!(unsafe { HEAP.alloc(huge) }.is_null());
+        }
+        // Неудачный рост оставляет старый блок целым / A failed grow leaves the old block intact
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = unsafe { HEAP.alloc(layout) };
+        unsafe { ptr.write(0xA5) };
+        assert!(unsafe { HEAP.realloc(ptr, layout, isize::MAX as usize - 7) }.is_null());
+        assert_eq!(unsafe { ptr.read() }, 0xA5);
+        unsafe { HEAP.dealloc(ptr, layout) };
+        assert_eq!(used(), before);
+    }
+
+    #[test]
+    fn over_aligned_requests_come_back_aligned() {
+        let _kernel = testing::setup();
+        for (size, align) in [(8, 64), (16, 128), (24, 2048), (100, 4 * PAGE_SIZE), (PAGE_SIZE + 1, 8 * PAGE_SIZE)] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            // Соседи в одном кэше — оба выровнены / Neighbours in one cache — both aligned
+            let ptrs = [(); 2].map(|_| unsafe { HEAP.alloc(layout) });
+            for ptr in ptrs {
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0, "size {size}, align {align}: {ptr:p}");
+            }
+            for ptr in ptrs { unsafe { HEAP.dealloc(ptr, layout) }; }
+        }
+    }
+
+    #[test]
+    fn a_five_page_buffer_frees_exactly_its_eight_pages() {
+        let _kernel = testing::setup();
+        let free = pmm::free_memory();
+        let layout = Layout::from_size_align(5 * PAGE_SIZE, 8).unwrap();
+        let ptr = unsafe { HEAP.alloc(layout) };
+        assert_eq!(LARGE_ORDERS.lock().get(&(ptr as u64)), Some(&3));
+        assert_eq!(pmm::free_memory(), free - 8 * PAGE_SIZE as u64);
+
+        // Order берётся из таблицы, а не из размера в `Layout`
+        // The order comes from the table, not from the `Layout` size
+        unsafe { HEAP.dealloc(ptr, Layout::from_size_align(3 * PAGE_SIZE, 8).unwrap()) };
+        assert_eq!(pmm::free_memory(), free);
+        assert!(!LARGE_ORDERS.lock().contains_key(&(ptr as u64)));
+    }
+
+    #[test]
+    fn realloc_stays_in_place_within_a_class_and_moves_across() {
+        let _kernel = testing::setup();
+        let small = Layout::from_size_align(40, 8).unwrap();
+        let ptr = unsafe { HEAP.alloc(small) };
+        unsafe { core::ptr::write_bytes(ptr, 0x5A, 40) };
+        assert_eq!(unsafe { HEAP.realloc(ptr, small, 60) }, ptr);
+
+        let grown = unsafe { HEAP.realloc(ptr, Layout::from_size_align(60, 8).unwrap(), 200) };
+        assert_ne!(grown, ptr);
+        assert!(unsafe { core::slice::from_raw_parts(grown, 40) }.iter().all(|&b| b == 0x5A));
+        unsafe { HEAP.dealloc(grown, Layout::from_size_align(200, 8).unwrap()) };
+    }
+
+    #[test]
+    fn realloc_shrinks_a_large_block_in_place() {
+        let _kernel = testing::setup();
+        let free = pmm::free_memory();
+        let layout = Layout::from_size_align(8 * PAGE_SIZE, 8).unwrap();
+        let ptr = unsafe { HEAP.alloc(layout) };
+        assert_eq!(unsafe { HEAP.realloc(ptr, layout, 2 * PAGE_SIZE) }, ptr);
+        assert_eq!(pmm::free_memory(), free - 2 * PAGE_SIZE as u64);
+        assert_eq!(LARGE_ORDERS.lock().get(&(ptr as u64)), Some(&1));
+        unsafe { HEAP.dealloc(ptr, Layout::from_size_align(2 * PAGE_SIZE, 8).unwrap()) };
+        assert_eq!(pmm::free_memory(), free);
+    }
+
+    #[test]
+    fn freed_objects_are_reused_and_counted() {
+        let _kernel = testing::setup();
+        let mut cache = SlabCache::new(64);
+        let [a, b] = [(); 2].map(|_| cache.alloc().unwrap());
+        assert_eq!((cache.used, cache.total), (2, PAGE_SIZE / 64));
+        cache.free(a);
+        assert_eq!(cache.alloc(), Some(a));
+        cache.free(b);
+        cache.free(a);
+        assert_eq!(cache.used, 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "slab_debug"))]
+    #[should_panic(expected = "with nothing allocated")]
+    fn used_never_goes_below_zero() {
+        let _kernel = testing::setup();
+        let mut cache = SlabCache::new(64);
+        let ptr = cache.alloc().unwrap();
+        cache.free(ptr);
+        cache.free(ptr);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "double free")]
+    fn a_double_free_is_caught() {
+        let _kernel = testing::setup();
+        let mut cache = SlabCache::new(64);
+        let [a, b] = [(); 2].map(|_| cache.alloc().unwrap());
+        cache.free(a);
+        cache.free(b);
+        cache.free(a);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "not an object of this cache")]
+    fn a_foreign_pointer_is_caught() {
+        let _kernel = testing::setup();
+        let mut other = SlabCache::new(128);
+        let mut cache = SlabCache::new(64);
+        let ptr = other.alloc().unwrap();
+        cache.alloc().unwrap();
+        cache.free(ptr);
+    }
+
+    #[test]
+    fn a_consumer_never_sees_a_node_before_its_link() {
+        const PRODUCERS: usize = 4;
+        const CHAINS: usize = 500;
+        const CHAIN: usize = 4;
+        const NODES: usize = PRODUCERS * CHAINS * CHAIN;
+        // Яд в `next`, пока производитель не связал узел
+        // Poison in `next` until a producer has linked the node
+        let poison = NonNull::<FreeNode>::dangling();
+        let mut nodes: Vec<_> = (0..NODES).map(|_| FreeNode { next: Some(poison) }).collect();
+        let base = nodes.as_mut_ptr() as usize;
+        let node = move |i: usize| NonNull::new((base + i * core::mem::size_of::<FreeNode>()) as *mut FreeNode).unwrap();
+        let list = FreeList::new();
+
+        std::thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let list = &list;
+                s.spawn(move || for c in 0..CHAINS {
+                    let first = (p * CHAINS + c) * CHAIN;
+                    for i in first..first + CHAIN - 1 {
+                        unsafe { (*node(i).as_ptr()).next = Some(node(i + 1)); }
+                    }
+                    unsafe { list.push_chain(node(first), node(first + CHAIN - 1)); }
+                });
+            }
+
+            let mut seen = alloc::vec![false; NODES];
+            let mut taken = 0;
+            while taken < NODES {
+                let Some(got) = (unsafe { list.pop() }) else { core::hint::spin_loop(); continue };
+                assert_ne!(unsafe { (*got.as_ptr()).next }, Some(poison), "a node was visible before its link");
+                let i = (got.as_ptr() as usize - base) / core::mem::size_of::<FreeNode>();
+                assert!(!core::mem::replace(&mut seen[i], true), "node {i} taken twice");
+                taken += 1;
+            }
+        });
+        assert!(list.head().is_none());
+        drop(nodes);
+    }
+}
@@ -4,10 +4,45 @@
 //!   pmm  — Physical Memory Manager (Buddy Allocator)
 //!   vmm  — Virtual Memory Manager (Page Tables + VMA)
 //!   heap — Kernel Heap (Slab Allocator)
+//!
+//! reclaim — возврат чистых файловых страниц при нехватке памяти
+//! reclaim — dropping clean file pages under memory pressure
+//!
+//! dma — барьеры, CLFLUSH и непрерывные буферы для драйверов
+//! dma — barriers, CLFLUSH and contiguous buffers for drivers
+//!
+//! shared — объекты shared memory со счётчиком ссылок
+//! shared — reference-counted shared memory objects
+//!
+//! cow — счётчики ссылок на фреймы после fork
+//! cow — frame reference counts after fork
 
 pub mod pmm;
 pub mod vmm;
 pub mod heap;
+pub mod reclaim;
+pub mod dma;
+pub mod shared;
+pub mod cow;
+
+/// Ошибка подсистемы памяти — одна на pmm, vmm и объекты памяти.
+/// Memory-management error — one type across pmm, vmm and memory objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmError {
+    /// PMM исчерпан (в т.ч. под таблицу страниц) / PMM exhausted (page tables included)
+    OutOfMemory,
+    /// Пустой, перевёрнутый или переполняющийся диапазон / Empty, inverted or overflowing range
+    InvalidRange,
+    /// VMA пересекается с уже добавленной / The VMA overlaps one already added
+    VmaOverlap,
+    /// Страницу нельзя замаппить (вне VMA) / The page can't be mapped (outside any VMA)
+    Unmappable(vmm::VirtAddr),
+    /// Пользовательская страница и на запись, и на исполнение (W^X)
+    /// A user page both writable and executable (W^X)
+    WriteExec,
+    /// Биты 48–63 не копия бита 47 / Bits 48–63 don't copy bit 47
+    NonCanonical(vmm::VirtAddr),
+}
 
 /// Физический адрес / Physical address
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
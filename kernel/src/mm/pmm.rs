@@ -18,7 +18,9 @@
 //! тот тоже свободен (merging/coalescing).
 
 use core::sync::atomic::{AtomicU64, Ordering};
-use spin::Mutex;
+use limine::memory_map::EntryType;
+use crate::sync::IrqMutex;
+use super::MmError;
 
 // ── Константы / Constants ─────────────────────────────────────────────────────
 
@@ -26,6 +28,12 @@ pub const PAGE_SIZE:  usize = 4096;       // 4 KB
 pub const MAX_ORDER:  usize = 11;         // до / up to 4096 * 2^10 = 4MB
 pub const MAX_PAGES:  usize = 1024 * 1024; // поддерживаем до 4GB / support up to 4GB
 
+/// Ниже 1MB — BIOS, таблицы реального режима; туда не ходим, даже если
+/// Limine отдаёт это как USABLE. Заодно физический 0 никогда не выдаётся.
+/// Below 1MB — BIOS, real-mode tables; we stay out even if Limine reports it
+/// USABLE. It also means physical 0 is never handed out.
+const LOW_MEMORY_END: u64 = 0x100000;
+
 // ── Физический адрес / Physical address ──────────────────────────────────────
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -97,6 +105,12 @@ struct BuddyAllocator {
     /// Число свободных страниц / Free page count
     free_pages:  usize,
 
+    /// Свободных блоков каждого order — видно фрагментацию: один блок
+    /// order 10 и 1024 разрозненные страницы дают одинаковый `free_pages`.
+    /// Free blocks per order — shows fragmentation: one order-10 block and
+    /// 1024 scattered pages give the same `free_pages`.
+    free_counts: [usize; MAX_ORDER],
+
     /// Начало физической памяти (обычно 0x100000 / 1MB на x86)
     /// Start of physical memory (usually 0x100000 / 1MB on x86)
     mem_start:   u64,
@@ -115,21 +129,60 @@ impl BuddyAllocator {
             ],
             total_pages: 0,
             free_pages:  0,
+            free_counts: [0; MAX_ORDER],
             mem_start:   0,
         }
     }
 
+    /// Сбросить состояние на месте — структура весит ~1.4MB, на стек её
+    /// не положить.
+    /// Reset state in place — the struct is ~1.4MB and can't go on the stack.
+    fn reset(&mut self) {
+        for bitmap in self.free.iter_mut() {
+            bitmap.bits.fill(0);
+            bitmap.len = 0;
+        }
+        self.total_pages = 0;
+        self.free_pages  = 0;
+        self.free_counts = [0; MAX_ORDER];
+        self.mem_start   = 0;
+    }
+
+    /// Сбросить и заново зарегистрировать `regions`, потом слить блоки.
+    /// Reset, register `regions` afresh, then merge blocks.
+    fn init(&mut self, regions: impl Iterator<Item = (u64, u64)> + Clone) {
+        self.reset();
+        // Первый проход — самая низкая база, в каком бы порядке ни шли регионы
+        // First pass — the lowest base, whatever order the regions come in
+        self.mem_start = regions.clone()
+            .filter_map(|(start, size)| page_bounds(start, size))
+            .map(|(start, _)| start)
+            .min()
+            .unwrap_or(0);
+        for (start, size) in regions {
+            self.add_region(start, size);
+        }
+        self.merge_all();
+        debug_assert!(self.counts_consistent());
+    }
+
     /// Добавить свободный регион памяти (от Limine).
     /// Add free memory region (from Limine).
+    ///
+    /// `mem_start` уже выставлен по самому нижнему региону (`init`):
+    /// Limine не обязан сортировать карту, и регион ниже `mem_start` дал
+    /// бы отрицательный pfn.
+    /// `mem_start` is already set from the lowest region (`init`):
+    /// Limine need not sort the map, and a region below `mem_start` would
+    /// give a negative pfn.
     fn add_region(&mut self, start: u64, size: u64) {
-        // Выровнять начало вверх, конец вниз по PAGE_SIZE
-        // Align start up, end down to PAGE_SIZE
-        let start = align_up(start, PAGE_SIZE as u64);
-        let end   = align_down(start + size, PAGE_SIZE as u64);
+        let Some((start, end)) = page_bounds(start, size) else { return };
+        debug_assert!(start >= self.mem_start, "region below mem_start");
+        let start = start.max(self.mem_start);
 
-        if start >= end { return; }
-
-        if self.mem_start == 0 { self.mem_start = start; }
+        // Битмапы покрывают MAX_PAGES от mem_start — дальше отрезаем
+        // The bitmaps cover MAX_PAGES from mem_start — cut off the rest
+        let end = end.min(self.mem_start + (MAX_PAGES * PAGE_SIZE) as u64);
 
         // Добавляем страницы по одной в order 0
         // Add pages one by one at order 0
@@ -138,21 +191,24 @@ impl BuddyAllocator {
             let pfn = ((addr - self.mem_start) / PAGE_SIZE as u64) as usize;
             self.free[0].set(pfn, true);
             self.free[0].len = self.free[0].len.max(pfn + 1);
+            self.free_counts[0] += 1;
             self.free_pages  += 1;
             self.total_pages += 1;
             addr += PAGE_SIZE as u64;
         }
+        // Слияние — одно на всю карту, после всех регионов (`init`)
+        // Merging happens once for the whole map, after every region (`init`)
+    }
 
-        // Попробовать слить соседние блоки в блоки большего order
-        // Try to merge adjacent blocks into higher order blocks
-        self.merge_all();
+    /// Блоки всех order в сумме дают `free_pages` / Blocks of all orders add up to `free_pages`
+    fn counts_consistent(&self) -> bool {
+        self.free_counts.iter().enumerate().map(|(order, &n)| n << order).sum::<usize>() == self.free_pages
     }
 
     /// Слить все возможные блоки снизу вверх.
     /// Merge all possible blocks bottom-up.
     fn merge_all(&mut self) {
         for order in 0..MAX_ORDER - 1 {
-            let block_pages = 1 << order;
             let mut i = 0;
             while i + 1 < self.free[order].len {
                 if self.free[order].get(i) && self.free[order].get(i + 1)
@@ -163,12 +219,13 @@ impl BuddyAllocator {
                     self.free[order].set(i,     false);
                     self.free[order].set(i + 1, false);
                     let parent = i / 2;
+                    self.free_counts[order]     -= 2;
+                    self.free_counts[order + 1] += 1;
                     self.free[order + 1].set(parent, true);
                     self.free[order + 1].len =
                         self.free[order + 1].len.max(parent + 1);
                 }
                 i += 2;
-                let _ = block_pages; // подавить предупреждение / suppress warning
             }
         }
     }
@@ -178,6 +235,8 @@ impl BuddyAllocator {
     /// Возвращает физический адрес начала блока.
     /// Returns physical address of block start.
     fn alloc(&mut self, order: usize) -> Option<PhysAddr> {
+        crate::assert_irqs_disabled!();
+
         // Ищем свободный блок начиная с нужного order и выше
         // Search for free block starting at requested order and above
         let found_order = (order..MAX_ORDER)
@@ -186,6 +245,7 @@ impl BuddyAllocator {
         // Берём блок
         let idx = self.free[found_order].find_free().unwrap();
         self.free[found_order].set(idx, false);
+        self.free_counts[found_order] -= 1;
 
         // Разбиваем (split) до нужного order
         // Split down to requested order
@@ -201,6 +261,7 @@ impl BuddyAllocator {
             self.free[current_order].set(right, true);
             self.free[current_order].len =
                 self.free[current_order].len.max(right + 1);
+            self.free_counts[current_order] += 1;
             current_idx = left;
         }
 
@@ -210,18 +271,131 @@ impl BuddyAllocator {
         let phys = self.mem_start + (pages_offset * PAGE_SIZE) as u64;
 
         self.free_pages -= 1 << order;
+        debug_assert!(self.counts_consistent());
 
         Some(PhysAddr::new(phys))
     }
 
+    /// Выделить 2^order страниц с адресом, кратным `align`.
+    ///
+    /// Блоки выровнены относительно `mem_start`, а не нуля, поэтому берём
+    /// блок с запасом на `align`, вырезаем из него выровненный кусок, а
+    /// голову и хвост возвращаем в free lists.
+    /// Allocate 2^order pages at an address that is a multiple of `align`.
+    ///
+    /// Blocks are aligned relative to `mem_start`, not to zero, so we take a
+    /// block with room for `align`, cut the aligned piece out of it and
+    /// return the head and tail to the free lists.
+    fn alloc_aligned(&mut self, order: usize, align: u64) -> Option<PhysAddr> {
+        let want = PAGE_SIZE as u64 * (1 << order);
+        if self.mem_start.is_multiple_of(align) && align <= want {
+            return self.alloc(order);
+        }
+        // Худший случай — выровненный адрес на `align - PAGE_SIZE` от начала
+        // Worst case — the aligned address is `align - PAGE_SIZE` past the start
+        let pages = (want + align - PAGE_SIZE as u64) / PAGE_SIZE as u64;
+        let big = pages.next_power_of_two().trailing_zeros() as usize;
+        if big >= MAX_ORDER { return None; }
+
+        let block = self.alloc(big)?.as_u64();
+        let start = align_up(block, align);
+        let end   = block + PAGE_SIZE as u64 * (1 << big);
+        self.free_range(block, start);
+        self.free_range(start + want, end);
+        Some(PhysAddr::new(start))
+    }
+
+    /// Свободный блок, в который входит страница `pfn`: `(order, индекс)`.
+    /// The free block that page `pfn` belongs to: `(order, index)`.
+    fn free_block_of(&self, pfn: usize) -> Option<(usize, usize)> {
+        (0..MAX_ORDER)
+            .map(|order| (order, pfn >> order))
+            .find(|&(order, idx)| idx < self.free[order].len && self.free[order].get(idx))
+    }
+
+    /// Забрать свободные страницы из `[start, end)`; занятые и лежащие вне
+    /// управляемой памяти пропускаются. Блоки, задетые краями, режутся,
+    /// остаток возвращается — их buddy лежат в этом же, уже занятом, блоке,
+    /// поэтому слиться обратно в диапазон они не могут.
+    /// Take the free pages in `[start, end)`; taken pages and pages outside
+    /// managed memory are skipped. Blocks cut by the edges are split and the
+    /// rest returned — their buddies lie in this same, now taken, block, so
+    /// they can't merge back into the range.
+    fn take_range(&mut self, start: u64, end: u64) {
+        let start = start.max(self.mem_start);
+        if start >= end { return; }
+        let first = ((start - self.mem_start) / PAGE_SIZE as u64) as usize;
+        let last  = ((end - self.mem_start).div_ceil(PAGE_SIZE as u64) as usize).min(self.free[0].len);
+        let (start, end) = (self.mem_start + (first * PAGE_SIZE) as u64, self.mem_start + (last * PAGE_SIZE) as u64);
+
+        let mut pfn = first;
+        while pfn < last {
+            let Some((order, idx)) = self.free_block_of(pfn) else { pfn += 1; continue };
+            self.free[order].set(idx, false);
+            self.free_counts[order] -= 1;
+            self.free_pages -= 1 << order;
+            let block_start = self.mem_start + ((idx << order) * PAGE_SIZE) as u64;
+            let block_end   = block_start + (PAGE_SIZE << order) as u64;
+            self.free_range(block_start, start.max(block_start));
+            self.free_range(end.min(block_end).max(block_start), block_end);
+            pfn = (idx + 1) << order;
+        }
+        debug_assert!(self.counts_consistent());
+    }
+
+    /// Найти `pages` подряд свободных страниц — поперёк границ buddy-блоков —
+    /// и забрать их. Блоки, задетые краями, режутся, остаток возвращается.
+    /// Find `pages` consecutive free pages — across buddy block boundaries —
+    /// and take them. Blocks cut by the edges are split and the rest returned.
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysAddr> {
+        crate::assert_irqs_disabled!();
+        if pages == 0 || pages > self.free_pages { return None; }
+
+        let span = self.free[0].len;
+        let mut run_start = 0;
+        let mut pfn = 0;
+        while pfn < span && pfn - run_start < pages {
+            // Прыгаем блоками: внутри свободного блока свободна каждая страница
+            // Jump by blocks: every page inside a free block is free
+            match self.free_block_of(pfn) {
+                Some((order, idx)) => pfn = (idx + 1) << order,
+                None => { pfn += 1; run_start = pfn; }
+            }
+        }
+        if pfn - run_start < pages { return None; }
+
+        let start = self.mem_start + (run_start * PAGE_SIZE) as u64;
+        self.take_range(start, start + (pages * PAGE_SIZE) as u64);
+        Some(PhysAddr::new(start))
+    }
+
+    /// Вернуть `[start, end)` наибольшими блоками, выровненными по своему
+    /// размеру относительно `mem_start`.
+    /// Return `[start, end)` as the largest blocks aligned to their own size
+    /// relative to `mem_start`.
+    fn free_range(&mut self, mut start: u64, end: u64) {
+        while start < end {
+            let pfn   = ((start - self.mem_start) / PAGE_SIZE as u64) as usize;
+            let pages = ((end - start) / PAGE_SIZE as u64) as usize;
+            let order = (0..MAX_ORDER).rev()
+                .find(|&o| pfn.is_multiple_of(1 << o) && (1 << o) <= pages)
+                .unwrap_or(0);
+            self.free(PhysAddr::new(start), order);
+            start += PAGE_SIZE as u64 * (1 << order);
+        }
+    }
+
     /// Освободить блок / Free block.
     fn free(&mut self, addr: PhysAddr, order: usize) {
+        crate::assert_irqs_disabled!();
+
         let pages_offset = ((addr.as_u64() - self.mem_start) / PAGE_SIZE as u64)
             as usize;
         let mut idx   = pages_offset / (1 << order);
         let mut order = order;
 
         self.free[order].set(idx, true);
+        self.free_counts[order] += 1;
         self.free_pages += 1 << order;
 
         // Попробовать слить с buddy / Try to merge with buddy
@@ -231,19 +405,30 @@ impl BuddyAllocator {
                 // Buddy свободен — сливаем / Buddy is free — merge
                 self.free[order].set(idx,   false);
                 self.free[order].set(buddy, false);
-                idx   = idx / 2;
+                self.free_counts[order] -= 2;
+                idx   /= 2;
                 order += 1;
                 self.free[order].set(idx, true);
                 self.free[order].len = self.free[order].len.max(idx + 1);
+                self.free_counts[order] += 1;
             } else {
                 break;
             }
         }
+        debug_assert!(self.counts_consistent());
     }
 }
 
 // ── Выравнивание / Alignment helpers ─────────────────────────────────────────
 
+/// Целые страницы региона выше `LOW_MEMORY_END`: начало вверх, конец вниз.
+/// The region's whole pages above `LOW_MEMORY_END`: start up, end down.
+fn page_bounds(start: u64, size: u64) -> Option<(u64, u64)> {
+    let end   = align_down(start.saturating_add(size), PAGE_SIZE as u64);
+    let start = align_up(start.max(LOW_MEMORY_END), PAGE_SIZE as u64);
+    (start < end).then_some((start, end))
+}
+
 const fn align_up(addr: u64, align: u64) -> u64 {
     (addr + align - 1) & !(align - 1)
 }
@@ -254,11 +439,17 @@ const fn align_down(addr: u64, align: u64) -> u64 {
 
 // ── Глобальный PMM / Global PMM ───────────────────────────────────────────────
 
-static PMM: Mutex<BuddyAllocator> = Mutex::new(BuddyAllocator::new());
+// IrqMutex — PMM зовётся и из page fault обработчика
+// IrqMutex — the PMM is also called from the page-fault handler
+static PMM: IrqMutex<BuddyAllocator> = IrqMutex::new(BuddyAllocator::new());
 
 /// Статистика памяти / Memory statistics
 static TOTAL_BYTES: AtomicU64 = AtomicU64::new(0);
 static FREE_BYTES:  AtomicU64 = AtomicU64::new(0);
+/// Конец последней управляемой страницы / End of the last managed page
+static PHYS_END:    AtomicU64 = AtomicU64::new(0);
+/// Начало самого нижнего региона / Start of the lowest region
+static MEM_START:   AtomicU64 = AtomicU64::new(0);
 
 /// Инициализировать PMM — вызывается из kernel_main.
 /// Initialize PMM — called from kernel_main.
@@ -266,46 +457,162 @@ static FREE_BYTES:  AtomicU64 = AtomicU64::new(0);
 /// Читает карту памяти от Limine и регистрирует свободные регионы.
 /// Reads memory map from Limine and registers free regions.
 pub fn init() {
-    // TODO: получить реальную карту памяти от Limine
-    // TODO: get real memory map from Limine
-    //
-    // Когда подключим Limine protocol, будет примерно так:
-    // When we connect Limine protocol, it will look like:
-    //
-    // let mmap = MMAP_REQUEST.get_response().unwrap();
-    // for entry in mmap.entries() {
-    //     if entry.entry_type == EntryType::USABLE {
-    //         pmm.add_region(entry.base, entry.length);
-    //     }
-    // }
-    //
-    // Пока — заглушка с 16MB тестовой памяти для разработки
-    // For now — stub with 16MB test memory for development
-    let mut pmm = PMM.lock();
-    pmm.add_region(0x100000, 16 * 1024 * 1024); // 1MB..17MB
-
-    TOTAL_BYTES.store(pmm.total_pages as u64 * PAGE_SIZE as u64, Ordering::Relaxed);
-    FREE_BYTES.store(pmm.free_pages   as u64 * PAGE_SIZE as u64, Ordering::Relaxed);
+    let entries = crate::arch::current::memory_map()
+        .expect("[pmm] Limine memory map missing — no RAM to manage");
+
+    // Только USABLE: RESERVED, ACPI_*, BOOTLOADER_RECLAIMABLE (там ещё
+    // лежат ответы Limine и его таблицы страниц) и прочее не трогаем.
+    // USABLE only: RESERVED, ACPI_*, BOOTLOADER_RECLAIMABLE (Limine's
+    // responses and page tables still live there) and the rest stay untouched.
+    let usable = || entries.iter()
+        .filter(|entry| entry.entry_type == EntryType::USABLE)
+        .map(|entry| (entry.base, entry.length));
+    init_with_regions(usable());
+    // Limine кладёт ядро в EXECUTABLE_AND_MODULES, но на карту с ошибкой
+    // полагаться не будем — образ резервируется явно.
+    // Limine puts the kernel in EXECUTABLE_AND_MODULES, but we won't rely on
+    // a possibly wrong map — the image is reserved explicitly.
+    if let Some((start, size)) = crate::arch::current::kernel_phys_range() {
+        reserve_region(start, size);
+    }
 
+    // Самый крупный свободный блок — сразу видно, раздроблена ли память
+    // The largest free block — shows at once whether memory is fragmented
+    let largest = (0..MAX_ORDER).rev().find(|&order| free_blocks(order) > 0).unwrap_or(0);
     crate::kprintln!(
-        "[pmm] Total: {} MB, Free: {} MB",
+        "[pmm] {} usable regions, {} MB registered, {} MB free, largest block {} KB",
+        usable().count(),
         TOTAL_BYTES.load(Ordering::Relaxed) / 1024 / 1024,
         FREE_BYTES.load(Ordering::Relaxed)  / 1024 / 1024,
+        (PAGE_SIZE << largest) / 1024,
     );
 }
 
+/// Инициализировать PMM заданным набором регионов `(start, size)`.
+/// Initialize the PMM from an explicit set of `(start, size)` regions.
+///
+/// Не зависит от загрузчика: `init` зовёт её с картой памяти, а тесты —
+/// с фиксированной синтетической картой, чтобы адреса аллокаций,
+/// слияние и фрагментация были детерминированы. Прежнее состояние
+/// полностью сбрасывается.
+/// Independent of the bootloader: `init` calls it with the memory map and
+/// tests call it with a fixed synthetic map, so allocation addresses,
+/// merging and fragmentation are deterministic. Any previous state is
+/// discarded.
+pub fn init_with_regions(regions: impl IntoIterator<Item = (u64, u64), IntoIter: Clone>) {
+    let mut pmm = PMM.lock();
+    pmm.init(regions.into_iter());
+    PHYS_END.store(pmm.mem_start + (pmm.free[0].len * PAGE_SIZE) as u64, Ordering::Relaxed);
+    MEM_START.store(pmm.mem_start, Ordering::Relaxed);
+
+    TOTAL_BYTES.store(pmm.total_pages as u64 * PAGE_SIZE as u64, Ordering::Relaxed);
+    FREE_BYTES.store(pmm.free_pages   as u64 * PAGE_SIZE as u64, Ordering::Relaxed);
+}
+
 // ── Публичный API / Public API ────────────────────────────────────────────────
 
 /// Выделить одну страницу (4KB) / Allocate one page (4KB).
-pub fn alloc_page() -> Option<PhysAddr> {
+pub fn alloc_page() -> Result<PhysAddr, MmError> {
     alloc_pages(0)
 }
 
+/// Выделить обнулённую страницу — для таблиц страниц и анонимной памяти.
+/// Обнуляет через HHDM и только при успехе.
+/// Allocate a zeroed page — for page tables and anonymous memory. Zeroes
+/// through the HHDM, and only on success.
+pub fn alloc_zeroed_page() -> Result<PhysAddr, MmError> {
+    let phys = alloc_page()?;
+    unsafe { super::vmm::phys_to_virt(phys).as_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE); }
+    Ok(phys)
+}
+
 /// Выделить 2^order страниц / Allocate 2^order pages.
-pub fn alloc_pages(order: usize) -> Option<PhysAddr> {
-    let addr = PMM.lock().alloc(order)?;
+pub fn alloc_pages(order: usize) -> Result<PhysAddr, MmError> {
+    let addr = PMM.lock().alloc(order).ok_or(MmError::OutOfMemory)?;
     FREE_BYTES.fetch_sub((PAGE_SIZE << order) as u64, Ordering::Relaxed);
-    Some(addr)
+    Ok(addr)
+}
+
+/// Выделить 2^order страниц по физическому адресу, кратному `align` —
+/// для таблиц страниц и DMA, которым мало выравнивания по размеру блока.
+/// `align` — степень двойки не меньше `PAGE_SIZE`, иначе `InvalidRange`.
+/// Allocate 2^order pages at a physical address that is a multiple of
+/// `align` — for page tables and DMA that need more than block-size
+/// alignment. `align` must be a power of two of at least `PAGE_SIZE`,
+/// otherwise `InvalidRange`.
+pub fn alloc_pages_aligned(order: usize, align: usize) -> Result<PhysAddr, MmError> {
+    if !align.is_power_of_two() || align < PAGE_SIZE || order >= MAX_ORDER {
+        return Err(MmError::InvalidRange);
+    }
+    let addr = PMM.lock().alloc_aligned(order, align as u64).ok_or(MmError::OutOfMemory)?;
+    FREE_BYTES.fetch_sub((PAGE_SIZE << order) as u64, Ordering::Relaxed);
+    Ok(addr)
+}
+
+/// Выделить `pages` физически смежных страниц — больше, чем даёт
+/// `MAX_ORDER`, для DMA-буферов. Ищет подряд свободные страницы поперёк
+/// границ buddy-блоков.
+///
+/// Может не найти, даже когда `free_memory()` велика: при фрагментации
+/// длинного свободного участка просто нет.
+/// Allocate `pages` physically contiguous pages — more than `MAX_ORDER`
+/// allows, for DMA buffers. Searches for consecutive free pages across
+/// buddy block boundaries.
+///
+/// May fail even when `free_memory()` is large: under fragmentation there
+/// simply is no long enough free stretch.
+pub fn alloc_contiguous(pages: usize) -> Result<PhysAddr, MmError> {
+    let addr = PMM.lock().alloc_contiguous(pages).ok_or(MmError::OutOfMemory)?;
+    FREE_BYTES.fetch_sub((pages * PAGE_SIZE) as u64, Ordering::Relaxed);
+    Ok(addr)
+}
+
+/// Вернуть то, что выдал `alloc_contiguous`, — с тем же `pages`.
+/// Return what `alloc_contiguous` handed out — with the same `pages`.
+pub fn free_contiguous(addr: PhysAddr, pages: usize) {
+    PMM.lock().free_range(addr.as_u64(), addr.as_u64() + (pages * PAGE_SIZE) as u64);
+    FREE_BYTES.fetch_add((pages * PAGE_SIZE) as u64, Ordering::Relaxed);
+}
+
+/// Сколько раз повторять выделение под конкуренцией / Retries under contention
+pub const ALLOC_RETRIES: u32 = 8;
+/// Потолок backoff (итераций spin) / Backoff ceiling (spin iterations)
+const ALLOC_MAX_SPIN: u32 = 1024;
+
+/// Выделить 2^order страниц, не блокируясь на занятом lock'е: `try_lock`,
+/// а при неудаче — ограниченный backoff и повтор. Повторяется и отказ при
+/// достаточной `free_memory()` — блок мог освобождаться на другом CPU.
+/// После `ALLOC_RETRIES` попыток — `OutOfMemory`. На одном CPU lock всегда
+/// свободен, и это обычный `alloc_pages`.
+/// Allocate 2^order pages without blocking on a busy lock: `try_lock`, and
+/// on failure a bounded backoff and retry. A failure while `free_memory()`
+/// still looks sufficient is retried too — a block may be being freed on
+/// another CPU. After `ALLOC_RETRIES` tries — `OutOfMemory`. On one CPU the lock
+/// is always free and this is plain `alloc_pages`.
+pub fn alloc_pages_backoff(order: usize) -> Result<PhysAddr, MmError> {
+    let bytes = (PAGE_SIZE << order) as u64;
+    let mut out_of_memory = false;
+    let addr = crate::sync::retry_with_backoff(ALLOC_RETRIES, ALLOC_MAX_SPIN, || {
+        if out_of_memory { return None; }
+        let addr = PMM.try_lock()?.alloc(order);
+        if addr.is_none() && free_memory() < bytes { out_of_memory = true; }
+        addr
+    }).ok_or(MmError::OutOfMemory)?;
+    FREE_BYTES.fetch_sub(bytes, Ordering::Relaxed);
+    Ok(addr)
+}
+
+/// Исключить `[start, start + size)` из выдачи: свободные страницы там
+/// больше никогда не вернёт `alloc_*`. Повторный вызов ничего не меняет;
+/// часть вне управляемой памяти молча пропускается.
+/// Exclude `[start, start + size)` from allocation: no `alloc_*` will ever
+/// return its free pages. Calling it again changes nothing; any part outside
+/// managed memory is silently skipped.
+pub fn reserve_region(start: u64, size: u64) {
+    let mut pmm = PMM.lock();
+    let before = pmm.free_pages;
+    pmm.take_range(start, start.saturating_add(size));
+    FREE_BYTES.fetch_sub(((before - pmm.free_pages) * PAGE_SIZE) as u64, Ordering::Relaxed);
 }
 
 /// Освободить одну страницу / Free one page.
@@ -314,11 +621,260 @@ pub fn free_page(addr: PhysAddr) {
 }
 
 /// Освободить 2^order страниц / Free 2^order pages.
+///
+/// Блок от `alloc_pages_aligned` может быть не выровнен по своему order
+/// относительно `mem_start` — поэтому через `free_range`, который режет
+/// такой блок на правильные куски.
+/// A block from `alloc_pages_aligned` may not be aligned to its order
+/// relative to `mem_start` — hence `free_range`, which cuts such a block
+/// into proper pieces.
 pub fn free_pages(addr: PhysAddr, order: usize) {
-    PMM.lock().free(addr, order);
+    PMM.lock().free_range(addr.as_u64(), addr.as_u64() + (PAGE_SIZE << order) as u64);
     FREE_BYTES.fetch_add((PAGE_SIZE << order) as u64, Ordering::Relaxed);
 }
 
+/// Ниже этого запаса свободной памяти включается reclaim.
+/// Below this much free memory, reclaim kicks in.
+pub const LOW_WATERMARK: u64 = 256 * PAGE_SIZE as u64; // 1MB
+
+pub fn below_low_watermark() -> bool { free_memory() < LOW_WATERMARK }
+
 /// Статистика / Statistics
 pub fn free_memory()  -> u64 { FREE_BYTES.load(Ordering::Relaxed) }
+
+/// Свободных блоков ровно этого order (не считая частей более крупных).
+/// Сумма `free_blocks(o) << o` по всем order — свободные страницы.
+/// Free blocks of exactly this order (not counting parts of larger ones).
+/// The sum of `free_blocks(o) << o` over all orders is the free page count.
+pub fn free_blocks(order: usize) -> usize {
+    if order >= MAX_ORDER { return 0; }
+    PMM.lock().free_counts[order]
+}
 pub fn total_memory() -> u64 { TOTAL_BYTES.load(Ordering::Relaxed) }
+/// Выше этого адреса PMM фреймов не выдаёт — столько должен покрыть HHDM.
+/// The PMM hands out no frame above this — the HHDM must cover this much.
+pub fn phys_end()     -> u64 { PHYS_END.load(Ordering::Relaxed) }
+
+/// Номер фрейма `addr` от начала памяти PMM; `None` — вне её. Годится в
+/// индексы таблиц на `MAX_PAGES` записей, где бы ни лежала RAM.
+/// The index of `addr`'s frame from the start of the PMM's memory; `None` —
+/// outside it. Fits as an index into `MAX_PAGES`-entry tables wherever RAM
+/// happens to sit.
+#[cfg(feature = "slab_debug")]
+pub fn frame_index(addr: PhysAddr) -> Option<usize> {
+    let offset = addr.as_u64().checked_sub(MEM_START.load(Ordering::Relaxed))?;
+    let index = (offset / PAGE_SIZE as u64) as usize;
+    (index < MAX_PAGES).then_some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    const MB: u64 = 0x10_0000;
+    const PAGE: u64 = PAGE_SIZE as u64;
+
+    /// Свой аллокатор на синтетической карте: глобальный PMM держит память
+    /// остальных тестов. Битмапы памяти не касаются, адреса — любые.
+    /// An allocator of its own on a synthetic map: the global PMM holds the
+    /// other tests' memory. The bitmaps never touch memory, so any addresses do.
+    fn buddy(regions: &[(u64, u64)]) -> Box<BuddyAllocator> {
+        // Нули — корректный пустой аллокатор, а на стек он не влезет
+        // Zeroes are a valid empty allocator, and it won't fit on the stack
+        let mut pmm = unsafe { Box::<BuddyAllocator>::new_zeroed().assume_init() };
+        pmm.init(regions.iter().copied());
+        pmm
+    }
+
+    #[test]
+    fn two_regions_hand_out_exact_addresses() {
+        // 16 страниц с 1MB и 8 страниц с 2MB / 16 pages at 1MB and 8 pages at 2MB
+        let mut pmm = buddy(&[(MB, 16 * PAGE), (2 * MB, 8 * PAGE)]);
+        assert_eq!((pmm.total_pages, pmm.free_pages), (24, 24));
+        assert_eq!((pmm.free_counts[4], pmm.free_counts[3]), (1, 1));
+
+        // Меньший подходящий блок — второй регион, он и делится
+        // The smallest fitting block is the second region's, and it gets split
+        assert_eq!(pmm.alloc(0), Some(PhysAddr::new(2 * MB)));
+        assert_eq!(pmm.alloc(0), Some(PhysAddr::new(2 * MB + PAGE)));
+        assert_eq!(pmm.alloc(2), Some(PhysAddr::new(2 * MB + 4 * PAGE)));
+        assert_eq!(pmm.alloc(2), Some(PhysAddr::new(MB)));
+        assert_eq!(pmm.alloc(4), None);
+    }
+
+    #[test]
+    fn freed_buddies_coalesce_within_a_region() {
+        let mut pmm = buddy(&[(MB, 16 * PAGE), (2 * MB, 8 * PAGE)]);
+        let pages: [PhysAddr; 8] = core::array::from_fn(|_| pmm.alloc(0).unwrap());
+        assert_eq!(pmm.free_counts[3], 0);
+
+        for page in pages { pmm.free(page, 0); }
+        // Восемь страниц снова один блок order 3, а не восемь order 0
+        // The eight pages are one order-3 block again, not eight order-0 ones
+        assert_eq!((pmm.free_counts[0], pmm.free_counts[3], pmm.free_counts[4]), (0, 1, 1));
+        assert_eq!(pmm.free_pages, 24);
+    }
+
+    #[test]
+    fn adjacent_regions_coalesce_separate_ones_do_not() {
+        // Два смежных региона по 8 страниц сливаются в один блок order 4
+        // Two adjacent 8-page regions merge into one order-4 block
+        let mut pmm = buddy(&[(MB, 8 * PAGE), (MB + 8 * PAGE, 8 * PAGE)]);
+        assert_eq!((pmm.free_counts[3], pmm.free_counts[4]), (0, 1));
+        assert_eq!(pmm.alloc(4), Some(PhysAddr::new(MB)));
+        pmm.free(PhysAddr::new(MB), 4);
+
+        // С дыркой между ними блоки те же, но они не buddy друг другу
+        // With a gap between them the blocks are the same, but not each other's buddies
+        let mut pmm = buddy(&[(MB, 8 * PAGE), (MB + 16 * PAGE, 8 * PAGE)]);
+        assert_eq!((pmm.free_counts[3], pmm.free_counts[4]), (2, 0));
+        assert_eq!(pmm.alloc(4), None);
+    }
+
+    #[test]
+    fn per_order_counts_reconcile_with_free_pages() {
+        // 64 страницы — один блок order 6 / 64 pages — one order-6 block
+        let mut pmm = buddy(&[(MB, 64 * PAGE)]);
+        assert_eq!(pmm.free_counts[6], 1);
+
+        let a = pmm.alloc(0).unwrap();
+        let b = pmm.alloc(3).unwrap();
+        let c = pmm.alloc(1).unwrap();
+        // Деление order 6 → 0 оставило по блоку в order 0..5, потом order 3
+        // и order 1 забрали свои
+        // Splitting order 6 → 0 left a block at each of orders 0..5, then
+        // orders 3 and 1 took theirs
+        assert_eq!(pmm.free_counts[..7], [1, 0, 1, 0, 1, 1, 0]);
+        assert_eq!(pmm.free_pages, 64 - 1 - 8 - 2);
+        assert!(pmm.counts_consistent());
+
+        pmm.free(b, 3);
+        pmm.free(a, 0);
+        assert!(pmm.counts_consistent());
+        pmm.free(c, 1);
+        assert_eq!(pmm.free_counts[..7], [0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(pmm.free_pages, 64);
+    }
+
+    #[test]
+    fn aligned_allocation_returns_its_slack() {
+        // mem_start на 1MB + 4KB — блоки buddy выровнены не по нулю
+        // mem_start at 1MB + 4KB — the buddy blocks aren't aligned to zero
+        let mut pmm = buddy(&[(MB + PAGE, MB)]);
+        let (free, counts) = (pmm.free_pages, pmm.free_counts);
+
+        let page = pmm.alloc_aligned(0, 16 * PAGE).unwrap();
+        assert_eq!(page.as_u64() % (16 * PAGE), 0);
+        // Голова и хвост большого блока вернулись — ушла ровно одна страница
+        // The big block's head and tail came back — exactly one page is gone
+        assert_eq!(pmm.free_pages, free - 1);
+        assert!(pmm.counts_consistent());
+
+        let block = pmm.alloc_aligned(2, 64 * PAGE).unwrap();
+        assert_eq!(block.as_u64() % (64 * PAGE), 0);
+        assert_eq!(pmm.free_pages, free - 1 - 4);
+
+        pmm.free_range(page.as_u64(), page.as_u64() + PAGE);
+        pmm.free_range(block.as_u64(), block.as_u64() + 4 * PAGE);
+        assert_eq!((pmm.free_pages, pmm.free_counts), (free, counts));
+    }
+
+    #[test]
+    fn contiguous_run_is_found_across_blocks_under_fragmentation() {
+        let mut pmm = buddy(&[(MB, 64 * PAGE)]);
+        let pages: [PhysAddr; 64] = core::array::from_fn(|_| pmm.alloc(0).unwrap());
+        // Каждая вторая страница свободна — 32 страницы, но ни одной пары
+        // Every other page is free — 32 pages, yet not a single pair
+        for page in pages.iter().step_by(2) { pmm.free(*page, 0); }
+        assert_eq!(pmm.free_pages, 32);
+        assert_eq!(pmm.alloc_contiguous(2), None);
+
+        // Теперь свободны 4..11 подряд — через границу блоков order 3 на 8
+        // Now 4..11 are free in a row — across the order-3 block boundary at 8
+        for page in [pages[5], pages[7], pages[9]] { pmm.free(page, 0); }
+        assert_eq!(pmm.alloc_contiguous(8), None);
+        let run = pmm.alloc_contiguous(7).unwrap();
+        assert_eq!(run, PhysAddr::new(MB + 4 * PAGE));
+        // Забрали ровно семь, остальное на месте и согласовано
+        // Exactly seven were taken, the rest is in place and consistent
+        assert_eq!(pmm.free_pages, 32 + 3 - 7);
+        assert!(pmm.counts_consistent());
+    }
+
+    #[test]
+    fn reserved_frames_are_never_handed_out() {
+        let mut pmm = buddy(&[(MB, 64 * PAGE)]);
+        let (lo, hi) = (MB + 5 * PAGE, MB + 23 * PAGE);
+        pmm.take_range(lo, hi);
+        // Повтор и кусок вне памяти ничего не меняют, а у диапазона,
+        // вылезающего за конец, резервируется только его часть
+        // A repeat and a piece outside memory change nothing, and of a range
+        // running past the end only its own part is reserved
+        pmm.take_range(lo, hi);
+        pmm.take_range(MB - 4 * PAGE, MB);
+        pmm.take_range(MB + 60 * PAGE, MB + 80 * PAGE);
+        assert_eq!(pmm.free_pages, 64 - 18 - 4);
+        assert!(pmm.counts_consistent());
+
+        let mut handed_out = 0;
+        while let Some(page) = pmm.alloc(0) {
+            assert!(!(lo..hi).contains(&page.as_u64()), "reserved {page:?} handed out");
+            handed_out += 1;
+        }
+        assert_eq!(handed_out, 64 - 18 - 4);
+    }
+
+    #[test]
+    fn descending_regions_allocate_inside_the_map() {
+        // Верхний регион первым: база — всё равно самый нижний
+        // The higher region first: the base is still the lowest one
+        let regions = [(8 * MB, 32 * PAGE), (4 * MB, 16 * PAGE), (MB, 8 * PAGE)];
+        let mut pmm = buddy(&regions);
+        assert_eq!(pmm.mem_start, MB);
+        assert_eq!(pmm.free_pages, 56);
+
+        let mut handed_out = 0;
+        while let Some(page) = pmm.alloc(0) {
+            let addr = page.as_u64();
+            assert!(regions.iter().any(|&(start, size)| (start..start + size).contains(&addr)), "{page:?} outside the map");
+            handed_out += 1;
+        }
+        assert_eq!(handed_out, 56);
+    }
+
+    #[test]
+    fn zeroed_page_comes_back_zeroed_even_when_reused() {
+        let _kernel = crate::testing::setup();
+        // Испачкать страницу и вернуть — следующая выдача берёт её же
+        // Dirty a page and give it back — the next allocation takes the same one
+        let dirty = alloc_page().unwrap();
+        let view = |phys: PhysAddr| unsafe {
+            core::slice::from_raw_parts_mut(crate::mm::vmm::phys_to_virt(phys).as_mut_ptr::<u8>(), PAGE_SIZE)
+        };
+        view(dirty).fill(0xAA);
+        free_page(dirty);
+
+        let page = alloc_zeroed_page().unwrap();
+        assert_eq!(page, dirty);
+        assert!(view(page).iter().all(|&b| b == 0));
+        free_page(page);
+    }
+
+    #[test]
+    fn backoff_gives_up_on_a_lock_that_stays_taken() {
+        let _kernel = crate::testing::setup();
+        let free = free_memory();
+        {
+            // Lock держит «другой CPU» всё время — ждать вечно нельзя
+            // "Another CPU" holds the lock throughout — we mustn't wait forever
+            let _held = PMM.lock();
+            assert_eq!(alloc_pages_backoff(0), Err(MmError::OutOfMemory));
+        }
+        assert_eq!(free_memory(), free);
+
+        let page = alloc_pages_backoff(0).unwrap();
+        assert_eq!(free_memory(), free - PAGE_SIZE as u64);
+        free_page(page);
+    }
+}
@@ -0,0 +1,199 @@
+//! Page reclaim — clock-алгоритм по биту Accessed
+//! Page reclaim — clock algorithm over the Accessed bit
+//!
+//! Swap пока нет, поэтому выбрасывать можно только страницы, которые
+//! заново получаются из источника: чистые (не Dirty) страницы файловых VMA.
+//! Анонимные и грязные страницы никогда не трогаем.
+//! There is no swap yet, so only pages that can be re-derived from their
+//! source may be dropped: clean (not Dirty) pages of file-backed VMAs.
+//! Anonymous and dirty pages are never touched.
+//!
+//! Перечитывает страницы pager (`set_pager`); пока его нет, выброшенную
+//! страницу вернуть нечем, и reclaim ничего не трогает.
+//! Pages are re-read by the pager (`set_pager`); until there is one, a
+//! dropped page can't be brought back, so reclaim touches nothing.
+//!
+//! Закреплённые (`PINNED`) страницы держит устройство — их не трогаем никогда.
+//! Pinned (`PINNED`) pages are held by a device — never touched.
+//!
+//! Clock: страница с Accessed получает второй шанс (бит сбрасывается),
+//! без Accessed — выбрасывается.
+//! Clock: a page with Accessed set gets a second chance (bit cleared),
+//! one without it is dropped.
+
+use core::sync::atomic::Ordering;
+use spin::RwLock;
+use super::pmm::{self, PAGE_SIZE};
+use super::vmm::{AddressSpace, PageFlags, VirtAddr, VmaKind};
+
+/// Источник содержимого файловых страниц / Where file page contents come from
+pub trait Pager: Sync {
+    /// Прочитать страницу `object` со смещения `offset` в `page` (обнулена).
+    /// `false` — прочитать нечего.
+    /// Read `object`'s page at `offset` into `page` (zeroed). `false` —
+    /// nothing to read.
+    fn read_page(&self, object: u64, offset: u64, page: &mut [u8]) -> bool;
+}
+
+static PAGER: RwLock<Option<&'static dyn Pager>> = RwLock::new(None);
+
+/// Поставить pager — с этого момента файловые страницы можно выбрасывать.
+/// Install the pager — from now on file pages may be dropped.
+#[cfg_attr(not(test), allow(dead_code))] // поставит файловый сервер / the file server will install it
+pub fn set_pager(pager: &'static dyn Pager) {
+    *PAGER.write() = Some(pager);
+}
+
+/// Страница файла для fault'а; без pager'а — `false`.
+/// A file page for a fault; without a pager — `false`.
+pub fn read_page(object: u64, offset: u64, page: &mut [u8]) -> bool {
+    PAGER.read().is_some_and(|pager| pager.read_page(object, offset, page))
+}
+
+/// Сколько страниц освобождать за один заход / Pages to free per pass
+pub const RECLAIM_BATCH: usize = 32;
+
+/// Сколько VMA смотрит один заход / VMAs examined per pass
+const MAX_SCAN_VMAS: usize = 64;
+
+/// Решение для одной страницы / Verdict for a single page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Можно выбросить / May be dropped
+    Reclaim,
+    /// Недавно использовалась — сбросить Accessed и пропустить
+    /// Recently used — clear Accessed and skip
+    SecondChance,
+    /// Трогать нельзя / Must not be touched
+    Keep,
+}
+
+/// Классифицировать замапленную страницу по виду VMA и флагам PTE.
+/// Classify a mapped page by its VMA kind and PTE flags.
+pub fn classify(kind: VmaKind, pte: PageFlags) -> Verdict {
+    if pte.contains(PageFlags::PINNED) { return Verdict::Keep; }
+    match kind {
+        VmaKind::File { .. } if !pte.contains(PageFlags::DIRTY) => {
+            if pte.contains(PageFlags::ACCESSED) { Verdict::SecondChance } else { Verdict::Reclaim }
+        }
+        _ => Verdict::Keep,
+    }
+}
+
+/// Один проход clock по файловым VMA `space`. Возвращает число
+/// освобождённых страниц (не больше `budget`).
+/// One clock pass over the file-backed VMAs of `space`. Returns the number
+/// of pages freed (at most `budget`).
+pub fn reclaim(space: &AddressSpace, budget: usize) -> usize {
+    if PAGER.read().is_none() { return 0; }
+    let mut ranges = [(VirtAddr::new(0), VirtAddr::new(0), VmaKind::Anonymous); MAX_SCAN_VMAS];
+    let count = space.vma_ranges(&mut ranges);
+
+    let hand  = space.clock_hand.load(Ordering::Relaxed);
+    let mut freed = 0;
+    let mut last  = hand;
+
+    // Два круга: первый может только раздать вторые шансы
+    // Two sweeps: the first one may only hand out second chances
+    for _ in 0..2 {
+        for &(start, end, kind) in ranges[..count].iter() {
+            if !matches!(kind, VmaKind::File { .. }) { continue; }
+            let mut addr = start.as_u64().max(hand.min(end.as_u64()));
+            if addr >= end.as_u64() { addr = start.as_u64(); }
+            while addr < end.as_u64() {
+                let virt = VirtAddr::new(addr);
+                addr += PAGE_SIZE as u64;
+                let Some(flags) = space.leaf_flags(virt) else { continue };
+                match classify(kind, flags) {
+                    Verdict::Reclaim => {
+                        if let Some(phys) = space.take_page(virt) {
+                            pmm::free_page(phys);
+                            freed += 1;
+                            last = addr;
+                            if freed >= budget {
+                                space.clock_hand.store(last, Ordering::Relaxed);
+                                return freed;
+                            }
+                        }
+                    }
+                    Verdict::SecondChance => { space.clear_accessed(virt); }
+                    Verdict::Keep => {}
+                }
+            }
+        }
+    }
+    space.clock_hand.store(last, Ordering::Relaxed);
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::vmm::{handle_page_fault, phys_to_virt, Vma};
+    use crate::testing;
+
+    /// Каждый байт страницы — номер объекта плюс номер страницы в нём
+    /// Every byte of a page is the object number plus the page's index in it
+    struct Pattern;
+
+    impl Pager for Pattern {
+        fn read_page(&self, object: u64, offset: u64, page: &mut [u8]) -> bool {
+            page.fill((object + offset / PAGE_SIZE as u64) as u8);
+            true
+        }
+    }
+
+    static PATTERN: Pattern = Pattern;
+
+    #[test]
+    fn classify_drops_only_clean_unused_file_pages() {
+        let file = VmaKind::File { object: 1, origin: 0 };
+        let present = PageFlags::PRESENT | PageFlags::USER;
+        assert_eq!(classify(file, present), Verdict::Reclaim);
+        assert_eq!(classify(file, present | PageFlags::ACCESSED), Verdict::SecondChance);
+        assert_eq!(classify(file, present | PageFlags::DIRTY), Verdict::Keep);
+        assert_eq!(classify(file, present | PageFlags::PINNED), Verdict::Keep);
+        assert_eq!(classify(VmaKind::Anonymous, present), Verdict::Keep);
+        assert_eq!(classify(VmaKind::CowAnonymous, present), Verdict::Keep);
+    }
+
+    #[test]
+    fn reclaimed_file_page_faults_back_in() {
+        let _kernel = testing::setup();
+        set_pager(&PATTERN);
+        let space = AddressSpace::new().unwrap();
+        let start = VirtAddr::new(0x50_0000);
+        let kind = VmaKind::File { object: 7, origin: start.as_u64() - PAGE_SIZE as u64 };
+        space.add_vma(Vma::with_size(start, 2 * PAGE_SIZE as u64, PageFlags::USER_RW.difference(PageFlags::WRITABLE), kind).unwrap()).unwrap();
+        let byte = |va: VirtAddr| unsafe { *phys_to_virt(space.translate(va).unwrap()).as_ptr::<u8>() };
+
+        let second = VirtAddr::new(start.as_u64() + PAGE_SIZE as u64 + 8);
+        assert!(handle_page_fault(&space, second, 0));
+        assert_eq!(byte(second), 9);
+        assert!(space.translate(start).is_none());
+
+        assert_eq!(reclaim(&space, RECLAIM_BATCH), 1);
+        assert!(space.translate(second).is_none());
+        assert!(handle_page_fault(&space, second, 0));
+        assert_eq!(byte(second), 9);
+    }
+
+    #[test]
+    fn pinned_file_page_is_never_reclaimed() {
+        let _kernel = testing::setup();
+        set_pager(&PATTERN);
+        let space = AddressSpace::new().unwrap();
+        let start = VirtAddr::new(0x70_0000);
+        let kind = VmaKind::File { object: 3, origin: start.as_u64() };
+        space.add_vma(Vma::with_size(start, 2 * PAGE_SIZE as u64, PageFlags::USER_RW.difference(PageFlags::WRITABLE), kind).unwrap()).unwrap();
+        space.pin(start, PAGE_SIZE as u64).unwrap();
+        let second = VirtAddr::new(start.as_u64() + PAGE_SIZE as u64);
+        assert!(handle_page_fault(&space, second, 0));
+
+        // Выбрасывается только незакреплённая / Only the unpinned one is dropped
+        assert_eq!(reclaim(&space, RECLAIM_BATCH), 1);
+        assert!(space.translate(start).is_some());
+        assert!(space.translate(second).is_none());
+        assert_eq!(reclaim(&space, RECLAIM_BATCH), 0);
+    }
+}
@@ -0,0 +1,65 @@
+//! Shared memory — физические страницы, которые мапят несколько пространств
+//! Shared memory — physical pages mapped by several address spaces
+//!
+//! Объект держат `Arc`: capability на него и каждое пространство, где он
+//! замаплен. Фреймы возвращаются в PMM только когда уходит последняя
+//! ссылка — ни одно пространство не освобождает их само.
+//! The object is held by `Arc`: by capabilities to it and by every address
+//! space that maps it. Frames go back to the PMM only when the last
+//! reference goes away — no address space frees them on its own.
+
+use alloc::sync::Arc;
+use super::pmm::{self, PhysAddr, PAGE_SIZE};
+use super::vmm::{phys_to_virt, PageFlags};
+use super::MmError;
+
+pub struct SharedMemObject {
+    base:  PhysAddr,
+    order: usize,
+}
+
+impl SharedMemObject {
+    /// Выделить 2^order обнулённых страниц / Allocate 2^order zeroed pages
+    pub fn alloc(order: usize) -> Result<Arc<Self>, MmError> {
+        let base = pmm::alloc_pages(order)?;
+        unsafe { phys_to_virt(base).as_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE << order); }
+        Ok(Arc::new(Self { base, order }))
+    }
+
+    pub fn base(&self) -> PhysAddr { self.base }
+    pub fn order(&self) -> usize { self.order }
+    pub fn size(&self) -> u64 { (PAGE_SIZE << self.order) as u64 }
+}
+
+impl Drop for SharedMemObject {
+    fn drop(&mut self) {
+        pmm::free_pages(self.base, self.order);
+    }
+}
+
+/// Что capability разрешает делать с shared объектом: какие страницы и с
+/// какими флагами их можно мапить. Исполнять shared память нельзя никогда.
+/// What a capability allows with a shared object: which pages and with
+/// which flags they may be mapped. Shared memory is never executable.
+#[derive(Clone)]
+pub struct MemoryCap {
+    pub object: Arc<SharedMemObject>,
+    flags:      PageFlags,
+}
+
+impl MemoryCap {
+    /// Из `flags` учитывается только WRITABLE / Only WRITABLE is taken from `flags`
+    pub fn new(object: Arc<SharedMemObject>, flags: PageFlags) -> Self {
+        let mut allowed = PageFlags::USER_RW;
+        if !flags.contains(PageFlags::WRITABLE) { allowed -= PageFlags::WRITABLE; }
+        Self { object, flags: allowed }
+    }
+
+    /// Флаги маппинга. Без права записи на саму capability WRITABLE
+    /// снимается: read-only не превращается в writable ни при какой передаче.
+    /// The mapping flags. Without the write right on the capability itself
+    /// WRITABLE is dropped: read-only never turns writable through any transfer.
+    pub fn map_flags(&self, may_write: bool) -> PageFlags {
+        if may_write { self.flags } else { self.flags - PageFlags::WRITABLE }
+    }
+}
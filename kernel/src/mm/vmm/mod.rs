@@ -0,0 +1,1782 @@
+//! Virtual Memory Manager — x86_64 4-level paging
+
+pub mod tlb;
+
+use bitflags::bitflags;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, RwLock};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use super::pmm::{self, PhysAddr, PAGE_SIZE};
+use super::shared::SharedMemObject;
+use super::MmError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct VirtAddr(pub u64);
+
+impl VirtAddr {
+    pub const fn new(addr: u64) -> Self { Self(addr) }
+    pub const fn as_u64(self)   -> u64  { self.0 }
+    pub const fn as_usize(self) -> usize { self.0 as usize }
+    pub const fn as_ptr<T>(self) -> *const T { self.0 as *const T }
+    pub const fn as_mut_ptr<T>(self) -> *mut T { self.0 as *mut T }
+
+    /// Биты 48–63 — копия бита 47. Иначе доступ — #GP, а не #PF, и индексы
+    /// таблиц из «сырых» битов бессмысленны.
+    /// Bits 48–63 copy bit 47. Otherwise an access is a #GP rather than a #PF,
+    /// and table indices computed from the raw bits are meaningless.
+    pub const fn is_canonical(self) -> bool {
+        ((self.0 << 16) as i64 >> 16) as u64 == self.0
+    }
+
+    /// Нижняя (пользовательская) половина / The lower (user) half
+    pub const fn is_user(self) -> bool {
+        self.0 < USER_END
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct PageFlags: u64 {
+        const PRESENT      = 1 << 0;
+        const WRITABLE     = 1 << 1;
+        const USER         = 1 << 2;
+        const WRITE_THROUGH = 1 << 3;
+        const NO_CACHE     = 1 << 4;
+        const ACCESSED     = 1 << 5;
+        const DIRTY        = 1 << 6;
+        const PAT          = 1 << 7;
+        const GLOBAL       = 1 << 8;
+        /// Бит для ПО: страница закреплена (DMA) — не reclaim, не COW
+        /// Software bit: page is pinned (DMA) — no reclaim, no COW
+        const PINNED       = 1 << 9;
+        /// PS в записи PD: она сама отображает 2MB (на уровне PTE это бит PAT)
+        /// PS in a PD entry: it maps 2MB itself (at the PTE level this bit is PAT)
+        const HUGE         = 1 << 7;
+        /// PAT для 2MB-страницы — бит 7 там занят PS / PAT for a 2MB page — bit 7 is taken by PS there
+        const PAT_HUGE     = 1 << 12;
+        const NO_EXEC      = 1 << 63;
+
+        const KERNEL_RO = Self::PRESENT.bits() | Self::GLOBAL.bits() | Self::NO_EXEC.bits();
+        const KERNEL_RW = Self::PRESENT.bits() | Self::WRITABLE.bits()
+                        | Self::GLOBAL.bits()  | Self::NO_EXEC.bits();
+        const KERNEL_EX = Self::PRESENT.bits() | Self::GLOBAL.bits();
+        const USER_RW   = Self::PRESENT.bits() | Self::WRITABLE.bits()
+                        | Self::USER.bits()    | Self::NO_EXEC.bits();
+        const USER_EX   = Self::PRESENT.bits() | Self::USER.bits();
+
+        // PAT индекс 5 — см. arch::x86_64::mm / PAT index 5 — see arch::x86_64::mm
+        const WRITE_COMBINING = Self::PAT.bits() | Self::WRITE_THROUGH.bits();
+    }
+}
+
+const _: () = assert!(
+    PageFlags::WRITE_COMBINING.bits()
+        == crate::arch::x86_64::mm::pat_index_bits(crate::arch::x86_64::mm::PAT_WC_INDEX)
+);
+
+const _: () = assert!(
+    VirtAddr::new(0x0000_7FFF_FFFF_F000).is_canonical()
+        && VirtAddr::new(PHYSICAL_MAP_OFFSET).is_canonical()
+        && !VirtAddr::new(USER_END).is_canonical()
+        && !VirtAddr::new(0xFFFF_7FFF_FFFF_FFFF).is_canonical()
+);
+
+impl PageFlags {
+    /// Флаги для write-combining; без PAT — некэшируемая память.
+    /// Flags for write-combining; without PAT — uncached memory.
+    pub fn write_combining() -> Self {
+        if crate::arch::x86_64::mm::pat_enabled() {
+            Self::WRITE_COMBINING
+        } else {
+            Self::NO_CACHE | Self::WRITE_THROUGH
+        }
+    }
+}
+
+/// Права доступа — то, что меняет `protect`; остальные биты PTE (кэш,
+/// PINNED, A/D) остаются как были.
+/// Access permissions — what `protect` changes; the PTE's other bits
+/// (caching, PINNED, A/D) stay as they were.
+pub const PERMISSION_FLAGS: PageFlags = PageFlags::WRITABLE.union(PageFlags::USER).union(PageFlags::NO_EXEC);
+
+/// W^X: пользовательская страница не бывает записываемой и исполняемой
+/// сразу. Ядро этим правилом не связано, JIT идёт через `map_unchecked`.
+/// W^X: a user page is never writable and executable at once. The kernel
+/// isn't bound by the rule; a JIT goes through `map_unchecked`.
+fn check_wx(flags: PageFlags) -> Result<(), MmError> {
+    let wx = flags.contains(PageFlags::USER | PageFlags::WRITABLE) && !flags.contains(PageFlags::NO_EXEC);
+    if wx { Err(MmError::WriteExec) } else { Ok(()) }
+}
+
+/// Конец пользовательской половины (не включительно) / End of the user half (exclusive)
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+/// С этого адреса `alloc_anonymous` ищет место — ниже лежат образы ELF.
+/// `alloc_anonymous` looks for room from this address up — ELF images live below.
+pub const USER_ALLOC_BASE: u64 = 0x0000_1000_0000_0000;
+
+#[derive(Clone, Copy)]
+pub enum VmaKind {
+    Anonymous,
+    /// Страницы файла — чистые можно выбросить и перечитать через pager
+    /// (`reclaim::set_pager`). `origin` — адрес нулевого смещения в файле:
+    /// он переживает деление VMA.
+    /// File pages — clean ones can be dropped and re-read through the pager
+    /// (`reclaim::set_pager`). `origin` — the address of file offset zero:
+    /// it survives the VMA being split.
+    #[cfg_attr(not(test), allow(dead_code))] // их создаст файловый сервер / the file server will create them
+    File { object: u64, origin: u64 },
+    /// Shared memory (база объекта) — фреймы принадлежат `SharedMemObject`,
+    /// пространство держит на него ссылку.
+    /// Shared memory (object base) — frames belong to the `SharedMemObject`,
+    /// the space holds a reference to it.
+    Shared(PhysAddr),
+    /// Anonymous после `fork`: фреймы могут быть общими с другим
+    /// пространством (`cow`), запись в такой — копия.
+    /// Anonymous after `fork`: frames may be shared with another space
+    /// (`cow`), and writing to one makes a copy.
+    CowAnonymous,
+    Kernel,
+}
+
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end:   VirtAddr,
+    pub flags: PageFlags,
+    pub kind:  VmaKind,
+}
+
+impl Vma {
+    /// Создать VMA `[start, end)`. Пустой или перевёрнутый диапазон — `None`.
+    /// Create a VMA `[start, end)`. An empty or inverted range — `None`.
+    pub fn new(start: VirtAddr, end: VirtAddr, flags: PageFlags, kind: VmaKind) -> Option<Self> {
+        if end.as_u64() <= start.as_u64() { return None; }
+        Some(Self { start, end, flags, kind })
+    }
+
+    /// Создать VMA `[start, start + size)` без переполнения.
+    /// Create a VMA `[start, start + size)` without overflow.
+    pub fn with_size(start: VirtAddr, size: u64, flags: PageFlags, kind: VmaKind) -> Option<Self> {
+        let end = start.as_u64().checked_add(size)?;
+        Self::new(start, VirtAddr::new(end), flags, kind)
+    }
+
+    /// Размер в байтах; `new` гарантирует `end > start`. Для тестов.
+    /// Size in bytes; `new` guarantees `end > start`. For tests.
+    #[cfg(test)]
+    pub fn size(&self) -> u64 {
+        self.end.as_u64() - self.start.as_u64()
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr.as_u64() >= self.start.as_u64() && addr.as_u64() < self.end.as_u64()
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    fn new(phys: PhysAddr, flags: PageFlags) -> Self {
+        Self((phys.as_u64() & !0xFFF) | flags.bits())
+    }
+    fn is_present(self) -> bool { self.0 & PageFlags::PRESENT.bits() != 0 }
+    /// Только для записей PD / For PD entries only
+    fn is_huge(self)    -> bool { self.0 & PageFlags::HUGE.bits() != 0 }
+    fn phys_addr(self)  -> PhysAddr { PhysAddr::new(self.0 & 0x000F_FFFF_FFFF_F000) }
+    fn flags(self)      -> PageFlags { PageFlags::from_bits_truncate(self.0 & !0x000F_FFFF_FFFF_F000) }
+}
+
+#[repr(C, align(4096))]
+struct PageTable {
+    entries: [PageTableEntry; 512],
+}
+
+impl PageTable {
+    fn is_empty(&self) -> bool {
+        self.entries.iter().all(|e| !e.is_present())
+    }
+}
+
+/// Размер большой страницы (запись PD с PS) / Huge page size (a PD entry with PS)
+pub const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Первый индекс PML4 верхней (ядерной) половины / The first PML4 index of the upper (kernel) half
+const KERNEL_PML4_START: usize = 256;
+
+/// VMA, отсортированные по `start` и не пересекающиеся — поиск двоичный.
+/// VMAs sorted by `start` and never overlapping — lookup is a binary search.
+struct VmaList {
+    vmas: Vec<Vma>,
+}
+
+impl VmaList {
+    const fn new() -> Self {
+        Self { vmas: Vec::new() }
+    }
+
+    /// Вставить на своё место. Пересечение — `VmaOverlap`, нет памяти — `OutOfMemory`.
+    /// Insert in place. An overlap — `VmaOverlap`, no memory — `OutOfMemory`.
+    fn insert(&mut self, vma: Vma) -> Result<(), MmError> {
+        let idx = self.vmas.partition_point(|v| v.start < vma.start);
+        let overlaps_prev = idx > 0 && self.vmas[idx - 1].end > vma.start;
+        let overlaps_next = self.vmas.get(idx).is_some_and(|next| next.start < vma.end);
+        if overlaps_prev || overlaps_next { return Err(MmError::VmaOverlap); }
+        self.reserve(1)?;
+        self.vmas.insert(idx, vma);
+        Ok(())
+    }
+
+    /// Запас под `extra` вставок — после него `insert` не упирается в память.
+    /// Room for `extra` inserts — after it `insert` can't run out of memory.
+    fn reserve(&mut self, extra: usize) -> Result<(), MmError> {
+        self.vmas.try_reserve(extra).map_err(|_| MmError::OutOfMemory)
+    }
+
+    /// Первая дыра не меньше `size` не ниже `from` и до `USER_END`.
+    /// The first gap of at least `size` at or above `from` and below `USER_END`.
+    fn find_gap(&self, from: u64, size: u64) -> Option<u64> {
+        let mut start = from;
+        for vma in self.vmas.iter().filter(|v| v.end.as_u64() > from) {
+            if vma.start.as_u64() >= start.checked_add(size)? { break; }
+            start = start.max(vma.end.as_u64());
+        }
+        (start.checked_add(size)? <= USER_END).then_some(start)
+    }
+
+    fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        let idx = self.vmas.partition_point(|v| v.start <= addr);
+        self.vmas[..idx].last().filter(|vma| vma.contains(addr))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.iter()
+    }
+
+    /// Границы менять нельзя — порядок держится на них.
+    /// Bounds must not change — the ordering relies on them.
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Vma> {
+        self.vmas.iter_mut()
+    }
+
+    /// Убрать VMA, начинающуюся ровно в `start` / Remove the VMA starting exactly at `start`
+    fn remove(&mut self, start: VirtAddr) -> Option<Vma> {
+        let idx = self.vmas.binary_search_by_key(&start, |v| v.start).ok()?;
+        Some(self.vmas.remove(idx))
+    }
+}
+
+/// Адресное пространство / Address space.
+///
+/// VMA список под `RwLock`: page fault'ы в разных VMA берут только read,
+/// map/unmap — write. Правки page tables сериализуются отдельным `tables`.
+/// VMA list is behind an `RwLock`: faults in different VMAs only take the
+/// read side, map/unmap take the write side. Page-table edits are
+/// serialized separately by `tables`.
+pub struct AddressSpace {
+    pub pml4: PhysAddr,
+    vmas:     RwLock<VmaList>,
+    tables:   Mutex<()>,
+    /// Стрелка clock-алгоритма reclaim / Reclaim clock hand
+    pub(crate) clock_hand: AtomicU64,
+    /// Замапленные shared объекты: (начало VMA, ссылка).
+    /// Mapped shared objects: (VMA start, reference).
+    shared:     Mutex<Vec<(VirtAddr, Arc<SharedMemObject>)>>,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, MmError> {
+        let pml4_phys = pmm::alloc_zeroed_page()?;
+        Ok(Self {
+            pml4:       pml4_phys,
+            vmas:       RwLock::new(VmaList::new()),
+            tables:     Mutex::new(()),
+            clock_hand: AtomicU64::new(0),
+            shared:     Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Замаппить страницу. Не хватило памяти под таблицу — `OutOfMemory`,
+    /// W+X для пользователя — `WriteExec`.
+    /// Map a page. No memory for a page table — `OutOfMemory`, W+X for
+    /// userspace — `WriteExec`.
+    pub fn map(&self, virt: VirtAddr, phys: PhysAddr, flags: PageFlags) -> Result<(), MmError> {
+        check_wx(flags)?;
+        self.map_unchecked(virt, phys, flags)
+    }
+
+    /// `map` без проверки W^X — только для JIT и ему подобных.
+    /// `map` without the W^X check — for a JIT and the like only.
+    pub fn map_unchecked(&self, virt: VirtAddr, phys: PhysAddr, flags: PageFlags) -> Result<(), MmError> {
+        if !virt.is_canonical() { return Err(MmError::NonCanonical(virt)); }
+        let _tables = self.tables.lock();
+        unsafe { map_page(self.pml4, virt, phys, flags) }
+    }
+
+    /// Замаппить 2MB одной записью PD. Оба адреса выровнены по 2MB, иначе
+    /// `InvalidRange`; занятое место (таблица PT или другая большая
+    /// страница) — тоже. Фрейм, как и в `map`, остаётся за вызывающим.
+    /// Map 2MB with a single PD entry. Both addresses must be 2MB-aligned,
+    /// otherwise `InvalidRange`; so is a taken slot (a PT or another huge
+    /// page). As with `map`, the frame stays the caller's.
+    pub fn map_huge_2m(&self, virt: VirtAddr, phys: PhysAddr, flags: PageFlags) -> Result<(), MmError> {
+        if !virt.is_canonical() { return Err(MmError::NonCanonical(virt)); }
+        if !virt.as_u64().is_multiple_of(HUGE_PAGE_SIZE) || !phys.as_u64().is_multiple_of(HUGE_PAGE_SIZE) {
+            return Err(MmError::InvalidRange);
+        }
+        check_wx(flags)?;
+        // PAT переезжает из бита 7 в бит 12 / PAT moves from bit 7 to bit 12
+        let mut flags = flags;
+        if flags.contains(PageFlags::PAT) { flags = (flags - PageFlags::PAT) | PageFlags::PAT_HUGE; }
+
+        let _tables = self.tables.lock();
+        unsafe {
+            let pml4 = phys_to_virt(self.pml4).as_mut_ptr::<PageTable>();
+            let pdpt = get_or_create(&mut (*pml4).entries[pml4_idx(virt)])?;
+            let pd   = get_or_create(&mut (*pdpt).entries[pdpt_idx(virt)])?;
+            let entry = &mut (*pd).entries[pd_idx(virt)];
+            if entry.is_present() { return Err(MmError::InvalidRange); }
+            *entry = PageTableEntry::new(phys, flags | PageFlags::HUGE);
+            tlb::flush(virt);
+        }
+        Ok(())
+    }
+
+    pub fn unmap(&self, virt: VirtAddr) -> Result<(), MmError> {
+        if !virt.is_canonical() { return Err(MmError::NonCanonical(virt)); }
+        let _tables = self.tables.lock();
+        unsafe { unmap_page(self.pml4, virt); }
+        Ok(())
+    }
+
+    /// Неканонический адрес не транслируется / A non-canonical address doesn't translate
+    pub fn translate(&self, virt: VirtAddr) -> Option<PhysAddr> {
+        if !virt.is_canonical() { return None; }
+        let _tables = self.tables.lock();
+        unsafe { translate_addr(self.pml4, virt) }
+    }
+
+    /// Замаппить страницу, только если она ещё не замаплена.
+    /// Map a page only if nothing is mapped there yet.
+    ///
+    /// Проверка и запись под одним `tables` lock — два потока, упавшие
+    /// в одну страницу, не замаппят её дважды.
+    /// Check and write happen under one `tables` lock — two threads faulting
+    /// on the same page won't both map it.
+    fn map_if_absent(&self, virt: VirtAddr, phys: PhysAddr, flags: PageFlags) -> Result<bool, MmError> {
+        let _tables = self.tables.lock();
+        unsafe {
+            if translate_addr(self.pml4, virt).is_some() { return Ok(false); }
+            map_page(self.pml4, virt, phys, flags)?;
+        }
+        Ok(true)
+    }
+
+    /// Сделать COW-страницу `virt` своей и записываемой. Фрейм больше
+    /// никто не держит — просто вернуть запись; иначе скопировать в новый
+    /// и отпустить общий.
+    /// Make the COW page `virt` private and writable. If nobody else holds
+    /// the frame, just restore write access; otherwise copy it into a new
+    /// one and let go of the shared one.
+    fn break_cow(&self, virt: VirtAddr, flags: PageFlags) -> bool {
+        let _tables = self.tables.lock();
+        unsafe {
+            let Some(entry) = leaf_entry(self.pml4, virt) else { return false };
+            let old = (*entry).phys_addr();
+            if !super::cow::is_shared(old) {
+                (*entry).0 |= PageFlags::WRITABLE.bits();
+            } else {
+                let Ok(new) = pmm::alloc_page() else { return false };
+                core::ptr::copy_nonoverlapping(phys_to_virt(old).as_ptr::<u8>(),
+                                               phys_to_virt(new).as_mut_ptr::<u8>(), PAGE_SIZE);
+                *entry = PageTableEntry::new(new, flags | PageFlags::PRESENT);
+                super::cow::release(old);
+            }
+            tlb::flush(virt);
+        }
+        true
+    }
+
+    /// Флаги листового PTE, если страница замаплена.
+    /// Leaf PTE flags, if the page is mapped.
+    pub(crate) fn leaf_flags(&self, virt: VirtAddr) -> Option<PageFlags> {
+        let _tables = self.tables.lock();
+        unsafe { leaf_entry(self.pml4, virt).map(|e| (*e).flags()) }
+    }
+
+    /// Сбросить биты в листовом PTE / Clear bits in the leaf PTE.
+    pub(crate) fn clear_leaf_flags(&self, virt: VirtAddr, flags: PageFlags) -> bool {
+        let _tables = self.tables.lock();
+        unsafe {
+            let Some(entry) = leaf_entry(self.pml4, virt) else { return false };
+            (*entry).0 &= !flags.bits();
+            tlb::flush(virt);
+        }
+        true
+    }
+
+    /// `(accessed, dirty)` листового PTE — что видит clock-reclaim.
+    /// `(accessed, dirty)` of the leaf PTE — what clock reclaim sees.
+    #[cfg_attr(not(test), allow(dead_code))] // для будущего swapper'а / for the future swapper
+    pub fn page_stats(&self, virt: VirtAddr) -> Option<(bool, bool)> {
+        self.leaf_flags(virt)
+            .map(|f| (f.contains(PageFlags::ACCESSED), f.contains(PageFlags::DIRTY)))
+    }
+
+    /// Сбросить Accessed — второй шанс для страницы. Не замаплена — `false`.
+    /// Clear Accessed — a second chance for the page. Not mapped — `false`.
+    pub fn clear_accessed(&self, virt: VirtAddr) -> bool {
+        self.clear_leaf_flags(virt, PageFlags::ACCESSED)
+    }
+
+    /// Поставить биты в листовом PTE / Set bits in the leaf PTE.
+    fn set_leaf_flags(&self, virt: VirtAddr, flags: PageFlags) -> bool {
+        let _tables = self.tables.lock();
+        unsafe {
+            let Some(entry) = leaf_entry(self.pml4, virt) else { return false };
+            (*entry).0 |= flags.bits();
+        }
+        true
+    }
+
+    /// Заменить флаги замапленной страницы, сохранив фрейм.
+    /// Replace a mapped page's flags, keeping its frame.
+    pub(crate) fn set_page_flags(&self, virt: VirtAddr, flags: PageFlags) -> bool {
+        let _tables = self.tables.lock();
+        unsafe {
+            let Some(entry) = leaf_entry(self.pml4, virt) else { return false };
+            *entry = PageTableEntry::new((*entry).phys_addr(), flags);
+            tlb::flush(virt);
+        }
+        true
+    }
+
+    /// Закрепить страницы `[virt, virt + len)`: сразу подкрепить их фреймами
+    /// (prefault) и пометить `PINNED` — reclaim их пропускает, а COW fork
+    /// обязан оставить их общими и записываемыми. Если какую-то страницу
+    /// замаппить нельзя, уже закреплённые откатываются.
+    /// Pin the pages of `[virt, virt + len)`: back them with frames right
+    /// away (prefault) and mark them `PINNED` — reclaim skips them and a COW
+    /// fork must keep them shared and writable. If any page can't be mapped,
+    /// the ones already pinned are rolled back.
+    #[cfg_attr(not(test), allow(dead_code))] // DMA-драйверов ещё нет / no DMA drivers yet
+    pub fn pin(&self, virt: VirtAddr, len: u64) -> Result<(), MmError> {
+        let (start, end) = page_span(virt, len).ok_or(MmError::InvalidRange)?;
+        let mut page = start;
+        while page < end {
+            let addr = VirtAddr::new(page);
+            let mapped = self.translate(addr).is_some() || {
+                // Записываемую VMA префолтим «записью» / Prefault a writable VMA as a write
+                let write = self.find_vma(addr).is_some_and(|(f, _)| f.contains(PageFlags::WRITABLE));
+                handle_page_fault(self, addr, if write { 0x2 } else { 0 })
+            };
+            if !mapped || !self.set_leaf_flags(addr, PageFlags::PINNED) {
+                self.unpin_range(start, page);
+                return Err(MmError::Unmappable(addr));
+            }
+            page += PAGE_SIZE as u64;
+        }
+        Ok(())
+    }
+
+    /// Снять закрепление / Unpin
+    #[cfg_attr(not(test), allow(dead_code))] // пара к `pin` / the pair of `pin`
+    pub fn unpin(&self, virt: VirtAddr, len: u64) {
+        if let Some((start, end)) = page_span(virt, len) { self.unpin_range(start, end); }
+    }
+
+    fn unpin_range(&self, start: u64, end: u64) {
+        for page in (start..end).step_by(PAGE_SIZE) {
+            self.clear_leaf_flags(VirtAddr::new(page), PageFlags::PINNED);
+        }
+    }
+
+    /// Закреплена ли страница / Whether the page is pinned
+    pub fn is_pinned(&self, virt: VirtAddr) -> bool {
+        self.leaf_flags(virt).is_some_and(|f| f.contains(PageFlags::PINNED))
+    }
+
+    /// Снять маппинг и вернуть физический фрейм, который там был.
+    /// Опустевшие таблицы уходят в PMM, как в `unmap`.
+    /// Unmap a page and return the physical frame that was there. Emptied
+    /// tables go back to the PMM, as in `unmap`.
+    pub(crate) fn take_page(&self, virt: VirtAddr) -> Option<PhysAddr> {
+        let _tables = self.tables.lock();
+        unsafe {
+            let phys = (*leaf_entry(self.pml4, virt)?).phys_addr();
+            unmap_page(self.pml4, virt);
+            Some(phys)
+        }
+    }
+
+    /// Снимок `(start, end, kind)` всех VMA / Snapshot of `(start, end, kind)` for every VMA.
+    pub(crate) fn vma_ranges(&self, out: &mut [(VirtAddr, VirtAddr, VmaKind)]) -> usize {
+        let vmas = self.vmas.read();
+        let mut n = 0;
+        for (slot, vma) in out.iter_mut().zip(vmas.iter()) {
+            *slot = (vma.start, vma.end, vma.kind);
+            n += 1;
+        }
+        n
+    }
+
+    #[cfg(not(test))]
+    pub fn activate(&self) {
+        unsafe {
+            core::arch::asm!("mov cr3, {}", in(reg) self.pml4.as_u64(), options(nostack));
+        }
+    }
+
+    // Таблицы в тестах не активируются: CR3 — PML4, запомненный потоком теста
+    // Tables are never activated in tests: CR3 is a PML4 the test thread remembers
+    #[cfg(test)]
+    pub fn activate(&self) {
+        ACTIVE_PML4.with(|cr3| cr3.set(self.pml4.as_u64()));
+    }
+
+    /// Последнее активированное пространство — это / The last space activated is this one
+    #[cfg(test)]
+    pub fn is_active(&self) -> bool {
+        ACTIVE_PML4.with(|cr3| cr3.get()) == self.pml4.as_u64()
+    }
+
+    pub fn add_vma(&self, vma: Vma) -> Result<(), MmError> {
+        self.vmas.write().insert(vma)
+    }
+
+    /// Найти VMA и скопировать её описание (flags, kind) под read lock.
+    /// Find the VMA covering `addr` and copy out its flags and kind under the read lock.
+    pub fn find_vma(&self, addr: VirtAddr) -> Option<(PageFlags, VmaKind)> {
+        self.vmas.read().find(addr).map(|vma| (vma.flags, vma.kind))
+    }
+
+    /// Добавить анонимную VMA. `start + size` с переполнением или `size == 0` — `InvalidRange`,
+    /// W+X — `WriteExec`.
+    /// Add an anonymous VMA. `start + size` overflowing or `size == 0` — `InvalidRange`,
+    /// W+X — `WriteExec`.
+    pub fn map_anonymous(&self, start: VirtAddr, size: u64, flags: PageFlags) -> Result<(), MmError> {
+        check_wx(flags)?;
+        let vma = Vma::with_size(start, size, flags, VmaKind::Anonymous).ok_or(MmError::InvalidRange)?;
+        self.add_vma(vma)
+    }
+
+    /// Анонимная VMA на `size` байт (с округлением до страниц) там, где
+    /// есть место, начиная с `USER_ALLOC_BASE`. Возвращает её начало;
+    /// места нет — `OutOfMemory`.
+    /// An anonymous VMA of `size` bytes (rounded up to pages) wherever there
+    /// is room, from `USER_ALLOC_BASE` up. Returns its start; no room —
+    /// `OutOfMemory`.
+    pub fn alloc_anonymous(&self, size: u64, flags: PageFlags) -> Result<VirtAddr, MmError> {
+        check_wx(flags)?;
+        if size == 0 { return Err(MmError::InvalidRange); }
+        let size = size.checked_next_multiple_of(PAGE_SIZE as u64).ok_or(MmError::InvalidRange)?;
+        let mut vmas = self.vmas.write();
+        let start = vmas.find_gap(USER_ALLOC_BASE, size).ok_or(MmError::OutOfMemory)?;
+        let vma = Vma::with_size(VirtAddr::new(start), size, flags, VmaKind::Anonymous).ok_or(MmError::InvalidRange)?;
+        vmas.insert(vma)?;
+        Ok(VirtAddr::new(start))
+    }
+
+    /// Пользовательский стек: `pages` анонимных страниц под `top` и страница
+    /// защиты прямо под ними — без VMA, так что переполнение даёт segfault,
+    /// а не молча растёт в соседние данные. Занятая страница защиты —
+    /// `VmaOverlap`.
+    /// A user stack: `pages` anonymous pages below `top` and a guard page
+    /// right beneath them — with no VMA, so an overflow segfaults instead of
+    /// silently growing into neighbouring data. A taken guard page —
+    /// `VmaOverlap`.
+    pub fn map_stack(&self, top: VirtAddr, pages: usize, flags: PageFlags) -> Result<(), MmError> {
+        if !top.as_u64().is_multiple_of(PAGE_SIZE as u64) || top.as_u64() > USER_END || pages == 0 {
+            return Err(MmError::InvalidRange);
+        }
+        check_wx(flags)?;
+        let size = (pages as u64).checked_mul(PAGE_SIZE as u64).ok_or(MmError::InvalidRange)?;
+        // Место под страницу защиты тоже должно быть / The guard page needs room too
+        let bottom = top.as_u64().checked_sub(size).ok_or(MmError::InvalidRange)?;
+        let guard = bottom.checked_sub(PAGE_SIZE as u64).ok_or(MmError::InvalidRange)?;
+        let vma = Vma::new(VirtAddr::new(bottom), top, flags, VmaKind::Anonymous).ok_or(MmError::InvalidRange)?;
+
+        let mut vmas = self.vmas.write();
+        if vmas.find(VirtAddr::new(guard)).is_some() { return Err(MmError::VmaOverlap); }
+        vmas.insert(vma)
+    }
+}
+
+impl AddressSpace {
+    /// Снять `[start, start + len)` (len округляется до страниц) со
+    /// своими фреймами. Диапазон обязан целиком лежать в одной
+    /// пользовательской VMA, которой пространство владеет (anonymous или
+    /// file) — shared снимается через `unmap_shared`, kernel не снимается
+    /// никогда. VMA укорачивается или делится на две. Закреплённые
+    /// страницы не снимаются.
+    /// Unmap `[start, start + len)` (len rounded up to pages) along with its
+    /// frames. The range must lie wholly inside one user VMA the space owns
+    /// (anonymous or file) — shared goes through `unmap_shared`, kernel is
+    /// never unmapped. The VMA is trimmed or split in two. Pinned pages are
+    /// not unmapped.
+    pub fn unmap_range(&self, start: VirtAddr, len: u64) -> Result<(), MmError> {
+        if !start.as_u64().is_multiple_of(PAGE_SIZE as u64) { return Err(MmError::InvalidRange); }
+        let (_, end) = page_span(start, len).ok_or(MmError::InvalidRange)?;
+        if !start.is_user() || end > USER_END { return Err(MmError::InvalidRange); }
+
+        let mut vmas = self.vmas.write();
+        let vma = vmas.find(start).ok_or(MmError::InvalidRange)?;
+        if end > vma.end.as_u64()
+            || !matches!(vma.kind, VmaKind::Anonymous | VmaKind::CowAnonymous | VmaKind::File { .. })
+        {
+            return Err(MmError::InvalidRange);
+        }
+        let (vma_start, vma_end, flags, kind) = (vma.start, vma.end, vma.flags, vma.kind);
+        // Дыра посередине — из одной VMA две / A hole in the middle — one VMA becomes two
+        let splits = vma_start != start && vma_end.as_u64() != end;
+        vmas.reserve(splits as usize)?;
+        if (start.as_u64()..end).step_by(PAGE_SIZE).any(|p| self.is_pinned(VirtAddr::new(p))) {
+            return Err(MmError::InvalidRange);
+        }
+
+        // Куски удалённой VMA не пересекаются и место под них есть
+        // Pieces of the removed VMA don't overlap and there is room for them
+        vmas.remove(vma_start);
+        if let Some(left) = Vma::new(vma_start, start, flags, kind) { vmas.insert(left)?; }
+        if let Some(right) = Vma::new(VirtAddr::new(end), vma_end, flags, kind) { vmas.insert(right)?; }
+        for page in (start.as_u64()..end).step_by(PAGE_SIZE) {
+            if let Some(phys) = self.take_page(VirtAddr::new(page)) { super::cow::release(phys); }
+        }
+        Ok(())
+    }
+
+    /// Копия пространства для fork. Верхняя половина — те же таблицы ядра.
+    /// Anonymous-страницы не копируются: оба пространства мапят один фрейм
+    /// только на чтение, VMA становятся `CowAnonymous`, копию делает первая
+    /// запись (`handle_page_fault`). Закреплённые страницы остаются общими
+    /// и записываемыми. Shared-объекты мапятся в потомка по ссылке.
+    /// Файловые VMA пока не наследуются.
+    /// A copy of the space for fork. The upper half is the same kernel
+    /// tables. Anonymous pages aren't copied: both spaces map the one frame
+    /// read-only, the VMAs become `CowAnonymous`, and the first write makes
+    /// the copy (`handle_page_fault`). Pinned pages stay shared and
+    /// writable. Shared objects are mapped into the child by reference. File
+    /// VMAs are not inherited yet.
+    #[cfg_attr(not(test), allow(dead_code))] // task_spawn пока грузит ELF заново / task_spawn still loads the ELF afresh
+    pub fn fork(&self) -> Result<AddressSpace, MmError> {
+        let child = AddressSpace::new()?;
+        unsafe {
+            let src = &*phys_to_virt(self.pml4).as_ptr::<PageTable>();
+            let dst = &mut *phys_to_virt(child.pml4).as_mut_ptr::<PageTable>();
+            dst.entries[KERNEL_PML4_START..].copy_from_slice(&src.entries[KERNEL_PML4_START..]);
+        }
+
+        let mut vmas = self.vmas.write();
+        for vma in vmas.iter_mut() {
+            match vma.kind {
+                VmaKind::Anonymous | VmaKind::CowAnonymous => {
+                    vma.kind = VmaKind::CowAnonymous;
+                    child.add_vma(Vma { start: vma.start, end: vma.end, flags: vma.flags, kind: VmaKind::CowAnonymous })?;
+                    for page in (vma.start.as_u64()..vma.end.as_u64()).step_by(PAGE_SIZE) {
+                        self.share_page(&child, VirtAddr::new(page))?;
+                    }
+                }
+                VmaKind::Shared(base) => {
+                    let obj = self.shared.lock().iter()
+                        .find(|(start, _)| *start == vma.start)
+                        .map(|(_, obj)| obj.clone());
+                    if let Some(obj) = obj {
+                        debug_assert!(obj.base() == base);
+                        child.map_shared(vma.start, obj, vma.flags)?;
+                    }
+                }
+                VmaKind::File { .. } | VmaKind::Kernel => {}
+            }
+        }
+        Ok(child)
+    }
+
+    /// Замапить фрейм страницы `virt` и в `child`, сняв запись у обоих
+    /// (кроме закреплённых). Незамапленная страница — пропуск.
+    /// Map the frame of page `virt` into `child` as well, dropping write
+    /// access in both (except pinned pages). An unmapped page is skipped.
+    fn share_page(&self, child: &AddressSpace, virt: VirtAddr) -> Result<(), MmError> {
+        let (phys, flags) = {
+            let _tables = self.tables.lock();
+            unsafe {
+                let Some(entry) = leaf_entry(self.pml4, virt) else { return Ok(()) };
+                let flags = (*entry).flags();
+                if !flags.contains(PageFlags::PINNED) {
+                    (*entry).0 &= !PageFlags::WRITABLE.bits();
+                    tlb::flush(virt);
+                }
+                ((*entry).phys_addr(), (*entry).flags())
+            }
+        };
+        // Ссылку берём до маппинга: упади map — потомок при разборе её отпустит
+        // Take the reference before mapping: if map fails, the child's teardown drops it
+        super::cow::share(phys);
+        child.map_unchecked(virt, phys, flags - PageFlags::ACCESSED - PageFlags::DIRTY).inspect_err(|_| super::cow::release(phys))
+    }
+
+    /// Сменить права `[start, start + len)` (как mprotect). Диапазон обязан
+    /// лежать в одной пользовательской VMA — дыра или стык двух VMA дают
+    /// `InvalidRange`. Присутствующие PTE переписываются сразу (фрейм тот
+    /// же), ещё не тронутые страницы получат новые права из VMA при
+    /// page fault. Часть VMA отделяется в свою.
+    /// Change the permissions of `[start, start + len)` (like mprotect). The
+    /// range must lie in a single user VMA — a hole or a seam between two
+    /// VMAs gives `InvalidRange`. Present PTEs are rewritten at once (same
+    /// frame); pages not yet touched pick up the new permissions from the
+    /// VMA at page-fault time. A part of a VMA is split off into its own.
+    #[cfg_attr(not(test), allow(dead_code))] // syscall'а mprotect пока нет / no mprotect syscall yet
+    pub fn protect(&self, start: VirtAddr, len: u64, flags: PageFlags) -> Result<(), MmError> {
+        if !start.as_u64().is_multiple_of(PAGE_SIZE as u64) { return Err(MmError::InvalidRange); }
+        let (_, end) = page_span(start, len).ok_or(MmError::InvalidRange)?;
+        if !start.is_user() || end > USER_END { return Err(MmError::InvalidRange); }
+
+        let mut vmas = self.vmas.write();
+        let vma = vmas.find(start).ok_or(MmError::InvalidRange)?;
+        if end > vma.end.as_u64() || matches!(vma.kind, VmaKind::Kernel) {
+            return Err(MmError::InvalidRange);
+        }
+        let (vma_start, vma_end, old_flags, kind) = (vma.start, vma.end, vma.flags, vma.kind);
+        let new_flags = (old_flags - PERMISSION_FLAGS) | (flags & PERMISSION_FLAGS);
+        check_wx(new_flags)?;
+        // Куски слева и справа — по месту на каждый сверх текущего
+        // Left and right pieces — room for each beyond the current one
+        let extra = (vma_start != start) as usize + (vma_end.as_u64() != end) as usize;
+        vmas.reserve(extra)?;
+
+        vmas.remove(vma_start);
+        if let Some(left) = Vma::new(vma_start, start, old_flags, kind) { vmas.insert(left)?; }
+        if let Some(right) = Vma::new(VirtAddr::new(end), vma_end, old_flags, kind) { vmas.insert(right)?; }
+        if let Some(middle) = Vma::new(start, VirtAddr::new(end), new_flags, kind) { vmas.insert(middle)?; }
+
+        let _tables = self.tables.lock();
+        for page in (start.as_u64()..end).step_by(PAGE_SIZE) {
+            unsafe {
+                let Some(entry) = leaf_entry(self.pml4, VirtAddr::new(page)) else { continue };
+                let (phys, old) = ((*entry).phys_addr(), (*entry).flags());
+                let mut wanted = (old - PERMISSION_FLAGS) | (flags & PERMISSION_FLAGS);
+                // Общий после fork фрейм остаётся только на чтение: запись
+                // в него разорвёт COW, а не попадёт в чужое пространство
+                // A frame shared since fork stays read-only: a write to it
+                // breaks COW instead of landing in another space
+                if !old.contains(PageFlags::PINNED) && super::cow::is_shared(phys) {
+                    wanted -= PageFlags::WRITABLE;
+                }
+                *entry = PageTableEntry::new(phys, wanted);
+                tlb::flush(VirtAddr::new(page));
+            }
+        }
+        Ok(())
+    }
+
+    /// Замапить shared объект целиком с `start`. Пространство берёт свою
+    /// ссылку на объект и отпускает её в `unmap_shared` или при уничтожении.
+    /// Map a whole shared object at `start`. The space takes its own
+    /// reference to the object and drops it in `unmap_shared` or on teardown.
+    ///
+    /// Не хватило памяти под таблицы посередине — уже замапленные страницы
+    /// снимаются вместе с опустевшими таблицами и VMA убирается:
+    /// пространство остаётся как было.
+    /// Running out of memory for page tables midway unmaps the pages already
+    /// mapped along with the tables they emptied and removes the VMA: the
+    /// space is left as it was.
+    pub fn map_shared(&self, start: VirtAddr, obj: Arc<SharedMemObject>, flags: PageFlags) -> Result<(), MmError> {
+        let vma = Vma::with_size(start, obj.size(), flags, VmaKind::Shared(obj.base()))
+            .ok_or(MmError::InvalidRange)?;
+        check_wx(flags)?;
+        self.add_vma(vma)?;
+        for offset in (0..obj.size()).step_by(PAGE_SIZE) {
+            let mapped = self.map(
+                VirtAddr::new(start.as_u64() + offset),
+                PhysAddr::new(obj.base().as_u64() + offset),
+                flags,
+            );
+            if let Err(err) = mapped {
+                // Фреймы принадлежат объекту — только снять PTE
+                // The frames belong to the object — only clear the PTEs
+                for done in (0..offset).step_by(PAGE_SIZE) {
+                    self.take_page(VirtAddr::new(start.as_u64() + done));
+                }
+                self.vmas.write().remove(start);
+                return Err(err);
+            }
+        }
+        self.shared.lock().push((start, obj));
+        Ok(())
+    }
+
+    /// Снять shared маппинг с началом `start`. Фреймы не освобождаются —
+    /// это сделает объект, когда уйдёт последняя ссылка.
+    /// Unmap the shared mapping starting at `start`. Frames are not freed —
+    /// the object does that when its last reference goes away.
+    pub fn unmap_shared(&self, start: VirtAddr) -> bool {
+        let Some(vma) = self.vmas.write().remove(start) else { return false };
+        for page in (vma.start.as_u64()..vma.end.as_u64()).step_by(PAGE_SIZE) {
+            self.take_page(VirtAddr::new(page));
+        }
+        let obj = {
+            let mut shared = self.shared.lock();
+            let idx = shared.iter().position(|(s, _)| *s == start);
+            idx.map(|i| shared.swap_remove(i))
+        };
+        // Ссылка уходит после всех lock'ов / The reference goes after every lock
+        drop(obj);
+        true
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Разобрать пространство: свои фреймы (anonymous, file) — в PMM,
+    /// shared не трогаем, только отпускаем ссылку; затем таблицы
+    /// нижней половины и PML4. Верхняя половина — общая с ядром.
+    /// Tear the space down: its own frames (anonymous, file) go to the
+    /// PMM, shared ones are left alone and their reference dropped; then
+    /// the lower-half tables and the PML4. The upper half is shared with
+    /// the kernel.
+    fn drop(&mut self) {
+        let pml4 = self.pml4;
+        for vma in self.vmas.get_mut().iter() {
+            if matches!(vma.kind, VmaKind::Shared(_) | VmaKind::Kernel) { continue; }
+            for page in (vma.start.as_u64()..vma.end.as_u64()).step_by(PAGE_SIZE) {
+                if let Some(entry) = unsafe { leaf_entry(pml4, VirtAddr::new(page)) } {
+                    super::cow::release(unsafe { (*entry).phys_addr() });
+                }
+            }
+        }
+        self.shared.get_mut().clear();
+        unsafe {
+            let pml4 = &*phys_to_virt(self.pml4).as_ptr::<PageTable>();
+            for entry in pml4.entries[..256].iter().filter(|e| e.is_present()) {
+                free_table(entry.phys_addr(), 3);
+            }
+        }
+        pmm::free_page(self.pml4);
+    }
+}
+
+/// Освободить таблицу уровня `level` (3 — PDPT ... 1 — PT) и всё под ней,
+/// кроме листовых фреймов.
+/// Free a table of `level` (3 — PDPT ... 1 — PT) and everything below it,
+/// except leaf frames.
+unsafe fn free_table(table: PhysAddr, level: u8) {
+    if level > 1 {
+        let t = unsafe { &*phys_to_virt(table).as_ptr::<PageTable>() };
+        // 2MB-страница в PD — не таблица, и фрейм не наш
+        // A 2MB page in a PD is not a table, and the frame isn't ours
+        for entry in t.entries.iter().filter(|e| e.is_present() && !(level == 2 && e.is_huge())) {
+            unsafe { free_table(entry.phys_addr(), level - 1); }
+        }
+    }
+    pmm::free_page(table);
+}
+
+/// Страницы, покрывающие `[virt, virt + len)` / Pages covering `[virt, virt + len)`
+fn page_span(virt: VirtAddr, len: u64) -> Option<(u64, u64)> {
+    if len == 0 { return None; }
+    let start = virt.as_u64() & !(PAGE_SIZE as u64 - 1);
+    let end = virt.as_u64().checked_add(len)?.checked_next_multiple_of(PAGE_SIZE as u64)?;
+    Some((start, end))
+}
+
+/// Бит error code: нарушение зарезервированного бита в таблице страниц.
+/// Error code bit: reserved bit violation in a paging structure.
+const PF_RESERVED: u64 = 1 << 3;
+
+/// Класс page fault по error code / Page fault class by error code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultClass {
+    /// Обычный fault — можно подгрузить страницу / Ordinary fault — may demand-page
+    Demand,
+    /// Кто-то записал в таблицу страниц биты, которые обязаны быть нулём.
+    /// Никогда не восстановимо.
+    /// Something set must-be-zero bits in a page table. Never recoverable.
+    ReservedBit,
+}
+
+pub fn classify_fault(error: u64) -> FaultClass {
+    if error & PF_RESERVED != 0 { FaultClass::ReservedBit } else { FaultClass::Demand }
+}
+
+/// Напечатать сырые записи всех уровней для `virt` / Print the raw entries of every level for `virt`
+fn dump_walk(pml4_phys: PhysAddr, virt: VirtAddr) {
+    let indices = [pml4_idx(virt), pdpt_idx(virt), pd_idx(virt), pt_idx(virt)];
+    let mut table = pml4_phys;
+    for (level, idx) in ["PML4E", "PDPTE", "PDE", "PTE"].iter().zip(indices) {
+        let entry = unsafe { (*phys_to_virt(table).as_ptr::<PageTable>()).entries[idx] };
+        crate::kprintln!("  {:5}[{:3}] = {:#018x}", level, idx, entry.0);
+        if !entry.is_present() || (*level == "PDE" && entry.is_huge()) { break; }
+        table = entry.phys_addr();
+    }
+}
+
+/// Обработать page fault. Берёт только read lock на VMA список, поэтому
+/// fault'ы в разных VMA идут параллельно.
+/// Handle a page fault. Only the VMA read lock is taken, so faults in
+/// different VMAs proceed concurrently.
+pub fn handle_page_fault(space: &AddressSpace, fault_addr: VirtAddr, error: u64) -> bool {
+    if classify_fault(error) == FaultClass::ReservedBit {
+        crate::kprintln!("[vmm] reserved-bit #PF at {:#x}, error={:#x} — corrupt page table:",
+                         fault_addr.as_u64(), error);
+        dump_walk(space.pml4, fault_addr);
+        panic!("page table corruption (reserved bit set)");
+    }
+    let is_write = error & 0x2 != 0;
+    let is_present = error & 0x1 != 0;
+    // Вне VMA, в том числе страница защиты стека / Outside any VMA, stack guard pages included
+    let (flags, kind) = match space.find_vma(fault_addr) { Some(v) => v, None => return false };
+    if is_write && !flags.contains(PageFlags::WRITABLE) { return false; }
+    match kind {
+        // Запись в присутствующую COW-страницу — своя копия
+        // A write to a present COW page — a private copy
+        VmaKind::CowAnonymous if is_write && is_present => {
+            let page_start = VirtAddr::new(fault_addr.as_u64() & !(PAGE_SIZE as u64 - 1));
+            space.break_cow(page_start, flags)
+        }
+        // Страница есть, а fault всё равно случился — нарушение прав (NX,
+        // запись в read-only): «обработать» значит вечно падать снова
+        // The page is there and still faulted — a protection violation (NX,
+        // a write to read-only): "handling" it would just fault forever
+        VmaKind::Anonymous | VmaKind::CowAnonymous | VmaKind::File { .. } if is_present => false,
+        VmaKind::Anonymous | VmaKind::CowAnonymous => fault_in(space, fault_addr, flags, |_, _| true),
+        // Выброшенная reclaim'ом или ещё не читанная страница файла
+        // A file page dropped by reclaim or never read yet
+        VmaKind::File { object, origin } => fault_in(space, fault_addr, flags, |page_start, page| {
+            page_start.checked_sub(origin).is_some_and(|offset| super::reclaim::read_page(object, offset, page))
+        }),
+        _ => false,
+    }
+}
+
+/// Новая обнулённая страница под `fault_addr`, заполненная `fill`
+/// (адрес страницы, её байты); `fill` вернул `false` — fault не обработан.
+/// A new zeroed page under `fault_addr`, filled by `fill` (the page's
+/// address, its bytes); `fill` returning `false` leaves the fault unhandled.
+fn fault_in(
+    space: &AddressSpace,
+    fault_addr: VirtAddr,
+    flags: PageFlags,
+    fill: impl FnOnce(u64, &mut [u8]) -> bool,
+) -> bool {
+    // Память на исходе — сначала выбросить чистые файловые страницы
+    // Memory running low — drop clean file pages first
+    if pmm::below_low_watermark() {
+        super::reclaim::reclaim(space, super::reclaim::RECLAIM_BATCH);
+    }
+    let Ok(phys) = pmm::alloc_zeroed_page() else { return false };
+    let page_start = VirtAddr::new(fault_addr.as_u64() & !(PAGE_SIZE as u64 - 1));
+    // Фрейм только что выделен и ещё ни с кем не разделён
+    // The frame was just allocated and isn't shared with anyone yet
+    let page = unsafe { core::slice::from_raw_parts_mut(phys_to_virt(phys).as_mut_ptr::<u8>(), PAGE_SIZE) };
+    if !fill(page_start.as_u64(), page) {
+        pmm::free_page(phys);
+        return false;
+    }
+    // Другой поток мог уже замаппить эту страницу — тогда наш фрейм
+    // лишний, а повтор доступа пройдёт.
+    // Another thread may have mapped this page already — then our
+    // frame is spare and the retried access goes through.
+    match space.map_if_absent(page_start, phys, flags) {
+        Ok(true)  => true,
+        Ok(false) => { pmm::free_page(phys); true }
+        Err(_)    => { pmm::free_page(phys); false }
+    }
+}
+
+fn pml4_idx(addr: VirtAddr) -> usize { ((addr.as_u64() >> 39) & 0x1FF) as usize }
+fn pdpt_idx(addr: VirtAddr) -> usize { ((addr.as_u64() >> 30) & 0x1FF) as usize }
+fn pd_idx  (addr: VirtAddr) -> usize { ((addr.as_u64() >> 21) & 0x1FF) as usize }
+fn pt_idx  (addr: VirtAddr) -> usize { ((addr.as_u64() >> 12) & 0x1FF) as usize }
+
+unsafe fn get_or_create(entry: &mut PageTableEntry) -> Result<*mut PageTable, MmError> {
+    if !entry.is_present() {
+        let phys = pmm::alloc_zeroed_page()?;
+        *entry = PageTableEntry::new(
+            phys,
+            PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER,
+        );
+    }
+    Ok(phys_to_virt(entry.phys_addr()).as_mut_ptr::<PageTable>())
+}
+
+/// Промежуточные таблицы, созданные до отказа, остаются — они пустые и
+/// пригодятся следующему маппингу.
+/// Intermediate tables created before a failure stay — they're empty and
+/// will serve the next mapping.
+unsafe fn map_page(pml4_phys: PhysAddr, virt: VirtAddr, phys: PhysAddr, flags: PageFlags) -> Result<(), MmError> {
+    unsafe {
+        let pml4 = phys_to_virt(pml4_phys).as_mut_ptr::<PageTable>();
+        let pdpt = get_or_create(&mut (*pml4).entries[pml4_idx(virt)])?;
+        let pd   = get_or_create(&mut (*pdpt).entries[pdpt_idx(virt)])?;
+        // Внутри 2MB-страницы 4KB не замаппить — PT там нет
+        // No 4KB mapping inside a 2MB page — there is no PT there
+        if (*pd).entries[pd_idx(virt)].is_huge() { return Err(MmError::InvalidRange); }
+        let pt   = get_or_create(&mut (*pd  ).entries[pd_idx  (virt)])?;
+        (*pt).entries[pt_idx(virt)] = PageTableEntry::new(phys, flags);
+        tlb::flush(virt);
+    }
+    Ok(())
+}
+
+unsafe fn unmap_page(pml4_phys: PhysAddr, virt: VirtAddr) {
+    unsafe {
+        let pml4 = phys_to_virt(pml4_phys).as_mut_ptr::<PageTable>();
+        let e0 = &mut (*pml4).entries[pml4_idx(virt)];
+        if !e0.is_present() { return; }
+        let pdpt = phys_to_virt(e0.phys_addr()).as_mut_ptr::<PageTable>();
+        let e1 = &mut (*pdpt).entries[pdpt_idx(virt)];
+        if !e1.is_present() { return; }
+        let pd = phys_to_virt(e1.phys_addr()).as_mut_ptr::<PageTable>();
+        let e2 = &mut (*pd).entries[pd_idx(virt)];
+        if !e2.is_present() { return; }
+        // Большая страница снимается целиком / A huge page goes as a whole
+        if e2.is_huge() {
+            *e2 = PageTableEntry(0);
+            tlb::flush(virt);
+            return;
+        }
+        let pt = phys_to_virt(e2.phys_addr()).as_mut_ptr::<PageTable>();
+        (*pt).entries[pt_idx(virt)] = PageTableEntry(0);
+        tlb::flush(virt);
+
+        // Опустевшие таблицы пользовательской половины — обратно в PMM,
+        // снизу вверх. Ядерную не трогаем: её таблицы общие для всех
+        // пространств, а часть (образ ядра) и вовсе от Limine, не из PMM.
+        // invlpg выше уже сбросил и кэши структур страниц.
+        // Emptied user-half tables go back to the PMM, bottom up. The kernel
+        // half stays: its tables are shared by every space, and some (the
+        // kernel image) come from Limine rather than the PMM. The invlpg above
+        // has already flushed the paging-structure caches too.
+        if pml4_idx(virt) >= KERNEL_PML4_START || !(*pt).is_empty() { return; }
+        pmm::free_page(e2.phys_addr());
+        *e2 = PageTableEntry(0);
+        if !(*pd).is_empty() { return; }
+        pmm::free_page(e1.phys_addr());
+        *e1 = PageTableEntry(0);
+        if !(*pdpt).is_empty() { return; }
+        pmm::free_page(e0.phys_addr());
+        *e0 = PageTableEntry(0);
+    }
+}
+
+/// Указатель на листовой PTE, если он присутствует.
+/// Pointer to the leaf PTE, if present.
+unsafe fn leaf_entry(pml4_phys: PhysAddr, virt: VirtAddr) -> Option<*mut PageTableEntry> {
+    unsafe {
+        let pml4 = phys_to_virt(pml4_phys).as_mut_ptr::<PageTable>();
+        let e0 = (*pml4).entries[pml4_idx(virt)];
+        if !e0.is_present() { return None; }
+        let pdpt = phys_to_virt(e0.phys_addr()).as_mut_ptr::<PageTable>();
+        let e1 = (*pdpt).entries[pdpt_idx(virt)];
+        if !e1.is_present() { return None; }
+        let pd = phys_to_virt(e1.phys_addr()).as_mut_ptr::<PageTable>();
+        let e2 = (*pd).entries[pd_idx(virt)];
+        // 2MB-страница — не листовой PTE / A 2MB page is not a leaf PTE
+        if !e2.is_present() || e2.is_huge() { return None; }
+        let pt = phys_to_virt(e2.phys_addr()).as_mut_ptr::<PageTable>();
+        let e3 = &mut (*pt).entries[pt_idx(virt)];
+        if !e3.is_present() { return None; }
+        Some(e3 as *mut PageTableEntry)
+    }
+}
+
+unsafe fn translate_addr(pml4_phys: PhysAddr, virt: VirtAddr) -> Option<PhysAddr> {
+    unsafe {
+        let pml4 = phys_to_virt(pml4_phys).as_ptr::<PageTable>();
+        let e0 = (*pml4).entries[pml4_idx(virt)];
+        if !e0.is_present() { return None; }
+        let pdpt = phys_to_virt(e0.phys_addr()).as_ptr::<PageTable>();
+        let e1 = (*pdpt).entries[pdpt_idx(virt)];
+        if !e1.is_present() { return None; }
+        let pd = phys_to_virt(e1.phys_addr()).as_ptr::<PageTable>();
+        let e2 = (*pd).entries[pd_idx(virt)];
+        if !e2.is_present() { return None; }
+        if e2.is_huge() {
+            let base = e2.0 & 0x000F_FFFF_FFE0_0000;
+            return Some(PhysAddr::new(base + (virt.as_u64() & (HUGE_PAGE_SIZE - 1))));
+        }
+        let pt = phys_to_virt(e2.phys_addr()).as_ptr::<PageTable>();
+        let e3 = (*pt).entries[pt_idx(virt)];
+        if !e3.is_present() { return None; }
+        Some(PhysAddr::new(e3.phys_addr().as_u64() + (virt.as_u64() & 0xFFF)))
+    }
+}
+
+#[cfg(not(test))]
+pub const PHYSICAL_MAP_OFFSET: u64 = 0xFFFF_8000_0000_0000;
+// В тестах «физическая» память — буфер процесса, отображённый как есть
+// In tests "physical" memory is a process buffer, mapped as is
+#[cfg(test)]
+pub const PHYSICAL_MAP_OFFSET: u64 = 0;
+
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    VirtAddr::new(phys.as_u64() + PHYSICAL_MAP_OFFSET)
+}
+
+pub fn virt_to_phys(virt: VirtAddr) -> PhysAddr {
+    PhysAddr::new(virt.as_u64() - PHYSICAL_MAP_OFFSET)
+}
+
+static KERNEL_SPACE: Mutex<Option<AddressSpace>> = Mutex::new(None);
+
+/// Пустое пользовательское пространство: верхняя половина — те же таблицы,
+/// что у ядра. До `init` — паника.
+/// An empty user space: the upper half is the kernel's own tables. Before
+/// `init` — a panic.
+pub fn new_user_space() -> Result<AddressSpace, MmError> {
+    let space = AddressSpace::new()?;
+    let kernel = KERNEL_SPACE.lock();
+    let kernel = kernel.as_ref().expect("VMM: user space before vmm::init");
+    unsafe {
+        let src = &*phys_to_virt(kernel.pml4).as_ptr::<PageTable>();
+        let dst = &mut *phys_to_virt(space.pml4).as_mut_ptr::<PageTable>();
+        dst.entries[KERNEL_PML4_START..].copy_from_slice(&src.entries[KERNEL_PML4_START..]);
+    }
+    Ok(space)
+}
+
+/// Сколько физической памяти HHDM покрывает как минимум / The least physical memory the HHDM covers
+const HHDM_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Конец HHDM: всё, что может выдать PMM (`init`) / The HHDM end: everything the PMM can hand out (`init`)
+static HHDM_END: AtomicU64 = AtomicU64::new(HHDM_MIN_SIZE);
+
+/// Биты типа памяти в PTE (индекс PAT) / Memory-type bits of a PTE (the PAT index)
+pub fn cache_bits(flags: PageFlags) -> PageFlags {
+    flags & (PageFlags::PAT | PageFlags::NO_CACHE | PageFlags::WRITE_THROUGH)
+}
+
+/// Два маппинга одного фрейма с разным типом памяти — недопустимый алиас:
+/// кэшированный вид может вытеснить грязные строки поверх WC/UC записей.
+/// Two mappings of one frame with different memory types are an illegal
+/// alias: the cached view may evict dirty lines over WC/UC writes.
+pub fn cache_alias_conflict(a: PageFlags, b: PageFlags) -> bool {
+    cache_bits(a) != cache_bits(b)
+}
+
+/// Привести HHDM-вид фреймов `[phys, phys + size)` к типу памяти `flags`,
+/// сначала выгнав их строки из кэша. Фреймы вне HHDM не трогаются.
+/// Возвращает, сколько страниц перепомечено.
+/// Bring the HHDM view of the frames `[phys, phys + size)` to the memory
+/// type of `flags`, first flushing their lines from the cache. Frames
+/// outside the HHDM are left alone. Returns how many pages were retagged.
+fn match_hhdm_cache(space: &AddressSpace, phys: PhysAddr, size: u64, flags: PageFlags) -> usize {
+    let end = phys.as_u64().saturating_add(size).min(HHDM_END.load(Ordering::Relaxed));
+    let mut changed = 0;
+    for frame in (phys.as_u64()..end).step_by(PAGE_SIZE) {
+        let view = phys_to_virt(PhysAddr::new(frame));
+        let Some(current) = space.leaf_flags(view) else { continue };
+        if !cache_alias_conflict(current, flags) { continue; }
+        super::dma::flush_cache_range(view, PAGE_SIZE);
+        let wanted = (current - cache_bits(current)) | cache_bits(flags);
+        if space.set_page_flags(view, wanted) { changed += 1; }
+    }
+    changed
+}
+
+/// `handle_page_fault` для пространства ядра — когда у задачи своего нет.
+/// Занятое пространство (#PF под его lock'ом) — не обработано.
+/// `handle_page_fault` for the kernel space — when the task has none of its
+/// own. A busy space (a #PF under its lock) — not handled.
+pub fn handle_kernel_fault(fault_addr: VirtAddr, error: u64) -> bool {
+    let Some(guard) = KERNEL_SPACE.try_lock() else { return false };
+    guard.as_ref().is_some_and(|space| handle_page_fault(space, fault_addr, error))
+}
+
+/// Замаппить физический диапазон в пространство ядра: выровненные куски
+/// по 2MB — одной записью PD, остальное постранично.
+/// Map a physical range into the kernel address space: aligned 2MB chunks
+/// with a single PD entry each, the rest page by page.
+///
+/// Тип памяти не по умолчанию (WC, UC) для RAM под HHDM переносится и на
+/// HHDM-вид — иначе у фрейма два несовместимых алиаса.
+/// A non-default memory type (WC, UC) for RAM under the HHDM is carried
+/// over to the HHDM view too — otherwise the frame has two incompatible aliases.
+pub fn map_kernel_range(virt: VirtAddr, phys: PhysAddr, size: u64, flags: PageFlags) -> Result<(), MmError> {
+    let guard = KERNEL_SPACE.lock();
+    let space = guard.as_ref().expect("VMM not initialized");
+    if !cache_bits(flags).is_empty() {
+        match_hhdm_cache(space, phys, size, flags);
+    }
+    let mut offset = 0u64;
+    while offset < size {
+        let (v, p) = (VirtAddr::new(virt.as_u64() + offset), PhysAddr::new(phys.as_u64() + offset));
+        // Слот PD уже с таблицей PT — тогда постранично
+        // The PD slot already holds a PT — page by page then
+        if size - offset >= HUGE_PAGE_SIZE && space.map_huge_2m(v, p, flags).is_ok() {
+            offset += HUGE_PAGE_SIZE;
+            continue;
+        }
+        space.map(v, p, flags)?;
+        offset += PAGE_SIZE as u64;
+    }
+    Ok(())
+}
+
+/// Снять `[virt, virt + size)` из пространства ядра. Фреймы не освобождаются;
+/// 2MB-маппинг из `map_kernel_range` уходит целиком.
+/// Unmap `[virt, virt + size)` from the kernel space. Frames are not freed;
+/// a 2MB mapping from `map_kernel_range` goes as a whole.
+pub fn unmap_kernel_range(virt: VirtAddr, size: u64) {
+    let guard = KERNEL_SPACE.lock();
+    let space = guard.as_ref().expect("VMM not initialized");
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let _ = space.unmap(VirtAddr::new(virt.as_u64() + offset));
+    }
+}
+
+/// Сделать страницу ядра guard-страницей: снять маппинг, фрейм не трогать
+/// (он часть образа ядра). Только 4KB-страницы — внутри 2MB `false`.
+/// Turn a kernel page into a guard page: unmap it, leave the frame alone
+/// (it's part of the kernel image). 4KB pages only — inside 2MB, `false`.
+pub fn unmap_kernel_guard(virt: VirtAddr) -> bool {
+    let guard = KERNEL_SPACE.lock();
+    let space = guard.as_ref().expect("VMM not initialized");
+    space.take_page(virt).is_some()
+}
+
+/// Перепометить страницы ядра `[start, end)` флагами `flags` (фреймы те же).
+/// Возвращает сколько страниц изменено.
+/// Re-mark kernel pages `[start, end)` with `flags` (same frames).
+/// Returns how many pages were changed.
+pub fn protect_kernel_range(start: VirtAddr, end: VirtAddr, flags: PageFlags) -> usize {
+    let guard = KERNEL_SPACE.lock();
+    let space = guard.as_ref().expect("VMM not initialized");
+    let mut changed = 0;
+    let mut page = start.as_u64() & !(PAGE_SIZE as u64 - 1);
+    while page < end.as_u64() {
+        if space.set_page_flags(VirtAddr::new(page), flags) { changed += 1; }
+        page += PAGE_SIZE as u64;
+    }
+    changed
+}
+
+pub fn init() {
+    let space = AddressSpace::new().expect("VMM: failed to allocate PML4");
+    // Фреймы PMM читаются через HHDM — он обязан покрыть их все
+    // PMM frames are accessed through the HHDM — it must cover all of them
+    let hhdm_end = pmm::phys_end().max(HHDM_MIN_SIZE);
+    HHDM_END.store(hhdm_end, Ordering::Relaxed);
+    let mut offset = 0u64;
+    while offset < hhdm_end {
+        space.map(
+            VirtAddr::new(PHYSICAL_MAP_OFFSET + offset),
+            PhysAddr::new(offset),
+            PageFlags::KERNEL_RW,
+        ).expect("VMM: out of memory for the physical map");
+        offset += PAGE_SIZE as u64;
+    }
+
+    // Copy the kernel's higher-half PML4 entry from Limine's page tables.
+    //
+    // Our new PML4 only has PHYSICAL_MAP_OFFSET mapped. After activate() the
+    // CPU would try to fetch instructions at 0xFFFFFFFF8xxxxxxx — which is
+    // PML4[511] — and triple-fault because that entry is empty.
+    //
+    // Solution: read current CR3 (Limine's PML4), copy PML4[511] into ours.
+    // 0xFFFFFFFF80000000 >> 39 & 0x1FF = 511
+    unsafe {
+        let cr3: u64;
+        core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nostack, readonly));
+
+        let limine_pml4 = phys_to_virt(PhysAddr::new(cr3 & !0xFFF)).as_ptr::<u64>();
+        let new_pml4    = phys_to_virt(space.pml4).as_mut_ptr::<u64>();
+        *new_pml4.add(511) = *limine_pml4.add(511);
+    }
+
+    space.activate();
+    crate::kprintln!("[vmm] PML4={:#x}", space.pml4.as_u64());
+    *KERNEL_SPACE.lock() = Some(space);
+}
+
+#[cfg(test)]
+std::thread_local! {
+    static ACTIVE_PML4: core::cell::Cell<u64> = const { core::cell::Cell::new(0) };
+}
+
+/// `init` для тестов: пустое пространство ядра, CR3 не трогаем
+/// `init` for tests: an empty kernel space, CR3 is left alone
+#[cfg(test)]
+pub fn init_for_tests() {
+    let space = AddressSpace::new().expect("VMM: failed to allocate PML4");
+    HHDM_END.store(pmm::phys_end(), Ordering::Relaxed);
+    *KERNEL_SPACE.lock() = Some(space);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const PF_PRESENT: u64 = 1 << 0;
+    const PF_WRITE:   u64 = 1 << 1;
+    const PF_USER:    u64 = 1 << 2;
+
+    #[test]
+    fn protect_keeps_cow_frames_read_only() {
+        let _kernel = testing::setup();
+        let parent = AddressSpace::new().unwrap();
+        let va = VirtAddr::new(0x6000_0000);
+        parent.map_anonymous(va, PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(handle_page_fault(&parent, va, PF_USER | PF_WRITE));
+        let frame = parent.translate(va).unwrap();
+        unsafe { *phys_to_virt(frame).as_mut_ptr::<u8>() = 1; }
+
+        let child = parent.fork().unwrap();
+        // mprotect(RW) в потомке не должен открыть запись в общий фрейм
+        // mprotect(RW) in the child mustn't open the shared frame for writing
+        child.protect(va, PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(!child.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE));
+        parent.protect(va, PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(!parent.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE));
+
+        // Запись в потомке — своя копия / A write in the child — a private copy
+        assert!(handle_page_fault(&child, va, PF_USER | PF_WRITE | PF_PRESENT));
+        let copy = child.translate(va).unwrap();
+        assert_ne!(copy, frame);
+        assert!(child.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE));
+        unsafe { *phys_to_virt(copy).as_mut_ptr::<u8>() = 2; }
+        assert_eq!(parent.translate(va), Some(frame));
+        assert_eq!(unsafe { *phys_to_virt(frame).as_ptr::<u8>() }, 1);
+
+        // Потомок отпустил фрейм — родителю его снова можно открыть
+        // The child let go of the frame — the parent may open it again
+        parent.protect(va, PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(parent.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE));
+    }
+
+    #[test]
+    fn reserved_bit_faults_are_never_demand() {
+        assert_eq!(classify_fault(0), FaultClass::Demand);
+        assert_eq!(classify_fault(PF_USER | PF_WRITE | PF_PRESENT), FaultClass::Demand);
+        assert_eq!(classify_fault(PF_RESERVED), FaultClass::ReservedBit);
+        assert_eq!(classify_fault(PF_RESERVED | PF_USER | PF_WRITE | PF_PRESENT), FaultClass::ReservedBit);
+    }
+
+    #[test]
+    fn concurrent_faults_in_two_vmas() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        const PAGES: u64 = 64;
+        let bases = [0x1000_0000u64, 0x2000_0000];
+        for base in bases {
+            space.map_anonymous(VirtAddr::new(base), PAGES * PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        }
+        let free = pmm::free_memory();
+        std::thread::scope(|s| {
+            for base in bases {
+                let space = &space;
+                s.spawn(move || {
+                    for page in 0..PAGES {
+                        let va = VirtAddr::new(base + page * PAGE_SIZE as u64);
+                        assert!(handle_page_fault(space, va, PF_USER | PF_WRITE));
+                        // Повторный fault той же страницы (гонка потоков) тоже проходит
+                        // A second fault on the same page (a thread race) goes through too
+                        assert!(handle_page_fault(space, va, PF_USER));
+                    }
+                });
+            }
+        });
+        for base in bases {
+            for page in 0..PAGES {
+                assert!(space.translate(VirtAddr::new(base + page * PAGE_SIZE as u64)).is_some());
+            }
+        }
+        assert!(pmm::free_memory() <= free - 2 * PAGES * PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn protection_fault_on_present_page_is_not_handled() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let va = VirtAddr::new(0x3000_0000);
+        space.map_anonymous(va, PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(handle_page_fault(&space, va, PF_USER | PF_WRITE));
+        let phys = space.translate(va);
+        // Например, исполнение NX-страницы / E.g. executing an NX page
+        assert!(!handle_page_fault(&space, va, PF_USER | PF_PRESENT));
+        assert!(!handle_page_fault(&space, va, PF_USER | PF_PRESENT | PF_WRITE));
+        assert_eq!(space.translate(va), phys);
+    }
+
+    #[test]
+    fn unmapping_returns_the_emptied_tables() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        // Свой PML4-слот — все три уровня таблиц создаются заново
+        // A PML4 slot of its own — all three table levels get created afresh
+        let va = VirtAddr::new(0x1000_0000_0000);
+        let free = pmm::free_memory();
+        space.map_anonymous(va, 2 * PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(handle_page_fault(&space, va, PF_USER | PF_WRITE));
+        assert!(handle_page_fault(&space, VirtAddr::new(va.as_u64() + PAGE_SIZE as u64), PF_USER));
+        space.unmap_range(va, PAGE_SIZE as u64).unwrap();
+        assert!(pmm::free_memory() < free);
+        space.unmap_range(VirtAddr::new(va.as_u64() + PAGE_SIZE as u64), PAGE_SIZE as u64).unwrap();
+        assert_eq!(pmm::free_memory(), free);
+
+        let obj = SharedMemObject::alloc(1).unwrap();
+        let free = pmm::free_memory();
+        space.map_shared(va, obj.clone(), PageFlags::USER_RW).unwrap();
+        assert!(space.unmap_shared(va));
+        assert_eq!(pmm::free_memory(), free);
+    }
+
+    #[test]
+    fn pinned_pages_are_prefaulted_and_stay_shared_writable_across_fork() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let va = VirtAddr::new(0x6100_0000);
+        let page = PAGE_SIZE as u64;
+        space.map_anonymous(va, 2 * page, PageFlags::USER_RW).unwrap();
+        // Второй страницы за VMA нет — откат, первая не закреплена
+        // There's no page past the VMA — rolled back, the first isn't pinned
+        let past = VirtAddr::new(va.as_u64() + page);
+        assert_eq!(space.pin(past, 2 * page), Err(MmError::Unmappable(VirtAddr::new(va.as_u64() + 2 * page))));
+        assert!(!space.is_pinned(past));
+
+        space.pin(VirtAddr::new(va.as_u64() + 8), page).unwrap();
+        assert!(space.is_pinned(va) && space.is_pinned(past));
+        let frame = space.translate(va).unwrap();
+
+        let child = space.fork().unwrap();
+        assert_eq!(child.translate(va), Some(frame));
+        for s in [&space, &child] {
+            assert!(s.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE | PageFlags::PINNED));
+        }
+        space.unpin(va, 2 * page);
+        assert!(!space.is_pinned(va) && !space.is_pinned(past));
+        assert!(space.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE));
+    }
+
+    #[test]
+    fn a_write_in_the_forked_child_leaves_the_parent_unchanged() {
+        let _kernel = testing::setup();
+        let parent = AddressSpace::new().unwrap();
+        let va = VirtAddr::new(0x6200_0000);
+        parent.map_anonymous(va, 2 * PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(handle_page_fault(&parent, va, PF_USER | PF_WRITE));
+        let frame = parent.translate(va).unwrap();
+        unsafe { *phys_to_virt(frame).as_mut_ptr::<u8>() = 0xAA; }
+
+        let child = parent.fork().unwrap();
+        assert!(matches!(child.find_vma(va), Some((_, VmaKind::CowAnonymous))));
+        assert_eq!(child.translate(va), Some(frame));
+        assert!(handle_page_fault(&child, va, PF_USER | PF_WRITE | PF_PRESENT));
+        let copy = child.translate(va).unwrap();
+        assert_ne!(copy, frame);
+        assert_eq!(unsafe { *phys_to_virt(copy).as_ptr::<u8>() }, 0xAA);
+        unsafe { *phys_to_virt(copy).as_mut_ptr::<u8>() = 0x55; }
+        assert_eq!(unsafe { *phys_to_virt(frame).as_ptr::<u8>() }, 0xAA);
+        assert!(!parent.leaf_flags(va).unwrap().contains(PageFlags::WRITABLE));
+
+        // Нетронутая страница у каждого своя, свежая / An untouched page is fresh and private to each
+        let second = VirtAddr::new(va.as_u64() + PAGE_SIZE as u64);
+        assert!(handle_page_fault(&child, second, PF_USER | PF_WRITE));
+        assert!(parent.translate(second).is_none());
+    }
+
+    #[test]
+    fn protect_rewrites_a_whole_vma_or_splits_off_a_part() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let page = PAGE_SIZE as u64;
+        let va = VirtAddr::new(0x6300_0000);
+        let at = |i: u64| VirtAddr::new(va.as_u64() + i * page);
+        space.map_anonymous(va, 3 * page, PageFlags::USER_RW).unwrap();
+        assert!(handle_page_fault(&space, at(0), PF_USER | PF_WRITE));
+        let frame = space.translate(at(0));
+        let read_only = PageFlags::USER_RW - PageFlags::WRITABLE;
+
+        // Целиком: PTE переписан, фрейм тот же / Whole: the PTE is rewritten, same frame
+        space.protect(va, 3 * page, read_only).unwrap();
+        assert!(!space.leaf_flags(at(0)).unwrap().contains(PageFlags::WRITABLE));
+        assert_eq!(space.translate(at(0)), frame);
+        assert!(matches!(space.find_vma(at(2)), Some((f, _)) if f == read_only));
+
+        // Середина: три VMA, новые права — только у неё
+        // The middle: three VMAs, only it gets the new rights
+        space.protect(at(1), page, PageFlags::USER_RW).unwrap();
+        assert!(matches!(space.find_vma(at(0)), Some((f, _)) if f == read_only));
+        assert!(matches!(space.find_vma(at(1)), Some((f, _)) if f == PageFlags::USER_RW));
+        assert!(matches!(space.find_vma(at(2)), Some((f, _)) if f == read_only));
+        assert!(handle_page_fault(&space, at(1), PF_USER | PF_WRITE));
+        assert!(!handle_page_fault(&space, at(2), PF_USER | PF_WRITE));
+
+        // Через стык VMA, за VMA и W+X — отказ / Across a seam, past the VMA and W+X — refused
+        assert_eq!(space.protect(at(1), 2 * page, read_only), Err(MmError::InvalidRange));
+        assert_eq!(space.protect(at(2), 2 * page, read_only), Err(MmError::InvalidRange));
+        assert_eq!(space.protect(at(1), page, PageFlags::USER_EX | PageFlags::WRITABLE), Err(MmError::WriteExec));
+    }
+
+    #[test]
+    fn accessed_and_dirty_bits_are_observable_and_accessed_clears() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let va = VirtAddr::new(0x6400_0000);
+        assert_eq!(space.page_stats(va), None);
+        assert!(!space.clear_accessed(va));
+        space.map_anonymous(va, PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        assert!(handle_page_fault(&space, va, PF_USER));
+        assert_eq!(space.page_stats(va), Some((false, false)));
+
+        // Биты ставит CPU — здесь за него / The CPU sets the bits — here we stand in for it
+        assert!(space.set_leaf_flags(va, PageFlags::ACCESSED | PageFlags::DIRTY));
+        assert_eq!(space.page_stats(va), Some((true, true)));
+        assert!(space.clear_accessed(va));
+        assert_eq!(space.page_stats(va), Some((false, true)));
+    }
+
+    #[test]
+    fn non_canonical_addresses_are_rejected() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let frame = PhysAddr::new(0x20_0000);
+        // Последняя страница нижней половины / The last page of the lower half
+        let canonical = VirtAddr::new(USER_END - PAGE_SIZE as u64);
+        assert!(canonical.is_canonical());
+        space.map(canonical, frame, PageFlags::KERNEL_RW).unwrap();
+        assert_eq!(space.translate(canonical), Some(frame));
+        assert_eq!(space.unmap(canonical), Ok(()));
+
+        for raw in [USER_END, 0x0001_0000_0000_0000, 0xFFFF_7FFF_FFFF_F000, 0x8000_0000_0000_0000] {
+            let va = VirtAddr::new(raw);
+            assert!(!va.is_canonical());
+            assert_eq!(space.map(va, frame, PageFlags::KERNEL_RW), Err(MmError::NonCanonical(va)));
+            assert_eq!(space.map_huge_2m(va, frame, PageFlags::KERNEL_RW), Err(MmError::NonCanonical(va)));
+            assert_eq!(space.unmap(va), Err(MmError::NonCanonical(va)));
+            assert_eq!(space.translate(va), None);
+        }
+    }
+
+    #[test]
+    fn map_shared_rolls_back_when_page_tables_run_out() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let obj = SharedMemObject::alloc(1).unwrap();
+        // Вторая страница — уже в следующей PT / The second page is already in the next PT
+        let va = VirtAddr::new(0x4000_0000 + HUGE_PAGE_SIZE - PAGE_SIZE as u64);
+
+        // Оставить PMM ровно на PDPT, PD и первую PT / Leave the PMM just a PDPT, a PD and the first PT
+        let mut taken = Vec::new();
+        while let Ok(frame) = pmm::alloc_page() { taken.push(frame); }
+        for frame in taken.drain(..3) { pmm::free_page(frame); }
+        let free = pmm::free_memory();
+
+        assert_eq!(space.map_shared(va, obj.clone(), PageFlags::USER_RW), Err(MmError::OutOfMemory));
+        assert_eq!(pmm::free_memory(), free);
+        assert!(space.translate(va).is_none());
+        assert!(space.find_vma(va).is_none());
+        assert_eq!(Arc::strong_count(&obj), 1);
+        for frame in taken { pmm::free_page(frame); }
+
+        // Пространство цело — повтор проходит / The space is intact — a retry succeeds
+        space.map_shared(va, obj.clone(), PageFlags::USER_RW).unwrap();
+        assert_eq!(space.translate(va), Some(obj.base()));
+    }
+
+    #[test]
+    fn cached_and_wc_views_of_a_frame_are_an_alias_conflict() {
+        let cached = PageFlags::KERNEL_RW;
+        let wc = PageFlags::KERNEL_RW | PageFlags::WRITE_COMBINING;
+        assert!(cache_alias_conflict(cached, wc));
+        assert!(cache_alias_conflict(wc, cached | PageFlags::NO_CACHE));
+        assert!(!cache_alias_conflict(cached, PageFlags::USER_RW));
+        assert!(!cache_alias_conflict(wc, PageFlags::USER_RW | PageFlags::WRITE_COMBINING));
+
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let frame = pmm::alloc_page().unwrap();
+        let view = phys_to_virt(frame);
+        space.map(view, frame, cached).unwrap();
+        // HHDM-вид перепомечен под WC, фрейм и права те же
+        // The HHDM view is retagged as WC, same frame and rights
+        assert_eq!(match_hhdm_cache(&space, frame, PAGE_SIZE as u64, wc), 1);
+        let now = space.leaf_flags(view).unwrap();
+        assert!(!cache_alias_conflict(now, wc));
+        assert!(now.contains(PageFlags::KERNEL_RW));
+        assert_eq!(space.translate(view), Some(frame));
+        assert_eq!(match_hhdm_cache(&space, frame, PAGE_SIZE as u64, wc), 0);
+        pmm::free_page(frame);
+    }
+
+    #[test]
+    fn unmap_frees_emptied_user_tables_but_not_kernel_ones() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let frame = pmm::alloc_page().unwrap();
+        let table_pages = 3 * PAGE_SIZE as u64;
+
+        let user = VirtAddr::new(0x2000_0000_0000);
+        let free = pmm::free_memory();
+        space.map(user, frame, PageFlags::USER_RW).unwrap();
+        assert_eq!(pmm::free_memory(), free - table_pages);
+        space.unmap(user).unwrap();
+        assert_eq!(pmm::free_memory(), free);
+
+        // Таблицы ядерной половины общие — остаются / Kernel-half tables are shared — they stay
+        let kernel = VirtAddr::new(0xFFFF_9000_0000_0000);
+        space.map(kernel, frame, PageFlags::KERNEL_RW).unwrap();
+        space.unmap(kernel).unwrap();
+        assert_eq!(pmm::free_memory(), free - table_pages);
+        let pml4 = unsafe { &*phys_to_virt(space.pml4).as_ptr::<PageTable>() };
+        unsafe { free_table(pml4.entries[pml4_idx(kernel)].phys_addr(), 3); }
+        pmm::free_page(frame);
+    }
+
+    #[test]
+    fn two_hundred_vmas_are_found_and_overlaps_rejected() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let page = PAGE_SIZE as u64;
+        // Через одну страницу, в обратном порядке — вставка держит сортировку
+        // Every other page, in reverse order — insertion keeps the order
+        for i in (0..200u64).rev() {
+            space.map_anonymous(VirtAddr::new(0x1000_0000 + 2 * i * page), page, PageFlags::USER_RW).unwrap();
+        }
+        for i in [0u64, 1, 99, 150, 199] {
+            let start = 0x1000_0000 + 2 * i * page;
+            assert!(space.find_vma(VirtAddr::new(start + 8)).is_some());
+            assert!(space.find_vma(VirtAddr::new(start + page)).is_none());
+        }
+        let taken = VirtAddr::new(0x1000_0000 + 20 * page);
+        assert_eq!(space.map_anonymous(taken, page, PageFlags::USER_RW), Err(MmError::VmaOverlap));
+        // Захватывает соседа слева и справа / Reaching into the left and the right neighbour
+        assert_eq!(space.map_anonymous(VirtAddr::new(taken.as_u64() - page), 2 * page, PageFlags::USER_RW), Err(MmError::VmaOverlap));
+        assert_eq!(space.map_anonymous(VirtAddr::new(taken.as_u64() + page), 2 * page, PageFlags::USER_RW), Err(MmError::VmaOverlap));
+        space.map_anonymous(VirtAddr::new(taken.as_u64() + page), page, PageFlags::USER_RW).unwrap();
+    }
+
+    #[test]
+    fn a_fault_in_the_stack_guard_page_is_not_handled() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let page = PAGE_SIZE as u64;
+        let top = VirtAddr::new(0x7000_0000);
+        space.map_stack(top, 4, PageFlags::USER_RW).unwrap();
+        let bottom = top.as_u64() - 4 * page;
+        assert!(handle_page_fault(&space, VirtAddr::new(top.as_u64() - 8), PF_USER | PF_WRITE));
+        assert!(handle_page_fault(&space, VirtAddr::new(bottom), PF_USER | PF_WRITE));
+        let guard = VirtAddr::new(bottom - 8);
+        assert!(!handle_page_fault(&space, guard, PF_USER | PF_WRITE));
+        assert!(space.translate(guard).is_none());
+        // Стек вплотную над чужой VMA — страницы защиты нет, отказ
+        // A stack right above another VMA — no room for the guard page, refused
+        let low = VirtAddr::new(0x6000_0000);
+        space.map_anonymous(low, page, PageFlags::USER_RW).unwrap();
+        assert_eq!(space.map_stack(VirtAddr::new(low.as_u64() + 2 * page), 1, PageFlags::USER_RW), Err(MmError::VmaOverlap));
+    }
+
+    #[test]
+    fn write_exec_user_mappings_are_refused() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let frame = PhysAddr::new(0x20_0000);
+        let wx = PageFlags::USER_EX | PageFlags::WRITABLE;
+        let va = VirtAddr::new(0x6500_0000);
+        assert_eq!(space.map(va, frame, wx), Err(MmError::WriteExec));
+        assert_eq!(space.map_anonymous(va, PAGE_SIZE as u64, wx), Err(MmError::WriteExec));
+        assert!(space.translate(va).is_none() && space.find_vma(va).is_none());
+
+        space.map(va, frame, PageFlags::USER_RW).unwrap();
+        space.map(VirtAddr::new(va.as_u64() + PAGE_SIZE as u64), frame, PageFlags::USER_EX).unwrap();
+        space.map_anonymous(VirtAddr::new(0x6600_0000), PAGE_SIZE as u64, PageFlags::USER_RW).unwrap();
+        space.map_anonymous(VirtAddr::new(0x6700_0000), PAGE_SIZE as u64, PageFlags::USER_EX).unwrap();
+        // Ядро правилом не связано, и есть лазейка для JIT
+        // The kernel isn't bound by the rule, and there's the JIT escape hatch
+        space.map(VirtAddr::new(0x6800_0000), frame, PageFlags::KERNEL_EX | PageFlags::WRITABLE).unwrap();
+        space.map_unchecked(VirtAddr::new(0x6900_0000), frame, wx).unwrap();
+    }
+
+    #[test]
+    fn overflowing_or_inverted_ranges_are_rejected() {
+        let _kernel = testing::setup();
+        let (start, end) = (VirtAddr::new(0x7000_0000), VirtAddr::new(0x6000_0000));
+        assert!(Vma::new(start, end, PageFlags::USER_RW, VmaKind::Anonymous).is_none());
+        assert!(Vma::new(start, start, PageFlags::USER_RW, VmaKind::Anonymous).is_none());
+        assert!(Vma::with_size(VirtAddr::new(u64::MAX - 0xFFF), 0x2000, PageFlags::USER_RW, VmaKind::Anonymous).is_none());
+        let vma = Vma::with_size(start, 0x3000, PageFlags::USER_RW, VmaKind::Anonymous).unwrap();
+        assert_eq!(vma.size(), 0x3000);
+
+        let space = AddressSpace::new().unwrap();
+        // start + size переполняется и «заворачивается» в нижние адреса
+        // start + size overflows and wraps around to low addresses
+        let wrapping = u64::MAX - start.as_u64() + 0x1000 + 1;
+        assert_eq!(space.map_anonymous(start, wrapping, PageFlags::USER_RW), Err(MmError::InvalidRange));
+        assert_eq!(space.map_anonymous(start, 0, PageFlags::USER_RW), Err(MmError::InvalidRange));
+        assert_eq!(space.alloc_anonymous(u64::MAX, PageFlags::USER_RW), Err(MmError::InvalidRange));
+        // Ничего не вставлено — место свободно / Nothing was inserted — the room is free
+        assert!(space.find_vma(start).is_none());
+        space.map_anonymous(start, 0x1000, PageFlags::USER_RW).unwrap();
+    }
+
+    #[test]
+    fn alloc_anonymous_takes_the_first_gap_that_fits() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let page = PAGE_SIZE as u64;
+        space.map_anonymous(VirtAddr::new(USER_ALLOC_BASE + page), page, PageFlags::USER_RW).unwrap();
+        // Одна страница влезает перед занятой, две — только после
+        // One page fits before the taken one, two only after it
+        assert_eq!(space.alloc_anonymous(2 * page, PageFlags::USER_RW), Ok(VirtAddr::new(USER_ALLOC_BASE + 2 * page)));
+        assert_eq!(space.alloc_anonymous(1, PageFlags::USER_RW), Ok(VirtAddr::new(USER_ALLOC_BASE)));
+        assert_eq!(space.alloc_anonymous(page, PageFlags::USER_RW), Ok(VirtAddr::new(USER_ALLOC_BASE + 4 * page)));
+        assert_eq!(space.alloc_anonymous(USER_END, PageFlags::USER_RW), Err(MmError::OutOfMemory));
+        assert_eq!(space.alloc_anonymous(page, PageFlags::USER_EX | PageFlags::WRITABLE), Err(MmError::WriteExec));
+    }
+
+    #[test]
+    fn shared_frames_outlive_all_but_the_last_mapper() {
+        let _kernel = testing::setup();
+        let (a, b) = (AddressSpace::new().unwrap(), AddressSpace::new().unwrap());
+        let va = VirtAddr::new(0x5000_0000);
+        let obj = SharedMemObject::alloc(1).unwrap();
+        a.map_shared(va, obj.clone(), PageFlags::USER_RW).unwrap();
+        b.map_shared(va, obj.clone(), PageFlags::USER_RW).unwrap();
+        drop(obj);
+        let free = pmm::free_memory();
+        let frame = a.translate(va).unwrap();
+        assert_eq!(b.translate(va), Some(frame));
+
+        assert!(a.unmap_shared(va));
+        assert!(!a.unmap_shared(va));
+        assert_eq!(a.translate(va), None);
+        // Второй маппер всё ещё видит те же байты / The second mapper still sees the same bytes
+        unsafe { *phys_to_virt(frame).as_mut_ptr::<u8>() = 7; }
+        assert_eq!(b.translate(va), Some(frame));
+        drop(b);
+        // Оба фрейма объекта вернулись / Both of the object's frames came back
+        assert!(pmm::free_memory() >= free + 2 * PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn huge_page_maps_and_translates_all_of_its_2mb() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let (va, pa) = (VirtAddr::new(0x4000_0000), PhysAddr::new(0x60_0000));
+        let flags = PageFlags::USER_RW | PageFlags::PAT;
+        assert_eq!(space.map_huge_2m(VirtAddr::new(va.as_u64() + PAGE_SIZE as u64), pa, flags), Err(MmError::InvalidRange));
+        assert_eq!(space.map_huge_2m(va, PhysAddr::new(pa.as_u64() + PAGE_SIZE as u64), flags), Err(MmError::InvalidRange));
+        assert_eq!(space.map_huge_2m(va, pa, PageFlags::USER_EX | PageFlags::WRITABLE), Err(MmError::WriteExec));
+
+        space.map_huge_2m(va, pa, flags).unwrap();
+        for offset in [0, 0x1234, HUGE_PAGE_SIZE - 1] {
+            let phys = space.translate(VirtAddr::new(va.as_u64() + offset));
+            assert_eq!(phys, Some(PhysAddr::new(pa.as_u64() + offset)));
+        }
+        assert_eq!(space.translate(VirtAddr::new(va.as_u64() + HUGE_PAGE_SIZE)), None);
+        // Слот занят, а 4KB внутри 2MB не мапится / The slot is taken, and no 4KB inside 2MB
+        assert_eq!(space.map_huge_2m(va, pa, flags), Err(MmError::InvalidRange));
+        assert_eq!(space.map(VirtAddr::new(va.as_u64() + PAGE_SIZE as u64), pa, PageFlags::USER_RW), Err(MmError::InvalidRange));
+        // PAT 2MB-записи — бит 12, бит 7 там — сам HUGE
+        // A 2MB entry's PAT is bit 12, bit 7 there is HUGE itself
+        let pd_entry = unsafe {
+            let pml4 = &*phys_to_virt(space.pml4).as_ptr::<PageTable>();
+            let pdpt = &*phys_to_virt(pml4.entries[pml4_idx(va)].phys_addr()).as_ptr::<PageTable>();
+            let pd = &*phys_to_virt(pdpt.entries[pdpt_idx(va)].phys_addr()).as_ptr::<PageTable>();
+            pd.entries[pd_idx(va)]
+        };
+        assert!(pd_entry.is_huge() && pd_entry.0 & PageFlags::PAT_HUGE.bits() != 0);
+    }
+}
@@ -0,0 +1,86 @@
+//! Сброс TLB / TLB invalidation
+//!
+//! `invlpg` чистит TLB только своего CPU. Любая правка PTE идёт через
+//! `flush`: локальный сброс плюс `flush_others` для остальных ядер. Пока CPU
+//! один, `flush_others` пуст; с APIC туда добавится только отправка IPI.
+//! `invlpg` only clears the TLB of its own CPU. Every PTE edit goes through
+//! `flush`: a local flush plus `flush_others` for the other cores. With a
+//! single CPU `flush_others` is empty; with the APIC only the IPI send gets
+//! added there.
+
+use super::VirtAddr;
+
+#[cfg(test)]
+std::thread_local! {
+    /// Сколько было `flush_local` и `flush_others` в этом потоке — для тестов.
+    /// How many `flush_local` and `flush_others` this thread made — for tests.
+    static FLUSHES: core::cell::Cell<(usize, usize)> = const { core::cell::Cell::new((0, 0)) };
+}
+
+/// Вектор IPI shootdown — займём, когда появится APIC.
+/// Shootdown IPI vector — taken once the APIC exists.
+pub const SHOOTDOWN_VECTOR: u8 = 0xFD;
+
+/// Сбросить `addr` на этом CPU и на остальных / Flush `addr` on this CPU and the others
+pub fn flush(addr: VirtAddr) {
+    flush_local(addr);
+    flush_others(addr);
+}
+
+/// Сбросить `addr` в TLB этого CPU / Flush `addr` from this CPU's TLB
+pub fn flush_local(addr: VirtAddr) {
+    // В тестах таблицы не активны — и сбрасывать нечего
+    // In tests the tables are never active — nothing to flush
+    #[cfg(not(test))]
+    unsafe {
+        core::arch::asm!("invlpg [{}]", in(reg) addr.as_u64(), options(nostack));
+    }
+    #[cfg(test)]
+    {
+        let _ = addr;
+        FLUSHES.with(|f| f.set((f.get().0 + 1, f.get().1)));
+    }
+}
+
+/// Сбросить `addr` на остальных CPU. Однопроцессорная система — ничего.
+/// Flush `addr` on the other CPUs. A uniprocessor system — nothing.
+pub fn flush_others(_addr: VirtAddr) {
+    // TODO: SMP — положить адрес в очередь shootdown, послать IPI
+    // SHOOTDOWN_VECTOR остальным CPU и дождаться, пока все сбросят.
+    // TODO: SMP — put the address in the shootdown queue, send IPI
+    // SHOOTDOWN_VECTOR to the other CPUs and wait until all have flushed.
+    #[cfg(test)]
+    FLUSHES.with(|f| f.set((f.get().0, f.get().1 + 1)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::pmm::{self, PAGE_SIZE};
+    use crate::mm::vmm::{AddressSpace, PageFlags};
+    use crate::testing;
+
+    /// Сбросы, сделанные `f`: (локальные, остальным CPU)
+    /// The flushes `f` made: (local, to the other CPUs)
+    fn flushes(f: impl FnOnce()) -> (usize, usize) {
+        let before = FLUSHES.with(|c| c.get());
+        f();
+        let after = FLUSHES.with(|c| c.get());
+        (after.0 - before.0, after.1 - before.1)
+    }
+
+    #[test]
+    fn every_pte_change_flushes_locally_and_shoots_down_once() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let frame = pmm::alloc_page().unwrap();
+        let va = VirtAddr::new(0x6A00_0000);
+        assert_eq!(flushes(|| space.map(va, frame, PageFlags::USER_RW).unwrap()), (1, 1));
+        assert_eq!(flushes(|| assert!(space.clear_accessed(va))), (1, 1));
+        assert_eq!(flushes(|| space.unmap(va).unwrap()), (1, 1));
+        // Нечего менять — нечего и сбрасывать / Nothing to change — nothing to flush
+        assert_eq!(flushes(|| space.unmap(va).unwrap()), (0, 0));
+        assert_eq!(flushes(|| assert!(space.translate(VirtAddr::new(va.as_u64() + PAGE_SIZE as u64)).is_none())), (0, 0));
+        pmm::free_page(frame);
+    }
+}
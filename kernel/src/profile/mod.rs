@@ -0,0 +1,190 @@
+//! Статистический профайлер — RIP на каждом тике таймера
+//! Statistical profiler — RIP on every timer tick
+//!
+//! Гистограмма по корзинам адресов (`1 << BUCKET_SHIFT` байт), отдельно
+//! для ядра и userspace. Вместе с таблицей символов даёт плоский профиль.
+//! Включается `profile=1`, иначе тик не делает ничего лишнего.
+//! A histogram over address buckets (`1 << BUCKET_SHIFT` bytes), kept
+//! separately for kernel and user space. Together with the symbol table it
+//! gives a flat profile. Enabled by `profile=1`; otherwise the tick does no
+//! extra work.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::IrqMutex;
+
+/// Размер корзины — 64 байта / Bucket size — 64 bytes
+pub const BUCKET_SHIFT: u32 = 6;
+
+/// Слотов в гистограмме / Histogram slots
+pub const SLOTS: usize = 1024;
+
+/// Чей код прервали / Whose code was interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    Kernel = 0,
+    User   = 1,
+}
+
+impl Mode {
+    /// По CS из кадра прерывания: RPL 3 — userspace.
+    /// From the CS in the interrupt frame: RPL 3 — user space.
+    pub const fn from_cs(cs: u64) -> Self {
+        if cs & 3 == 3 { Self::User } else { Self::Kernel }
+    }
+}
+
+/// Одна строка профиля / One profile row
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Sample {
+    /// Начало корзины / Bucket start
+    pub addr:  u64,
+    pub mode:  Mode,
+    pub count: u32,
+}
+
+impl Sample {
+    pub const EMPTY: Self = Self { addr: 0, mode: Mode::Kernel, count: 0 };
+
+    /// Байты `repr(C)`-раскладки для userspace, дыра выравнивания — нули.
+    /// The bytes of the `repr(C)` layout for user space, the padding zeroed.
+    pub fn to_bytes(self) -> [u8; core::mem::size_of::<Sample>()] {
+        let mut bytes = [0; core::mem::size_of::<Sample>()];
+        let count = core::mem::offset_of!(Sample, count);
+        bytes[..8].copy_from_slice(&self.addr.to_ne_bytes());
+        bytes[core::mem::offset_of!(Sample, mode)] = self.mode as u8;
+        bytes[count..count + 4].copy_from_slice(&self.count.to_ne_bytes());
+        bytes
+    }
+}
+
+/// Открытая адресация по (корзина, режим) / Open addressing on (bucket, mode)
+pub struct Histogram {
+    slots:   [Sample; SLOTS],
+    /// Не влезли — таблица полна / Didn't fit — table full
+    dropped: u64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self { slots: [Sample::EMPTY; SLOTS], dropped: 0 }
+    }
+
+    pub const fn bucket(rip: u64) -> u64 {
+        rip >> BUCKET_SHIFT << BUCKET_SHIFT
+    }
+
+    pub fn record(&mut self, rip: u64, mode: Mode) {
+        let addr = Self::bucket(rip);
+        let start = ((addr >> BUCKET_SHIFT) ^ mode as u64) as usize % SLOTS;
+        for i in 0..SLOTS {
+            let slot = &mut self.slots[(start + i) % SLOTS];
+            if slot.count == 0 {
+                *slot = Sample { addr, mode, count: 1 };
+                return;
+            }
+            if slot.addr == addr && slot.mode == mode {
+                slot.count = slot.count.saturating_add(1);
+                return;
+            }
+        }
+        self.dropped += 1;
+    }
+
+    /// Скопировать непустые корзины в `out`; вернуть сколько их всего.
+    /// Copy the non-empty buckets into `out`; return how many there are.
+    pub fn snapshot(&self, out: &mut [Sample]) -> usize {
+        let mut n = 0;
+        for slot in self.slots.iter().filter(|s| s.count != 0) {
+            if let Some(dst) = out.get_mut(n) { *dst = *slot; }
+            n += 1;
+        }
+        n
+    }
+
+    /// Сколько сэмплов не влезло — для тестов / How many samples didn't fit — for tests
+    #[cfg(test)]
+    pub fn dropped(&self) -> u64 { self.dropped }
+}
+
+static ENABLED:   AtomicBool = AtomicBool::new(false);
+static HISTOGRAM: IrqMutex<Histogram> = IrqMutex::new(Histogram::new());
+
+pub fn init() {
+    ENABLED.store(crate::cmdline::flag("profile"), Ordering::Relaxed);
+    if enabled() {
+        crate::kprintln!("[profile] sampling RIP every tick ({}-byte buckets)", 1 << BUCKET_SHIFT);
+    }
+}
+
+#[inline]
+pub fn enabled() -> bool { ENABLED.load(Ordering::Relaxed) }
+
+/// Вызывается из обработчика таймера / Called from the timer handler
+#[inline]
+pub fn sample(rip: u64, cs: u64) {
+    if !enabled() { return; }
+    HISTOGRAM.lock().record(rip, Mode::from_cs(cs));
+}
+
+/// Снимок профиля (см. `Histogram::snapshot`) / Profile snapshot (see `Histogram::snapshot`)
+pub fn snapshot(out: &mut [Sample]) -> usize {
+    HISTOGRAM.lock().snapshot(out)
+}
+
+/// Включить или выключить без cmdline — для тестов / Toggle without the cmdline — for tests
+#[cfg(test)]
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(h: &Histogram) -> alloc::vec::Vec<Sample> {
+        let mut out = alloc::vec![Sample::EMPTY; SLOTS];
+        let n = h.snapshot(&mut out);
+        out.truncate(n);
+        out
+    }
+
+    #[test]
+    fn rips_in_one_bucket_accumulate() {
+        let mut h = Histogram::new();
+        let base = 0xFFFF_FFFF_8010_0040;
+        for rip in [base, base + 1, base + 63] { h.record(rip, Mode::Kernel); }
+        h.record(base + 64, Mode::Kernel);
+        let mut got = samples(&h);
+        got.sort_by_key(|s| s.addr);
+        assert_eq!(got.iter().map(|s| (s.addr, s.count)).collect::<alloc::vec::Vec<_>>(),
+                   [(base, 3), (base + 64, 1)]);
+    }
+
+    #[test]
+    fn kernel_and_user_samples_are_tagged_apart() {
+        assert_eq!(Mode::from_cs(0x08), Mode::Kernel);
+        assert_eq!(Mode::from_cs(0x23), Mode::User);
+        let mut h = Histogram::new();
+        h.record(0x40_1000, Mode::from_cs(0x23));
+        h.record(0x40_1000, Mode::from_cs(0x08));
+        h.record(0x40_1004, Mode::from_cs(0x23));
+        let got = samples(&h);
+        assert_eq!(got.len(), 2);
+        let user = got.iter().find(|s| s.mode == Mode::User).unwrap();
+        let kernel = got.iter().find(|s| s.mode == Mode::Kernel).unwrap();
+        assert_eq!((user.addr, user.count), (0x40_1000, 2));
+        assert_eq!((kernel.addr, kernel.count), (0x40_1000, 1));
+    }
+
+    #[test]
+    fn full_table_counts_drops_and_snapshot_reports_the_total() {
+        let mut h = Histogram::new();
+        for i in 0..SLOTS as u64 + 3 { h.record(i << BUCKET_SHIFT, Mode::User); }
+        assert_eq!(h.dropped(), 3);
+        let mut out = [Sample::EMPTY; 4];
+        assert_eq!(h.snapshot(&mut out), SLOTS);
+        assert!(out.iter().all(|s| s.count == 1));
+    }
+}
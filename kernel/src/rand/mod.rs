@@ -0,0 +1,117 @@
+//! Генератор случайных чисел ядра / Kernel random number generator
+//!
+//! RDRAND, если CPU его умеет, иначе программный splitmix64, засеянный TSC.
+//! RDRAND when the CPU has it, otherwise a software splitmix64 seeded from the TSC.
+//!
+//! `rngseed=<u64>` в командной строке — ТОЛЬКО для тестов: RDRAND
+//! выключается, а программный генератор засевается заданным числом, так
+//! что любая рандомизированная раскладка (ASLR и т.п.) воспроизводится
+//! в точности.
+//! `rngseed=<u64>` on the command line is for TESTING ONLY: RDRAND is
+//! disabled and the software generator is seeded with the given value, so
+//! any randomized layout (ASLR etc.) reproduces exactly.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::IrqMutex;
+
+/// splitmix64 — маленький, быстрый, любое зерно (даже 0) годится.
+/// splitmix64 — small, fast, any seed (even 0) is fine.
+pub struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+static PRNG:       IrqMutex<Prng> = IrqMutex::new(Prng::new(0));
+static USE_RDRAND: AtomicBool = AtomicBool::new(false);
+
+fn has_rdrand() -> bool {
+    let leaf = core::arch::x86_64::__cpuid(1);
+    leaf.ecx & (1 << 30) != 0
+}
+
+/// Одна попытка RDRAND (может не дать числа при исчерпании энтропии).
+/// A single RDRAND attempt (may fail when entropy is exhausted).
+fn rdrand() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    unsafe {
+        core::arch::asm!(
+            "rdrand {v}",
+            "setc {ok}",
+            v  = out(reg) value,
+            ok = out(reg_byte) ok,
+            options(nomem, nostack),
+        );
+    }
+    (ok != 0).then_some(value)
+}
+
+/// Разобрать число в десятичном или `0x` виде / Parse a decimal or `0x` number
+fn parse_u64(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None      => s.parse().ok(),
+    }
+}
+
+/// Инициализировать — после cmdline / Initialize — after cmdline
+pub fn init() {
+    if let Some(seed) = crate::cmdline::get("rngseed").and_then(parse_u64) {
+        *PRNG.lock() = Prng::new(seed);
+        USE_RDRAND.store(false, Ordering::Relaxed);
+        crate::kprintln!("[rand] Deterministic seed {:#x} — testing only, RDRAND disabled", seed);
+        return;
+    }
+
+    let rdrand_ok = has_rdrand();
+    let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+    let seed = if rdrand_ok { rdrand().unwrap_or(0) ^ tsc } else { tsc };
+    *PRNG.lock() = Prng::new(seed);
+    USE_RDRAND.store(rdrand_ok, Ordering::Relaxed);
+    crate::kprintln!("[rand] {}", if rdrand_ok { "RDRAND" } else { "software PRNG (no RDRAND)" });
+}
+
+/// Следующее случайное число. Читателей (ASLR) у ядра пока нет.
+/// Next random number. The kernel has no readers of it (ASLR) yet.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn next_u64() -> u64 {
+    if USE_RDRAND.load(Ordering::Relaxed) {
+        // Intel рекомендует до 10 повторов / Intel recommends up to 10 retries
+        for _ in 0..10 {
+            if let Some(v) = rdrand() { return v; }
+        }
+    }
+    PRNG.lock().next_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        let (mut a, mut b) = (Prng::new(0x1455), Prng::new(0x1455));
+        for _ in 0..64 { assert_eq!(a.next_u64(), b.next_u64()); }
+        assert_ne!(Prng::new(1).next_u64(), Prng::new(2).next_u64());
+    }
+
+    #[test]
+    fn seeds_parse_in_decimal_and_hex() {
+        assert_eq!(parse_u64("42"), Some(42));
+        assert_eq!(parse_u64("0x2a"), Some(42));
+        assert_eq!(parse_u64("seed"), None);
+    }
+}
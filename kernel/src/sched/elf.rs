@@ -0,0 +1,352 @@
+//! Загрузчик ELF64 — образ из памяти в новое `AddressSpace`
+//! ELF64 loader — an image from memory into a fresh `AddressSpace`
+//!
+//! Только статические исполняемые (ET_EXEC) x86_64, little-endian: ни
+//! интерпретатора, ни релокаций. Каждый PT_LOAD становится anonymous VMA,
+//! страницы которой заполнены сразу — файловые байты, остальное (bss)
+//! нули. Флаги сегмента переводятся в `PageFlags` под W^X: сегмент и на
+//! запись, и на исполнение не загружается. PT_TLS не мапится — его шаблон
+//! отдаётся вызывающему для TLS задачи.
+//! Static x86_64 little-endian executables (ET_EXEC) only: no interpreter,
+//! no relocations. Each PT_LOAD becomes an anonymous VMA whose pages are
+//! filled up front — the file bytes, the rest (bss) zeroes. Segment flags
+//! turn into `PageFlags` under W^X: a segment both writable and executable
+//! is not loaded. PT_TLS isn't mapped — its template goes back to the
+//! caller for the task's TLS.
+
+use crate::mm::pmm::{self, PAGE_SIZE};
+use crate::mm::vmm::{phys_to_virt, AddressSpace, PageFlags, VirtAddr, USER_END};
+use crate::mm::MmError;
+use super::tls::{TlsLayout, TlsTemplate};
+
+const ELF_MAGIC:   [u8; 4] = *b"\x7fELF";
+const ELFCLASS64:  u8  = 2;
+const ELFDATA2LSB: u8  = 1;
+const ET_EXEC:     u16 = 2;
+const EM_X86_64:   u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_TLS:  u32 = 7;
+const PF_X:    u32 = 1 << 0;
+const PF_W:    u32 = 1 << 1;
+
+/// Размер заголовка ELF64 и записи program header
+/// Size of the ELF64 header and of a program header entry
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+/// Почему образ не загружен / Why the image wasn't loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// Заголовок или сегмент выходит за конец образа
+    /// A header or segment runs past the end of the image
+    Truncated,
+    /// Не ELF / Not an ELF
+    BadMagic,
+    /// Не ELF64 LE исполняемый для x86_64 / Not an ELF64 LE x86_64 executable
+    Unsupported,
+    /// Сегмент вне нижней половины, `filesz > memsz` или вход не в сегменте
+    /// A segment outside the lower half, `filesz > memsz` or the entry outside any segment
+    BadSegment,
+    /// Сегмент и на запись, и на исполнение — W^X такого не пустит
+    /// A segment both writable and executable — W^X won't let it in
+    WriteExec,
+    /// Маппинг не удался / Mapping failed
+    Memory(MmError),
+}
+
+impl From<MmError> for ElfError {
+    fn from(e: MmError) -> Self { Self::Memory(e) }
+}
+
+fn u16_at(image: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([image[at], image[at + 1]])
+}
+
+fn u32_at(image: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(image[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(image: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(image[at..at + 8].try_into().unwrap())
+}
+
+/// Program header PT_LOAD / A PT_LOAD program header
+struct Segment {
+    flags:  u32,
+    offset: u64,
+    vaddr:  u64,
+    filesz: u64,
+    memsz:  u64,
+}
+
+impl Segment {
+    /// Права страниц: W — RW без исполнения, X — только исполнение, иначе
+    /// только чтение. W+X сюда не доходит — его отвергает `load_segment`.
+    /// Page permissions: W — RW without exec, X — exec only, otherwise
+    /// read-only. W+X never gets here — `load_segment` rejects it.
+    fn page_flags(&self) -> PageFlags {
+        if self.flags & PF_W != 0 {
+            PageFlags::USER_RW
+        } else if self.flags & PF_X != 0 {
+            PageFlags::USER_EX
+        } else {
+            PageFlags::USER_RW.difference(PageFlags::WRITABLE)
+        }
+    }
+}
+
+/// Загруженный образ / A loaded image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loaded<'a> {
+    pub entry: u64,
+    /// Шаблон PT_TLS и его .tdata из образа / The PT_TLS template and its .tdata from the image
+    pub tls:   Option<(TlsTemplate, &'a [u8])>,
+}
+
+/// Загрузить `image` в `space`. При ошибке пространство остаётся частично
+/// заполненным — вызывающий его выбрасывает.
+/// Load `image` into `space`. On error the space is left partly filled —
+/// the caller throws it away.
+pub fn load<'a>(space: &AddressSpace, image: &'a [u8]) -> Result<Loaded<'a>, ElfError> {
+    if image.len() < EHDR_SIZE { return Err(ElfError::Truncated); }
+    if image[..4] != ELF_MAGIC { return Err(ElfError::BadMagic); }
+    if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB
+        || u16_at(image, 16) != ET_EXEC || u16_at(image, 18) != EM_X86_64
+        || (u16_at(image, 54) as usize) < PHDR_SIZE
+    {
+        return Err(ElfError::Unsupported);
+    }
+    let entry = u64_at(image, 24);
+    let phoff = u64_at(image, 32) as usize;
+    let phentsize = u16_at(image, 54) as usize;
+    let phnum = u16_at(image, 56) as usize;
+    let table_end = phnum.checked_mul(phentsize).and_then(|n| n.checked_add(phoff));
+    if table_end.is_none_or(|end| end > image.len()) { return Err(ElfError::Truncated); }
+
+    let mut entry_mapped = false;
+    let mut tls = None;
+    for i in 0..phnum {
+        let at = phoff + i * phentsize;
+        match u32_at(image, at) {
+            PT_LOAD => {}
+            PT_TLS  => { tls = Some(tls_template(image, at)?); continue; }
+            _       => continue,
+        }
+        let seg = Segment {
+            flags:  u32_at(image, at + 4),
+            offset: u64_at(image, at + 8),
+            vaddr:  u64_at(image, at + 16),
+            filesz: u64_at(image, at + 32),
+            memsz:  u64_at(image, at + 40),
+        };
+        if seg.memsz == 0 { continue; }
+        load_segment(space, image, &seg)?;
+        if seg.flags & PF_X != 0 && (seg.vaddr..seg.vaddr + seg.memsz).contains(&entry) {
+            entry_mapped = true;
+        }
+    }
+    if !entry_mapped { return Err(ElfError::BadSegment); }
+    Ok(Loaded { entry, tls })
+}
+
+/// Шаблон из PT_TLS по смещению `at`; раскладка должна считаться.
+/// The template from the PT_TLS at offset `at`; its layout must compute.
+fn tls_template(image: &[u8], at: usize) -> Result<(TlsTemplate, &[u8]), ElfError> {
+    let offset = u64_at(image, at + 8);
+    let filesz = u64_at(image, at + 32);
+    let template = TlsTemplate {
+        file_size: filesz as usize,
+        mem_size:  u64_at(image, at + 40) as usize,
+        align:     u64_at(image, at + 48) as usize,
+    };
+    TlsLayout::compute(Some(&template)).ok_or(ElfError::BadSegment)?;
+    let end = offset.checked_add(filesz).ok_or(ElfError::Truncated)?;
+    if end > image.len() as u64 { return Err(ElfError::Truncated); }
+    Ok((template, &image[offset as usize..end as usize]))
+}
+
+fn load_segment(space: &AddressSpace, image: &[u8], seg: &Segment) -> Result<(), ElfError> {
+    let page = PAGE_SIZE as u64;
+    let end = seg.vaddr.checked_add(seg.memsz).ok_or(ElfError::BadSegment)?;
+    if seg.filesz > seg.memsz || end > USER_END { return Err(ElfError::BadSegment); }
+    if seg.flags & PF_W != 0 && seg.flags & PF_X != 0 { return Err(ElfError::WriteExec); }
+    let file_end = seg.offset.checked_add(seg.filesz).ok_or(ElfError::Truncated)?;
+    if file_end > image.len() as u64 { return Err(ElfError::Truncated); }
+
+    let start = seg.vaddr & !(page - 1);
+    let end = end.div_ceil(page) * page;
+    let flags = seg.page_flags();
+    space.map_anonymous(VirtAddr::new(start), end - start, flags)?;
+
+    let data_end = seg.vaddr + seg.filesz;
+    for va in (start..end).step_by(PAGE_SIZE) {
+        let phys = pmm::alloc_zeroed_page()?;
+        // Файловая часть страницы; остальное уже нули
+        // The file-backed part of the page; the rest is zeroes already
+        let lo = va.max(seg.vaddr);
+        let hi = (va + page).min(data_end);
+        if lo < hi {
+            let src = (seg.offset + (lo - seg.vaddr)) as usize;
+            let dst = phys_to_virt(phys).as_u64() + (lo - va);
+            let len = (hi - lo) as usize;
+            unsafe { core::ptr::copy_nonoverlapping(image[src..src + len].as_ptr(), dst as *mut u8, len); }
+        }
+        if let Err(e) = space.map(VirtAddr::new(va), phys, flags) {
+            pmm::free_page(phys);
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use alloc::vec::Vec;
+
+    const BASE: u64 = 0x40_0000;
+    const CODE: &[u8] = &[0x90, 0x90, 0xEB, 0xFE];
+    /// `mov eax, 11` (task_exit); `xor edi, edi`; `syscall`
+    const EXIT_CODE: &[u8] = &[0xB8, 0x0B, 0x00, 0x00, 0x00, 0x31, 0xFF, 0x0F, 0x05];
+
+    fn image(flags: u32, bss: u64) -> Vec<u8> {
+        image_with(CODE, flags, bss)
+    }
+
+    /// ELF с одним PT_LOAD: заголовки и `code` с `BASE`, `bss` байт нулей сверху
+    /// An ELF with one PT_LOAD: the headers and `code` at `BASE`, `bss` zero bytes on top
+    fn image_with(code: &[u8], flags: u32, bss: u64) -> Vec<u8> {
+        let filesz = (EHDR_SIZE + PHDR_SIZE + code.len()) as u64;
+        let mut elf = Vec::new();
+        elf.extend_from_slice(&ELF_MAGIC);
+        elf.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, 1]);
+        elf.resize(16, 0);
+        elf.extend_from_slice(&ET_EXEC.to_le_bytes());
+        elf.extend_from_slice(&EM_X86_64.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&(BASE + (EHDR_SIZE + PHDR_SIZE) as u64).to_le_bytes()); // e_entry
+        elf.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes());                      // e_phoff
+        elf.extend_from_slice(&0u64.to_le_bytes());                                    // e_shoff
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        elf.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        elf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        elf.extend_from_slice(&1u16.to_le_bytes());                                     // e_phnum
+        elf.resize(EHDR_SIZE, 0);
+
+        elf.extend_from_slice(&PT_LOAD.to_le_bytes());
+        elf.extend_from_slice(&flags.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes());            // p_offset
+        elf.extend_from_slice(&BASE.to_le_bytes());            // p_vaddr
+        elf.extend_from_slice(&BASE.to_le_bytes());            // p_paddr
+        elf.extend_from_slice(&filesz.to_le_bytes());
+        elf.extend_from_slice(&(filesz + bss).to_le_bytes());  // p_memsz
+        elf.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+        elf.extend_from_slice(code);
+        elf
+    }
+
+    fn load_fresh(image: &[u8]) -> Result<u64, ElfError> {
+        load(&AddressSpace::new().unwrap(), image).map(|loaded| loaded.entry)
+    }
+
+    #[test]
+    fn loads_code_and_zeroes_bss() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let elf = image(PF_X | 4, 2 * PAGE_SIZE as u64);
+        assert_eq!(load(&space, &elf), Ok(Loaded { entry: BASE + (EHDR_SIZE + PHDR_SIZE) as u64, tls: None }));
+
+        let first = space.translate(VirtAddr::new(BASE)).unwrap();
+        let loaded = unsafe { core::slice::from_raw_parts(phys_to_virt(first).as_u64() as *const u8, PAGE_SIZE) };
+        assert_eq!(&loaded[..elf.len()], &elf[..]);
+        assert!(loaded[elf.len()..].iter().all(|&b| b == 0));
+        let last = space.translate(VirtAddr::new(BASE + 2 * PAGE_SIZE as u64)).unwrap();
+        let bss = unsafe { core::slice::from_raw_parts(phys_to_virt(last).as_u64() as *const u8, PAGE_SIZE) };
+        assert!(bss.iter().all(|&b| b == 0));
+
+        let flags = space.leaf_flags(VirtAddr::new(BASE)).unwrap();
+        assert!(!flags.contains(PageFlags::WRITABLE) && !flags.contains(PageFlags::NO_EXEC));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let _kernel = testing::setup();
+        let elf = image(PF_X, 0);
+        assert_eq!(load_fresh(&elf[..EHDR_SIZE - 1]), Err(ElfError::Truncated));
+        let mut bad = elf.clone();
+        bad[1] = b'X';
+        assert_eq!(load_fresh(&bad), Err(ElfError::BadMagic));
+        for (at, value) in [(4, 1u8), (5, 2), (16, 3), (18, 3)] {
+            let mut bad = elf.clone();
+            bad[at] = value;
+            assert_eq!(load_fresh(&bad), Err(ElfError::Unsupported), "byte {at}");
+        }
+        // Таблица program header'ов за концом образа
+        // The program header table past the end of the image
+        let mut bad = elf.clone();
+        bad[56] = 0xFF;
+        assert_eq!(load_fresh(&bad), Err(ElfError::Truncated));
+    }
+
+    #[test]
+    fn rejects_bad_segments() {
+        let _kernel = testing::setup();
+        let elf = image(PF_X, 0);
+        let phdr = EHDR_SIZE;
+        let patch = |at: usize, value: u64| {
+            let mut bad = elf.clone();
+            bad[phdr + at..phdr + at + 8].copy_from_slice(&value.to_le_bytes());
+            bad
+        };
+        // filesz > memsz
+        assert_eq!(load_fresh(&patch(40, 1)), Err(ElfError::BadSegment));
+        // Сегмент в верхней половине / A segment in the upper half
+        assert_eq!(load_fresh(&patch(16, USER_END)), Err(ElfError::BadSegment));
+        // Файловые байты за концом образа / File bytes past the end of the image
+        assert_eq!(load_fresh(&patch(8, 1)), Err(ElfError::Truncated));
+        // Вход вне исполняемого сегмента / The entry outside the executable segment
+        assert_eq!(load_fresh(&image(4, 0)), Err(ElfError::BadSegment));
+        assert_eq!(load_fresh(&image(PF_W | PF_X, 0)), Err(ElfError::WriteExec));
+    }
+
+    #[test]
+    fn a_tiny_elf_runs_through_to_task_exit() {
+        use crate::arch::x86_64::{gdt, idt::{self, FullContext}};
+        use crate::sched::{self, task::TaskState, DYING};
+
+        let _kernel = testing::setup();
+        let elf = image_with(EXIT_CODE, PF_X | 4, 0);
+        let entry = BASE + (EHDR_SIZE + PHDR_SIZE) as u64;
+        let id = sched::spawn_elf(&elf, Vec::new()).unwrap();
+
+        // Загрузочный поток уходит в задачу: `iretq` в ring 3 на её вход
+        // The boot thread leaves for the task: an `iretq` into ring 3 at its entry
+        sched::schedule();
+        assert_eq!(sched::current_id(), Some(id));
+        let task = sched::current().unwrap();
+        let at = idt::switched_to();
+        assert_eq!(at, task.saved_rsp);
+        let ctx = unsafe { &*task.kernel_stack.hhdm_alias(VirtAddr::new(at)).as_ptr::<FullContext>() };
+        assert_eq!((ctx.frame.rip, ctx.frame.cs, ctx.frame.rsp), (entry, gdt::USER_CODE as u64, sched::USER_STACK_TOP));
+        assert_eq!(ctx.frame.rflags, sched::USER_RFLAGS);
+        let space = task.space.clone().unwrap();
+        assert!(space.is_active());
+        drop(task);
+        let code = space.translate(VirtAddr::new(entry)).unwrap();
+        let code = unsafe { core::slice::from_raw_parts(phys_to_virt(code).as_ptr::<u8>(), EXIT_CODE.len()) };
+        assert_eq!(code, EXIT_CODE);
+
+        // На хосте код не исполнить — его `task_exit` доходит до ядра так
+        // On the host the code can't run — its `task_exit` reaches the kernel like this
+        sched::retire_current(0);
+        assert_eq!(sched::task_row(id), None);
+        assert!(DYING.lock().iter().any(|task| task.id == id && task.state == TaskState::Zombie));
+        drop(space);
+        let free = pmm::free_memory();
+        sched::reap();
+        assert!(pmm::free_memory() > free);
+        sched::set_current(None);
+    }
+}
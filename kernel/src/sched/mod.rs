@@ -3,7 +3,9 @@
 //! Multilevel Feedback Queue адаптированный под IPC события.
 //! Multilevel Feedback Queue adapted for IPC events.
 //!
-//! Очереди / Queues:
+//! Очереди (кванты по умолчанию, меняются через `sched.quanta=` или
+//! `set_quanta`) / Queues (default quanta, tunable via `sched.quanta=` or
+//! `set_quanta`):
 //!   0 →  1ms — IPC wake-ups        (highest priority)
 //!   1 →  5ms — interactive tasks
 //!   2 → 20ms — normal tasks
@@ -11,21 +13,1346 @@
 //!
 //! IPC пробуждение ВСЕГДА идёт в очередь 0.
 //! IPC wake-up ALWAYS goes to queue 0.
+//!
+//! Готовые задачи стоят в FIFO-очереди своего уровня; выбирается голова
+//! самой приоритетной непустой. Исчерпавшая квант задача опускается на
+//! уровень ниже и встаёт в хвост.
+//! Ready tasks wait in their level's FIFO queue; the head of the
+//! highest-priority non-empty one is picked. A task that used up its
+//! quantum drops a level and goes to the tail.
+//!
+//! Раз в `BOOST_INTERVAL_MS` все готовые и выполняющиеся задачи поднимаются
+//! в очередь 0 со свежим квантом — фоновые не голодают за занятыми верхними
+//! очередями. Дальше, как после IPC-буста, квант 0 возвращает их в базу.
+//! Every `BOOST_INTERVAL_MS` all ready and running tasks are raised to
+//! queue 0 with a fresh quantum — background tasks don't starve behind busy
+//! upper queues. From there, as after an IPC boost, the level-0 quantum
+//! returns them to their base.
+//!
+//! Когда все очереди пусты, а текущей задаче выполняться нечем, CPU
+//! достаётся задаче простоя: она `hlt`ит с включёнными прерываниями, в
+//! очередях не стоит, квантов не тратит и в подъёмах не участвует.
+//! When every queue is empty and the current task has nothing to run, the
+//! CPU goes to the idle task: it `hlt`s with interrupts enabled, never sits
+//! in a queue, spends no quanta and takes no part in boosts.
+//!
+//! Класс приоритета задаёт базовую очередь (Interactive → 1, Normal → 2,
+//! Background → 3); после IPC-буста задача возвращается именно в неё.
+//! The priority class sets the base queue (Interactive → 1, Normal → 2,
+//! Background → 3); after an IPC boost the task returns exactly there.
+//!
+//! Время берётся только из `time::now`, тики — из `on_timer`: в тестах с
+//! `time::VirtualClock` и ручными `on_timer`/`wake_expired` весь ход
+//! планировщика воспроизводим.
+//! Time comes only from `time::now` and ticks from `on_timer`: in tests,
+//! with a `time::VirtualClock` and manual `on_timer`/`wake_expired` calls,
+//! the whole course of the scheduler is reproducible.
+
+pub mod elf;
+pub mod task;
+pub mod tls;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+use crate::arch::x86_64::gdt::{self, IoBitmap};
+use crate::arch::x86_64::idt::{FullContext, InterruptFrame};
+use crate::arch::x86_64::segbase;
+use crate::cmdline;
+use crate::sync::{IrqMutex, IrqMutexGuard};
+use cupruxos_abi::Priority;
+use crate::ipc::cap::{self, CapEntry, CapKind};
+use crate::ipc::{CapId, TaskId};
+use crate::mm::vmm::{self, AddressSpace, PageFlags, VirtAddr};
+use crate::mm::MmError;
+use elf::ElfError;
+use task::{Task, TaskState};
+
+/// Число уровней MLFQ / Number of MLFQ levels
+pub const LEVELS: usize = 4;
+
+/// Кванты по умолчанию (мс) / Default quanta (ms)
+pub const DEFAULT_QUANTA: [u32; LEVELS] = [1, 5, 20, 100];
+
+static QUANTA: [AtomicU32; LEVELS] = [
+    AtomicU32::new(DEFAULT_QUANTA[0]),
+    AtomicU32::new(DEFAULT_QUANTA[1]),
+    AtomicU32::new(DEFAULT_QUANTA[2]),
+    AtomicU32::new(DEFAULT_QUANTA[3]),
+];
+
+/// Кванты допустимы: ненулевые и не убывают по уровням.
+/// Quanta are valid: nonzero and non-decreasing across levels.
+pub fn valid_quanta(quanta: &[u32; LEVELS]) -> bool {
+    quanta[0] != 0 && quanta.windows(2).all(|w| w[0] <= w[1])
+}
+
+/// Разобрать `a,b,c,d` (мс) / Parse `a,b,c,d` (ms)
+pub fn parse_quanta(s: &str) -> Option<[u32; LEVELS]> {
+    let mut quanta = [0; LEVELS];
+    let mut parts = s.split(',');
+    for q in quanta.iter_mut() {
+        *q = parts.next()?.trim().parse().ok()?;
+    }
+    if parts.next().is_some() || !valid_quanta(&quanta) { return None; }
+    Some(quanta)
+}
+
+/// Поставить новые кванты; невалидные — `false`, старые остаются.
+/// Install new quanta; invalid ones — `false`, the old ones stay.
+pub fn set_quanta(quanta: [u32; LEVELS]) -> bool {
+    if !valid_quanta(&quanta) { return false; }
+    for (slot, q) in QUANTA.iter().zip(quanta) { slot.store(q, Ordering::Relaxed); }
+    true
+}
+
+/// Квант уровня в тиках (1 тик = 1мс) / Quantum of a level in ticks (1 tick = 1ms)
+pub fn quantum(level: u8) -> u32 {
+    QUANTA[(level as usize).min(LEVELS - 1)].load(Ordering::Relaxed)
+}
+
+/// Очередь IPC-пробуждений / IPC wake-up queue
+pub const IPC_BOOST_LEVEL: u8 = 0;
+
+/// Период подъёма всех задач в очередь 0, мс / Period of raising every task to queue 0, ms
+pub const BOOST_INTERVAL_MS: u64 = 1000;
+
+/// `time::now` последнего подъёма, нс / `time::now` of the last boost, ns
+static LAST_BOOST: AtomicU64 = AtomicU64::new(0);
+
+/// Стартовая очередь класса / Starting queue of a class
+pub const fn level_of(priority: Priority) -> u8 {
+    match priority {
+        Priority::Interactive => 1,
+        Priority::Normal      => 2,
+        Priority::Background  => 3,
+    }
+}
+
+// IrqMutex: задачи нужны и из прерываний (таймер, исключения, syscall'ы) —
+// прерывание посреди владения не должно застать lock занятым.
+// IrqMutex: tasks are needed from interrupts too (timer, exceptions,
+// syscalls) — an interrupt must never find the lock held under it.
+// Box: `schedule` держит адрес `saved_rsp` уходящей задачи за пределами
+// lock'а — перестройка дерева не должна его сдвинуть.
+// Box: `schedule` keeps the address of the outgoing task's `saved_rsp`
+// beyond the lock — rebalancing the tree must not move it.
+static TASKS:   IrqMutex<BTreeMap<TaskId, Box<Task>>> = IrqMutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Очереди готовых задач по уровням MLFQ; берутся только под TASKS.
+/// Записи не вычищаются при смене состояния или уровня — выбор пропускает
+/// устаревшие (см. `Task::queued`).
+/// Ready queues by MLFQ level; taken only under TASKS. Entries aren't
+/// purged when a task changes state or level — picking skips stale ones
+/// (see `Task::queued`).
+static RUN_QUEUES: Mutex<[VecDeque<TaskId>; LEVELS]> = Mutex::new([const { VecDeque::new() }; LEVELS]);
+
+/// Поставить готовую задачу в хвост очереди её уровня. Не готова или уже
+/// стоит там — ничего.
+/// Put a ready task at the tail of its level's queue. Not ready or already
+/// there — nothing.
+fn enqueue(task: &mut Task) {
+    if is_idle(task.id) { return; }
+    if task.state != TaskState::Runnable || task.queued == Some(task.queue_level) { return; }
+    task.queued = Some(task.queue_level);
+    RUN_QUEUES.lock()[task.queue_level as usize].push_back(task.id);
+}
+
+/// Создать задачу со своим стеком ядра, без контекста — для тестов.
+/// `None` — PMM исчерпан.
+/// Create a task with its own kernel stack and no context — for tests.
+/// `None` — PMM exhausted.
+#[cfg(test)]
+pub fn spawn() -> Option<TaskId> {
+    let id = TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let mut task = Box::new(Task::new(id)?);
+    let mut tasks = TASKS.lock();
+    enqueue(&mut task);
+    tasks.insert(id, task);
+    Some(id)
+}
+
+/// Готовая задача с первым контекстом ядра — `pick_next` её выберет. На
+/// хосте контексты не возобновляются, так что точка входа не важна. Для тестов.
+/// A ready task with a first kernel context — `pick_next` will pick it. On
+/// the host contexts are never resumed, so the entry point doesn't matter.
+/// For tests.
+#[cfg(test)]
+pub fn spawn_ready() -> Option<TaskId> {
+    let id = spawn()?;
+    seed_context(TASKS.lock().get_mut(&id)?, FullContext::kernel_entry(0, 0, USER_RFLAGS));
+    Some(id)
+}
+
+/// Вершина пользовательского стека новой задачи / Top of a new task's user stack
+pub const USER_STACK_TOP: u64 = 0x0000_7FFF_FFFF_F000;
+/// Начало TLS-области новой задачи / Start of a new task's TLS area
+pub const USER_TLS_BASE: u64 = 0x0000_7FFF_0000_0000;
+/// Страниц пользовательского стека (страница защиты — сверх них)
+/// Pages of the user stack (the guard page comes on top of them)
+pub const USER_STACK_PAGES: usize = 16;
+
+/// Создать задачу из ELF-образа: своё пространство с сегментами и стеком
+/// под `USER_STACK_TOP`, `caps` — в её таблице, первый вход — в ring 3 на
+/// точку входа. Задача сразу готова.
+/// Create a task from an ELF image: its own space with the segments and a
+/// stack below `USER_STACK_TOP`, `caps` in its table, the first entry into
+/// ring 3 at the entry point. The task is ready at once.
+pub fn spawn_elf(image: &[u8], caps: Vec<CapEntry>) -> Result<TaskId, ElfError> {
+    let space = vmm::new_user_space()?;
+    let loaded = elf::load(&space, image)?;
+    space.map_stack(VirtAddr::new(USER_STACK_TOP), USER_STACK_PAGES, PageFlags::USER_RW)?;
+
+    let id = TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let mut task = Box::new(Task::new(id).ok_or(ElfError::Memory(MmError::OutOfMemory))?);
+    let (template, tdata) = loaded.tls.map_or((None, &[][..]), |(t, data)| (Some(t), data));
+    if !setup_tls(&mut task, &space, template.as_ref(), tdata) {
+        return Err(ElfError::Memory(MmError::OutOfMemory));
+    }
+    task.space = Some(Arc::new(space));
+    seed_context(&mut task, FullContext::user_entry(loaded.entry, USER_STACK_TOP, USER_RFLAGS));
+    // До TASKS: таблица capability берётся только после него
+    // Before TASKS: the capability table is only ever taken after it
+    for entry in caps { cap::install(id, entry); }
+    let mut tasks = TASKS.lock();
+    enqueue(&mut task);
+    tasks.insert(id, task);
+    Ok(id)
+}
+
+/// Переименовать задачу (syscall task_set_name). `false` — нет задачи.
+/// Rename a task (the task_set_name syscall). `false` — no such task.
+pub fn set_name(id: TaskId, name: &[u8]) -> bool {
+    match TASKS.lock().get_mut(&id) {
+        Some(task) => { task.set_name(name); true }
+        None       => false,
+    }
+}
+
+/// Напечатать список задач — то, что покажет `ps`. Зовётся и из паники:
+/// `TASKS` мог остаться занятым под ней — тогда списка не будет.
+/// Print the task list — what `ps` will show. Called from a panic too:
+/// `TASKS` may have been left held under it — then there is no list.
+pub fn dump_tasks() {
+    let Some(tasks) = TASKS.try_lock() else {
+        crate::kprintln!("[sched] task list is locked — not shown");
+        return;
+    };
+    let _ = write_tasks(&mut crate::drivers::console::Console, &tasks);
+}
+
+fn write_tasks(out: &mut impl core::fmt::Write, tasks: &BTreeMap<TaskId, Box<Task>>) -> core::fmt::Result {
+    let current = TaskId(CURRENT.load(Ordering::Relaxed));
+    writeln!(out, "  {:>5} {:<13} {:>5}  NAME", "ID", "STATE", "LEVEL")?;
+    for task in tasks.values() {
+        writeln!(out, "{} {:>5} {:<13} {:>5}  {}", if task.id == current { '*' } else { ' ' },
+                 task.id.0, task.state.name(), task.queue_level, task.name())?;
+    }
+    Ok(())
+}
+
+/// Строка задачи `id` из списка задач — для тестов
+/// Task `id`'s row of the task list — for tests
+#[cfg(test)]
+pub fn task_row(id: TaskId) -> Option<alloc::string::String> {
+    let mut list = alloc::string::String::new();
+    write_tasks(&mut list, &TASKS.lock()).ok()?;
+    let id = alloc::format!("{}", id.0);
+    list.lines().skip(1)
+        .find(|row| row.trim_start_matches(['*', ' ']).split(' ').next() == Some(&*id))
+        .map(Into::into)
+}
+
+/// Завершить задачу — её стек ядра возвращается в PMM, таблица capability
+/// выбрасывается. Только для тестов: живая задача уходит через
+/// `exit_current` или `kill_current`.
+/// Terminate a task — its kernel stack goes back to the PMM, its capability
+/// table is thrown away. Tests only: a live task leaves through
+/// `exit_current` or `kill_current`.
+#[cfg(test)]
+pub fn exit(id: TaskId) {
+    TASKS.lock().remove(&id);
+    crate::ipc::call::forget_task(id);
+    cap::drop_table(id);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityError {
+    NoSuchTask,
+    /// Выше Normal без SchedControl capability / Above Normal without a SchedControl capability
+    Denied,
+}
+
+/// Сменить класс задачи. Понизить можно всегда; поднять выше `Normal` —
+/// только с capability `SchedControl`. Меняется база: если задача сейчас
+/// в IPC-бусте, она остаётся там и потом вернётся в новую базу.
+/// Change a task's class. Lowering is always allowed; raising above `Normal`
+/// needs a `SchedControl` capability. This sets the base: a task currently
+/// IPC-boosted stays boosted and later returns to the new base.
+pub fn set_priority(id: TaskId, priority: Priority) -> Result<(), PriorityError> {
+    let level = level_of(priority);
+    let mut tasks = TASKS.lock();
+    let task = tasks.get_mut(&id).ok_or(PriorityError::NoSuchTask)?;
+
+    let raising = level < task.base_level;
+    if raising && priority < Priority::Normal
+        && !cap::holds(id, |k| matches!(k, CapKind::SchedControl))
+    {
+        return Err(PriorityError::Denied);
+    }
+
+    let boosted = task.queue_level < task.base_level;
+    task.base_level = level;
+    if !boosted { task.queue_level = level; }
+    enqueue(task);
+    Ok(())
+}
+
+/// IPC-пробуждение: задача прыгает в очередь 0 со свежим квантом; по его
+/// исчерпании `charge_tick` вернёт её в базу.
+/// IPC wake-up: the task jumps to queue 0 with a fresh quantum; once it runs
+/// out, `charge_tick` returns the task to its base.
+fn ipc_boost(task: &mut Task) {
+    task.queue_level = IPC_BOOST_LEVEL;
+    task.ticks_used = 0;
+}
+
+/// Прошло `BOOST_INTERVAL_MS` с прошлого подъёма — поднять готовые и
+/// выполняющуюся задачи в очередь 0. Очереди не перебираются по задаче:
+/// нижние целиком дописываются в хвост нулевой, в прежнем порядке.
+/// `BOOST_INTERVAL_MS` has passed since the last boost — raise the ready and
+/// running tasks to queue 0. The queues aren't rebuilt task by task: the
+/// lower ones are appended whole to the tail of queue 0, in their order.
+fn boost_if_due(tasks: &mut BTreeMap<TaskId, Box<Task>>, now: u64) -> bool {
+    let last = LAST_BOOST.load(Ordering::Relaxed);
+    if now.saturating_sub(last) < BOOST_INTERVAL_MS * 1_000_000 { return false; }
+    LAST_BOOST.store(now, Ordering::Relaxed);
+
+    let mut queues = RUN_QUEUES.lock();
+    let (top, lower) = queues.split_at_mut(1);
+    for queue in lower { top[0].append(queue); }
+    drop(queues);
+    // Устаревшая запись могла попасть в очередь 0 рядом с живой — лишний
+    // выбор отсеет проверка `queued` в `pick_next`
+    // A stale entry may land in queue 0 next to a live one — the state check
+    // of `queued` in `pick_next` weeds out the extra pick
+    for task in tasks.values_mut() {
+        if is_idle(task.id) || !matches!(task.state, TaskState::Runnable | TaskState::Running) { continue; }
+        task.queue_level = 0;
+        task.ticks_used = 0;
+        if task.queued.is_some() { task.queued = Some(0); }
+    }
+    true
+}
+
+/// Тик таймера для выполняющейся задачи. `true` — квант исчерпан, пора
+/// перепланировать: задача из IPC-буста возвращается в базу, остальные
+/// опускаются на уровень ниже.
+/// Timer tick for the running task. `true` — the quantum is used up and
+/// it's time to reschedule: an IPC-boosted task returns to its base, the
+/// rest drop one level.
+fn charge_tick(task: &mut Task) -> bool {
+    if is_idle(task.id) { return false; }
+    task.ticks_used += 1;
+    if task.ticks_used < quantum(task.queue_level) { return false; }
+    task.ticks_used = 0;
+    task.queue_level = if task.queue_level < task.base_level {
+        task.base_level
+    } else {
+        (task.queue_level + 1).min(LEVELS as u8 - 1)
+    };
+    true
+}
+
+/// Запарковать выполняющуюся задачу до IPC-пробуждения (`wake_ipc`).
+/// С CPU она уходит на ближайшем возврате в ring 3. `false` — нет задачи
+/// или она не Running.
+/// Park the running task until an IPC wake-up (`wake_ipc`). It leaves the
+/// CPU on the next return to ring 3. `false` — no such task or it isn't
+/// Running.
+pub fn block_on_ipc(id: TaskId) -> bool {
+    let mut tasks = TASKS.lock();
+    let Some(task) = tasks.get_mut(&id) else { return false };
+    if !task.state.can_transition(TaskState::BlockedOnIpc) { return false; }
+    task.state.transition(TaskState::BlockedOnIpc);
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Разбудить запаркованную в IPC задачу — сразу в очередь 0.
+/// Wake a task parked in IPC — straight into queue 0.
+pub fn wake_ipc(id: TaskId) -> bool {
+    let mut tasks = TASKS.lock();
+    let Some(task) = tasks.get_mut(&id) else { return false };
+    if !unpark_ipc(task) { return false; }
+    ipc_boost(task);
+    enqueue(task);
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Снять парковку IPC без буста: ожидание кончилось само (сообщение
+/// забрано повтором или истёк дедлайн). Не запаркована — ничего.
+/// Lift an IPC parking without a boost: the wait ended on its own (the
+/// message was taken by a retry or the deadline passed). Not parked — nothing.
+pub fn unpark(id: TaskId) {
+    if let Some(task) = TASKS.lock().get_mut(&id) {
+        if unpark_ipc(task) { enqueue(task); }
+    }
+}
+
+fn unpark_ipc(task: &mut Task) -> bool {
+    if task.state != TaskState::BlockedOnIpc { return false; }
+    resume(task);
+    true
+}
 
-// TODO: Этап 5 — реализация планировщика
-// TODO: Phase 5 — Scheduler implementation
+/// Заблокированная задача снова готова. Ещё не ушла с CPU (повторяет
+/// syscall или ждёт в `sleep`) — значит, выполняется, и в очередь не встаёт.
+/// A blocked task is ready again. Not off the CPU yet (retrying its syscall
+/// or waiting in `sleep`) — so it is running and doesn't get queued.
+fn resume(task: &mut Task) {
+    task.state.transition(TaskState::Runnable);
+    if current_id() == Some(task.id) { task.state.transition(TaskState::Running); }
+}
+
+/// Спящие по (дедлайн, id): одинаковые дедлайны будятся по возрастанию id.
+/// Здесь же дедлайны запаркованных в `ipc::recv_timeout`.
+/// Sleepers by (deadline, id): equal deadlines wake in ascending id order.
+/// The deadlines of those parked in `ipc::recv_timeout` live here too.
+// IrqMutex: таймер берёт его в `wake_sleepers`, а `ipc_deadline` — с IF=1
+// IrqMutex: the timer takes it in `wake_sleepers`, `ipc_deadline` with IF=1
+static SLEEPERS: IrqMutex<BTreeSet<(u64, TaskId)>> = IrqMutex::new(BTreeSet::new());
+
+/// Усыпить выполняющуюся задачу до `deadline_ns` (часы `time::now`).
+/// `false` — нет задачи или она не Running.
+/// Put the running task to sleep until `deadline_ns` (the `time::now`
+/// clock). `false` — no such task or it isn't Running.
+pub fn sleep_until(id: TaskId, deadline_ns: u64) -> bool {
+    let mut tasks = TASKS.lock();
+    let Some(task) = tasks.get_mut(&id) else { return false };
+    if !task.state.can_transition(TaskState::BlockedOnSleep) { return false; }
+    task.state.transition(TaskState::BlockedOnSleep);
+    SLEEPERS.lock().insert((deadline_ns, id));
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Усыпить текущую задачу до `deadline_ns` и вернуться, когда она
+/// проснётся: CPU тем временем отдан другим, а если готовых нет — ждёт
+/// прерывания. `false` — текущей задачи нет, сна не было.
+/// Put the current task to sleep until `deadline_ns` and return once it
+/// wakes: meanwhile the CPU goes to others, and with none ready it waits for
+/// an interrupt. `false` — no current task, no sleep happened.
+pub fn sleep(deadline_ns: u64) -> bool {
+    let Some(id) = current_id() else { return false };
+    if !sleep_until(id, deadline_ns) { return false; }
+    let were_enabled = crate::arch::x86_64::interrupts::are_enabled();
+    loop {
+        schedule();
+        // Проверка и `hlt` — без окна: будящий тик не проскочит между ними
+        // The check and `hlt` — with no window: the waking tick can't slip between them
+        crate::arch::x86_64::interrupts::disable();
+        let asleep = TASKS.lock().get(&id).is_some_and(|t| t.state == TaskState::BlockedOnSleep);
+        if !asleep { break; }
+        unsafe { core::arch::asm!("sti", "hlt", options(nomem, nostack)); }
+    }
+    if were_enabled { crate::arch::x86_64::interrupts::enable(); }
+    true
+}
+
+/// Запаркованную в IPC задачу разбудить не позже `deadline_ns`. Повтор с
+/// тем же дедлайном ничего не меняет.
+/// Wake a task parked in IPC no later than `deadline_ns`. Repeating it with
+/// the same deadline changes nothing.
+pub fn ipc_deadline(id: TaskId, deadline_ns: u64) {
+    SLEEPERS.lock().insert((deadline_ns, id));
+}
+
+/// Ожидание кончилось раньше дедлайна / The wait ended before the deadline
+pub fn cancel_deadline(id: TaskId, deadline_ns: u64) {
+    SLEEPERS.lock().remove(&(deadline_ns, id));
+}
+
+/// Разбудить всех, чей дедлайн `<= now`, в порядке дедлайнов; `woke`
+/// вызывается для каждой. Возвращает, сколько разбужено. Для тестов — в
+/// ядре это делает `on_timer`.
+/// Wake everyone whose deadline is `<= now`, in deadline order; `woke` is
+/// called for each. Returns how many were woken. For tests — in the kernel
+/// `on_timer` does this.
+#[cfg(test)]
+pub fn wake_expired(now: u64, woke: impl FnMut(TaskId)) -> usize {
+    wake_sleepers(&mut TASKS.lock(), now, woke)
+}
+
+fn wake_sleepers(tasks: &mut BTreeMap<TaskId, Box<Task>>, now: u64, mut woke: impl FnMut(TaskId)) -> usize {
+    let mut sleepers = SLEEPERS.lock();
+    let mut count = 0;
+    while let Some(&(deadline, id)) = sleepers.first() {
+        if deadline > now { break; }
+        sleepers.pop_first();
+        // Задача могла уйти, пока спала / The task may have gone while asleep
+        let Some(task) = tasks.get_mut(&id) else { continue };
+        match task.state {
+            // Проспавшая — как после IPC, в очередь 0 со свежим квантом
+            // A sleeper that woke — like after IPC, into queue 0 with a fresh quantum
+            TaskState::BlockedOnSleep => {
+                resume(task);
+                ipc_boost(task);
+            }
+            // Дедлайн `recv_timeout`: повтор syscall вернёт таймаут
+            // A `recv_timeout` deadline: the syscall retry returns the timeout
+            TaskState::BlockedOnIpc => { unpark_ipc(task); }
+            // Сообщение успело раньше / The message got there first
+            _ => continue,
+        }
+        enqueue(task);
+        woke(id);
+        count += 1;
+    }
+    count
+}
+
+/// RFLAGS для входа в ring 3: IF обязан стоять, иначе таймер не сможет
+/// вытеснить задачу, которая не делает syscall'ов.
+/// RFLAGS for entering ring 3: IF must be set, or the timer can never
+/// preempt a task that makes no syscalls.
+pub const USER_RFLAGS: u64 = 0x202;
+
+/// Выполняющаяся задача (0 — никакой) / The running task (0 — none)
+static CURRENT: AtomicU64 = AtomicU64::new(0);
+
+/// Id выполняющейся задачи, без lock'а / The running task's id, lock-free
+pub fn current_id() -> Option<TaskId> {
+    match CURRENT.load(Ordering::Relaxed) {
+        0  => None,
+        id => Some(TaskId(id)),
+    }
+}
+
+/// Задача в пространстве `space`, без контекста — для тестов.
+/// A task in `space`, with no context — for tests.
+#[cfg(test)]
+pub fn spawn_in(space: AddressSpace) -> Option<TaskId> {
+    let id = spawn()?;
+    TASKS.lock().get_mut(&id)?.space = Some(Arc::new(space));
+    Some(id)
+}
+
+/// Сделать `id` выполняющейся без переключения контекста — для тестов.
+/// Make `id` the running task without a context switch — for tests.
+#[cfg(test)]
+pub fn set_current(id: Option<TaskId>) {
+    let mut tasks = TASKS.lock();
+    if let Some(task) = id.and_then(|id| tasks.get_mut(&id)) {
+        if task.state == TaskState::Runnable { task.state.transition(TaskState::Running); }
+    }
+    CURRENT.store(id.map_or(0, |id| id.0), Ordering::Relaxed);
+}
+
+/// Выполняющаяся задача — та, на которую планировщик переключился
+/// последним. Одна на систему, пока нет SMP; потом — per-CPU.
+/// The running task — the one the scheduler last switched to. One for the
+/// whole system until SMP lands; per-CPU after that.
+///
+/// Guard держит `TASKS` (с выключенными прерываниями), так что звать
+/// можно и из обработчиков — но не держать через блокирующий вызов и не
+/// звать, пока `TASKS` уже взят. Исключение из ring 3 этого не застанет:
+/// userspace lock'ов ядра не держит.
+/// The guard holds `TASKS` (with interrupts off), so it may be called from
+/// handlers too — but not held across a blocking call, nor taken while
+/// `TASKS` is already held. A ring-3 exception can't run into that:
+/// userspace holds no kernel locks.
+pub fn current() -> Option<CurrentTask> {
+    let tasks = TASKS.lock();
+    let id = current_id()?;
+    tasks.contains_key(&id).then_some(CurrentTask { tasks, id })
+}
+
+/// Пространство текущей задачи. `None` — задачи нет или она в пространстве
+/// ядра. Ссылка клонируется, `TASKS` не остаётся занятым.
+/// The current task's address space. `None` — no task, or it runs in the
+/// kernel space. The reference is cloned, so `TASKS` isn't left held.
+pub fn current_space() -> Option<Arc<AddressSpace>> {
+    current().and_then(|task| task.space.clone())
+}
+
+pub struct CurrentTask {
+    tasks: IrqMutexGuard<'static, BTreeMap<TaskId, Box<Task>>>,
+    id:    TaskId,
+}
+
+impl core::ops::Deref for CurrentTask {
+    type Target = Task;
+    fn deref(&self) -> &Task { &self.tasks[&self.id] }
+}
+
+impl core::ops::DerefMut for CurrentTask {
+    fn deref_mut(&mut self) -> &mut Task {
+        self.tasks.get_mut(&self.id).expect("current task vanished under its guard")
+    }
+}
+
+/// Квант исчерпан — переключиться на ближайшем возврате в ring 3.
+/// Quantum used up — switch on the next return to ring 3.
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+/// Обработчик таймера. `rsp` — сохранённые регистры и кадр прерывания
+/// текущей задачи на её стеке ядра; возвращает `rsp`, с которого
+/// продолжить — свой или следующей задачи. Переключение происходит
+/// только при возврате в ring 3: ядро не вытесняется, флаг ждёт.
+/// The timer handler. `rsp` — the current task's saved registers and
+/// interrupt frame on its kernel stack; returns the `rsp` to resume from —
+/// its own or the next task's. Switching happens only when returning to
+/// ring 3: the kernel is not preempted, the flag waits.
+pub fn on_timer(rsp: u64, frame: &InterruptFrame) -> u64 {
+    let current = TaskId(CURRENT.load(Ordering::Relaxed));
+    if current.0 == 0 { return rsp; }
+    // Прерванный код мог держать TASKS — тогда тик пропускаем
+    // The interrupted code may hold TASKS — then skip this tick
+    let Some(mut tasks) = TASKS.try_lock() else { return rsp };
+    let now = crate::time::now();
+    if wake_sleepers(&mut tasks, now, |_| {}) > 0 {
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+    }
+    if boost_if_due(&mut tasks, now) {
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+    }
+
+    if tasks.get_mut(&current).is_some_and(|task| charge_tick(task)) {
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+    }
+    let to_user = frame.cs & 3 == 3;
+    // Простой вытесняется и в ring 0: его `hlt` ничего не держит
+    // Idle is preempted in ring 0 too: its `hlt` holds nothing
+    if !(to_user || is_idle(current)) || !NEED_RESCHED.load(Ordering::Relaxed) { return rsp; }
+    debug_assert!(!to_user || frame.rflags & USER_RFLAGS == USER_RFLAGS, "ring 3 running with IF clear");
+
+    NEED_RESCHED.store(false, Ordering::Relaxed);
+    let Some(next) = pick_or_idle(&mut tasks, current) else { return rsp };
+    let mut prev = tasks.remove(&current);
+    if let Some(prev) = prev.as_mut() {
+        prev.saved_rsp = rsp;
+        // Запаркованная задача остаётся ждать / A parked task stays waiting
+        if prev.state == TaskState::Running { prev.state.transition(TaskState::Runnable); }
+        // Уже после выбора — иначе задача могла бы выбрать саму себя
+        // Only after picking — otherwise the task could pick itself
+        enqueue(prev);
+    }
+    let next_task = tasks.get_mut(&next).expect("picked task vanished");
+    next_task.state.transition(TaskState::Running);
+    switch_to(prev.as_deref_mut(), next_task);
+    let next_rsp = next_task.saved_rsp;
+    if let Some(prev) = prev { tasks.insert(current, prev); }
+    CURRENT.store(next.0, Ordering::Relaxed);
+    next_rsp
+}
+
+/// Задачи, снятые `kill_current` и `exit_current`. Стек ядра погибшей ещё
+/// под ногами, пока CPU не ушёл на другую задачу, а разбор пространства и
+/// стека слишком тяжёл для исключения под `TASKS` — поэтому они только
+/// встают сюда, а освобождает их `reap` на входе в syscall.
+/// Tasks taken down by `kill_current` and `exit_current`. A dead task's
+/// kernel stack is still in use until the CPU moves to another task, and
+/// tearing down its space and stack is too heavy for an exception under
+/// `TASKS` — so they only queue up here, and `reap` on syscall entry frees
+/// them.
+static DYING: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+
+/// Освободить погибшие задачи. Зовётся на входе в syscall: мы на стеке
+/// живой задачи и не внутри прерывания.
+/// Free the dead tasks. Called on syscall entry: we are on a live task's
+/// stack and not inside an interrupt.
+pub fn reap() {
+    let dead = core::mem::take(&mut *DYING.lock());
+    drop(dead);
+}
+
+/// Завершить текущую задачу после фатального исключения в ring 3 (#GP,
+/// #UD, #DE) и вернуть `rsp` следующей. Готовых задач нет — CPU
+/// простаивает на стеке погибшей задачи до следующего прерывания.
+/// Terminate the current task after a fatal ring-3 exception (#GP, #UD,
+/// #DE) and return the next task's `rsp`. With no ready task the CPU idles
+/// on the dead task's stack until the next interrupt.
+pub fn kill_current() -> u64 {
+    let current = TaskId(CURRENT.swap(0, Ordering::Relaxed));
+    // До TASKS: отказ ждущим её ответа будит их через `wake_ipc`
+    // Before TASKS: failing whoever awaited its reply wakes them via `wake_ipc`
+    crate::ipc::call::forget_task(current);
+    cap::drop_table(current);
+    let mut tasks = TASKS.lock();
+    if let Some(mut task) = tasks.remove(&current) {
+        task.state.transition(TaskState::Zombie);
+        crate::kprintln!("[sched] task {} ({}) terminated", current.0, task.name());
+        DYING.lock().push(*task);
+    }
+
+    let Some(next) = pick_or_idle(&mut tasks, current) else {
+        drop(tasks);
+        loop {
+            // sti; hlt — без окна между ними / sti; hlt — with no window between them
+            unsafe { core::arch::asm!("sti", "hlt", "cli", options(nomem, nostack)); }
+        }
+    };
+    let next_task = tasks.get_mut(&next).expect("picked task vanished");
+    next_task.state.transition(TaskState::Running);
+    switch_to(None, next_task);
+    CURRENT.store(next.0, Ordering::Relaxed);
+    next_task.saved_rsp
+}
+
+/// Завершить текущую задачу по её просьбе (`task_exit`) и уйти на
+/// следующую. Сюда не возвращаются: задача уже Zombie в DYING, её стек
+/// освободит следующий `reap`. Готовых нет — CPU уходит в простой; до
+/// `init`, пока простоя нет, ждёт прерывания на этом стеке.
+/// Terminate the current task at its own request (`task_exit`) and move on
+/// to the next one. Nothing returns here: the task is already a Zombie in
+/// DYING and the next `reap` frees its stack. With none ready the CPU goes
+/// idle; before `init`, with no idle task yet, it waits for interrupts on
+/// this stack.
+pub fn exit_current(code: usize) -> ! {
+    retire_current(code);
+    loop {
+        schedule();
+        unsafe { core::arch::asm!("sti", "hlt", "cli", options(nomem, nostack)); }
+    }
+}
+
+/// Учёт `exit_current` без ухода с CPU: текущая задача становится Zombie в
+/// DYING, а `CURRENT` указывает на неё до ближайшего переключения.
+/// The bookkeeping of `exit_current` without leaving the CPU: the current
+/// task becomes a Zombie in DYING, and `CURRENT` points at it until the
+/// next switch.
+fn retire_current(code: usize) {
+    let current = TaskId(CURRENT.load(Ordering::Relaxed));
+    // До TASKS, как в `kill_current` / Before TASKS, as in `kill_current`
+    crate::ipc::call::forget_task(current);
+    cap::drop_table(current);
+    let mut tasks = TASKS.lock();
+    if let Some(mut task) = tasks.remove(&current) {
+        task.state.transition(TaskState::Zombie);
+        crate::kprintln!("[sched] task {} ({}) exited with {}", current.0, task.name(), code);
+        DYING.lock().push(*task);
+    }
+}
+
+/// Снять следующую задачу: голова самой приоритетной непустой очереди,
+/// внутри уровня — по кругу (FIFO). Устаревшие записи выбрасываются;
+/// задача без контекста (`saved_rsp == 0`) уходит в хвост и ждёт.
+/// Take the next task: the head of the highest-priority non-empty queue,
+/// round-robin (FIFO) within a level. Stale entries are dropped; a task
+/// with no context yet (`saved_rsp == 0`) goes to the tail and waits.
+fn pick_next(tasks: &mut BTreeMap<TaskId, Box<Task>>) -> Option<TaskId> {
+    let mut queues = RUN_QUEUES.lock();
+    for (level, queue) in queues.iter_mut().enumerate() {
+        for _ in 0..queue.len() {
+            let Some(id) = queue.pop_front() else { break };
+            let Some(task) = tasks.get_mut(&id) else { continue };
+            if task.queued != Some(level as u8) { continue; }
+            if task.state != TaskState::Runnable { task.queued = None; continue; }
+            if task.saved_rsp == 0 { queue.push_back(id); continue; }
+            task.queued = None;
+            return Some(id);
+        }
+    }
+    None
+}
+
+/// Задача простоя (0 — ещё нет). Одна на систему, пока нет SMP; потом —
+/// своя на каждом CPU.
+/// The idle task (0 — none yet). One for the whole system until SMP lands;
+/// each CPU gets its own after that.
+static IDLE: AtomicU64 = AtomicU64::new(0);
+
+fn is_idle(id: TaskId) -> bool {
+    id.0 != 0 && id.0 == IDLE.load(Ordering::Relaxed)
+}
+
+/// `pick_next`, а при пустых очередях — простой, если `current` выполнять
+/// нечего (заблокирована, погибла или её нет). `None` — остаётся `current`.
+/// `pick_next`, or with every queue empty — idle, if `current` has nothing
+/// to run (blocked, dead or absent). `None` — `current` stays.
+fn pick_or_idle(tasks: &mut BTreeMap<TaskId, Box<Task>>, current: TaskId) -> Option<TaskId> {
+    if let Some(next) = pick_next(tasks) { return Some(next); }
+    let idle = TaskId(IDLE.load(Ordering::Relaxed));
+    if idle.0 == 0 || idle == current { return None; }
+    let runs = tasks.get(&current).is_some_and(|task| task.state == TaskState::Running);
+    (!runs).then_some(idle)
+}
+
+/// Тело задачи простоя: ждать прерывания, и так всегда. Таймер будит
+/// спящих и вытесняет простой, как только есть кому работать.
+/// The idle task's body: wait for an interrupt, forever. The timer wakes
+/// sleepers and preempts idle as soon as someone has work to do.
+extern "C" fn idle_task() -> ! {
+    loop { unsafe { core::arch::asm!("sti", "hlt", options(nomem, nostack)); } }
+}
+
+/// Создать задачу простоя: её контекст на вершине стека ядра входит в
+/// `idle_task` в ring 0 с включёнными прерываниями.
+/// Create the idle task: its context at the top of its kernel stack enters
+/// `idle_task` in ring 0 with interrupts enabled.
+fn spawn_idle() -> Option<TaskId> {
+    let id = TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let mut task = Box::new(Task::new(id)?);
+    task.set_name(b"idle");
+    // Стек начинается под самим контекстом; −8: на входе в функцию
+    // rsp ≡ 8 (mod 16), как после `call`
+    // The stack starts below the context itself; −8: on function entry
+    // rsp ≡ 8 (mod 16), as after a `call`
+    let ctx = task.kernel_stack.top().as_u64() - core::mem::size_of::<FullContext>() as u64;
+    seed_context(&mut task, FullContext::kernel_entry(idle_task as *const () as u64, ctx - 8, USER_RFLAGS));
+    TASKS.lock().insert(id, task);
+    IDLE.store(id.0, Ordering::Relaxed);
+    Some(id)
+}
+
+/// Переключить CPU с `prev` на `next`: сохранить FS/GS base уходящей
+/// задачи (она могла сменить их сама через `wrfsbase`), поставить базы
+/// `next` и его стек ядра — прерывания из ring 3 пойдут туда. CR3
+/// перезагружается, только если пространство другое: у задачи ядра своего
+/// нет, ей годится любое. Регистры меняет вызывающий — выход `isr_timer`
+/// или `idt::switch_context`.
+/// Switch the CPU from `prev` to `next`: save the outgoing task's FS/GS
+/// base (it may have changed them itself via `wrfsbase`), load `next`'s
+/// bases and its kernel stack — interrupts from ring 3 land there. CR3 is
+/// reloaded only for a different space: a kernel task has none of its own
+/// and any will do. The caller swaps the registers — the `isr_timer` exit
+/// or `idt::switch_context`.
+fn switch_to(prev: Option<&mut Task>, next: &Task) {
+    let mut same_space = false;
+    if let Some(prev) = prev {
+        prev.fs_base = segbase::read_fs_base();
+        prev.gs_base = segbase::read_user_gs_base();
+        same_space = match (&prev.space, &next.space) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _                  => false,
+        };
+    }
+    gdt::set_kernel_stack(next.kernel_stack.top().as_u64());
+    segbase::write_fs_base(next.fs_base);
+    segbase::write_user_gs_base(next.gs_base);
+    gdt::load_io_bitmap(next.io_bitmap.as_deref());
+    if let Some(space) = next.space.as_ref().filter(|_| !same_space) { space.activate(); }
+}
+
+/// Куда `schedule` сохраняет контекст, когда уходит не задача (загрузочный
+/// поток в `start`): вернуться туда уже некому.
+/// Where `schedule` saves the context when the one leaving isn't a task
+/// (the boot thread in `start`): nobody will ever return there.
+static mut ABANDONED_RSP: u64 = 0;
+
+/// Отдать CPU из кода ядра (не из прерывания): следующая готовая задача
+/// продолжается сразу, а текущая — когда её снова выберут, с возврата
+/// отсюда. Готовых нет — ничего. Выполнявшаяся задача снова готова;
+/// запаркованная остаётся ждать.
+/// Give up the CPU from kernel code (not from an interrupt): the next ready
+/// task resumes at once, and the current one — when picked again, by
+/// returning from here. With none ready — nothing. A running task becomes
+/// ready again; a parked one stays waiting.
+pub fn schedule() {
+    let were_enabled = crate::arch::x86_64::interrupts::are_enabled();
+    // До возобновления IF выключен: вне lock'а нас не должен прервать таймер
+    // IF stays off until we resume: the timer must not cut in outside the lock
+    crate::arch::x86_64::interrupts::disable();
+    let switch = {
+        let mut tasks = TASKS.lock();
+        NEED_RESCHED.store(false, Ordering::Relaxed);
+        let current = TaskId(CURRENT.load(Ordering::Relaxed));
+        pick_or_idle(&mut tasks, current).map(|next| {
+            let mut prev = tasks.remove(&current);
+            let next_task = tasks.get_mut(&next).expect("picked task vanished");
+            next_task.state.transition(TaskState::Running);
+            switch_to(prev.as_deref_mut(), next_task);
+            let next_rsp = next_task.saved_rsp;
+            let prev_rsp = match prev {
+                Some(mut prev) => {
+                    if prev.state == TaskState::Running { prev.state.transition(TaskState::Runnable); }
+                    enqueue(&mut prev);
+                    let slot = &raw mut prev.saved_rsp;
+                    tasks.insert(current, prev);
+                    slot
+                }
+                None => &raw mut ABANDONED_RSP,
+            };
+            CURRENT.store(next.0, Ordering::Relaxed);
+            (prev_rsp, next_rsp)
+        })
+    };
+    // Слот в Box — TASKS уже отпущен, но задачу никто не тронет: IF выключен
+    // The slot is inside a Box — TASKS is released, but nobody touches the task: IF is off
+    if let Some((prev_rsp, next_rsp)) = switch {
+        unsafe { crate::arch::x86_64::idt::switch_context(prev_rsp, next_rsp); }
+    }
+    if were_enabled { crate::arch::x86_64::interrupts::enable(); }
+}
+
+/// Положить первый контекст на вершину стека ядра задачи.
+/// Put the first context at the top of the task's kernel stack.
+fn seed_context(task: &mut Task, ctx: FullContext) {
+    let at = task.kernel_stack.top().as_u64() - core::mem::size_of::<FullContext>() as u64;
+    let alias = task.kernel_stack.hhdm_alias(VirtAddr::new(at));
+    unsafe { alias.as_mut_ptr::<FullContext>().write(ctx); }
+    task.saved_rsp = at;
+}
+
+/// Открыть задаче порты из её capability `IoPort`. Доступ к остальным
+/// портам по-прежнему даёт #GP. `false` — нет задачи или cap не `IoPort`.
+/// Open the ports of the task's `IoPort` capability to it. Any other port
+/// still raises #GP. `false` — no such task or the cap isn't an `IoPort`.
+pub fn grant_io_ports(id: TaskId, cap: CapId) -> bool {
+    let Some(entry) = cap::lookup(id, cap) else { return false };
+    let CapKind::IoPort { base, count } = entry.kind else { return false };
+    let mut tasks = TASKS.lock();
+    let Some(task) = tasks.get_mut(&id) else { return false };
+    if task.io_bitmap.is_none() {
+        // Через Vec — 8KB не должны пройти через стек ядра
+        // Via Vec — 8KB must not pass through the kernel stack
+        let all_denied = alloc::vec![0xFF; gdt::IO_BITMAP_BYTES].into_boxed_slice();
+        task.io_bitmap = Box::<IoBitmap>::try_from(all_denied).ok();
+    }
+    let Some(bitmap) = task.io_bitmap.as_mut() else { return false };
+    for port in base as usize..(base as usize + count as usize).min(1 << 16) {
+        bitmap[port / 8] &= !(1 << (port % 8));
+    }
+    true
+}
+
+/// Создать TLS задачи в `space` по `PT_TLS` (или только TCB, если его нет)
+/// под `USER_TLS_BASE` и запомнить thread pointer — он ставится в FS_BASE
+/// при переключении.
+/// Create a task's TLS in `space` from `PT_TLS` (or just a TCB if there is
+/// none) at `USER_TLS_BASE` and remember the thread pointer — it goes into
+/// FS_BASE on switch.
+fn setup_tls(
+    task: &mut Task,
+    space: &crate::mm::vmm::AddressSpace,
+    template: Option<&tls::TlsTemplate>,
+    image: &[u8],
+) -> bool {
+    let Some(tp) = tls::setup(space, VirtAddr::new(USER_TLS_BASE), template, image) else { return false };
+    task.fs_base = tp;
+    true
+}
 
 pub fn init() {
-    // stub
+    if let Some(arg) = cmdline::get("sched.quanta") {
+        match parse_quanta(arg) {
+            Some(quanta) => { set_quanta(quanta); }
+            None => crate::kprintln!("[sched] bad sched.quanta=\"{}\" — keeping defaults", arg),
+        }
+    }
+    spawn_idle().expect("[sched] no memory for the idle task");
 }
 
+/// Корневые capability: init получает их от ядра и раздаёт дальше —
+/// другого источника у них нет.
+/// The root capabilities: init gets them from the kernel and hands them on —
+/// there is no other source for them.
+fn root_caps() -> Vec<CapEntry> {
+    let root = |kind| CapEntry { kind, rights: cap::Rights::all(), badge: 0, parent: None };
+    alloc::vec![
+        root(CapKind::SchedControl),
+        root(CapKind::IoPort { base: 0, count: u16::MAX }),
+    ]
+}
+
+/// Запустить первый userspace процесс — модуль Limine с именем `init` — с
+/// корневыми capability. Нет модуля или образ не загрузился — ядро
+/// остаётся без userspace.
+/// Launch the first userspace process — the Limine module named `init` —
+/// with the root capabilities. With no module or an image that won't load,
+/// the kernel runs without userspace.
 pub fn spawn_init() {
-    // stub — запустить первый userspace процесс
-    // stub — launch first userspace process
+    let Some((path, image)) = crate::arch::current::modules()
+        .find(|(path, _)| task::module_name(path) == "init")
+    else {
+        crate::kprintln!("[sched] no init module — nothing to launch");
+        return;
+    };
+    match spawn_elf(image, root_caps()) {
+        Ok(id) => {
+            set_name(id, b"init");
+            crate::kprintln!("[sched] init ({}) is task {}", path, id.0);
+        }
+        Err(e) => crate::kprintln!("[sched] init ({}) failed to load: {:?}", path, e),
+    }
 }
 
+/// Уйти с загрузочного потока на первую готовую задачу — или в простой.
+/// Сюда CPU больше не вернётся.
+/// Leave the boot thread for the first ready task — or for idle. The CPU
+/// never comes back here.
 pub fn start() -> ! {
-    // stub — войти в цикл планировщика
-    // stub — enter scheduler loop
-    loop { core::hint::spin_loop(); }
+    schedule();
+    unreachable!("[sched] the boot thread was resumed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Тик таймера, прервавший ядро: учёт без переключения
+    /// A timer tick that interrupted the kernel: accounting without a switch
+    fn kernel_tick() {
+        testing::CLOCK.advance(1_000_000);
+        let frame = InterruptFrame { rip: 0, cs: 0x08, rflags: 0x2, rsp: 0, ss: 0x10 };
+        assert_eq!(on_timer(0x1000, &frame), 0x1000);
+    }
+
+    /// Тик таймера, прервавший цикл в ring 3; `rsp` — его контекст. Возвращает,
+    /// с какого `rsp` продолжить.
+    /// A timer tick that interrupted a loop in ring 3; `rsp` is its context.
+    /// Returns the `rsp` to resume from.
+    fn user_tick(rsp: u64) -> u64 {
+        testing::CLOCK.advance(1_000_000);
+        let frame = InterruptFrame {
+            rip: 0x40_1000, cs: gdt::USER_CODE as u64, rflags: USER_RFLAGS, rsp: 0x7FFF_F000, ss: gdt::USER_DATA as u64,
+        };
+        on_timer(rsp, &frame)
+    }
+
+    /// Подъём только что был — следующий не раньше `BOOST_INTERVAL_MS`
+    /// A boost just happened — the next one is `BOOST_INTERVAL_MS` away
+    fn boost_now() {
+        LAST_BOOST.store(crate::time::now(), Ordering::Relaxed);
+    }
+
+    fn queued_at(id: TaskId) -> Option<u8> {
+        let tasks = TASKS.lock();
+        let level = tasks[&id].queued?;
+        RUN_QUEUES.lock()[level as usize].contains(&id).then_some(level)
+    }
+
+    #[test]
+    fn parse_quanta_accepts_four_non_decreasing_values() {
+        assert_eq!(parse_quanta("1,5,20,100"), Some(DEFAULT_QUANTA));
+        assert_eq!(parse_quanta(" 2, 2 ,4,8 "), Some([2, 2, 4, 8]));
+    }
+
+    #[test]
+    fn parse_quanta_rejects_malformed_lists() {
+        for bad in ["", "1,5,20", "1,5,20,100,200", "0,5,20,100", "5,1,20,100", "1,x,20,100", "1,5,,100", "-1,5,20,100"] {
+            assert_eq!(parse_quanta(bad), None, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn background_starts_at_the_lowest_queue() {
+        let _kernel = testing::setup();
+        let id = spawn().unwrap();
+        assert_eq!(set_priority(id, Priority::Background), Ok(()));
+        assert_eq!(TASKS.lock()[&id].queue_level, LEVELS as u8 - 1);
+        assert_eq!(queued_at(id), Some(LEVELS as u8 - 1));
+        // Вернуться в Normal можно и без capability / Back to Normal needs no capability
+        assert_eq!(set_priority(id, Priority::Normal), Ok(()));
+        exit(id);
+    }
+
+    #[test]
+    fn raising_above_normal_needs_sched_control() {
+        let _kernel = testing::setup();
+        let id = spawn().unwrap();
+        assert_eq!(set_priority(id, Priority::Interactive), Err(PriorityError::Denied));
+        assert_eq!(TASKS.lock()[&id].base_level, level_of(Priority::Normal));
+
+        cap::install(id, CapEntry { kind: CapKind::SchedControl, rights: cap::Rights::all(), badge: 0, parent: None });
+        assert_eq!(set_priority(id, Priority::Interactive), Ok(()));
+        assert_eq!(TASKS.lock()[&id].queue_level, level_of(Priority::Interactive));
+        exit(id);
+    }
+
+    #[test]
+    fn priority_set_during_ipc_boost_is_the_base_to_return_to() {
+        let _kernel = testing::setup();
+        let id = spawn().unwrap();
+        ipc_boost(TASKS.lock().get_mut(&id).unwrap());
+        assert_eq!(set_priority(id, Priority::Background), Ok(()));
+        assert_eq!(TASKS.lock()[&id].queue_level, IPC_BOOST_LEVEL);
+        let expired = (0..quantum(IPC_BOOST_LEVEL)).map(|_| charge_tick(TASKS.lock().get_mut(&id).unwrap()));
+        assert_eq!(expired.filter(|&e| e).count(), 1);
+        assert_eq!(TASKS.lock()[&id].queue_level, LEVELS as u8 - 1);
+        exit(id);
+    }
+
+    #[test]
+    fn timer_demotes_a_task_that_uses_up_its_quantum() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        boost_now();
+        let level = TASKS.lock()[&id].queue_level;
+        for _ in 1..quantum(level) { kernel_tick(); }
+        assert_eq!(TASKS.lock()[&id].queue_level, level);
+        kernel_tick();
+        assert_eq!(TASKS.lock()[&id].queue_level, level + 1);
+        assert!(NEED_RESCHED.swap(false, Ordering::Relaxed));
+
+        // Подъём по часам: очередь 0, а её квант в том же тике — обратно в базу
+        // The clock-driven boost: queue 0, and its quantum in the same tick — back to base
+        testing::CLOCK.advance(BOOST_INTERVAL_MS * 1_000_000);
+        kernel_tick();
+        assert_eq!(TASKS.lock()[&id].queue_level, level);
+        NEED_RESCHED.store(false, Ordering::Relaxed);
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn ipc_wake_boosts_until_the_level_zero_quantum_runs_out() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        assert_eq!(set_priority(id, Priority::Background), Ok(()));
+        boost_now();
+        assert!(block_on_ipc(id));
+        assert!(wake_ipc(id));
+        assert_eq!(TASKS.lock()[&id].state, TaskState::Running);
+        assert_eq!(TASKS.lock()[&id].queue_level, IPC_BOOST_LEVEL);
+
+        for _ in 0..quantum(IPC_BOOST_LEVEL) { kernel_tick(); }
+        assert_eq!(TASKS.lock()[&id].queue_level, LEVELS as u8 - 1);
+        NEED_RESCHED.store(false, Ordering::Relaxed);
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn timer_ends_an_expired_ipc_wait() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        boost_now();
+        let port = crate::ipc::create_port();
+        let deadline = crate::time::now() + 1_500_000;
+        assert!(crate::ipc::recv_timeout(port, deadline).is_err());
+        assert_eq!(TASKS.lock()[&id].state, TaskState::BlockedOnIpc);
+
+        kernel_tick();
+        assert_eq!(TASKS.lock()[&id].state, TaskState::BlockedOnIpc);
+        kernel_tick();
+        assert_eq!(TASKS.lock()[&id].state, TaskState::Running);
+        assert_eq!(crate::ipc::recv_timeout(port, deadline).err(), Some(crate::ipc::IpcError::TimedOut));
+        NEED_RESCHED.store(false, Ordering::Relaxed);
+        crate::ipc::destroy_port(port);
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn each_task_has_its_own_kernel_stack_and_the_switch_loads_it() {
+        let _kernel = testing::setup();
+        let a = testing::user_task();
+        let b = spawn_ready().unwrap();
+        let (top_a, top_b) = {
+            let tasks = TASKS.lock();
+            (tasks[&a].kernel_stack.top().as_u64(), tasks[&b].kernel_stack.top().as_u64())
+        };
+        assert!(top_a.abs_diff(top_b) >= task::KERNEL_STACK_SIZE as u64);
+
+        schedule();
+        assert_eq!(current_id(), Some(b));
+        assert_eq!(gdt::kernel_stack(), top_b);
+        schedule();
+        assert_eq!(current_id(), Some(a));
+        assert_eq!(gdt::kernel_stack(), top_a);
+        exit(b);
+        testing::end_task(a);
+    }
+
+    #[test]
+    fn each_task_sees_its_own_fs_and_gs_base_after_a_switch() {
+        let _kernel = testing::setup();
+        let a = testing::user_task();
+        let b = spawn_ready().unwrap();
+        {
+            let mut tasks = TASKS.lock();
+            let task = tasks.get_mut(&b).unwrap();
+            (task.fs_base, task.gs_base) = (0x7000_0000, 0x7100_0000);
+        }
+        // Свои базы `a` поставила сама, через `wrfsbase` / `a` set its own bases itself, via `wrfsbase`
+        segbase::write_fs_base(0x1000_0000);
+        segbase::write_user_gs_base(0x1100_0000);
+
+        schedule();
+        assert_eq!((segbase::read_fs_base(), segbase::read_user_gs_base()), (0x7000_0000, 0x7100_0000));
+        schedule();
+        assert_eq!((segbase::read_fs_base(), segbase::read_user_gs_base()), (0x1000_0000, 0x1100_0000));
+        exit(b);
+        testing::end_task(a);
+    }
+
+    #[test]
+    fn a_ring3_loop_without_syscalls_is_preempted_after_its_quantum() {
+        let _kernel = testing::setup();
+        let spinner = testing::user_task();
+        let other = spawn_ready().unwrap();
+        boost_now();
+        let level = TASKS.lock()[&spinner].queue_level;
+        for _ in 1..quantum(level) {
+            assert_eq!(user_tick(0x1000), 0x1000);
+        }
+        let rsp = user_tick(0x1000);
+        assert_eq!(current_id(), Some(other));
+        assert_eq!(rsp, TASKS.lock()[&other].saved_rsp);
+        assert_eq!(TASKS.lock()[&spinner].saved_rsp, 0x1000);
+        assert_eq!(TASKS.lock()[&spinner].state, TaskState::Runnable);
+        exit(spinner);
+        testing::end_task(other);
+    }
+
+    #[test]
+    fn two_sleepers_wake_in_deadline_order_on_the_virtual_clock() {
+        let _kernel = testing::setup();
+        let start = crate::time::now();
+        let sleepers = [3_000_000, 2_000_000].map(|after| {
+            let id = spawn().unwrap();
+            set_current(Some(id));
+            assert!(sleep_until(id, start + after));
+            id
+        });
+        set_current(None);
+        NEED_RESCHED.store(false, Ordering::Relaxed);
+
+        let mut woken = Vec::new();
+        for _ in 0..3 {
+            testing::CLOCK.advance(1_000_000);
+            wake_expired(crate::time::now(), |id| woken.push((crate::time::now() - start, id)));
+        }
+        assert_eq!(woken, [(2_000_000, sleepers[1]), (3_000_000, sleepers[0])]);
+        for id in sleepers { exit(id); }
+    }
+
+    #[test]
+    fn current_is_the_task_last_switched_to() {
+        let _kernel = testing::setup();
+        assert!(current().is_none());
+        let a = testing::user_task();
+        let b = spawn_ready().unwrap();
+        assert_eq!(current().map(|task| task.id), Some(a));
+
+        schedule();
+        let task = current().unwrap();
+        assert_eq!((task.id, task.state), (b, TaskState::Running));
+        drop(task);
+        assert!(current_space().is_none());
+        schedule();
+        assert_eq!(current().map(|task| task.id), Some(a));
+        assert!(current_space().is_some());
+        exit(b);
+        testing::end_task(a);
+    }
+
+    #[test]
+    fn two_tasks_take_turns_and_cr3_reloads_only_across_spaces() {
+        let _kernel = testing::setup();
+        let a = testing::user_task();
+        let b = spawn_ready().unwrap();
+        let shared = TASKS.lock()[&a].space.clone();
+        TASKS.lock().get_mut(&b).unwrap().space = shared.clone();
+        // Пока CR3 не перезагружали, активно `marker` / Until CR3 is reloaded, `marker` is active
+        let marker = vmm::new_user_space().unwrap();
+        marker.activate();
+
+        let mut counter = 0;
+        let mut turns = Vec::new();
+        for _ in 0..6 {
+            counter += 1;
+            turns.push(current_id().unwrap());
+            schedule();
+            let current = current().unwrap();
+            assert_eq!(crate::arch::x86_64::idt::switched_to(), current.saved_rsp);
+        }
+        assert_eq!(counter, 6);
+        assert_eq!(turns, [a, b, a, b, a, b]);
+        assert!(marker.is_active());
+
+        exit(b);
+        let c = spawn_ready().unwrap();
+        let own = Arc::new(vmm::new_user_space().unwrap());
+        TASKS.lock().get_mut(&c).unwrap().space = Some(own.clone());
+        schedule();
+        assert_eq!(current_id(), Some(c));
+        assert!(own.is_active());
+        schedule();
+        assert_eq!(current_id(), Some(a));
+        assert!(shared.unwrap().is_active());
+        exit(c);
+        testing::end_task(a);
+    }
+
+    #[test]
+    fn a_starved_background_task_runs_after_the_boost_interval() {
+        let _kernel = testing::setup();
+        // Две задачи перебрасываются IPC: разбуженная всегда в очереди 0
+        // Two tasks bounce IPC between them: the woken one is always in queue 0
+        let hogs = [testing::user_task(), spawn_ready().unwrap()];
+        let background = spawn_ready().unwrap();
+        assert_eq!(set_priority(background, Priority::Background), Ok(()));
+        boost_now();
+
+        let mut ticks = 0;
+        while current_id() != Some(background) {
+            assert!(ticks <= BOOST_INTERVAL_MS + 2, "the background task starved");
+            let me = current_id().unwrap();
+            wake_ipc(if me == hogs[0] { hogs[1] } else { hogs[0] });
+            assert!(block_on_ipc(me));
+            user_tick(0x1000);
+            ticks += 1;
+        }
+        assert!(ticks >= BOOST_INTERVAL_MS, "ran after {ticks} ms — before any boost");
+        for id in hogs { exit(id); }
+        testing::end_task(background);
+    }
+
+    #[test]
+    fn a_sleeper_is_not_picked_before_its_deadline_and_is_right_after() {
+        let _kernel = testing::setup();
+        let sleeper = testing::user_task();
+        let other = spawn_ready().unwrap();
+        boost_now();
+        assert!(sleep_until(sleeper, crate::time::now() + 2_000_000));
+        schedule();
+        assert_eq!(current_id(), Some(other));
+
+        // `other` уступает, но спящая ещё не готова / `other` yields, but the sleeper isn't ready yet
+        testing::CLOCK.advance(1_000_000);
+        assert_eq!(wake_expired(crate::time::now(), |_| {}), 0);
+        schedule();
+        assert_eq!(current_id(), Some(other));
+
+        testing::CLOCK.advance(1_000_000);
+        assert_eq!(wake_expired(crate::time::now(), |_| {}), 1);
+        assert_eq!(queued_at(sleeper), Some(0));
+        schedule();
+        assert_eq!(current_id(), Some(sleeper));
+        exit(other);
+        testing::end_task(sleeper);
+    }
+
+    #[test]
+    fn idle_runs_only_with_every_queue_empty_and_a_wakeup_preempts_it() {
+        let _kernel = testing::setup();
+        let idle = spawn_idle().unwrap();
+        let sleeper = testing::user_task();
+        boost_now();
+        assert!(sleep_until(sleeper, crate::time::now() + 1_000_000));
+        schedule();
+        assert_eq!(current_id(), Some(idle));
+        assert_eq!(TASKS.lock()[&idle].queued, None);
+
+        // Простой вытесняется и в ring 0, а квант его не тратится
+        // Idle is preempted in ring 0 too, and it spends no quantum
+        testing::CLOCK.advance(1_000_000);
+        let frame = InterruptFrame { rip: 0, cs: 0x08, rflags: 0x202, rsp: 0, ss: 0x10 };
+        let rsp = on_timer(0x2000, &frame);
+        assert_eq!(current_id(), Some(sleeper));
+        assert_eq!(rsp, TASKS.lock()[&sleeper].saved_rsp);
+        let tasks = TASKS.lock();
+        assert_eq!((tasks[&idle].ticks_used, tasks[&idle].saved_rsp, tasks[&idle].queued), (0, 0x2000, None));
+        drop(tasks);
+
+        IDLE.store(0, Ordering::Relaxed);
+        exit(idle);
+        testing::end_task(sleeper);
+    }
 }
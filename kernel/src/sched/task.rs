@@ -0,0 +1,339 @@
+//! Task — единица планирования / unit of scheduling
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::fmt;
+use cupruxos_abi::{Priority, TASK_NAME_LEN};
+use crate::arch::x86_64::gdt::IoBitmap;
+use crate::ipc::TaskId;
+use crate::mm::pmm::{self, PhysAddr, PAGE_SIZE};
+use crate::mm::vmm::{self, AddressSpace, PageFlags, VirtAddr};
+
+/// Размер стека ядра задачи: 2^2 страниц = 16KB.
+/// Task kernel stack size: 2^2 pages = 16KB.
+pub const KERNEL_STACK_ORDER: usize = 2;
+pub const KERNEL_STACK_SIZE:  usize = PAGE_SIZE << KERNEL_STACK_ORDER;
+
+/// Область стеков ядра: у задачи N слот N — guard-страница и под ней стек.
+/// The kernel stack region: task N owns slot N — a guard page, then the stack.
+pub const KERNEL_STACK_REGION: u64 = 0xFFFF_C000_0000_0000;
+pub const KERNEL_STACK_SLOT:   u64 = (PAGE_SIZE + KERNEL_STACK_SIZE) as u64;
+
+/// Слотов в области (её PML4-запись) / Slots in the region (its PML4 entry)
+const KERNEL_STACK_SLOTS: u64 = (1 << 39) / KERNEL_STACK_SLOT;
+
+/// Чей guard содержит `addr` — по адресу, без таблицы задач.
+/// Whose guard page contains `addr` — from the address alone, no task table.
+pub fn guard_owner(addr: VirtAddr) -> Option<TaskId> {
+    let offset = addr.as_u64().checked_sub(KERNEL_STACK_REGION)?;
+    let slot = offset / KERNEL_STACK_SLOT;
+    (slot < KERNEL_STACK_SLOTS && offset % KERNEL_STACK_SLOT < PAGE_SIZE as u64).then_some(TaskId(slot))
+}
+
+/// Стек ядра задачи — на него переключается CPU при прерывании из ring 3
+/// (TSS.rsp0). У каждой задачи свой, иначе прерванные задачи затрут
+/// друг другу кадры.
+/// Per-task kernel stack — the CPU switches to it on an interrupt from
+/// ring 3 (TSS.rsp0). Each task needs its own, or interrupted tasks would
+/// clobber each other's frames.
+///
+/// Живёт в своём слоте `KERNEL_STACK_REGION`, а не в HHDM: страница под
+/// ним не замаплена, и переполнение — чистый #PF, а не тихая порча
+/// соседней памяти.
+/// Lives in its own `KERNEL_STACK_REGION` slot rather than the HHDM: the
+/// page below it is unmapped, so an overflow is a clean #PF instead of
+/// silently corrupting neighbouring memory.
+pub struct KernelStack {
+    base:   PhysAddr,
+    bottom: VirtAddr,
+}
+
+impl KernelStack {
+    /// Выделить стек из PMM и замапить его в слот `id` / Allocate a stack from the PMM and map it in slot `id`
+    pub fn alloc(id: TaskId) -> Option<Self> {
+        if id.0 >= KERNEL_STACK_SLOTS { return None; }
+        let base = pmm::alloc_pages(KERNEL_STACK_ORDER).ok()?;
+        let bottom = VirtAddr::new(KERNEL_STACK_REGION + id.0 * KERNEL_STACK_SLOT + PAGE_SIZE as u64);
+        if vmm::map_kernel_range(bottom, base, KERNEL_STACK_SIZE as u64, PageFlags::KERNEL_RW).is_err() {
+            vmm::unmap_kernel_range(bottom, KERNEL_STACK_SIZE as u64);
+            pmm::free_pages(base, KERNEL_STACK_ORDER);
+            return None;
+        }
+        Some(Self { base, bottom })
+    }
+
+    /// Вершина стека (растёт вниз) / Stack top (grows down)
+    pub fn top(&self) -> VirtAddr {
+        VirtAddr::new(self.bottom.as_u64() + KERNEL_STACK_SIZE as u64)
+    }
+
+    /// Адрес `at` в стеке — через HHDM. Там стек виден всегда, даже когда
+    /// его слот не замаплен в активных таблицах (в тестах они не активны).
+    /// The address `at` in the stack — through the HHDM. The stack is always
+    /// visible there, even when its slot isn't mapped in the active tables
+    /// (in tests they never are).
+    pub fn hhdm_alias(&self, at: VirtAddr) -> VirtAddr {
+        vmm::phys_to_virt(PhysAddr::new(self.base.as_u64() + (at.as_u64() - self.bottom.as_u64())))
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        vmm::unmap_kernel_range(self.bottom, KERNEL_STACK_SIZE as u64);
+        pmm::free_pages(self.base, KERNEL_STACK_ORDER);
+    }
+}
+
+/// Состояние задачи / Task state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Готова, ждёт CPU / Ready, waiting for a CPU
+    Runnable,
+    Running,
+    BlockedOnIpc,
+    BlockedOnSleep,
+    /// Завершилась; ресурсы ещё не отпущены. Отсюда пути нет.
+    /// Terminated; resources not yet released. There is no way out.
+    Zombie,
+}
+
+impl TaskState {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Runnable       => "runnable",
+            Self::Running        => "running",
+            Self::BlockedOnIpc   => "blocked-ipc",
+            Self::BlockedOnSleep => "blocked-sleep",
+            Self::Zombie         => "zombie",
+        }
+    }
+
+    /// Таблица допустимых переходов / The allowed-transitions table
+    pub const fn can_transition(self, to: TaskState) -> bool {
+        use TaskState::*;
+        matches!(
+            (self, to),
+            (Runnable, Running)
+                | (Running, Runnable)
+                | (Running, BlockedOnIpc)
+                | (Running, BlockedOnSleep)
+                | (BlockedOnIpc, Runnable)
+                | (BlockedOnSleep, Runnable)
+                | (Runnable | Running | BlockedOnIpc | BlockedOnSleep, Zombie)
+        )
+    }
+
+    /// Единственный способ сменить состояние. Недопустимый переход (скажем,
+    /// разбудить Zombie) — паника в debug, в release — сообщение, и
+    /// состояние не меняется.
+    /// The only way to change state. An illegal transition (say, waking a
+    /// Zombie) panics in debug; in release it is logged and the state stays.
+    pub fn transition(&mut self, to: TaskState) -> bool {
+        if self.can_transition(to) {
+            *self = to;
+            return true;
+        }
+        debug_assert!(false, "illegal task state transition {:?} -> {:?}", self, to);
+        crate::kprintln!("[sched] illegal task state transition {:?} -> {:?}", self, to);
+        false
+    }
+}
+
+/// Задача / Task
+pub struct Task {
+    pub id:           TaskId,
+    /// Меняется только через `TaskState::transition` / Changed only via `TaskState::transition`
+    pub state:        TaskState,
+    pub kernel_stack: KernelStack,
+    /// Уровень MLFQ, куда задача возвращается после IPC-буста.
+    /// MLFQ level the task returns to after an IPC boost.
+    pub base_level:   u8,
+    /// Текущий уровень MLFQ / Current MLFQ level
+    pub queue_level:  u8,
+    /// Тиков потрачено из текущего кванта / Ticks spent of the current quantum
+    pub ticks_used:   u32,
+    /// Уровень, в очереди готовых которого стоит задача; запись в другой
+    /// очереди — устаревшая.
+    /// The level whose run queue holds the task; an entry in any other
+    /// queue is stale.
+    pub queued:       Option<u8>,
+    /// RSP сохранённого контекста на стеке ядра; 0 — задача ещё не готова.
+    /// RSP of the saved context on the kernel stack; 0 — not runnable yet.
+    pub saved_rsp:    u64,
+    /// Thread pointer для TLS (IA32_FS_BASE), 0 — не задан.
+    /// TLS thread pointer (IA32_FS_BASE), 0 — not set.
+    pub fs_base:      u64,
+    /// GS base пользователя; GS ядра (per-CPU) не сохраняется.
+    /// The user's GS base; the kernel's (per-CPU) GS is not saved.
+    pub gs_base:      u64,
+    /// Разрешённые I/O порты; `None` — никаких (in/out дают #GP).
+    /// Permitted I/O ports; `None` — none (in/out raise #GP).
+    pub io_bitmap:    Option<Box<IoBitmap>>,
+    /// Имя для `ps` и отчётов о падениях; дополнено нулями, UTF-8 не гарантирован.
+    /// Name for `ps` and fault reports; NUL-padded, UTF-8 not guaranteed.
+    pub name:         [u8; TASK_NAME_LEN],
+    /// Адресное пространство; `None` — задача живёт в пространстве ядра.
+    /// Верхняя половина обязана совпадать с ядерной.
+    /// The address space; `None` — the task lives in the kernel space. Its
+    /// upper half must match the kernel's.
+    pub space:        Option<Arc<AddressSpace>>,
+}
+
+impl Task {
+    pub fn new(id: TaskId) -> Option<Self> {
+        let level = super::level_of(Priority::Normal);
+        Some(Self {
+            id,
+            state:        TaskState::Runnable,
+            kernel_stack: KernelStack::alloc(id)?,
+            base_level:   level,
+            queue_level:  level,
+            ticks_used:   0,
+            queued:       None,
+            saved_rsp:    0,
+            fs_base:      0,
+            gs_base:      0,
+            io_bitmap:    None,
+            name:         default_name(id),
+            space:        None,
+        })
+    }
+
+    /// Сменить имя; длиннее `TASK_NAME_LEN` — обрезается. Если обрезка
+    /// рассекла бы UTF-8 символ, он отбрасывается целиком.
+    /// Rename; anything past `TASK_NAME_LEN` is cut off. If the cut would
+    /// split a UTF-8 character, the whole character is dropped.
+    pub fn set_name(&mut self, name: &[u8]) {
+        let mut len = name.len().min(TASK_NAME_LEN);
+        if let Err(e) = core::str::from_utf8(&name[..len]) {
+            // Неполный символ в конце — наша обрезка; прочий мусор оставляем как есть
+            // An incomplete char at the end is our cut; other garbage stays as is
+            if e.error_len().is_none() { len = e.valid_up_to(); }
+        }
+        self.name = [0; TASK_NAME_LEN];
+        self.name[..len].copy_from_slice(&name[..len]);
+    }
+
+    /// Имя для вывода / The name for display
+    pub fn name(&self) -> TaskName<'_> {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(TASK_NAME_LEN);
+        TaskName(&self.name[..len])
+    }
+}
+
+/// Имя по умолчанию — "task-N" / The default name — "task-N"
+fn default_name(id: TaskId) -> [u8; TASK_NAME_LEN] {
+    let mut name = [0; TASK_NAME_LEN];
+    let mut digits = [0u8; 20];
+    let mut n = id.0;
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 { break; }
+    }
+    name[..5].copy_from_slice(b"task-");
+    name[5..5 + digits.len() - i].copy_from_slice(&digits[i..]);
+    name
+}
+
+/// Имя модуля без каталога и расширения: "/boot/netd.elf" → "netd".
+/// A module's name without directory or extension: "/boot/netd.elf" → "netd".
+pub fn module_name(path: &str) -> &str {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    match file.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => file,
+    }
+}
+
+/// Имя задачи, безопасное для печати: невалидный UTF-8 — `\u{FFFD}`,
+/// управляющие символы — `?`, чтобы имя не испортило консоль.
+/// A task name that is safe to print: invalid UTF-8 becomes `\u{FFFD}` and
+/// control characters `?`, so a name cannot garble the console.
+pub struct TaskName<'a>(&'a [u8]);
+
+impl fmt::Display for TaskName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use fmt::Write;
+        for chunk in self.0.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                f.write_char(if c.is_control() { '?' } else { c })?;
+            }
+            if !chunk.invalid().is_empty() { f.write_char('\u{FFFD}')?; }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use crate::testing;
+
+    #[test]
+    fn default_and_module_names() {
+        let _kernel = testing::setup();
+        let task = Task::new(TaskId(42)).unwrap();
+        assert_eq!(task.name().to_string(), "task-42");
+        assert_eq!(module_name("/boot/netd.elf"), "netd");
+        assert_eq!(module_name("init"), "init");
+        assert_eq!(module_name("/boot/.hidden"), ".hidden");
+    }
+
+    #[test]
+    fn over_length_names_are_truncated() {
+        let _kernel = testing::setup();
+        let mut task = Task::new(TaskId(43)).unwrap();
+        task.set_name(&[b'x'; TASK_NAME_LEN + 10]);
+        assert_eq!(task.name().to_string(), "x".repeat(TASK_NAME_LEN));
+        // Обрезка посреди «ж» (2 байта) отбрасывает символ целиком
+        // A cut in the middle of "ж" (2 bytes) drops the whole character
+        let mut name = "a".repeat(TASK_NAME_LEN - 1);
+        name.push('ж');
+        task.set_name(name.as_bytes());
+        assert_eq!(task.name().to_string(), "a".repeat(TASK_NAME_LEN - 1));
+        task.set_name(b"short");
+        assert_eq!(task.name().to_string(), "short");
+    }
+
+    #[test]
+    fn names_display_safely() {
+        let _kernel = testing::setup();
+        let mut task = Task::new(TaskId(44)).unwrap();
+        task.set_name(b"a\xFFb\nc");
+        assert_eq!(task.name().to_string(), "a\u{FFFD}b?c");
+    }
+
+    #[test]
+    fn legal_transitions_go_through() {
+        use TaskState::*;
+        let mut state = Runnable;
+        for to in [Running, BlockedOnIpc, Runnable, Running, BlockedOnSleep, Runnable, Running, Zombie] {
+            assert!(state.transition(to), "{:?} -> {:?}", state, to);
+            assert_eq!(state, to);
+        }
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected() {
+        use TaskState::*;
+        for to in [Runnable, Running, BlockedOnIpc, BlockedOnSleep, Zombie] {
+            assert!(!Zombie.can_transition(to), "Zombie -> {:?}", to);
+        }
+        // Разбуженная задача сначала снова встаёт в очередь
+        // A woken task goes back to the queue first
+        assert!(!BlockedOnIpc.can_transition(Running));
+        assert!(!Runnable.can_transition(BlockedOnSleep));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "illegal task state transition Zombie -> Runnable")]
+    fn waking_a_zombie_panics_in_debug() {
+        let mut state = TaskState::Zombie;
+        state.transition(TaskState::Runnable);
+    }
+}
@@ -0,0 +1,157 @@
+//! TLS — блок thread-local данных задачи (x86_64, вариант II)
+//! TLS — a task's thread-local block (x86_64, variant II)
+//!
+//! Раскладка / Layout:
+//!
+//!   base                      tp = FS_BASE
+//!   │ pad │ .tdata │ .tbss   │ TCB (self-ptr) │
+//!         └── tls_offset ────┘
+//!
+//! Блок заканчивается ровно на thread pointer; `%fs:0` хранит сам tp —
+//! так его читают компилятор и libc.
+//! The block ends exactly at the thread pointer; `%fs:0` holds tp itself —
+//! that is how the compiler and libc read it.
+
+use crate::mm::pmm::{self, PAGE_SIZE};
+use crate::mm::vmm::{phys_to_virt, AddressSpace, PageFlags, VirtAddr};
+
+/// TCB — один указатель на себя / TCB — a single self-pointer
+pub const TCB_SIZE: usize = 8;
+
+/// Описание из программного заголовка `PT_TLS`.
+/// Description taken from the `PT_TLS` program header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsTemplate {
+    /// p_filesz — размер .tdata / size of .tdata
+    pub file_size: usize,
+    /// p_memsz — .tdata + .tbss
+    pub mem_size:  usize,
+    /// p_align
+    pub align:     usize,
+}
+
+/// Раскладка TLS-области / TLS area layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsLayout {
+    /// От начала блока (.tdata) до tp / From the block start (.tdata) to tp
+    pub tls_offset: usize,
+    /// Весь регион: блок + TCB / The whole area: block + TCB
+    pub total_size: usize,
+    pub align:      usize,
+}
+
+impl TlsLayout {
+    /// Посчитать раскладку. Без `PT_TLS` остаётся только TCB. `None` —
+    /// невалидный заголовок (align не степень двойки, filesz > memsz,
+    /// переполнение).
+    /// Compute the layout. Without `PT_TLS` only the TCB remains. `None` —
+    /// a malformed header (non power-of-two align, filesz > memsz, overflow).
+    pub fn compute(template: Option<&TlsTemplate>) -> Option<Self> {
+        let Some(t) = template else {
+            return Some(Self { tls_offset: 0, total_size: TCB_SIZE, align: TCB_SIZE });
+        };
+        let align = t.align.max(TCB_SIZE);
+        if !align.is_power_of_two() || t.file_size > t.mem_size { return None; }
+        let tls_offset = t.mem_size.checked_next_multiple_of(align)?;
+        Some(Self { tls_offset, total_size: tls_offset.checked_add(TCB_SIZE)?, align })
+    }
+}
+
+/// Выделить и заполнить TLS-область в `space` по адресу `base` (выровнен
+/// на страницу): .tdata из `image`, .tbss нулями, TCB указывает на себя.
+/// Возвращает значение для FS_BASE. При ошибке в `space` ничего не остаётся.
+/// Allocate and fill a TLS area in `space` at page-aligned `base`: .tdata
+/// from `image`, .tbss zeroed, TCB pointing at itself. Returns the FS_BASE
+/// value. On error nothing is left behind in `space`.
+pub fn setup(
+    space: &AddressSpace,
+    base: VirtAddr,
+    template: Option<&TlsTemplate>,
+    image: &[u8],
+) -> Option<u64> {
+    let layout = TlsLayout::compute(template)?;
+    let tdata = template.map_or(0, |t| t.file_size);
+    if image.len() < tdata { return None; }
+
+    let size = layout.total_size.next_multiple_of(PAGE_SIZE);
+    let tp = base.as_u64() + layout.tls_offset as u64;
+    space.map_anonymous(base, size as u64, PageFlags::USER_RW).ok()?;
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let Ok(phys) = pmm::alloc_zeroed_page() else { break };
+        fill_page(phys_to_virt(phys).as_mut_ptr::<u8>(), offset, &image[..tdata], layout.tls_offset, tp);
+        if space.map(VirtAddr::new(base.as_u64() + offset as u64), phys, PageFlags::USER_RW).is_err() {
+            pmm::free_page(phys);
+            break;
+        }
+        if offset + PAGE_SIZE == size { return Some(tp); }
+    }
+    // Уже замапленные фреймы уходят в PMM вместе с VMA
+    // The frames mapped so far go back to the PMM along with the VMA
+    let _ = space.unmap_range(base, size as u64);
+    None
+}
+
+/// Заполнить страницу области, начинающуюся со смещения `offset`: её
+/// часть .tdata и TCB, если он на ней. Страница уже обнулена.
+/// Fill the area's page starting at offset `offset`: its share of .tdata
+/// and the TCB if it lives there. The page is zeroed already.
+fn fill_page(page: *mut u8, offset: usize, tdata: &[u8], tls_offset: usize, tp: u64) {
+    let range = offset..offset + PAGE_SIZE;
+    if let Some(chunk) = tdata.get(offset..tdata.len().min(range.end)) {
+        unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), page, chunk.len()); }
+    }
+    // TCB выровнен на 8 и не пересекает страницу / The TCB is 8-aligned and never straddles a page
+    if range.contains(&tls_offset) {
+        unsafe { page.add(tls_offset - offset).cast::<u64>().write(tp); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn template(file_size: usize, mem_size: usize, align: usize) -> TlsTemplate {
+        TlsTemplate { file_size, mem_size, align }
+    }
+
+    #[test]
+    fn compute_rounds_the_block_up_to_the_alignment() {
+        assert_eq!(TlsLayout::compute(None), Some(TlsLayout { tls_offset: 0, total_size: TCB_SIZE, align: TCB_SIZE }));
+        // Блок кончается на tp, а tp выровнен как сам блок
+        // The block ends at tp, and tp is aligned like the block itself
+        assert_eq!(TlsLayout::compute(Some(&template(10, 20, 16))),
+                   Some(TlsLayout { tls_offset: 32, total_size: 40, align: 16 }));
+        assert_eq!(TlsLayout::compute(Some(&template(64, 64, 64))),
+                   Some(TlsLayout { tls_offset: 64, total_size: 72, align: 64 }));
+        // Выравнивание меньше TCB поднимается до него / An alignment below the TCB's is raised to it
+        assert_eq!(TlsLayout::compute(Some(&template(3, 3, 1))),
+                   Some(TlsLayout { tls_offset: 8, total_size: 16, align: 8 }));
+        assert_eq!(TlsLayout::compute(Some(&template(0, 0, 0))),
+                   Some(TlsLayout { tls_offset: 0, total_size: 8, align: 8 }));
+    }
+
+    #[test]
+    fn compute_rejects_malformed_headers() {
+        assert_eq!(TlsLayout::compute(Some(&template(0, 8, 24))), None);
+        assert_eq!(TlsLayout::compute(Some(&template(16, 8, 8))), None);
+        assert_eq!(TlsLayout::compute(Some(&template(0, usize::MAX - 4, 8))), None);
+    }
+
+    #[test]
+    fn setup_copies_tdata_and_points_the_tcb_at_itself() {
+        let _kernel = testing::setup();
+        let space = AddressSpace::new().unwrap();
+        let base = VirtAddr::new(0x7000_0000);
+        let image = [0xAB; 12];
+        let tp = setup(&space, base, Some(&template(12, PAGE_SIZE + 4, 32)), &image).unwrap();
+        assert_eq!(tp, base.as_u64() + (PAGE_SIZE + 32) as u64);
+
+        let read = |va: u64| unsafe { *phys_to_virt(space.translate(VirtAddr::new(va)).unwrap()).as_ptr::<u8>() };
+        assert_eq!(read(base.as_u64()), 0xAB);
+        assert_eq!(read(base.as_u64() + 11), 0xAB);
+        assert_eq!(read(base.as_u64() + 12), 0);
+        let tcb = space.translate(VirtAddr::new(tp)).unwrap();
+        assert_eq!(unsafe { *phys_to_virt(tcb).as_ptr::<u64>() }, tp);
+    }
+}
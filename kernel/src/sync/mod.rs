@@ -0,0 +1,283 @@
+//! Примитивы синхронизации ядра / Kernel synchronization primitives
+//!
+//! `spin::Mutex` не знает о прерываниях: если обработчик прерывания берёт
+//! тот же lock, что и прерванный код, на одном CPU будет deadlock.
+//! `IrqMutex` выключает прерывания на время владения lock'ом.
+//! `spin::Mutex` knows nothing about interrupts: if a handler takes the same
+//! lock as the code it interrupted, one CPU deadlocks. `IrqMutex` keeps
+//! interrupts disabled for as long as the lock is held.
+//!
+//! `RwLock` — для реестров, которые читают часто, а пишут редко (точки
+//! монтирования, framebuffer'ы). Ждущий писатель не пускает новых
+//! читателей — поток чтений его не заморит.
+//! `RwLock` is for registries that are read often and written rarely (mount
+//! points, framebuffers). A waiting writer keeps new readers out — a stream
+//! of reads can't starve it.
+
+use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use crate::arch::current::interrupts;
+
+/// Паника, если прерывания включены (только в debug сборке).
+/// Panic if interrupts are enabled (debug builds only).
+///
+/// Ставится в начало критических секций, которые обязаны работать с
+/// выключенными прерываниями — редкий deadlock превращается в
+/// детерминированную панику.
+/// Placed at the start of critical sections that must run with interrupts
+/// off — turns a rare deadlock into a deterministic panic.
+#[macro_export]
+macro_rules! assert_irqs_disabled {
+    () => {
+        if cfg!(debug_assertions) && $crate::arch::current::interrupts::are_enabled() {
+            panic!("interrupts enabled in IRQ-critical section at {}:{}", file!(), line!());
+        }
+    };
+}
+
+/// Mutex, выключающий прерывания на время владения.
+/// Mutex that disables interrupts while held.
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self { inner: Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let irqs_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqMutexGuard { guard: ManuallyDrop::new(self.inner.lock()), irqs_were_enabled }
+    }
+
+    /// Взять lock, если он свободен; иначе `None` и прерывания как были.
+    /// Take the lock if it is free; otherwise `None` with interrupts as they were.
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let irqs_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqMutexGuard { guard: ManuallyDrop::new(guard), irqs_were_enabled }),
+            None => {
+                if irqs_were_enabled { interrupts::enable(); }
+                None
+            }
+        }
+    }
+}
+
+/// Повторить `attempt` до `retries` раз, между попытками крутиться
+/// 1, 2, 4, ... итераций (потолок `max_spin`). Возвращает первый `Some`.
+/// Retry `attempt` up to `retries` times, spinning 1, 2, 4, ... iterations
+/// between tries (capped at `max_spin`). Returns the first `Some`.
+pub fn retry_with_backoff<T>(retries: u32, max_spin: u32, mut attempt: impl FnMut() -> Option<T>) -> Option<T> {
+    let mut spin = 1;
+    for try_no in 0..=retries {
+        if let Some(value) = attempt() { return Some(value); }
+        if try_no == retries { break; }
+        for _ in 0..spin { core::hint::spin_loop(); }
+        spin = (spin * 2).min(max_spin);
+    }
+    None
+}
+
+pub struct IrqMutexGuard<'a, T> {
+    guard:             ManuallyDrop<MutexGuard<'a, T>>,
+    irqs_were_enabled: bool,
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T { &self.guard }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T { &mut self.guard }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Сначала отпустить lock, потом включить прерывания
+        // Release the lock first, then re-enable interrupts
+        unsafe { ManuallyDrop::drop(&mut self.guard); }
+        if self.irqs_were_enabled { interrupts::enable(); }
+    }
+}
+
+/// Спиновый reader-writer lock с приоритетом писателя.
+/// Spinning reader-writer lock with writer preference.
+///
+/// Прерывания не трогает: обработчик, читающий lock, который прерванный
+/// код держит (или ждёт) на запись, зависнет. Поэтому из обработчиков —
+/// только `read`, и только если запись бывает лишь до включения таких
+/// обработчиков; иначе — `IrqMutex`. Держать guard через блокирующий
+/// вызов нельзя: остальные CPU будут крутиться всё это время.
+/// It leaves interrupts alone: a handler reading a lock that the interrupted
+/// code holds (or waits on) for writing hangs. So handlers may only `read`,
+/// and only when writes happen before such handlers can run; otherwise use
+/// `IrqMutex`. Never hold a guard across a blocking call: every other CPU
+/// would spin for the whole time.
+pub struct RwLock<T> {
+    /// WRITER | WRITER_WAITING | число читателей × READER
+    /// WRITER | WRITER_WAITING | reader count × READER
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+const WRITER:         usize = 1;
+const WRITER_WAITING: usize = 2;
+const READER:         usize = 4;
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self { state: AtomicUsize::new(0), value: UnsafeCell::new(value) }
+    }
+
+    /// Читатели идут параллельно, пока нет писателя — ни владеющего, ни ждущего.
+    /// Readers proceed in parallel while there is no writer — holding or waiting.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() { return guard; }
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | WRITER_WAITING) != 0 { return None; }
+        self.state
+            .compare_exchange_weak(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    /// Объявить ожидание (новые читатели больше не входят) и дождаться,
+    /// пока уйдут текущие.
+    /// Announce the wait (no new readers get in) and wait for the current
+    /// ones to leave.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WRITER_WAITING == 0 {
+                // Свободен — забрать, заодно сняв свой флаг ожидания
+                // Free — take it, clearing our waiting flag on the way
+                if self.state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return RwLockWriteGuard { lock: self };
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.lock.value.get() } }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.lock.value.get() } }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.lock.value.get() } }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Флаг ожидания другого писателя сохраняем / Keep another writer's waiting flag
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_retries_until_the_nth_attempt() {
+        // «Lock» освобождается на пятой попытке / The "lock" frees up on the fifth try
+        let mut tries = 0;
+        let got = retry_with_backoff(8, 16, || { tries += 1; (tries == 5).then_some(tries) });
+        assert_eq!((got, tries), (Some(5), 5));
+
+        // Не освобождается — первая попытка плюс `retries` повторов, и всё
+        // Never frees up — the first try plus `retries` retries, and that's it
+        let mut tries = 0;
+        assert_eq!(retry_with_backoff(3, 16, || { tries += 1; None::<()> }), None);
+        assert_eq!(tries, 4);
+    }
+
+    #[test]
+    fn irqs_disabled_assert_passes_with_if_clear() {
+        interrupts::disable();
+        crate::assert_irqs_disabled!();
+    }
+
+    #[test]
+    #[should_panic(expected = "interrupts enabled in IRQ-critical section")]
+    fn irqs_disabled_assert_fires_with_if_set() {
+        interrupts::enable();
+        crate::assert_irqs_disabled!();
+    }
+
+    #[test]
+    fn irq_mutex_clears_if_while_held_and_restores_it() {
+        let lock = IrqMutex::new(0);
+        interrupts::enable();
+        {
+            let _guard = lock.lock();
+            crate::assert_irqs_disabled!();
+        }
+        assert!(interrupts::are_enabled());
+        interrupts::disable();
+    }
+
+    #[test]
+    fn readers_share_and_a_waiting_writer_keeps_new_ones_out() {
+        let lock = RwLock::new(0);
+        let first = lock.read();
+        let second = lock.try_read().expect("readers proceed in parallel");
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                let mut guard = lock.write();
+                assert!(lock.try_read().is_none());
+                *guard = 1;
+            });
+            // Писатель объявил ожидание — новые читатели не входят
+            // The writer announced its wait — new readers stay out
+            while lock.state.load(Ordering::Relaxed) & WRITER_WAITING == 0 { core::hint::spin_loop(); }
+            assert!(lock.try_read().is_none());
+            drop((first, second));
+            writer.join().unwrap();
+        });
+        assert_eq!(*lock.read(), 1);
+    }
+}
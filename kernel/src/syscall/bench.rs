@@ -0,0 +1,65 @@
+//! Замер стоимости syscall в тактах TSC / Syscall cost in TSC cycles
+//!
+//! Скользящее среднее по последним `WINDOW` вызовам — бенчмарк-задача
+//! видит, если изменение (например, проверки copy_from_user) раздуло
+//! латентность. Включается `syscall.bench=1`, иначе накладных нет.
+//! Rolling average over the last `WINDOW` calls — a benchmark task can see
+//! when a change (e.g. copy_from_user validation) blew up latency. Enabled by
+//! `syscall.bench=1`; otherwise there is no overhead.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+pub const WINDOW: usize = 64;
+
+/// Кольцевой буфер замеров / Ring buffer of samples
+pub struct RollingAverage {
+    samples: [u64; WINDOW],
+    next:    usize,
+    filled:  usize,
+    sum:     u64,
+}
+
+impl RollingAverage {
+    pub const fn new() -> Self {
+        Self { samples: [0; WINDOW], next: 0, filled: 0, sum: 0 }
+    }
+
+    pub fn record(&mut self, sample: u64) {
+        if self.filled == WINDOW {
+            self.sum -= self.samples[self.next];
+        } else {
+            self.filled += 1;
+        }
+        self.samples[self.next] = sample;
+        self.sum = self.sum.saturating_add(sample);
+        self.next = (self.next + 1) % WINDOW;
+    }
+
+    /// Среднее по окну; нет замеров — 0 / Window average; no samples — 0
+    pub fn average(&self) -> u64 {
+        if self.filled == 0 { 0 } else { self.sum / self.filled as u64 }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATS:   Mutex<RollingAverage> = Mutex::new(RollingAverage::new());
+
+pub fn init() {
+    ENABLED.store(crate::cmdline::flag("syscall.bench"), Ordering::Relaxed);
+}
+
+#[inline]
+pub fn enabled() -> bool { ENABLED.load(Ordering::Relaxed) }
+
+#[inline]
+pub fn now() -> u64 { unsafe { core::arch::x86_64::_rdtsc() } }
+
+pub fn record(cycles: u64) {
+    STATS.lock().record(cycles);
+}
+
+/// Средняя стоимость syscall в тактах / Average syscall cost in cycles
+pub fn average() -> u64 {
+    STATS.lock().average()
+}
@@ -1,39 +1,847 @@
 //! Syscall handler — ~15 system calls
 //!
-//! Номера / Numbers:
+//! Номера / Numbers (`cupruxos_abi::Syscall`):
 //!   0  ipc_call(cap, msg)      — синхронный IPC вызов
 //!   1  ipc_send(cap, msg)      — асинхронная отправка
 //!   2  ipc_recv(cap)           — ждать сообщения
 //!   3  ipc_reply(msg)          — ответить на вызов
 //!   4  cap_create_port()       — создать порт
-//!   5  cap_grant(cap, task)    — передать capability
-//!   6  cap_revoke(cap)         — отозвать capability
+//!   5  cap_grant(cap, task, mask) — передать capability с правами & mask
+//!   6  cap_revoke(cap)         — отозвать capability и всё выданное из неё
 //!   7  mem_map(cap, addr)      — замаппить регион
-//!   8  mem_unmap(addr)         — размаппить
+//!   8  mem_unmap(addr, len)    — размаппить свою anonymous/file память
 //!   9  mem_alloc(size)         — запросить анонимную память
 //!   10 task_spawn(bin, caps)   — создать задачу
 //!   11 task_exit(code)         — завершиться
 //!   12 task_yield()            — отдать CPU
 //!   13 time_now()              — текущее время (нс)
 //!   14 time_sleep(ns)          — заснуть
+//!   15 syscall_stats()         — средняя стоимость syscall (syscall.bench=1)
+//!   16 ipc_recv_timeout(cap, msg, deadline_ns) — recv или таймаут
+//!   17 task_set_priority(class) — сменить класс планирования
+//!   18 ipc_call_buf(cap, args)  — вызов с ответом в буфер вызывающего
+//!   19 profile_dump(buf, count)  — снимок профайлера (profile=1)
+//!   20 io_grant(cap)            — открыть порты из IoPort capability
+//!   21 evq_create()             — создать очередь событий
+//!   22 evq_attach(evq, port, token) — подключить порт (бит 63 token — edge)
+//!   23 evq_wait(evq, buf, count) — ждать готовых источников
+//!   24 ipc_send_batch(cap, msgs, count) — несколько send за один вход в ядро
+//!   25 task_set_name(ptr, len)  — назвать себя (обрезается до TASK_NAME_LEN)
+//!   26 notify(cap, bits)        — OR бит в слово notification
+//!   27 notify_wait(cap, out)    — ждать ненулевого слова, забрать и обнулить
+//!   28 notify_create()          — создать notification
+//!   29 mem_share(size, writable) — создать shared memory, вернуть её MemoryCap
 
-// TODO: Этап 7 — реализация syscall handler
-// TODO: Phase 7 — syscall handler implementation
+pub mod bench;
+mod user;
+
+pub use user::{copy_from_user, copy_to_user, UserError};
 
 pub fn init() {
-    // stub — установить обработчик (syscall/svc/ecall)
-    // stub — install handler (syscall/svc/ecall)
+    bench::init();
+    crate::arch::current::syscall::init();
+}
+
+use alloc::vec::Vec;
+use cupruxos_abi::{CallBufArgs, Errno, EvqEvent, Priority, Syscall, EVQ_EDGE, MAX_MSG_CAPS, NO_CAP, TASK_NAME_LEN};
+use crate::ipc::cap::{self, CapEntry, CapKind, GrantError, RevokeError, Rights};
+use crate::ipc::event::{self, Event, EventQueue, Trigger};
+use crate::ipc::notify::Notification;
+use crate::ipc::{self, CapId, IpcError, Message, PortId, MAX_INLINE_PAYLOAD};
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::MmError;
+use crate::mm::shared::{MemoryCap, SharedMemObject};
+use crate::mm::vmm::{self, VirtAddr, VmaKind};
+use crate::sched::elf::ElfError;
+use crate::sched::PriorityError;
+
+/// MmError → errno для mem_map/mem_alloc (в libcuprum — `NoMemory`/`InvalidArg`).
+/// MmError → errno for mem_map/mem_alloc (`NoMemory`/`InvalidArg` in libcuprum).
+fn mm_errno(err: MmError) -> isize {
+    match err {
+        MmError::OutOfMemory   => Errno::NoMemory as isize,
+        MmError::InvalidRange
+        | MmError::VmaOverlap
+        | MmError::WriteExec
+        | MmError::NonCanonical(_)
+        | MmError::Unmappable(_) => Errno::InvalidArg as isize,
+    }
+}
+
+/// Задача запаркована: вход в ядро вернёт её на ту же инструкцию `syscall`
+/// с тем же номером, и после пробуждения вызов повторится. В userspace не
+/// виден никогда.
+/// The task is parked: the kernel entry puts it back on the same `syscall`
+/// instruction with the same number, and the call repeats after wakeup.
+/// Never visible to userspace.
+pub const RESTART: isize = -512;
+
+/// IpcError → errno; `WouldBlock` — повтор после пробуждения.
+/// IpcError → errno; `WouldBlock` — a retry after wakeup.
+fn ipc_errno(err: IpcError) -> isize {
+    match err {
+        IpcError::NoSuchPort
+        | IpcError::InvalidCap   => Errno::InvalidCap as isize,
+        IpcError::NoPermission   => Errno::NoPermission as isize,
+        IpcError::TimedOut       => Errno::TimedOut as isize,
+        IpcError::QueueFull      => Errno::WouldBlock as isize,
+        IpcError::WouldBlock     => RESTART,
+        IpcError::NoCaller
+        | IpcError::CallFailed   => Errno::NotFound as isize,
+    }
+}
+
+/// Новый порт — в таблицу текущей задачи со всеми правами.
+/// A new port — into the current task's table with every right.
+fn cap_create_port() -> Result<isize, isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidArg as isize)?;
+    let port = ipc::port(ipc::create_port()).ok_or(Errno::NoMemory as isize)?;
+    let entry = CapEntry { kind: CapKind::Port(port), rights: Rights::all(), badge: 0, parent: None };
+    Ok(cap::install(task, entry).0 as isize)
+}
+
+/// Заснуть на `ns` наносекунд; возврат — уже после пробуждения.
+/// Sleep for `ns` nanoseconds; returns only after waking.
+fn time_sleep(ns: u64) -> Result<(), isize> {
+    let deadline = crate::time::now().saturating_add(ns);
+    if !crate::sched::sleep(deadline) { return Err(Errno::InvalidArg as isize); }
+    Ok(())
+}
+
+/// Выдать задаче за `task_cap` ослабленную копию `cap`: права `исходные & mask`.
+/// Give the task behind `task_cap` an attenuated copy of `cap`: rights `source & mask`.
+fn cap_grant(cap: usize, task_cap: usize, mask: usize) -> Result<isize, isize> {
+    let from = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    let to = match cap::lookup(from, CapId(task_cap as u64)).map(|entry| entry.kind) {
+        Some(CapKind::Task(task)) => task,
+        _                         => return Err(Errno::InvalidCap as isize),
+    };
+    let mask = Rights::from_bits_truncate(mask as u32);
+    match cap::grant(from, CapId(cap as u64), to, mask) {
+        Ok(granted)                => Ok(granted.0 as isize),
+        Err(GrantError::NoSuchCap) => Err(Errno::InvalidCap as isize),
+        Err(GrantError::Denied)    => Err(Errno::NoPermission as isize),
+    }
+}
+
+/// Сменить класс текущей задачи; выше `Normal` без `SchedControl` — `NoPermission`.
+/// Change the current task's class; above `Normal` without `SchedControl` — `NoPermission`.
+fn task_set_priority(class: usize) -> Result<(), isize> {
+    let priority = Priority::from_raw(class).ok_or(Errno::InvalidArg as isize)?;
+    let task = crate::sched::current_id().ok_or(Errno::NotFound as isize)?;
+    crate::sched::set_priority(task, priority).map_err(|e| match e {
+        PriorityError::NoSuchTask => Errno::NotFound as isize,
+        PriorityError::Denied     => Errno::NoPermission as isize,
+    })
+}
+
+/// Переименовать текущую задачу. Больше `TASK_NAME_LEN` не читаем — хвост
+/// всё равно обрежется.
+/// Rename the current task. No more than `TASK_NAME_LEN` is read — the tail
+/// would be cut anyway.
+fn task_set_name(ptr: usize, len: usize) -> Result<(), isize> {
+    let task = crate::sched::current_id().ok_or(Errno::NotFound as isize)?;
+    let mut name = [0u8; TASK_NAME_LEN];
+    let len = len.min(TASK_NAME_LEN);
+    copy_from_user(&mut name[..len], VirtAddr::new(ptr as u64)).map_err(UserError::errno)?;
+    if !crate::sched::set_name(task, &name[..len]) { return Err(Errno::NotFound as isize); }
+    Ok(())
+}
+
+/// Запустить ELF из shared memory за `bin`. `caps` — `MAX_MSG_CAPS` слотов
+/// (`NO_CAP` — пусто, 0 — ни одного): каждая выдаётся новой задаче
+/// производной со всеми правами исходной, нужен GRANT. Возвращает
+/// capability на задачу.
+/// Launch the ELF in the shared memory behind `bin`. `caps` — `MAX_MSG_CAPS`
+/// slots (`NO_CAP` — empty, 0 — none at all): each is given to the new task
+/// as a derived copy with all of the source's rights, GRANT required.
+/// Returns a capability to the task.
+fn task_spawn(bin: usize, caps: usize) -> Result<isize, isize> {
+    let parent = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    let (object, _) = cap::shared_object(parent, CapId(bin as u64)).ok_or(Errno::InvalidCap as isize)?;
+    let mut slots = [0u8; MAX_MSG_CAPS * 8];
+    if caps != 0 {
+        copy_from_user(&mut slots, VirtAddr::new(caps as u64)).map_err(UserError::errno)?;
+    } else {
+        slots.fill(0xFF);
+    }
+    let mut seeded = Vec::new();
+    for slot in slots.as_chunks::<8>().0 {
+        let raw = u64::from_ne_bytes(*slot);
+        if raw == NO_CAP { continue; }
+        seeded.push(cap::derive(parent, CapId(raw), Rights::all()).map_err(|e| match e {
+            GrantError::NoSuchCap => Errno::InvalidCap as isize,
+            GrantError::Denied    => Errno::NoPermission as isize,
+        })?);
+    }
+    // Объект держит `object` — образ не исчезнет, пока грузится
+    // `object` holds the object — the image can't vanish while it loads
+    let image = unsafe {
+        core::slice::from_raw_parts(vmm::phys_to_virt(object.base()).as_ptr::<u8>(), object.size() as usize)
+    };
+    let child = crate::sched::spawn_elf(image, seeded).map_err(|e| match e {
+        ElfError::Memory(err) => mm_errno(err),
+        _                     => Errno::InvalidArg as isize,
+    })?;
+    let entry = CapEntry { kind: CapKind::Task(child), rights: Rights::all(), badge: 0, parent: None };
+    Ok(cap::install(parent, entry).0 as isize)
+}
+
+/// Отозвать capability и всё, что от неё выдано; вернуть число удалённых.
+/// Revoke a capability and everything granted from it; return how many were removed.
+fn cap_revoke(cap: usize) -> Result<isize, isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    match cap::revoke(task, CapId(cap as u64)) {
+        Ok(count)                   => Ok(count as isize),
+        Err(RevokeError::NoSuchCap) => Err(Errno::InvalidCap as isize),
+        Err(RevokeError::Denied)    => Err(Errno::NoPermission as isize),
+    }
+}
+
+fn io_grant(cap: usize) -> Result<(), isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    if crate::sched::grant_io_ports(task, CapId(cap as u64)) { Ok(()) } else { Err(Errno::NoPermission as isize) }
+}
+
+/// Порт за capability текущей задачи; нет права `need` — `NoPermission`.
+/// The port behind the current task's capability; without the `need` right — `NoPermission`.
+fn port_cap(cap: usize, need: Rights) -> Result<PortId, isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    let entry = cap::lookup(task, CapId(cap as u64)).ok_or(Errno::InvalidCap as isize)?;
+    let CapKind::Port(port) = entry.kind else { return Err(Errno::InvalidCap as isize) };
+    if !entry.rights.contains(need) { return Err(Errno::NoPermission as isize); }
+    Ok(port.id)
+}
+
+/// Payload длиной `len` (уже `<= MAX_INLINE_PAYLOAD`) и слоты capability из `Message` пользователя.
+/// A `len`-byte payload (already `<= MAX_INLINE_PAYLOAD`) and the capability slots from a user `Message`.
+fn read_message(ptr: usize, len: usize) -> Result<Message, isize> {
+    let mut msg = Message::empty();
+    copy_from_user(&mut msg.payload[..len], VirtAddr::new(ptr as u64)).map_err(UserError::errno)?;
+    msg.payload_len = len;
+    let mut caps = [0u8; MAX_MSG_CAPS * 8];
+    let caps_ptr = ptr.checked_add(core::mem::offset_of!(Message, caps)).ok_or(Errno::InvalidArg as isize)?;
+    copy_from_user(&mut caps, VirtAddr::new(caps_ptr as u64)).map_err(UserError::errno)?;
+    for (slot, raw) in msg.caps.iter_mut().zip(caps.as_chunks::<8>().0) {
+        *slot = u64::from_ne_bytes(*raw);
+    }
+    Ok(msg)
+}
+
+/// Записать `Message` целиком поверх пользовательского / Write a whole `Message` over the user's
+fn write_message(ptr: usize, msg: &Message) -> Result<(), isize> {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(msg as *const Message as *const u8, core::mem::size_of::<Message>())
+    };
+    copy_to_user(VirtAddr::new(ptr as u64), bytes).map_err(UserError::errno)
+}
+
+/// Ответ пишется поверх запроса / The reply is written over the request
+fn ipc_call(cap: usize, ptr: usize, len: usize) -> Result<(), isize> {
+    let port = port_cap(cap, Rights::SEND)?;
+    let caller = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    let msg = read_message(ptr, len)?;
+    let reply = ipc::call(port, &msg, caller).map_err(ipc_errno)?;
+    write_message(ptr, &reply)
+}
+
+/// Ответ — в `args.reply`, сколько влезет; возвращается полная длина
+/// ответа. Больше `reply_cap` — значит, обрезан: в буфере префикс.
+/// The reply goes into `args.reply`, as much as fits; the full reply length
+/// is returned. Above `reply_cap` means truncated: the buffer holds a prefix.
+fn ipc_call_buf(cap: usize, args_ptr: usize) -> Result<isize, isize> {
+    let port = port_cap(cap, Rights::SEND)?;
+    let caller = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    let mut raw = [0u8; core::mem::size_of::<CallBufArgs>()];
+    copy_from_user(&mut raw, VirtAddr::new(args_ptr as u64)).map_err(UserError::errno)?;
+    // `repr(C)` из одних usize — любые байты годятся
+    // `repr(C)` of usizes only — any bytes will do
+    let args = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const CallBufArgs) };
+    if args.request_len > MAX_INLINE_PAYLOAD { return Err(Errno::InvalidArg as isize); }
+    let msg = read_message(args.request, args.request_len)?;
+    let body = ipc::call_buf(port, &msg, caller).map_err(ipc_errno)?;
+    let bytes = body.as_bytes();
+    let fits = bytes.len().min(args.reply_cap);
+    copy_to_user(VirtAddr::new(args.reply as u64), &bytes[..fits]).map_err(UserError::errno)?;
+    Ok(bytes.len() as isize)
+}
+
+fn ipc_send(cap: usize, ptr: usize, len: usize) -> Result<(), isize> {
+    let port = port_cap(cap, Rights::SEND)?;
+    ipc::send(port, &read_message(ptr, len)?).map_err(ipc_errno)
+}
+
+/// `count` сообщений подряд с `ptr` — в порт одним пакетом; возвращает
+/// число принятых. Больше, чем вмещает очередь, не читаем. payload_len >
+/// `MAX_INLINE_PAYLOAD` обрывает пакет, у первого — `InvalidArg`.
+/// `count` consecutive messages at `ptr` — into the port as one batch;
+/// returns the number accepted. No more than the queue holds is read. A
+/// payload_len > `MAX_INLINE_PAYLOAD` cuts the batch short, on the first
+/// one — `InvalidArg`.
+fn ipc_send_batch(cap: usize, ptr: usize, count: usize) -> Result<isize, isize> {
+    let port = ipc::port(port_cap(cap, Rights::SEND)?).ok_or(Errno::InvalidCap as isize)?;
+    let size = core::mem::size_of::<Message>();
+    let mut msgs = Vec::new();
+    for i in 0..count.min(ipc::object::PORT_QUEUE_LEN) {
+        let at = i.checked_mul(size).and_then(|off| ptr.checked_add(off)).ok_or(Errno::InvalidArg as isize)?;
+        let mut msg = Message::empty();
+        // `repr(C)` без дыр, любые байты — допустимое `Message`
+        // `repr(C)` without padding, any bytes are a valid `Message`
+        let bytes = unsafe { core::slice::from_raw_parts_mut(&mut msg as *mut Message as *mut u8, size) };
+        copy_from_user(bytes, VirtAddr::new(at as u64)).map_err(UserError::errno)?;
+        if msg.payload_len > MAX_INLINE_PAYLOAD {
+            if i == 0 { return Err(Errno::InvalidArg as isize); }
+            break;
+        }
+        msgs.push(msg);
+    }
+    Ok(port.send_batch(&msgs) as isize)
+}
+
+fn ipc_recv(cap: usize, ptr: usize) -> Result<(), isize> {
+    let msg = ipc::recv(port_cap(cap, Rights::RECV)?).map_err(ipc_errno)?;
+    write_message(ptr, &msg)
+}
+
+fn ipc_recv_timeout(cap: usize, ptr: usize, deadline_ns: u64) -> Result<(), isize> {
+    let msg = ipc::recv_timeout(port_cap(cap, Rights::RECV)?, deadline_ns).map_err(ipc_errno)?;
+    write_message(ptr, &msg)
+}
+
+/// Замапить shared memory за capability целиком с `addr` — с флагами
+/// самой capability, не шире.
+/// Map the shared memory behind a capability whole at `addr` — with the
+/// capability's own flags, no wider.
+fn mem_map(cap: usize, addr: usize) -> Result<(), isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidCap as isize)?;
+    let (object, flags) = cap::shared_object(task, CapId(cap as u64)).ok_or(Errno::InvalidCap as isize)?;
+    let at = VirtAddr::new(addr as u64);
+    if !at.is_user() { return Err(Errno::InvalidArg as isize); }
+    let space = crate::sched::current_space().ok_or(Errno::InvalidArg as isize)?;
+    space.map_shared(at, object, flags).map_err(mm_errno)
+}
+
+/// Новая shared memory на `size` байт (с округлением до 2^n страниц),
+/// обнулённая. Без `writable` её не запишет ни один получатель — и сам
+/// создатель тоже.
+/// New zeroed shared memory of `size` bytes (rounded up to 2^n pages).
+/// Without `writable` no receiver can write it — nor can the creator.
+fn mem_share(size: usize, writable: usize) -> Result<isize, isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidArg as isize)?;
+    let order = size.div_ceil(PAGE_SIZE).next_power_of_two().trailing_zeros() as usize;
+    let object = SharedMemObject::alloc(order).map_err(mm_errno)?;
+    let mut flags = vmm::PageFlags::USER_RW;
+    if writable == 0 { flags -= vmm::PageFlags::WRITABLE; }
+    let entry = CapEntry { kind: CapKind::Shared(MemoryCap::new(object, flags)), rights: Rights::all(), badge: 0, parent: None };
+    Ok(cap::install(task, entry).0 as isize)
+}
+
+/// Анонимная память на `size` байт там, где ядро найдёт место; возвращает
+/// её адрес. Страницы подкрепляются фреймами при первом касании.
+/// Anonymous memory of `size` bytes wherever the kernel finds room; returns
+/// its address. Pages are backed by frames on first touch.
+fn mem_alloc(size: usize) -> Result<isize, isize> {
+    let space = crate::sched::current_space().ok_or(Errno::InvalidArg as isize)?;
+    let at = space.alloc_anonymous(size as u64, vmm::PageFlags::USER_RW).map_err(mm_errno)?;
+    Ok(at.as_u64() as isize)
+}
+
+/// Снять `[addr, addr + len)` со своими фреймами; диапазон — внутри одной
+/// собственной VMA (см. `AddressSpace::unmap_range`). Shared маппинг
+/// снимается только целиком, по своему началу.
+/// Unmap `[addr, addr + len)` with its frames; the range lies inside one
+/// owned VMA (see `AddressSpace::unmap_range`). A shared mapping goes only
+/// as a whole, by its start.
+fn mem_unmap(addr: usize, len: usize) -> Result<(), isize> {
+    let space = crate::sched::current_space().ok_or(Errno::InvalidArg as isize)?;
+    let at = VirtAddr::new(addr as u64);
+    if let Some((_, VmaKind::Shared(_))) = space.find_vma(at) {
+        return if space.unmap_shared(at) { Ok(()) } else { Err(Errno::InvalidArg as isize) };
+    }
+    space.unmap_range(at, len as u64).map_err(mm_errno)
+}
+
+/// Биты — через указатель: старший бит в rax выглядел бы ошибкой.
+/// The bits go through a pointer: a high bit in rax would look like an error.
+fn notify_wait(cap: usize, ptr: usize) -> Result<(), isize> {
+    let bits = ipc::wait_notify(CapId(cap as u64)).map_err(ipc_errno)?;
+    copy_to_user(VirtAddr::new(ptr as u64), &bits.to_ne_bytes()).map_err(UserError::errno)
+}
+
+/// Больше событий за один `evq_wait` не отдаётся / No more events per `evq_wait`
+const EVQ_WAIT_MAX: usize = 64;
+
+fn notify_create() -> Result<isize, isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidArg as isize)?;
+    let entry = CapEntry { kind: CapKind::Notification(Notification::new()), rights: Rights::all(), badge: 0, parent: None };
+    Ok(cap::install(task, entry).0 as isize)
+}
+
+fn evq_create() -> Result<isize, isize> {
+    let task = crate::sched::current_id().ok_or(Errno::InvalidArg as isize)?;
+    let entry = CapEntry { kind: CapKind::EventQueue(EventQueue::new()), rights: Rights::all(), badge: 0, parent: None };
+    Ok(cap::install(task, entry).0 as isize)
+}
+
+/// Подключить порт (нужно право RECV) к очереди; бит `EVQ_EDGE` в `token` — edge.
+/// Attach a port (the RECV right is needed) to the queue; the `EVQ_EDGE` bit in `token` — edge.
+fn evq_attach(evq: usize, port: usize, token: u64) -> Result<(), isize> {
+    let queue = event::lookup(CapId(evq as u64)).map_err(ipc_errno)?;
+    let port = ipc::port(port_cap(port, Rights::RECV)?).ok_or(Errno::InvalidCap as isize)?;
+    let trigger = if token & EVQ_EDGE != 0 { Trigger::Edge } else { Trigger::Level };
+    // Уже подключён / Already attached
+    if !port.watch(&queue, token & !EVQ_EDGE, trigger) { return Err(Errno::InvalidArg as isize); }
+    Ok(())
+}
+
+/// Готовые источники в пользовательский массив из `count` `EvqEvent`;
+/// возвращает сколько записано.
+/// Ready sources into a user array of `count` `EvqEvent`s; returns how
+/// many were written.
+fn evq_wait(evq: usize, ptr: usize, count: usize) -> Result<isize, isize> {
+    let mut events = alloc::vec![Event::EMPTY; count.min(EVQ_WAIT_MAX)];
+    let n = event::wait_events(CapId(evq as u64), &mut events).map_err(ipc_errno)?;
+    let size = core::mem::size_of::<EvqEvent>();
+    for (i, event) in events[..n].iter().enumerate() {
+        let at = ptr.checked_add(i * size).ok_or(Errno::InvalidArg as isize)?;
+        copy_to_user(VirtAddr::new(at as u64), &event.to_bytes()).map_err(UserError::errno)?;
+    }
+    Ok(n as isize)
+}
+
+fn ipc_reply(ptr: usize, len: usize) -> Result<(), isize> {
+    let server = crate::sched::current_id().ok_or(Errno::NotFound as isize)?;
+    ipc::reply(server, &read_message(ptr, len)?).map_err(ipc_errno)
+}
+
+/// Снимок профиля в пользовательский массив из `count` записей `Sample`;
+/// возвращает полное число корзин — больше `count`, значит, не влезли.
+/// A profile snapshot into a user array of `count` `Sample` entries;
+/// returns the total bucket count — above `count` means some didn't fit.
+fn profile_dump(ptr: usize, count: usize) -> Result<isize, isize> {
+    use crate::profile::{Sample, SLOTS};
+    if ptr == 0 && count != 0 { return Err(Errno::InvalidArg as isize); }
+    let mut samples = alloc::vec![Sample::EMPTY; count.min(SLOTS)];
+    let total = crate::profile::snapshot(&mut samples);
+    let size = core::mem::size_of::<Sample>();
+    for (i, sample) in samples.iter().take(total).enumerate() {
+        let at = ptr.checked_add(i * size).ok_or(Errno::InvalidArg as isize)?;
+        copy_to_user(VirtAddr::new(at as u64), &sample.to_bytes()).map_err(UserError::errno)?;
+    }
+    Ok(total as isize)
+}
+
+/// Округлить размер до страниц без переполнения.
+/// Round a size up to whole pages without overflowing.
+fn page_round_up(size: usize) -> Option<usize> {
+    Some(size.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1))
 }
 
 #[no_mangle]
 pub extern "C" fn syscall_handler(
     number: usize,
-    _arg0: usize,
-    _arg1: usize,
-    _arg2: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
 ) -> isize {
-    match number {
-        0..=14 => -1, // TODO: реализовать / implement
-        _      => -38, // ENOSYS
+    crate::sched::reap();
+    if !bench::enabled() {
+        return dispatch(number, arg0, arg1, arg2);
+    }
+    let start = bench::now();
+    let ret = dispatch(number, arg0, arg1, arg2);
+    bench::record(bench::now() - start);
+    ret
+}
+
+fn dispatch(number: usize, arg0: usize, arg1: usize, arg2: usize) -> isize {
+    let Some(syscall) = Syscall::from_raw(number) else { return Errno::NotSupported as isize };
+    match syscall {
+        // len > MAX_INLINE_PAYLOAD — такое только через MemoryCap
+        // len > MAX_INLINE_PAYLOAD — that only goes through a MemoryCap
+        Syscall::IpcCall | Syscall::IpcSend if arg2 > MAX_INLINE_PAYLOAD => Errno::InvalidArg as isize,
+        Syscall::IpcReply if arg1 > MAX_INLINE_PAYLOAD => Errno::InvalidArg as isize,
+        Syscall::IpcCall  => ipc_call(arg0, arg1, arg2).map_or_else(|e| e, |()| 0),
+        Syscall::IpcSend  => ipc_send(arg0, arg1, arg2).map_or_else(|e| e, |()| 0),
+        Syscall::IpcRecv  => ipc_recv(arg0, arg1).map_or_else(|e| e, |()| 0),
+        Syscall::IpcReply => ipc_reply(arg0, arg1).map_or_else(|e| e, |()| 0),
+        // Размер, который переполнится при округлении — отказ, а не крошечная VMA
+        // A size that overflows when rounded — reject instead of a tiny VMA
+        Syscall::MemAlloc if arg0 == 0 || page_round_up(arg0).is_none() => Errno::InvalidArg as isize,
+        // Неканонический адрес дал бы #GP в ядре / A non-canonical address would #GP in the kernel
+        Syscall::MemMap if !VirtAddr::new(arg1 as u64).is_canonical() => Errno::InvalidArg as isize,
+        Syscall::MemUnmap if !VirtAddr::new(arg0 as u64).is_canonical() => Errno::InvalidArg as isize,
+        // Адрес ядра или пустой диапазон — сразу, без VMA-поиска
+        // A kernel address or an empty range — up front, without a VMA lookup
+        Syscall::MemUnmap if !VirtAddr::new(arg0 as u64).is_user() || arg1 == 0 => Errno::InvalidArg as isize,
+        Syscall::MemUnmap => mem_unmap(arg0, arg1).map_or_else(|e| e, |()| 0),
+        Syscall::MemMap => mem_map(arg0, arg1).map_or_else(|e| e, |()| 0),
+        Syscall::MemShare if arg0 == 0 || page_round_up(arg0).is_none() => Errno::InvalidArg as isize,
+        Syscall::MemShare => mem_share(arg0, arg1).unwrap_or_else(|e| e),
+        Syscall::CapCreatePort => cap_create_port().unwrap_or_else(|e| e),
+        Syscall::CapGrant => cap_grant(arg0, arg1, arg2).unwrap_or_else(|e| e),
+        Syscall::CapRevoke => cap_revoke(arg0).unwrap_or_else(|e| e),
+        Syscall::TaskYield => { crate::sched::schedule(); 0 }
+        Syscall::TaskSpawn => task_spawn(arg0, arg1).unwrap_or_else(|e| e),
+        Syscall::TimeSleep => time_sleep(arg0 as u64).map_or_else(|e| e, |()| 0),
+        Syscall::TaskExit => crate::sched::exit_current(arg0),
+        Syscall::MemAlloc => mem_alloc(arg0).unwrap_or_else(|e| e),
+        Syscall::TimeNow => crate::time::now().min(isize::MAX as u64) as isize,
+        Syscall::SyscallStats if bench::enabled() => bench::average() as isize,
+        Syscall::SyscallStats => Errno::NotSupported as isize,
+        // Ждёт одновременно на очереди порта и в sleep-очереди до deadline.
+        // Если к пробуждению готово и то и другое — побеждает сообщение (0),
+        // иначе TimedOut.
+        // Waits on the port queue and the sleep queue until the deadline.
+        // If both are ready at wakeup the message wins (0), otherwise TimedOut.
+        Syscall::IpcRecvTimeout => ipc_recv_timeout(arg0, arg1, arg2 as u64).map_or_else(|e| e, |()| 0),
+        Syscall::TaskSetPriority => task_set_priority(arg0).map_or_else(|e| e, |()| 0),
+        // Нет CallBufArgs / No CallBufArgs
+        Syscall::IpcCallBuf if arg1 == 0 => Errno::InvalidArg as isize,
+        Syscall::IpcCallBuf => ipc_call_buf(arg0, arg1).unwrap_or_else(|e| e),
+        Syscall::ProfileDump if !crate::profile::enabled() => Errno::NotSupported as isize,
+        Syscall::ProfileDump => profile_dump(arg0, arg1).unwrap_or_else(|e| e),
+        Syscall::IoGrant => io_grant(arg0).map_or_else(|e| e, |()| 0),
+        Syscall::EvqCreate => evq_create().unwrap_or_else(|e| e),
+        Syscall::EvqAttach => evq_attach(arg0, arg1, arg2 as u64).map_or_else(|e| e, |()| 0),
+        // Некуда писать события / Nowhere to write the events
+        Syscall::EvqWait if arg1 == 0 || arg2 == 0 => Errno::InvalidArg as isize,
+        Syscall::EvqWait => evq_wait(arg0, arg1, arg2).unwrap_or_else(|e| e),
+        Syscall::IpcSendBatch if arg1 == 0 && arg2 != 0 => Errno::InvalidArg as isize,
+        Syscall::IpcSendBatch if arg2 == 0 => 0,
+        Syscall::IpcSendBatch => ipc_send_batch(arg0, arg1, arg2).unwrap_or_else(|e| e),
+        Syscall::TaskSetName if arg0 == 0 && arg1 != 0 => Errno::InvalidArg as isize,
+        Syscall::TaskSetName => task_set_name(arg0, arg1).map_or_else(|e| e, |()| 0),
+        Syscall::Notify => ipc::notify(CapId(arg0 as u64), arg1 as u64).map_or_else(ipc_errno, |()| 0),
+        Syscall::NotifyWait => notify_wait(arg0, arg1).map_or_else(|e| e, |()| 0),
+        Syscall::NotifyCreate => notify_create().unwrap_or_else(|e| e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn oversize_inline_payload_is_rejected() {
+        let over = MAX_INLINE_PAYLOAD + 1;
+        for call in [Syscall::IpcSend, Syscall::IpcCall] {
+            assert_eq!(dispatch(call as usize, 0, 0, over), Errno::InvalidArg as isize);
+        }
+        assert_eq!(dispatch(Syscall::IpcReply as usize, 0, over, 0), Errno::InvalidArg as isize);
+    }
+
+    #[test]
+    fn task_set_priority_maps_denied_to_no_permission() {
+        let _kernel = testing::setup();
+        let id = crate::sched::spawn().unwrap();
+        crate::sched::set_current(Some(id));
+        let call = Syscall::TaskSetPriority as usize;
+        assert_eq!(dispatch(call, Priority::Interactive as usize, 0, 0), Errno::NoPermission as isize);
+        assert_eq!(dispatch(call, Priority::Background as usize, 0, 0), 0);
+        assert_eq!(dispatch(call, 99, 0, 0), Errno::InvalidArg as isize);
+        crate::sched::set_current(None);
+        crate::sched::exit(id);
+    }
+
+    #[test]
+    fn profile_dump_copies_tagged_buckets_out() {
+        use crate::profile::{self, Sample};
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let buf = testing::user_buffer(&space, 64 * core::mem::size_of::<Sample>());
+        profile::set_enabled(true);
+        profile::sample(0x40_1008, 0x23);
+        let call = Syscall::ProfileDump as usize;
+        let total = dispatch(call, buf.as_u64() as usize, 64, 0);
+        profile::set_enabled(false);
+        assert!(total >= 1);
+        let out = unsafe { core::slice::from_raw_parts(buf.as_ptr::<Sample>(), total as usize) };
+        assert!(out.iter().any(|s| s.addr == 0x40_1000 && s.mode == profile::Mode::User && s.count >= 1));
+        // Выключенный профайлер — NotSupported / A disabled profiler — NotSupported
+        assert_eq!(dispatch(call, buf.as_u64() as usize, 64, 0), Errno::NotSupported as isize);
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn mem_unmap_removes_an_owned_region() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let start = 0x4000_0000u64;
+        let size = 4 * PAGE_SIZE as u64;
+        space.map_anonymous(VirtAddr::new(start), size, vmm::PageFlags::USER_RW).unwrap();
+        for page in (start..start + size).step_by(PAGE_SIZE) {
+            assert!(vmm::handle_page_fault(&space, VirtAddr::new(page), 0b110));
+        }
+        let free = crate::mm::pmm::free_memory();
+        let call = Syscall::MemUnmap as usize;
+        // Середина — VMA делится, края остаются / The middle — the VMA splits, the edges stay
+        assert_eq!(dispatch(call, (start + PAGE_SIZE as u64) as usize, 2 * PAGE_SIZE, 0), 0);
+        assert_eq!(crate::mm::pmm::free_memory(), free + 2 * PAGE_SIZE as u64);
+        assert!(space.translate(VirtAddr::new(start + PAGE_SIZE as u64)).is_none());
+        assert!(space.find_vma(VirtAddr::new(start + 2 * PAGE_SIZE as u64)).is_none());
+        assert!(space.translate(VirtAddr::new(start)).is_some());
+        assert!(space.translate(VirtAddr::new(start + 3 * PAGE_SIZE as u64)).is_some());
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn mem_unmap_rejects_kernel_and_unmapped_ranges() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let call = Syscall::MemUnmap as usize;
+        assert_eq!(dispatch(call, 0xFFFF_8000_0000_0000, PAGE_SIZE, 0), Errno::InvalidArg as isize);
+        assert_eq!(dispatch(call, 0x5000_0000, PAGE_SIZE, 0), Errno::InvalidArg as isize);
+        assert_eq!(dispatch(call, 0x5000_0000, 0, 0), Errno::InvalidArg as isize);
+        testing::end_task(id);
+    }
+
+    fn port_for(task: crate::ipc::TaskId) -> (usize, alloc::sync::Arc<ipc::object::Port>) {
+        let port = ipc::port(ipc::create_port()).unwrap();
+        let entry = CapEntry { kind: CapKind::Port(port.clone()), rights: Rights::all(), badge: 0, parent: None };
+        (cap::install(task, entry).0 as usize, port)
+    }
+
+    #[test]
+    fn ipc_send_batch_reports_how_many_fit() {
+        use ipc::object::PORT_QUEUE_LEN;
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let (cap, port) = port_for(id);
+        let count = PORT_QUEUE_LEN + 4;
+        let buf = testing::user_buffer(&space, count * core::mem::size_of::<Message>());
+        let msgs = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr::<Message>(), count) };
+        for (i, msg) in msgs.iter_mut().enumerate() { *msg = Message::from_bytes(&[i as u8]).unwrap(); }
+
+        let call = Syscall::IpcSendBatch as usize;
+        assert_eq!(dispatch(call, cap, buf.as_u64() as usize, 3), 3);
+        assert_eq!(dispatch(call, cap, buf.as_u64() as usize, count), (PORT_QUEUE_LEN - 3) as isize);
+        assert_eq!(dispatch(call, cap, buf.as_u64() as usize, count), 0);
+        for expected in (0..3).chain(0..PORT_QUEUE_LEN as u8 - 3) {
+            assert_eq!(port.try_recv().unwrap().msg.as_bytes(), [expected]);
+        }
+        assert!(port.try_recv().is_none());
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn ipc_send_batch_stops_at_an_oversize_message() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let (cap, port) = port_for(id);
+        let buf = testing::user_buffer(&space, 3 * core::mem::size_of::<Message>());
+        let msgs = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr::<Message>(), 3) };
+        msgs.fill(Message::from_bytes(b"ok").unwrap());
+        msgs[1].payload_len = MAX_INLINE_PAYLOAD + 1;
+
+        let call = Syscall::IpcSendBatch as usize;
+        assert_eq!(dispatch(call, cap, buf.as_u64() as usize, 3), 1);
+        let second = buf.as_u64() as usize + core::mem::size_of::<Message>();
+        assert_eq!(dispatch(call, cap, second, 2), Errno::InvalidArg as isize);
+        assert!(port.try_recv().is_some() && port.try_recv().is_none());
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn task_set_name_truncates_into_the_task_list() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let buf = testing::user_buffer(&space, 2 * TASK_NAME_LEN);
+        let long = [b'n'; 2 * TASK_NAME_LEN];
+        unsafe { core::ptr::copy_nonoverlapping(long.as_ptr(), buf.as_mut_ptr::<u8>(), long.len()); }
+
+        let call = Syscall::TaskSetName as usize;
+        assert_eq!(dispatch(call, buf.as_u64() as usize, long.len(), 0), 0);
+        let row = crate::sched::task_row(id).unwrap();
+        assert!(row.ends_with(&format!("  {}", "n".repeat(TASK_NAME_LEN))), "{row}");
+        assert_eq!(dispatch(call, buf.as_u64() as usize, 3, 0), 0);
+        assert!(crate::sched::task_row(id).unwrap().ends_with("  nnn"));
+        assert_eq!(dispatch(call, 0, 3, 0), Errno::InvalidArg as isize);
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn evq_wait_reports_only_the_ready_port() {
+        use ipc::message::Envelope;
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let buf = testing::user_buffer(&space, 4 * core::mem::size_of::<EvqEvent>());
+        let (quiet, _) = port_for(id);
+        let (busy, port) = port_for(id);
+        let evq = dispatch(Syscall::EvqCreate as usize, 0, 0, 0) as usize;
+        assert_eq!(dispatch(Syscall::EvqAttach as usize, evq, quiet, 1), 0);
+        assert_eq!(dispatch(Syscall::EvqAttach as usize, evq, busy, 2), 0);
+        assert_eq!(dispatch(Syscall::EvqAttach as usize, evq, busy, 3), Errno::InvalidArg as isize);
+
+        // Ничего не готово — задача паркуется до события
+        // Nothing is ready — the task parks until an event
+        let wait = Syscall::EvqWait as usize;
+        assert_eq!(dispatch(wait, evq, buf.as_u64() as usize, 4), RESTART);
+        assert!(crate::sched::task_row(id).unwrap().contains("blocked-ipc"));
+        assert!(port.send(Envelope::plain(&Message::from_bytes(b"hi").unwrap(), None)).is_ok());
+        assert!(crate::sched::task_row(id).unwrap().contains("running"));
+        assert_eq!(dispatch(wait, evq, buf.as_u64() as usize, 4), 1);
+        let events = unsafe { core::slice::from_raw_parts(buf.as_mut_ptr::<EvqEvent>(), 4) };
+        assert_eq!(events[0], EvqEvent { port: port.id.0, token: 2 });
+        assert_eq!(events[1], EvqEvent::default());
+        testing::end_task(id);
+    }
+
+    /// Запрос, `CallBufArgs` и буфер ответа на `cap` байт — в памяти вызывающего
+    /// The request, `CallBufArgs` and a `cap`-byte reply buffer — in the caller's memory
+    fn call_buf_args(cap: usize) -> (usize, *const u8) {
+        let space = crate::sched::current_space().unwrap();
+        let msg_size = core::mem::size_of::<Message>();
+        let buf = testing::user_buffer(&space, msg_size + core::mem::size_of::<CallBufArgs>() + cap);
+        let request = buf.as_u64() as usize;
+        let args = request + msg_size;
+        let reply = args + core::mem::size_of::<CallBufArgs>();
+        unsafe {
+            (request as *mut Message).write(Message::from_bytes(b"get").unwrap());
+            (args as *mut CallBufArgs).write(CallBufArgs { request, request_len: 3, reply, reply_cap: cap });
+        }
+        (args, reply as *const u8)
+    }
+
+    /// `ipc_call_buf` до конца: вызов паркуется, `server` принимает и
+    /// отвечает `answer`, повтор отдаёт результат.
+    /// `ipc_call_buf` all the way: the call parks, `server` receives and
+    /// replies with `answer`, the retry returns the result.
+    fn call_buf_with(reply_cap: usize, answer: impl FnOnce(crate::ipc::TaskId) -> Message) -> (isize, Vec<u8>) {
+        let server = crate::sched::spawn_in(vmm::new_user_space().unwrap()).unwrap();
+        let caller = testing::user_task();
+        let (cap, port) = port_for(caller);
+        let (args, reply) = call_buf_args(reply_cap);
+        let call = Syscall::IpcCallBuf as usize;
+        assert_eq!(dispatch(call, cap, args, 0), RESTART);
+
+        crate::sched::set_current(Some(server));
+        assert_eq!(ipc::recv(port.id).unwrap().as_bytes(), b"get");
+        ipc::reply(server, &answer(server)).unwrap();
+        crate::sched::set_current(Some(caller));
+        let ret = dispatch(call, cap, args, 0);
+        let out = unsafe { core::slice::from_raw_parts(reply, reply_cap) }.to_vec();
+        testing::end_task(caller);
+        crate::sched::exit(server);
+        (ret, out)
+    }
+
+    #[test]
+    fn call_buf_reply_that_fits() {
+        let _kernel = testing::setup();
+        let (len, out) = call_buf_with(16, |_| Message::from_bytes(b"pong").unwrap());
+        assert_eq!(len, 4);
+        assert_eq!(&out[..4], b"pong");
+        assert!(out[4..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn call_buf_truncated_reply_keeps_a_prefix() {
+        let _kernel = testing::setup();
+        let body: Vec<u8> = (0..100).collect();
+        let (len, out) = call_buf_with(10, |_| Message::from_bytes(&body).unwrap());
+        // Полная длина больше буфера — признак обрезки
+        // A full length above the buffer is the truncation signal
+        assert_eq!(len, 100);
+        assert_eq!(out, body[..10]);
+    }
+
+    #[test]
+    fn call_buf_reports_the_length_of_a_server_buffer() {
+        let _kernel = testing::setup();
+        let object = SharedMemObject::alloc(1).unwrap();
+        let len = MAX_INLINE_PAYLOAD * 10;
+        let base = crate::mm::vmm::phys_to_virt(object.base()).as_mut_ptr::<u8>();
+        for i in 0..len { unsafe { base.add(i).write(i as u8) }; }
+
+        let (ret, out) = call_buf_with(2 * PAGE_SIZE, |server| {
+            let mem = MemoryCap::new(object.clone(), vmm::PageFlags::USER_RW);
+            let entry = CapEntry { kind: CapKind::Shared(mem), rights: Rights::all(), badge: 0, parent: None };
+            let mut msg = Message::from_bytes(&(len as u64).to_le_bytes()).unwrap();
+            msg.caps[0] = cap::install(server, entry).0;
+            msg
+        });
+        assert_eq!(ret, len as isize);
+        assert!(out[..len].iter().enumerate().all(|(i, &b)| b == i as u8));
+        assert!(out[len..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn mem_share_hands_out_a_cap_with_the_asked_flags() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let call = Syscall::MemShare as usize;
+        assert_eq!(dispatch(call, 0, 1, 0), Errno::InvalidArg as isize);
+
+        let rw = dispatch(call, 3 * PAGE_SIZE, 1, 0);
+        let (object, flags) = cap::shared_object(id, CapId(rw as u64)).unwrap();
+        assert_eq!(object.size(), 4 * PAGE_SIZE as u64);
+        assert!(flags.contains(vmm::PageFlags::WRITABLE));
+        let ro = dispatch(call, PAGE_SIZE, 0, 0);
+        let (_, flags) = cap::shared_object(id, CapId(ro as u64)).unwrap();
+        assert!(!flags.contains(vmm::PageFlags::WRITABLE));
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn mem_syscalls_reject_non_canonical_addresses() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let cap = dispatch(Syscall::MemShare as usize, PAGE_SIZE, 1, 0) as usize;
+        for raw in [0x0000_8000_0000_0000usize, 0x1234_0000_0000_0000, 0xFFFF_7000_0000_0000] {
+            assert_eq!(dispatch(Syscall::MemMap as usize, cap, raw, 0), Errno::InvalidArg as isize);
+            assert_eq!(dispatch(Syscall::MemUnmap as usize, raw, PAGE_SIZE, 0), Errno::InvalidArg as isize);
+        }
+        assert_eq!(dispatch(Syscall::MemMap as usize, cap, 0x4000_0000, 0), 0);
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn mem_alloc_rejects_sizes_that_overflow_when_rounded() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let call = Syscall::MemAlloc as usize;
+        assert_eq!(dispatch(call, 0, 0, 0), Errno::InvalidArg as isize);
+        assert_eq!(dispatch(call, usize::MAX - 1, 0, 0), Errno::InvalidArg as isize);
+
+        let at = dispatch(call, PAGE_SIZE + 1, 0, 0);
+        assert!(at > 0);
+        let second = VirtAddr::new(at as u64 + PAGE_SIZE as u64);
+        assert!(vmm::handle_page_fault(&space, second, 0b110));
+        assert!(space.find_vma(VirtAddr::new(at as u64 + 2 * PAGE_SIZE as u64)).is_none());
+        testing::end_task(id);
+    }
+
+    #[test]
+    fn time_now_follows_the_clock() {
+        let _kernel = testing::setup();
+        testing::CLOCK.set(1_500);
+        assert_eq!(dispatch(Syscall::TimeNow as usize, 0, 0, 0), 1_500);
+    }
+
+    #[test]
+    fn mem_unmap_drops_a_shared_mapping_as_a_whole() {
+        let _kernel = testing::setup();
+        let id = testing::user_task();
+        let space = crate::sched::current_space().unwrap();
+        let cap = dispatch(Syscall::MemShare as usize, 2 * PAGE_SIZE, 1, 0) as usize;
+        let at = 0x4800_0000usize;
+        assert_eq!(dispatch(Syscall::MemMap as usize, cap, at, 0), 0);
+        let call = Syscall::MemUnmap as usize;
+        // Не с начала — отказ / Not from the start — refused
+        assert_eq!(dispatch(call, at + PAGE_SIZE, PAGE_SIZE, 0), Errno::InvalidArg as isize);
+        assert!(space.translate(VirtAddr::new((at + PAGE_SIZE) as u64)).is_some());
+        assert_eq!(dispatch(call, at, 2 * PAGE_SIZE, 0), 0);
+        assert!(space.translate(VirtAddr::new(at as u64)).is_none());
+        assert!(space.find_vma(VirtAddr::new(at as u64)).is_none());
+        testing::end_task(id);
     }
 }
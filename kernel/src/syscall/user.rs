@@ -0,0 +1,97 @@
+//! Копирование из/в userspace — указателям пользователя не верим
+//! Copying from/to user space — user pointers are never trusted
+//!
+//! Каждая задетая страница проверяется в `AddressSpace` текущей задачи до
+//! разыменования: лежит в нижней половине, замаплена с USER (и WRITABLE
+//! для `copy_to_user`). Ещё не подгруженные и COW-страницы проходят тот же
+//! `handle_page_fault`, что и fault из ring 3, — ядро само не падает.
+//! Every touched page is checked in the current task's `AddressSpace`
+//! before it is dereferenced: in the lower half, mapped with USER (and
+//! WRITABLE for `copy_to_user`). Pages not faulted in yet and COW pages go
+//! through the same `handle_page_fault` as a ring-3 fault — the kernel
+//! never faults itself.
+
+use cupruxos_abi::Errno;
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::vmm::{self, AddressSpace, PageFlags, VirtAddr, USER_END};
+
+/// Почему указатель пользователя отвергнут / Why a user pointer was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserError {
+    /// Адрес ядра, переполнение или страница вне VMA
+    /// A kernel address, an overflow or a page outside any VMA
+    InvalidArg,
+    /// Страница есть, но без USER или WRITABLE
+    /// The page exists but lacks USER or WRITABLE
+    NoPermission,
+}
+
+impl UserError {
+    /// Код возврата syscall / Syscall return code
+    pub fn errno(self) -> isize {
+        match self {
+            UserError::InvalidArg   => Errno::InvalidArg as isize,
+            UserError::NoPermission => Errno::NoPermission as isize,
+        }
+    }
+}
+
+// Биты error code, с которыми проверка подгружает страницу как fault из ring 3
+// Error code bits the check uses to fault a page in as a ring-3 fault would
+const PF_PRESENT: u64 = 1 << 0;
+const PF_WRITE:   u64 = 1 << 1;
+const PF_USER:    u64 = 1 << 2;
+
+/// Скопировать `dst.len()` байт с адреса пользователя `user_ptr`.
+/// Copy `dst.len()` bytes from the user address `user_ptr`.
+pub fn copy_from_user(dst: &mut [u8], user_ptr: VirtAddr) -> Result<(), UserError> {
+    let space = crate::sched::current_space().ok_or(UserError::InvalidArg)?;
+    check_range(&space, user_ptr, dst.len(), false)?;
+    unsafe { core::ptr::copy_nonoverlapping(user_ptr.as_ptr::<u8>(), dst.as_mut_ptr(), dst.len()); }
+    Ok(())
+}
+
+/// Скопировать `src` на адрес пользователя `user_ptr`.
+/// Copy `src` to the user address `user_ptr`.
+pub fn copy_to_user(user_ptr: VirtAddr, src: &[u8]) -> Result<(), UserError> {
+    let space = crate::sched::current_space().ok_or(UserError::InvalidArg)?;
+    check_range(&space, user_ptr, src.len(), true)?;
+    unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), user_ptr.as_mut_ptr::<u8>(), src.len()); }
+    Ok(())
+}
+
+/// Все страницы `[start, start + len)` доступны пользователю.
+/// TODO: поток той же задачи может снять маппинг между проверкой и
+/// копированием — нужна таблица исключений для fault'ов в копировании.
+/// Every page of `[start, start + len)` is accessible to the user.
+/// TODO: a thread of the same task can unmap between the check and the
+/// copy — that needs an exception table for faults inside the copy.
+fn check_range(space: &AddressSpace, start: VirtAddr, len: usize, write: bool) -> Result<(), UserError> {
+    if len == 0 { return Ok(()); }
+    let end = start.as_u64().checked_add(len as u64).ok_or(UserError::InvalidArg)?;
+    if !start.is_user() || end > USER_END { return Err(UserError::InvalidArg); }
+    let mut page = start.as_u64() & !(PAGE_SIZE as u64 - 1);
+    while page < end {
+        check_page(space, VirtAddr::new(page), write)?;
+        page += PAGE_SIZE as u64;
+    }
+    Ok(())
+}
+
+fn check_page(space: &AddressSpace, page: VirtAddr, write: bool) -> Result<(), UserError> {
+    let needed = if write { PageFlags::USER | PageFlags::WRITABLE } else { PageFlags::USER };
+    let mapped = space.leaf_flags(page);
+    if mapped.is_some_and(|f| f.contains(needed)) { return Ok(()); }
+
+    let (vma_flags, _) = space.find_vma(page).ok_or(UserError::InvalidArg)?;
+    if !vma_flags.contains(needed) { return Err(UserError::NoPermission); }
+    let mut error = PF_USER;
+    if write { error |= PF_WRITE; }
+    if mapped.is_some() { error |= PF_PRESENT; }
+    // Подгрузить страницу или разорвать COW / Fault the page in or break COW
+    if !vmm::handle_page_fault(space, page, error) { return Err(UserError::InvalidArg); }
+    match space.leaf_flags(page) {
+        Some(f) if f.contains(needed) => Ok(()),
+        _ => Err(UserError::NoPermission),
+    }
+}
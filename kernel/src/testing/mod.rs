@@ -0,0 +1,88 @@
+//! Окружение юнит-тестов на хосте
+//! Host unit-test environment
+//!
+//! Вместо RAM — выровненный буфер процесса: `PHYSICAL_MAP_OFFSET` в тестах
+//! ноль, так что PMM, таблицы страниц и copy-in/out работают по его адресам
+//! как есть. Таблицы никогда не активируются — `map`/`unmap` лишь пишут
+//! PTE, которые тест потом читает через `translate`.
+//! Instead of RAM — an aligned process buffer: `PHYSICAL_MAP_OFFSET` is zero
+//! in tests, so the PMM, page tables and copy-in/out work on its addresses
+//! as is. The tables are never activated — `map`/`unmap` only write PTEs
+//! that the test then reads back through `translate`.
+//!
+//! Глобальное состояние ядра одно на процесс, а cargo гоняет тесты в
+//! потоках: тест, трогающий PMM, задачи или порты, держит `setup()`.
+//! The kernel's global state is one per process while cargo runs tests on
+//! threads: a test touching the PMM, tasks or ports holds `setup()`.
+
+use std::alloc::{alloc_zeroed, Layout};
+use std::sync::{Mutex, MutexGuard, Once};
+use crate::ipc::TaskId;
+use crate::mm::pmm::{self, PAGE_SIZE};
+use crate::mm::vmm::{self, AddressSpace, PageFlags, VirtAddr};
+use crate::sched;
+use crate::time::{self, VirtualClock};
+
+/// Сколько «физической» памяти у тестов / How much "physical" memory tests get
+const MEMORY_SIZE: usize = 64 * 1024 * 1024;
+/// Выравнивание под самый большой блок buddy / Aligned for the largest buddy block
+const MEMORY_ALIGN: usize = 4 * 1024 * 1024;
+
+/// Часы тестов: стоят, пока тест их не сдвинет / The tests' clock: still until a test moves it
+pub static CLOCK: VirtualClock = VirtualClock::new();
+
+static SERIAL: Mutex<()> = Mutex::new(());
+static INIT: Once = Once::new();
+
+/// Поднять PMM, пространство ядра и часы (один раз) и занять ядро до
+/// конца теста.
+/// Bring up the PMM, the kernel space and the clock (once) and hold the
+/// kernel until the test ends.
+pub fn setup() -> MutexGuard<'static, ()> {
+    // Упавший тест не должен валить остальные / A failed test mustn't take the rest down
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    INIT.call_once(|| {
+        let layout = Layout::from_size_align(MEMORY_SIZE, MEMORY_ALIGN).unwrap();
+        let base = unsafe { alloc_zeroed(layout) };
+        assert!(!base.is_null(), "testing: no host memory");
+        pmm::init_with_regions([(base as u64, MEMORY_SIZE as u64)]);
+        vmm::init_for_tests();
+        time::set_source(&CLOCK);
+    });
+    // Упавший тест мог оставить свою задачу текущей / A failed test may have left its task current
+    sched::set_current(None);
+    guard
+}
+
+/// Текущая задача со своим пустым пользовательским пространством — как
+/// будто она только что вошла в syscall. Убирает её `end_task`.
+/// A current task with an empty user space of its own — as if it had just
+/// entered a syscall. `end_task` removes it.
+pub fn user_task() -> TaskId {
+    let id = sched::spawn_in(vmm::new_user_space().unwrap()).unwrap();
+    sched::set_current(Some(id));
+    id
+}
+
+pub fn end_task(id: TaskId) {
+    sched::set_current(None);
+    sched::exit(id);
+}
+
+/// `len` байт пользовательской памяти в `space`. Виртуальный адрес равен
+/// физическому: таблицы не активны, а copy-in/out ходит по виртуальному.
+/// `len` bytes of user memory in `space`. The virtual address equals the
+/// physical one: the tables aren't active while copy-in/out goes by the
+/// virtual address.
+pub fn user_buffer(space: &AddressSpace, len: usize) -> VirtAddr {
+    let pages = len.div_ceil(PAGE_SIZE).max(1).next_power_of_two();
+    let phys = pmm::alloc_pages(pages.trailing_zeros() as usize).unwrap();
+    let size = (pages * PAGE_SIZE) as u64;
+    unsafe { core::ptr::write_bytes(phys.as_u64() as *mut u8, 0, size as usize); }
+    let at = VirtAddr::new(phys.as_u64());
+    space.map_anonymous(at, size, PageFlags::USER_RW).unwrap();
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        space.map(VirtAddr::new(at.as_u64() + offset), pmm::PhysAddr::new(phys.as_u64() + offset), PageFlags::USER_RW).unwrap();
+    }
+    at
+}
@@ -0,0 +1,84 @@
+//! Часы ядра — `now()` в наносекундах от подменяемого источника
+//! Kernel clock — `now()` in nanoseconds from a swappable source
+//!
+//! Источник — TSC. В тестах его подменяет `VirtualClock`: он стоит на
+//! месте, пока его не сдвинут `advance`, и с ручным `sched::on_timer`
+//! планировщик (кванты, сон, бусты) проигрывается детерминированно, без
+//! железного таймера.
+//! The source is the TSC. Tests swap in a `VirtualClock`: it stands still
+//! until moved by `advance`, and with a manual `sched::on_timer` the
+//! scheduler (quanta, sleep, boosts) plays out deterministically, without a
+//! hardware timer.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::current::tsc;
+use crate::sync::RwLock;
+
+/// Источник времени / Time source
+pub trait Clock: Sync {
+    /// Наносекунды от произвольной, но фиксированной точки.
+    /// Nanoseconds since an arbitrary but fixed point.
+    fn now_ns(&self) -> u64;
+}
+
+/// Частота TSC, если CPU её не сообщил / TSC frequency if the CPU didn't report it
+const FALLBACK_TSC_HZ: u64 = 1_000_000_000;
+
+static TSC_HZ: AtomicU64 = AtomicU64::new(FALLBACK_TSC_HZ);
+
+pub struct TscClock;
+
+impl Clock for TscClock {
+    fn now_ns(&self) -> u64 {
+        let hz = TSC_HZ.load(Ordering::Relaxed);
+        (tsc::read() as u128 * 1_000_000_000 / hz as u128) as u64
+    }
+}
+
+/// Часы, которые идут только по команде / A clock that moves only when told to
+#[cfg(test)]
+pub struct VirtualClock {
+    ns: AtomicU64,
+}
+
+#[cfg(test)]
+impl VirtualClock {
+    pub const fn new() -> Self {
+        Self { ns: AtomicU64::new(0) }
+    }
+
+    pub fn advance(&self, ns: u64) {
+        self.ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, ns: u64) {
+        self.ns.store(ns, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl Clock for VirtualClock {
+    fn now_ns(&self) -> u64 { self.ns.load(Ordering::Relaxed) }
+}
+
+static TSC_CLOCK: TscClock = TscClock;
+static SOURCE: RwLock<&'static dyn Clock> = RwLock::new(&TSC_CLOCK);
+
+/// Текущее время, нс / Current time, ns
+pub fn now() -> u64 {
+    SOURCE.read().now_ns()
+}
+
+/// Подменить источник — до того, как таймер начнёт читать часы.
+/// Swap the source — before the timer starts reading the clock.
+#[cfg(test)]
+pub fn set_source(clock: &'static dyn Clock) {
+    *SOURCE.write() = clock;
+}
+
+pub fn init() {
+    match tsc::frequency_hz() {
+        Some(hz) if hz != 0 => TSC_HZ.store(hz, Ordering::Relaxed),
+        _ => crate::kprintln!("[time] TSC frequency unknown — assuming {} MHz", FALLBACK_TSC_HZ / 1_000_000),
+    }
+}
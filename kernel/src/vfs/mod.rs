@@ -5,6 +5,108 @@
 //!
 //! CuprumFS, ext2, FAT32 реализуют трейт FileSystem.
 //! CuprumFS, ext2, FAT32 implement the FileSystem trait.
+//!
+//! До появления диска корнем служит tmpfs — в памяти ядра.
+//! Until a disk exists the root is a tmpfs — in kernel memory.
+//!
+//! Syscall'ов к VFS пока нет — до них слой держат только `init` и тесты.
+//! There are no VFS syscalls yet — until then only `init` and the tests
+//! hold on to this layer.
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+pub mod tmpfs;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::sync::RwLock;
+
+/// Ошибки ФС / Filesystem errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    NotEmpty,
+    InvalidArg,
+    NoMemory,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+}
+
+/// Метаданные / Metadata
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub inode: u64,
+    pub kind:  FileKind,
+    pub size:  u64,
+}
+
+/// Конкретная ФС. Пути — абсолютные внутри ФС (`/a/b`).
+/// A concrete filesystem. Paths are absolute within the FS (`/a/b`).
+pub trait FileSystem: Send {
+    fn create(&mut self, path: &str) -> Result<()>;
+    fn mkdir(&mut self, path: &str) -> Result<()>;
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize>;
+    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> Result<usize>;
+    fn stat(&self, path: &str) -> Result<Stat>;
+    fn unlink(&mut self, path: &str) -> Result<()>;
+    fn readdir(&self, path: &str) -> Result<Vec<String>>;
+}
+
+struct Mount {
+    point: String,
+    fs:    Mutex<Box<dyn FileSystem>>,
+}
+
+/// Поиск — read, монтирование — write; операции над самой ФС сериализует
+/// её собственный `Mutex`, так что разные ФС не мешают друг другу.
+/// Lookup is a read, mounting is a write; operations on the filesystem
+/// itself are serialized by its own `Mutex`, so different filesystems don't
+/// block each other.
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+/// Смонтировать ФС в `point` / Mount a filesystem at `point`
+pub fn mount(point: &str, fs: Box<dyn FileSystem>) {
+    MOUNTS.write().push(Mount { point: String::from(point), fs: Mutex::new(fs) });
+}
+
+/// Выполнить `f` над ФС, отвечающей за `path`, и путём внутри неё
+/// (самая длинная подходящая точка монтирования).
+/// Run `f` on the filesystem owning `path` with the path inside it
+/// (longest matching mount point).
+pub fn with_fs<R>(path: &str, f: impl FnOnce(&mut dyn FileSystem, &str) -> Result<R>) -> Result<R> {
+    let mounts = MOUNTS.read();
+    let mount = mounts.iter()
+        .filter(|m| is_under(path, &m.point))
+        .max_by_key(|m| m.point.len())
+        .ok_or(Error::NotFound)?;
+    let inner = match &path[mount.point.len()..] {
+        _ if mount.point == "/" => path,
+        ""                      => "/",
+        rest                    => rest,
+    };
+    let mut fs = mount.fs.lock();
+    f(fs.as_mut(), inner)
+}
+
+fn is_under(path: &str, point: &str) -> bool {
+    point == "/"
+        || path == point
+        || (path.starts_with(point) && path.as_bytes().get(point.len()) == Some(&b'/'))
+}
 
-// TODO: Этап 8 — реализация VFS
-// TODO: Phase 8 — VFS implementation
+/// Корень — tmpfs, пока нет диска / Root is a tmpfs until a disk exists
+pub fn init() {
+    mount("/", Box::new(tmpfs::TmpFs::new(tmpfs::DEFAULT_LIMIT)));
+    crate::kprintln!("[vfs] tmpfs mounted at / ({} KB limit)", tmpfs::DEFAULT_LIMIT / 1024);
+}
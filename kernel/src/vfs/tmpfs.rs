@@ -0,0 +1,214 @@
+//! tmpfs — ФС целиком в памяти ядра / filesystem entirely in kernel memory
+//!
+//! Для раннего boot'а: сокеты, runtime-файлы, логи до появления диска.
+//! Объём данных ограничен `limit` — сверх него `Error::NoMemory`.
+//! For early boot: sockets, runtime files, logs before a disk exists.
+//! Data size is bounded by `limit` — beyond it, `Error::NoMemory`.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::{Error, FileKind, FileSystem, Result, Stat};
+
+/// Лимит по умолчанию — 4MB / Default limit — 4MB
+pub const DEFAULT_LIMIT: usize = 4 * 1024 * 1024;
+
+const ROOT: u64 = 1;
+
+enum Node {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, u64>),
+}
+
+pub struct TmpFs {
+    nodes:      BTreeMap<u64, Node>,
+    next_inode: u64,
+    /// Байт данных во всех файлах / Data bytes across all files
+    used:       usize,
+    limit:      usize,
+}
+
+impl TmpFs {
+    pub fn new(limit: usize) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT, Node::Dir(BTreeMap::new()));
+        Self { nodes, next_inode: ROOT + 1, used: 0, limit }
+    }
+
+    /// Сколько байт занято / Bytes in use
+    pub fn used(&self) -> usize { self.used }
+
+    /// Разрешить путь в inode / Resolve a path to an inode
+    fn lookup(&self, path: &str) -> Result<u64> {
+        let mut inode = ROOT;
+        for name in components(path) {
+            match self.nodes.get(&inode) {
+                Some(Node::Dir(entries)) => inode = *entries.get(name).ok_or(Error::NotFound)?,
+                Some(Node::File(_))      => return Err(Error::NotADirectory),
+                None                     => return Err(Error::NotFound),
+            }
+        }
+        Ok(inode)
+    }
+
+    /// Родительский каталог и имя последней компоненты.
+    /// Parent directory and the last component's name.
+    fn parent<'p>(&self, path: &'p str) -> Result<(u64, &'p str)> {
+        let path = path.trim_end_matches('/');
+        let (dir, name) = path.rsplit_once('/').ok_or(Error::InvalidArg)?;
+        if name.is_empty() { return Err(Error::InvalidArg); }
+        let parent = self.lookup(dir)?;
+        match self.nodes.get(&parent) {
+            Some(Node::Dir(_)) => Ok((parent, name)),
+            _                  => Err(Error::NotADirectory),
+        }
+    }
+
+    fn insert(&mut self, path: &str, node: Node) -> Result<()> {
+        let (parent, name) = self.parent(path)?;
+        let inode = self.next_inode;
+        let Some(Node::Dir(entries)) = self.nodes.get_mut(&parent) else { return Err(Error::NotADirectory) };
+        if entries.contains_key(name) { return Err(Error::AlreadyExists); }
+        entries.insert(String::from(name), inode);
+        self.nodes.insert(inode, node);
+        self.next_inode += 1;
+        Ok(())
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+impl FileSystem for TmpFs {
+    fn create(&mut self, path: &str) -> Result<()> {
+        self.insert(path, Node::File(Vec::new()))
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<()> {
+        self.insert(path, Node::Dir(BTreeMap::new()))
+    }
+
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        match self.nodes.get(&self.lookup(path)?) {
+            Some(Node::File(data)) => {
+                let start = (offset as usize).min(data.len());
+                let len = buf.len().min(data.len() - start);
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(len)
+            }
+            _ => Err(Error::IsADirectory),
+        }
+    }
+
+    fn write(&mut self, path: &str, offset: u64, src: &[u8]) -> Result<usize> {
+        let inode = self.lookup(path)?;
+        let (used, limit) = (self.used, self.limit);
+        let Some(Node::File(data)) = self.nodes.get_mut(&inode) else { return Err(Error::IsADirectory) };
+
+        let offset = usize::try_from(offset).map_err(|_| Error::InvalidArg)?;
+        let end = offset.checked_add(src.len()).ok_or(Error::InvalidArg)?;
+        let growth = end.saturating_sub(data.len());
+        if used + growth > limit { return Err(Error::NoMemory); }
+        if growth > 0 {
+            data.try_reserve(growth).map_err(|_| Error::NoMemory)?;
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(src);
+        self.used += growth;
+        Ok(src.len())
+    }
+
+    fn stat(&self, path: &str) -> Result<Stat> {
+        let inode = self.lookup(path)?;
+        let (kind, size) = match self.nodes.get(&inode) {
+            Some(Node::File(data))   => (FileKind::File, data.len() as u64),
+            Some(Node::Dir(entries)) => (FileKind::Directory, entries.len() as u64),
+            None                     => return Err(Error::NotFound),
+        };
+        Ok(Stat { inode, kind, size })
+    }
+
+    fn unlink(&mut self, path: &str) -> Result<()> {
+        let (parent, name) = self.parent(path)?;
+        let inode = self.lookup(path)?;
+        match self.nodes.get(&inode) {
+            Some(Node::Dir(entries)) if !entries.is_empty() => return Err(Error::NotEmpty),
+            Some(Node::File(data)) => self.used -= data.len(),
+            _ => {}
+        }
+        if let Some(Node::Dir(entries)) = self.nodes.get_mut(&parent) {
+            entries.remove(name);
+        }
+        self.nodes.remove(&inode);
+        Ok(())
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<String>> {
+        match self.nodes.get(&self.lookup(path)?) {
+            Some(Node::Dir(entries)) => Ok(entries.keys().cloned().collect()),
+            _                        => Err(Error::NotADirectory),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_data_reads_back() {
+        let mut fs = TmpFs::new(DEFAULT_LIMIT);
+        fs.create("/log").unwrap();
+        assert_eq!(fs.write("/log", 0, b"hello"), Ok(5));
+        // Запись за концом дополняет нулями / A write past the end pads with zeros
+        assert_eq!(fs.write("/log", 7, b"!"), Ok(1));
+        let mut buf = [0xFF; 16];
+        assert_eq!(fs.read("/log", 0, &mut buf), Ok(8));
+        assert_eq!(&buf[..8], b"hello\0\0!");
+        assert_eq!(fs.read("/log", 3, &mut buf[..2]), Ok(2));
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(fs.read("/log", 100, &mut buf), Ok(0));
+        let stat = fs.stat("/log").unwrap();
+        assert_eq!((stat.kind, stat.size), (FileKind::File, 8));
+        assert_eq!(fs.create("/log"), Err(Error::AlreadyExists));
+        assert_eq!(fs.read("/nope", 0, &mut buf), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn directories_list_and_unlink_only_when_empty() {
+        let mut fs = TmpFs::new(DEFAULT_LIMIT);
+        fs.mkdir("/run").unwrap();
+        fs.create("/run/b.sock").unwrap();
+        fs.create("/run/a.sock").unwrap();
+        assert_eq!(fs.readdir("/run").unwrap(), ["a.sock", "b.sock"]);
+        assert_eq!(fs.readdir("/").unwrap(), ["run"]);
+        assert_eq!(fs.stat("/run").unwrap().kind, FileKind::Directory);
+        assert_eq!(fs.create("/run/a.sock/x"), Err(Error::NotADirectory));
+        assert_eq!(fs.readdir("/run/a.sock"), Err(Error::NotADirectory));
+        let mut buf = [0; 4];
+        assert_eq!(fs.read("/run", 0, &mut buf), Err(Error::IsADirectory));
+
+        assert_eq!(fs.unlink("/run"), Err(Error::NotEmpty));
+        fs.unlink("/run/a.sock").unwrap();
+        fs.unlink("/run/b.sock").unwrap();
+        fs.unlink("/run").unwrap();
+        assert!(fs.readdir("/").unwrap().is_empty());
+    }
+
+    #[test]
+    fn writes_beyond_the_limit_fail_and_unlink_frees_room() {
+        let mut fs = TmpFs::new(8);
+        fs.create("/a").unwrap();
+        fs.create("/b").unwrap();
+        assert_eq!(fs.write("/a", 0, b"123456"), Ok(6));
+        assert_eq!(fs.write("/b", 0, b"789"), Err(Error::NoMemory));
+        // Перезапись без роста лимит не трогает / An overwrite that doesn't grow leaves the limit alone
+        assert_eq!(fs.write("/a", 0, b"abcdef"), Ok(6));
+        assert_eq!(fs.write("/b", 0, b"78"), Ok(2));
+        assert_eq!(fs.used(), 8);
+        fs.unlink("/a").unwrap();
+        assert_eq!(fs.used(), 2);
+        assert_eq!(fs.write("/b", 2, b"9"), Ok(1));
+    }
+}
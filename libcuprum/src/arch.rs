@@ -0,0 +1,31 @@
+//! Сырой вход в ядро / Raw kernel entry
+//!
+//! Соглашение x86_64 / x86_64 convention:
+//!   rax       — номер syscall / syscall number (и результат / and result)
+//!   rdi, rsi, rdx — arg0, arg1, arg2
+//!   rcx, r11  — портятся инструкцией `syscall` / clobbered by `syscall`
+
+use cupruxos_abi::Syscall;
+
+/// Выполнить syscall / Perform a syscall.
+///
+/// # Safety
+/// Аргументы-указатели должны быть валидны для того, что с ними сделает ядро.
+/// Pointer arguments must be valid for whatever the kernel does with them.
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn syscall(number: Syscall, arg0: usize, arg1: usize, arg2: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            inlateout("rax") number as usize as isize => ret,
+            in("rdi") arg0,
+            in("rsi") arg1,
+            in("rdx") arg2,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    ret
+}
@@ -1,2 +1,35 @@
 //! Capability management
 // TODO: Этап 7 / Phase 7
+
+use crate::ipc::PortCap;
+use crate::{arch, Error, Result};
+
+pub use cupruxos_abi::rights;
+use cupruxos_abi::Syscall;
+
+/// Создать порт; capability на него — со всеми правами.
+/// Create a port; the capability to it carries every right.
+pub fn create_port() -> Result<PortCap> {
+    let ret = unsafe { arch::syscall(Syscall::CapCreatePort, 0, 0, 0) };
+    Error::from_syscall(ret).map(|cap| PortCap(cap as u64))
+}
+
+/// Выдать задаче за `task` копию `cap` с правами `исходные & mask` (биты
+/// `rights`). Усилить права нельзя; без GRANT — `NoPermission`. Возвращает
+/// `CapId` в таблице получателя.
+/// Give the task behind `task` a copy of `cap` with rights `source & mask`
+/// (`rights` bits). Rights can't be amplified; without GRANT —
+/// `NoPermission`. Returns the `CapId` in the grantee's table.
+pub fn grant(cap: u64, task: u64, mask: u32) -> Result<u64> {
+    let ret = unsafe { arch::syscall(Syscall::CapGrant, cap as usize, task as usize, mask as usize) };
+    Error::from_syscall(ret).map(|id| id as u64)
+}
+
+/// Отозвать `cap` и всё, что из неё выдано, у всех задач (нужно право
+/// REVOKE). Возвращает, сколько capability исчезло.
+/// Revoke `cap` and everything granted from it, from every task (needs the
+/// REVOKE right). Returns how many capabilities went away.
+pub fn revoke(cap: u64) -> Result<usize> {
+    let ret = unsafe { arch::syscall(Syscall::CapRevoke, cap as usize, 0, 0) };
+    Error::from_syscall(ret)
+}
@@ -3,35 +3,297 @@
 //! Обёртки над ipc_* syscall'ами.
 //! Wrappers over ipc_* syscalls.
 
-use crate::Result;
+use crate::mem::MemoryCap;
+use crate::{arch, Error, Result};
+
+pub use cupruxos_abi::{MAX_INLINE_PAYLOAD, MAX_MSG_CAPS, NO_CAP};
+pub use cupruxos_abi::EvqEvent;
+use cupruxos_abi::{CallBufArgs, Syscall, EVQ_EDGE};
 
 /// Capability на порт / Port capability
 #[derive(Clone, Copy)]
 pub struct PortCap(pub u64);
 
+/// Capability на notification — слово сигналов / Notification capability — a signal word
+#[derive(Clone, Copy)]
+pub struct NotificationCap(pub u64);
+
+/// Capability на очередь событий / Event queue capability
+#[derive(Clone, Copy)]
+pub struct EventQueueCap(pub u64);
+
 /// Сообщение / Message (inline payload + capability slots)
+///
+/// `repr(C)` — ядро читает массивы сообщений (`send_batch`) напрямую.
+/// `repr(C)` — the kernel reads arrays of messages (`send_batch`) directly.
+#[repr(C)]
 pub struct Message {
-    pub payload: [u8; 512],
+    pub payload: [u8; MAX_INLINE_PAYLOAD],
     pub payload_len: usize,
+    /// Передаваемые capability (`NO_CAP` — пусто). Каждой нужно право
+    /// GRANT, получатель получает копию; у принятого сообщения здесь
+    /// `CapId` в таблице получателя.
+    /// Capabilities to transfer (`NO_CAP` — empty). Each needs the GRANT
+    /// right and the receiver gets a copy; in a received message these are
+    /// `CapId`s in the receiver's table.
+    pub caps: [u64; MAX_MSG_CAPS],
+}
+
+impl Message {
+    /// Пустое сообщение / Empty message
+    pub const fn empty() -> Self {
+        Self { payload: [0; MAX_INLINE_PAYLOAD], payload_len: 0, caps: [NO_CAP; MAX_MSG_CAPS] }
+    }
+
+    /// Положить capability в первый пустой слот. Мест нет — `InvalidArg`.
+    /// Put a capability into the first empty slot. No room — `InvalidArg`.
+    pub fn attach_cap(&mut self, cap: u64) -> Result<()> {
+        let slot = self.caps.iter_mut().find(|c| **c == NO_CAP).ok_or(Error::InvalidArg)?;
+        *slot = cap;
+        Ok(())
+    }
+
+    /// Занятые слоты capability / Occupied capability slots
+    pub fn caps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.caps.iter().copied().filter(|&c| c != NO_CAP)
+    }
+
+    /// Собрать сообщение из байт. Больше `MAX_INLINE_PAYLOAD` — `InvalidArg`.
+    /// Build a message from bytes. Longer than `MAX_INLINE_PAYLOAD` — `InvalidArg`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() > MAX_INLINE_PAYLOAD { return Err(Error::InvalidArg); }
+        let mut msg = Self::empty();
+        msg.payload[..bytes.len()].copy_from_slice(bytes);
+        msg.payload_len = bytes.len();
+        Ok(msg)
+    }
+
+    /// Полезная часть payload / Used part of the payload
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.payload[..self.payload_len.min(MAX_INLINE_PAYLOAD)]
+    }
+}
+
+/// Сколько байт можно передать inline; больше — через shared memory.
+/// How many bytes fit inline; anything larger goes through shared memory.
+pub const fn max_inline_payload() -> usize {
+    MAX_INLINE_PAYLOAD
 }
 
 /// Синхронный вызов — отправить и ждать ответа.
 /// Synchronous call — send and wait for reply.
-pub fn call(_port: PortCap, _msg: &Message) -> Result<Message> {
-    // TODO: arch::syscall(0, ...)
-    Err(crate::Error::Unknown(-1))
+pub fn call(port: PortCap, msg: &Message) -> Result<Message> {
+    let mut reply = Message::empty();
+    // Ответ ядро пишет поверх запроса / The kernel writes the reply over the request
+    reply.payload = msg.payload;
+    reply.payload_len = msg.payload_len;
+    reply.caps = msg.caps;
+    let ret = unsafe {
+        arch::syscall(Syscall::IpcCall, port.0 as usize, &mut reply as *mut Message as usize, msg.payload_len)
+    };
+    Error::from_syscall(ret)?;
+    Ok(reply)
+}
+
+/// Синхронный вызов с ответом в `reply_buf` — для ответов больше
+/// `MAX_INLINE_PAYLOAD`, которым не нужна shared memory. Возвращает длину
+/// ответа; не влез — `Error::Truncated(полная длина)`, в буфере префикс.
+/// Synchronous call with the reply written into `reply_buf` — for replies
+/// larger than `MAX_INLINE_PAYLOAD` that don't warrant shared memory.
+/// Returns the reply length; if it doesn't fit — `Error::Truncated(full
+/// length)` with a prefix in the buffer.
+pub fn call_buf(port: PortCap, req: &Message, reply_buf: &mut [u8]) -> Result<usize> {
+    let args = CallBufArgs {
+        request:     req as *const Message as usize,
+        request_len: req.payload_len,
+        reply:       reply_buf.as_mut_ptr() as usize,
+        reply_cap:   reply_buf.len(),
+    };
+    let ret = unsafe { arch::syscall(Syscall::IpcCallBuf, port.0 as usize, &args as *const CallBufArgs as usize, 0) };
+    reply_len(Error::from_syscall(ret)?, reply_buf.len())
+}
+
+/// Длина ответа против ёмкости буфера / Reply length against buffer capacity
+fn reply_len(len: usize, cap: usize) -> Result<usize> {
+    if len > cap { Err(Error::Truncated(len)) } else { Ok(len) }
 }
 
 /// Асинхронная отправка — не ждать ответа.
 /// Async send — don't wait for reply.
-pub fn send(_port: PortCap, _msg: &Message) -> Result<()> {
-    // TODO: arch::syscall(1, ...)
-    Err(crate::Error::Unknown(-1))
+pub fn send(port: PortCap, msg: &Message) -> Result<()> {
+    let ret = unsafe {
+        arch::syscall(Syscall::IpcSend, port.0 as usize, msg as *const Message as usize, msg.payload_len)
+    };
+    Error::from_syscall(ret).map(drop)
+}
+
+/// Отправить `msg` с регионом shared memory в свободном слоте `caps`:
+/// получатель замапит его через `mem::map_cap` — данные не копируются.
+/// Без права GRANT у `mem` — `NoPermission`.
+/// Send `msg` with a shared memory region in a free `caps` slot: the
+/// receiver maps it with `mem::map_cap` — the data isn't copied. Without
+/// the GRANT right on `mem` — `NoPermission`.
+pub fn send_memory(port: PortCap, mem: MemoryCap, mut msg: Message) -> Result<()> {
+    msg.attach_cap(mem.0)?;
+    send(port, &msg)
+}
+
+/// Отправить несколько сообщений за один syscall. Возвращает, сколько
+/// принято: сообщения идут по порядку, и на заполнившейся очереди порта
+/// ядро останавливается — `msgs[..n]` доставлены, `msgs[n..]` нет.
+/// Send several messages in one syscall. Returns how many were accepted:
+/// messages go in order and the kernel stops when the port queue fills —
+/// `msgs[..n]` were delivered, `msgs[n..]` were not.
+pub fn send_batch(port: PortCap, msgs: &[Message]) -> Result<usize> {
+    if msgs.iter().any(|m| m.payload_len > MAX_INLINE_PAYLOAD) { return Err(Error::InvalidArg); }
+    let ret = unsafe { arch::syscall(Syscall::IpcSendBatch, port.0 as usize, msgs.as_ptr() as usize, msgs.len()) };
+    Error::from_syscall(ret)
+}
+
+/// Ответить на последний принятый вызов.
+/// Reply to the last call received.
+pub fn reply(msg: &Message) -> Result<()> {
+    let ret = unsafe { arch::syscall(Syscall::IpcReply, msg as *const Message as usize, msg.payload_len, 0) };
+    Error::from_syscall(ret).map(drop)
+}
+
+/// Ответить на `call_buf` буфером сервера: тело — первые `len` байт
+/// региона за `mem` (нужен GRANT). Обычный `call` получит сам `mem` и
+/// длину в payload.
+/// Answer a `call_buf` with the server's buffer: the body is the first `len`
+/// bytes of the region behind `mem` (GRANT required). A plain `call` gets
+/// `mem` itself and the length in the payload.
+pub fn reply_memory(mem: MemoryCap, len: usize) -> Result<()> {
+    let mut msg = Message::from_bytes(&(len as u64).to_le_bytes())?;
+    msg.attach_cap(mem.0)?;
+    reply(&msg)
 }
 
 /// Ждать входящего сообщения.
 /// Wait for incoming message.
-pub fn recv(_port: PortCap) -> Result<Message> {
-    // TODO: arch::syscall(2, ...)
-    Err(crate::Error::Unknown(-1))
+pub fn recv(port: PortCap) -> Result<Message> {
+    let mut msg = Message::empty();
+    let ret = unsafe {
+        arch::syscall(Syscall::IpcRecv, port.0 as usize, &mut msg as *mut Message as usize, 0)
+    };
+    Error::from_syscall(ret)?;
+    Ok(msg)
+}
+
+/// Ждать сообщения не дольше дедлайна (нс, часы time_now). Дедлайн прошёл
+/// раньше — `Error::TimedOut`; сообщение, пришедшее позже, достанется
+/// следующему получателю.
+/// Wait for a message no longer than the deadline (ns, the time_now clock).
+/// The deadline passed first — `Error::TimedOut`; a message arriving later
+/// goes to the next receiver.
+pub fn recv_timeout(port: PortCap, deadline_ns: u64) -> Result<Message> {
+    let mut msg = Message::empty();
+    let ret = unsafe {
+        arch::syscall(Syscall::IpcRecvTimeout, port.0 as usize, &mut msg as *mut Message as usize, deadline_ns as usize)
+    };
+    Error::from_syscall(ret)?;
+    Ok(msg)
+}
+
+/// Дедлайн `recv_or_timeout` прошёл раньше сообщения
+/// The `recv_or_timeout` deadline passed before a message came
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+/// Результат `recv_or_timeout`: сообщение или `Timeout`
+/// Result of `recv_or_timeout`: a message or `Timeout`
+pub type RecvResult = core::result::Result<Message, Timeout>;
+
+/// Ждать сообщения или дедлайна (нс, часы time_now) — ядро серверного цикла
+/// с периодическим обслуживанием. Если сообщение и таймаут совпали,
+/// ядро отдаёт сообщение: следующий вызов сразу вернёт `Timeout`.
+/// Wait for a message or the deadline (ns, the time_now clock) — the core
+/// of a server loop with periodic maintenance. If a message and the timeout
+/// coincide the kernel hands out the message; the next call returns `Timeout`.
+pub fn recv_or_timeout(port: PortCap, deadline_ns: u64) -> Result<RecvResult> {
+    split_timeout(recv_timeout(port, deadline_ns))
+}
+
+/// `TimedOut` — не ошибка, а один из исходов / `TimedOut` is an outcome, not an error
+fn split_timeout(ret: Result<Message>) -> Result<RecvResult> {
+    match ret {
+        Ok(msg)              => Ok(Ok(msg)),
+        Err(Error::TimedOut) => Ok(Err(Timeout)),
+        Err(err)             => Err(err),
+    }
+}
+
+/// Создать notification с пустым словом / Create a notification with an empty word
+pub fn notify_create() -> Result<NotificationCap> {
+    let ret = unsafe { arch::syscall(Syscall::NotifyCreate, 0, 0, 0) };
+    Error::from_syscall(ret).map(|cap| NotificationCap(cap as u64))
+}
+
+/// Добавить `bits` в слово notification и разбудить ждущих.
+/// Add `bits` to the notification word and wake the waiters.
+pub fn notify(cap: NotificationCap, bits: u64) -> Result<()> {
+    let ret = unsafe { arch::syscall(Syscall::Notify, cap.0 as usize, bits as usize, 0) };
+    Error::from_syscall(ret).map(drop)
+}
+
+/// Ждать ненулевого слова notification; вернуть накопленные биты, обнулив
+/// слово. Несколько сигналов до вызова — одно пробуждение, их OR.
+/// Wait for a nonzero notification word; return the accumulated bits,
+/// clearing the word. Several signals before the call — one wakeup, their OR.
+pub fn wait_notify(cap: NotificationCap) -> Result<u64> {
+    let mut bits = 0u64;
+    let ret = unsafe { arch::syscall(Syscall::NotifyWait, cap.0 as usize, &mut bits as *mut u64 as usize, 0) };
+    Error::from_syscall(ret)?;
+    Ok(bits)
+}
+
+/// Создать очередь событий / Create an event queue
+pub fn evq_create() -> Result<EventQueueCap> {
+    let ret = unsafe { arch::syscall(Syscall::EvqCreate, 0, 0, 0) };
+    Error::from_syscall(ret).map(|cap| EventQueueCap(cap as u64))
+}
+
+/// Подключить порт к очереди; `token` вернётся в `EvqEvent`. `edge` —
+/// сообщать раз на каждое сообщение в пустой порт, а не пока он непуст.
+/// Attach a port to the queue; `token` comes back in the `EvqEvent`.
+/// `edge` — report once per message into an empty port rather than for as
+/// long as it is non-empty.
+pub fn evq_attach(evq: EventQueueCap, port: PortCap, token: u64, edge: bool) -> Result<()> {
+    if token & EVQ_EDGE != 0 { return Err(Error::InvalidArg); }
+    let token = if edge { token | EVQ_EDGE } else { token };
+    let ret = unsafe { arch::syscall(Syscall::EvqAttach, evq.0 as usize, port.0 as usize, token as usize) };
+    Error::from_syscall(ret).map(drop)
+}
+
+/// Ждать готовых источников; вернуть сколько записей `out` заполнено.
+/// Wait for ready sources; return how many entries of `out` were filled.
+pub fn evq_wait(evq: EventQueueCap, out: &mut [EvqEvent]) -> Result<usize> {
+    let ret = unsafe { arch::syscall(Syscall::EvqWait, evq.0 as usize, out.as_mut_ptr() as usize, out.len()) };
+    Error::from_syscall(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes_respects_the_shared_limit() {
+        assert_eq!(max_inline_payload(), cupruxos_abi::MAX_INLINE_PAYLOAD);
+        let msg = Message::from_bytes(&[7; MAX_INLINE_PAYLOAD]).unwrap();
+        assert_eq!(msg.as_bytes().len(), MAX_INLINE_PAYLOAD);
+        assert!(matches!(Message::from_bytes(&[0; MAX_INLINE_PAYLOAD + 1]), Err(Error::InvalidArg)));
+    }
+
+    #[test]
+    fn recv_or_timeout_hands_out_the_message() {
+        let msg = Message::from_bytes(b"ping").unwrap();
+        let got = split_timeout(Ok(msg)).unwrap().unwrap();
+        assert_eq!(got.as_bytes(), b"ping");
+    }
+
+    #[test]
+    fn recv_or_timeout_turns_the_deadline_into_timeout() {
+        assert_eq!(split_timeout(Err(Error::TimedOut)).unwrap().err(), Some(Timeout));
+        assert!(matches!(split_timeout(Err(Error::InvalidCap)), Err(Error::InvalidCap)));
+    }
 }
@@ -9,12 +9,17 @@
 
 #![no_std]
 
+mod arch;
+
 pub mod ipc;
 pub mod cap;
 pub mod mem;
+pub mod net;
 pub mod task;
 pub mod time;
 
+use cupruxos_abi::Errno;
+
 /// Ошибки syscall / Syscall errors
 #[derive(Debug)]
 pub enum Error {
@@ -23,7 +28,57 @@ pub enum Error {
     InvalidArg,
     NoMemory,
     NotFound,
+    /// Порт уже занят / The port is already taken
+    AddrInUse,
+    /// Данных пока нет / No data yet
+    WouldBlock,
+    /// Ядро не умеет или выключило этот syscall / The kernel lacks or disabled this syscall
+    NotSupported,
+    /// Дедлайн прошёл / The deadline passed
+    TimedOut,
+    /// Ответ не влез в буфер — полная длина; в буфере префикс.
+    /// The reply didn't fit the buffer — full length; the buffer holds a prefix.
+    Truncated(usize),
     Unknown(isize),
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
+
+impl Error {
+    /// Возврат syscall'а: неотрицательный — `Ok`, отрицательный — вариант
+    /// `cupruxos_abi::Errno` с тем же именем, незнакомый — `Unknown`.
+    /// A syscall return: non-negative — `Ok`, negative — the variant named
+    /// like its `cupruxos_abi::Errno`, an unfamiliar one — `Unknown`.
+    pub fn from_syscall(ret: isize) -> Result<usize> {
+        if ret >= 0 { return Ok(ret as usize); }
+        Err(match Errno::from_raw(ret) {
+            Some(Errno::InvalidCap)   => Error::InvalidCap,
+            Some(Errno::NoPermission) => Error::NoPermission,
+            Some(Errno::InvalidArg)   => Error::InvalidArg,
+            Some(Errno::NoMemory)     => Error::NoMemory,
+            Some(Errno::NotFound)     => Error::NotFound,
+            Some(Errno::AddrInUse)    => Error::AddrInUse,
+            Some(Errno::WouldBlock)   => Error::WouldBlock,
+            Some(Errno::NotSupported) => Error::NotSupported,
+            Some(Errno::TimedOut)     => Error::TimedOut,
+            None                      => Error::Unknown(ret),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::format;
+    use super::*;
+
+    #[test]
+    fn every_errno_becomes_the_error_of_the_same_name() {
+        for errno in Errno::ALL {
+            let err = Error::from_syscall(errno as isize).unwrap_err();
+            assert_eq!(format!("{err:?}"), format!("{errno:?}"));
+        }
+        assert!(matches!(Error::from_syscall(-100), Err(Error::Unknown(-100))));
+        assert_eq!(Error::from_syscall(7).unwrap(), 7);
+    }
+}
@@ -1,2 +1,42 @@
 //! Memory mapping via MemoryCap
 // TODO: Этап 7 / Phase 7
+
+use crate::{arch, Error, Result};
+
+use cupruxos_abi::Syscall;
+
+/// Capability на shared memory: физические страницы и флаги, с которыми
+/// их можно мапить. Передаётся в слоте `caps` сообщения — без копирования
+/// данных через payload.
+/// Shared memory capability: physical pages and the flags they may be
+/// mapped with. Passed in a message's `caps` slot — no copying the data
+/// through the payload.
+#[derive(Clone, Copy)]
+pub struct MemoryCap(pub u64);
+
+/// Анонимная обнулённая память на `size` байт (округляется до страниц) по
+/// адресу, который выберет ядро.
+/// Anonymous zeroed memory of `size` bytes (rounded up to pages) at an
+/// address the kernel picks.
+pub fn alloc(size: usize) -> Result<*mut u8> {
+    let ret = unsafe { arch::syscall(Syscall::MemAlloc, size, 0, 0) };
+    Error::from_syscall(ret).map(|addr| addr as *mut u8)
+}
+
+/// Создать обнулённую shared memory на `size` байт (округляется до 2^n
+/// страниц). Без `writable` записать её не сможет никто.
+/// Create zeroed shared memory of `size` bytes (rounded up to 2^n pages).
+/// Without `writable` nobody can write it.
+pub fn share(size: usize, writable: bool) -> Result<MemoryCap> {
+    let ret = unsafe { arch::syscall(Syscall::MemShare, size, writable as usize, 0) };
+    Error::from_syscall(ret).map(|cap| MemoryCap(cap as u64))
+}
+
+/// Замапить регион за `cap` целиком с адреса `at` (выровнен на страницу).
+/// Флаги — те, что разрешает capability: read-only не станет writable.
+/// Map the region behind `cap` whole at `at` (page-aligned). The flags are
+/// the ones the capability allows: read-only never becomes writable.
+pub fn map_cap(cap: MemoryCap, at: usize) -> Result<()> {
+    let ret = unsafe { arch::syscall(Syscall::MemMap, cap.0 as usize, at, 0) };
+    Error::from_syscall(ret).map(drop)
+}
@@ -0,0 +1,85 @@
+//! Сокеты — пока только loopback-датаграммы через сервер netd
+//! Sockets — loopback datagrams through the netd server for now
+//!
+//! Использование / Usage:
+//!   let sock = net::socket(netd)?;
+//!   sock.bind(7)?;
+//!   sock.sendto(9, b"ping")?;
+//!   let (len, from) = sock.recvfrom(&mut buf)?;
+
+use crate::ipc::{self, Message, PortCap};
+use crate::{Error, Result};
+use cupruxos_abi::net::{Header, Op, Status, HEADER_LEN};
+
+pub use cupruxos_abi::net::MAX_DATAGRAM;
+
+/// Сокет netd / A netd socket
+pub struct Socket {
+    netd: PortCap,
+    id:   u16,
+}
+
+/// Открыть непривязанный сокет / Open an unbound socket
+pub fn socket(netd: PortCap) -> Result<Socket> {
+    let (header, _) = request(netd, Header::request(Op::Socket, 0, 0, 0), &[])?;
+    Ok(Socket { netd, id: header.socket })
+}
+
+impl Socket {
+    /// Слушать `port` / Listen on `port`
+    pub fn bind(&self, port: u16) -> Result<()> {
+        request(self.netd, Header::request(Op::Bind, self.id, port, 0), &[]).map(drop)
+    }
+
+    /// Отправить датаграмму на локальный `port`. Сокет должен быть привязан.
+    /// Send a datagram to local `port`. The socket must be bound.
+    pub fn sendto(&self, port: u16, data: &[u8]) -> Result<()> {
+        if data.len() > MAX_DATAGRAM { return Err(Error::InvalidArg); }
+        let header = Header::request(Op::SendTo, self.id, port, data.len() as u16);
+        request(self.netd, header, data).map(drop)
+    }
+
+    /// Забрать датаграмму: `(длина, порт отправителя)`. Пусто — `WouldBlock`.
+    /// Take a datagram: `(length, sender port)`. Nothing queued — `WouldBlock`.
+    pub fn recvfrom(&self, buf: &mut [u8]) -> Result<(usize, u16)> {
+        let (header, reply) = request(self.netd, Header::request(Op::RecvFrom, self.id, 0, 0), &[])?;
+        let data = reply.as_bytes().get(HEADER_LEN..HEADER_LEN + header.len as usize)
+            .ok_or(Error::InvalidArg)?;
+        if data.len() > buf.len() { return Err(Error::Truncated(data.len())); }
+        buf[..data.len()].copy_from_slice(data);
+        Ok((data.len(), header.port))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = request(self.netd, Header::request(Op::Close, self.id, 0, 0), &[]);
+    }
+}
+
+/// Запрос с датаграммой `data`; вернуть разобранный заголовок и сам ответ.
+/// A request carrying `data`; return the parsed header and the reply itself.
+fn request(netd: PortCap, header: Header, data: &[u8]) -> Result<(Header, Message)> {
+    let mut msg = Message::empty();
+    msg.payload[..HEADER_LEN].copy_from_slice(&header.encode());
+    msg.payload[HEADER_LEN..HEADER_LEN + data.len()].copy_from_slice(data);
+    msg.payload_len = HEADER_LEN + data.len();
+    let reply = ipc::call(netd, &msg)?;
+    let header = status(Header::decode(reply.as_bytes()).ok_or(Error::InvalidArg)?)?;
+    Ok((header, reply))
+}
+
+/// Код ответа netd → `Error` / netd reply status → `Error`
+fn status(header: Header) -> Result<Header> {
+    match Status::from_raw(header.status) {
+        Status::Ok         => Ok(header),
+        Status::BadSocket
+        | Status::NoRoute  => Err(Error::NotFound),
+        Status::AddrInUse  => Err(Error::AddrInUse),
+        Status::WouldBlock => Err(Error::WouldBlock),
+        Status::NoBuffers  => Err(Error::NoMemory),
+        Status::NotBound
+        | Status::TooBig
+        | Status::Invalid  => Err(Error::InvalidArg),
+    }
+}
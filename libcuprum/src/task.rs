@@ -1,2 +1,45 @@
 //! Task management
 // TODO: Этап 7 / Phase 7
+
+use crate::mem::MemoryCap;
+use crate::{arch, Error, Result};
+
+pub use cupruxos_abi::{Priority, TASK_NAME_LEN};
+use cupruxos_abi::{Syscall, MAX_MSG_CAPS, NO_CAP};
+
+/// Capability на задачу — цель для `cap::grant`.
+/// A capability to a task — the target for `cap::grant`.
+#[derive(Clone, Copy)]
+pub struct TaskCap(pub u64);
+
+/// Запустить статический ELF из shared memory за `bin`. Каждая из `caps`
+/// (не больше `MAX_MSG_CAPS`, нужен GRANT) достаётся новой задаче
+/// производной копией — `cap::revoke` заберёт её обратно.
+/// Launch the static ELF in the shared memory behind `bin`. Each of `caps`
+/// (at most `MAX_MSG_CAPS`, GRANT required) goes to the new task as a
+/// derived copy — `cap::revoke` takes it back.
+pub fn spawn(bin: MemoryCap, caps: &[u64]) -> Result<TaskCap> {
+    if caps.len() > MAX_MSG_CAPS { return Err(Error::InvalidArg); }
+    let mut slots = [NO_CAP; MAX_MSG_CAPS];
+    slots[..caps.len()].copy_from_slice(caps);
+    let ret = unsafe { arch::syscall(Syscall::TaskSpawn, bin.0 as usize, slots.as_ptr() as usize, 0) };
+    Error::from_syscall(ret).map(|cap| TaskCap(cap as u64))
+}
+
+/// Сменить свой класс планирования. Понизить можно всегда; выше `Normal` —
+/// только с capability на управление планировщиком.
+/// Change this task's scheduling class. Lowering always works; above `Normal`
+/// needs a scheduler-control capability.
+pub fn set_priority(class: Priority) -> Result<()> {
+    let ret = unsafe { arch::syscall(Syscall::TaskSetPriority, class as usize, 0, 0) };
+    Error::from_syscall(ret).map(drop)
+}
+
+/// Назвать себя (для `ps` и отчётов о падениях). Длиннее `TASK_NAME_LEN`
+/// байт — ядро обрежет.
+/// Name this task (for `ps` and fault reports). Longer than `TASK_NAME_LEN`
+/// bytes — the kernel truncates it.
+pub fn set_name(name: &str) -> Result<()> {
+    let ret = unsafe { arch::syscall(Syscall::TaskSetName, name.as_ptr() as usize, name.len(), 0) };
+    Error::from_syscall(ret).map(drop)
+}
@@ -1,2 +1,12 @@
 //! Time syscalls
 // TODO: Этап 7 / Phase 7
+
+use crate::arch;
+
+use cupruxos_abi::Syscall;
+
+/// Текущее время, нс — те же часы, что у дедлайнов `ipc::recv_timeout`.
+/// The current time, ns — the same clock as the `ipc::recv_timeout` deadlines.
+pub fn now() -> u64 {
+    unsafe { arch::syscall(Syscall::TimeNow, 0, 0, 0) as u64 }
+}
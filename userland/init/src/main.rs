@@ -10,8 +10,8 @@ use core::panic::PanicInfo;
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // TODO: запустить VFS сервер, Driver Manager, Network стек
-    // TODO: launch VFS server, Driver Manager, Network stack
+    // TODO: запустить VFS сервер, Driver Manager, Network стек (netd)
+    // TODO: launch VFS server, Driver Manager, Network stack (netd)
     loop { core::hint::spin_loop(); }
 }
 
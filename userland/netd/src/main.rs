@@ -0,0 +1,79 @@
+//! netd — сетевой сервер / network server
+//!
+//! Пока только loopback: датаграммы ходят между локальными портами, NIC
+//! не нужен. Клиенты общаются с ним через `libcuprum::net`, протокол —
+//! `cupruxos_abi::net`.
+//! Loopback only for now: datagrams travel between local ports, no NIC
+//! needed. Clients talk to it through `libcuprum::net`; the protocol is
+//! `cupruxos_abi::net`.
+
+// Тесты маршрутизации — на хосте, с std / Routing tests run on the host, with std
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(test, allow(dead_code, unused_imports))]
+
+mod route;
+
+use cupruxos_abi::net::{Header, Op, Status, HEADER_LEN, MAX_DATAGRAM};
+use libcuprum::ipc::{self, Message, PortCap};
+use route::Loopback;
+
+/// Порт сервиса — init кладёт его первым в таблицу capability.
+/// The service port — init puts it first in the capability table.
+const SERVICE_PORT: PortCap = PortCap(0);
+
+static mut LOOPBACK: Loopback = Loopback::new();
+
+#[cfg(not(test))]
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Однопоточный сервер — единственная ссылка / Single-threaded server — the only reference
+    let net = unsafe { &mut *(&raw mut LOOPBACK) };
+    loop {
+        let Ok(request) = ipc::recv(SERVICE_PORT) else { continue };
+        let _ = ipc::reply(&handle(net, request.as_bytes()));
+    }
+}
+
+/// Выполнить один запрос, собрать ответ / Run one request, build the reply
+fn handle(net: &mut Loopback, request: &[u8]) -> Message {
+    let Some(header) = Header::decode(request) else {
+        return reply(Header::request(Op::Socket, 0, 0, 0), Err(Status::Invalid), &[]);
+    };
+    let body = &request[HEADER_LEN..];
+    let mut buf = [0u8; MAX_DATAGRAM];
+    let mut out = header;
+    out.len = 0;
+
+    let result = match Op::from_raw(header.op) {
+        Some(Op::Socket)   => net.socket().map(|id| out.socket = id),
+        Some(Op::Bind)     => net.bind(header.socket, header.port),
+        Some(Op::SendTo)   => match body.get(..header.len as usize) {
+            Some(data) => net.send_to(header.socket, header.port, data),
+            None       => Err(Status::Invalid),
+        },
+        Some(Op::RecvFrom) => net.recv_from(header.socket, &mut buf).map(|(len, from)| {
+            out.port = from;
+            out.len = len as u16;
+        }),
+        Some(Op::Close)    => net.close(header.socket),
+        None               => Err(Status::Invalid),
+    };
+    let len = out.len as usize;
+    reply(out, result, &buf[..len])
+}
+
+fn reply(mut header: Header, result: Result<(), Status>, data: &[u8]) -> Message {
+    header.status = match result { Ok(()) => Status::Ok, Err(status) => status } as u8;
+    let mut msg = Message::empty();
+    msg.payload[..HEADER_LEN].copy_from_slice(&header.encode());
+    msg.payload[HEADER_LEN..HEADER_LEN + data.len()].copy_from_slice(data);
+    msg.payload_len = HEADER_LEN + data.len();
+    msg
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop { core::hint::spin_loop(); }
+}
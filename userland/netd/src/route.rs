@@ -0,0 +1,184 @@
+//! Loopback — таблица сокетов и маршрутизация по локальным портам
+//! Loopback — the socket table and routing by local port
+//!
+//! Аллокатора в userspace ещё нет, поэтому всё в фиксированных массивах:
+//! `MAX_SOCKETS` сокетов, у каждого очередь на `QUEUE_LEN` датаграмм.
+//! Полная очередь — отправитель получает `NoBuffers`, датаграмма не теряется
+//! молча.
+//! There is no userspace allocator yet, so everything lives in fixed arrays:
+//! `MAX_SOCKETS` sockets, each with a queue of `QUEUE_LEN` datagrams. A full
+//! queue gives the sender `NoBuffers` rather than silently dropping.
+
+use cupruxos_abi::net::{Status, MAX_DATAGRAM};
+
+pub const MAX_SOCKETS: usize = 32;
+pub const QUEUE_LEN:   usize = 4;
+
+#[derive(Clone, Copy)]
+struct Datagram {
+    from: u16,
+    len:  usize,
+    data: [u8; MAX_DATAGRAM],
+}
+
+impl Datagram {
+    const EMPTY: Self = Self { from: 0, len: 0, data: [0; MAX_DATAGRAM] };
+}
+
+struct Socket {
+    /// `None` — не привязан / `None` — unbound
+    port:  Option<u16>,
+    queue: [Datagram; QUEUE_LEN],
+    head:  usize,
+    count: usize,
+}
+
+impl Socket {
+    const fn new() -> Self {
+        Self { port: None, queue: [Datagram::EMPTY; QUEUE_LEN], head: 0, count: 0 }
+    }
+}
+
+pub struct Loopback {
+    sockets: [Option<Socket>; MAX_SOCKETS],
+}
+
+impl Loopback {
+    pub const fn new() -> Self {
+        Self { sockets: [const { None }; MAX_SOCKETS] }
+    }
+
+    /// Новый непривязанный сокет / A new unbound socket
+    pub fn socket(&mut self) -> Result<u16, Status> {
+        let id = self.sockets.iter().position(Option::is_none).ok_or(Status::NoBuffers)?;
+        self.sockets[id] = Some(Socket::new());
+        Ok(id as u16)
+    }
+
+    /// Привязать к `port`. Порт 0 не бывает; занятый порт — `AddrInUse`.
+    /// Bind to `port`. There is no port 0; a taken port — `AddrInUse`.
+    pub fn bind(&mut self, id: u16, port: u16) -> Result<(), Status> {
+        if port == 0 { return Err(Status::Invalid); }
+        if self.route(port).is_some() { return Err(Status::AddrInUse); }
+        let sock = self.get_mut(id)?;
+        if sock.port.is_some() { return Err(Status::AddrInUse); }
+        sock.port = Some(port);
+        Ok(())
+    }
+
+    /// Положить датаграмму в очередь сокета, слушающего `port`.
+    /// Отправитель обязан быть привязан — его порт станет адресом ответа.
+    /// Queue a datagram on the socket listening on `port`. The sender must
+    /// be bound — its port becomes the reply address.
+    pub fn send_to(&mut self, id: u16, port: u16, data: &[u8]) -> Result<(), Status> {
+        if data.len() > MAX_DATAGRAM { return Err(Status::TooBig); }
+        let from = self.get_mut(id)?.port.ok_or(Status::NotBound)?;
+        let dst = self.route(port).ok_or(Status::NoRoute)?;
+        let sock = self.sockets[dst].as_mut().expect("route to a closed socket");
+        if sock.count == QUEUE_LEN { return Err(Status::NoBuffers); }
+
+        let slot = &mut sock.queue[(sock.head + sock.count) % QUEUE_LEN];
+        slot.from = from;
+        slot.len = data.len();
+        slot.data[..data.len()].copy_from_slice(data);
+        sock.count += 1;
+        Ok(())
+    }
+
+    /// Забрать старейшую датаграмму: `(длина, порт отправителя)`.
+    /// Take the oldest datagram: `(length, sender port)`.
+    pub fn recv_from(&mut self, id: u16, buf: &mut [u8; MAX_DATAGRAM]) -> Result<(usize, u16), Status> {
+        let sock = self.get_mut(id)?;
+        if sock.count == 0 { return Err(Status::WouldBlock); }
+        let dgram = &sock.queue[sock.head];
+        buf[..dgram.len].copy_from_slice(&dgram.data[..dgram.len]);
+        let result = (dgram.len, dgram.from);
+        sock.head = (sock.head + 1) % QUEUE_LEN;
+        sock.count -= 1;
+        Ok(result)
+    }
+
+    /// Закрыть сокет; непрочитанные датаграммы пропадают.
+    /// Close a socket; unread datagrams are lost.
+    pub fn close(&mut self, id: u16) -> Result<(), Status> {
+        self.get_mut(id)?;
+        self.sockets[id as usize] = None;
+        Ok(())
+    }
+
+    /// Сокет, слушающий `port` / The socket listening on `port`
+    fn route(&self, port: u16) -> Option<usize> {
+        self.sockets.iter().position(|s| s.as_ref().is_some_and(|s| s.port == Some(port)))
+    }
+
+    fn get_mut(&mut self, id: u16) -> Result<&mut Socket, Status> {
+        self.sockets.get_mut(id as usize).and_then(Option::as_mut).ok_or(Status::BadSocket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(net: &mut Loopback, port: u16) -> u16 {
+        let id = net.socket().unwrap();
+        net.bind(id, port).unwrap();
+        id
+    }
+
+    #[test]
+    fn datagram_reaches_the_bound_port_with_the_sender_address() {
+        let mut net = Loopback::new();
+        let a = bound(&mut net, 7);
+        let b = bound(&mut net, 9);
+        net.send_to(a, 9, b"ping").unwrap();
+        let mut buf = [0; MAX_DATAGRAM];
+        assert_eq!(net.recv_from(b, &mut buf), Ok((4, 7)));
+        assert_eq!(&buf[..4], b"ping");
+        assert_eq!(net.recv_from(b, &mut buf), Err(Status::WouldBlock));
+    }
+
+    #[test]
+    fn bind_rejects_port_zero_and_taken_ports() {
+        let mut net = Loopback::new();
+        let a = bound(&mut net, 7);
+        let b = net.socket().unwrap();
+        assert_eq!(net.bind(b, 0), Err(Status::Invalid));
+        assert_eq!(net.bind(b, 7), Err(Status::AddrInUse));
+        assert_eq!(net.bind(a, 8), Err(Status::AddrInUse));
+        // Закрытый порт снова свободен / A closed port is free again
+        net.close(a).unwrap();
+        assert_eq!(net.bind(b, 7), Ok(()));
+    }
+
+    #[test]
+    fn send_errors() {
+        let mut net = Loopback::new();
+        let a = bound(&mut net, 7);
+        let unbound = net.socket().unwrap();
+        assert_eq!(net.send_to(unbound, 7, b"x"), Err(Status::NotBound));
+        assert_eq!(net.send_to(a, 8, b"x"), Err(Status::NoRoute));
+        assert_eq!(net.send_to(a, 7, &[0; MAX_DATAGRAM + 1]), Err(Status::TooBig));
+        assert_eq!(net.send_to(MAX_SOCKETS as u16, 7, b"x"), Err(Status::BadSocket));
+    }
+
+    #[test]
+    fn full_queue_reports_no_buffers_and_keeps_order() {
+        let mut net = Loopback::new();
+        let a = bound(&mut net, 7);
+        for i in 0..QUEUE_LEN as u8 { net.send_to(a, 7, &[i]).unwrap(); }
+        assert_eq!(net.send_to(a, 7, b"x"), Err(Status::NoBuffers));
+        let mut buf = [0; MAX_DATAGRAM];
+        for i in 0..QUEUE_LEN as u8 {
+            assert_eq!(net.recv_from(a, &mut buf), Ok((1, 7)));
+            assert_eq!(buf[0], i);
+        }
+    }
+
+    #[test]
+    fn socket_table_runs_out() {
+        let mut net = Loopback::new();
+        for _ in 0..MAX_SOCKETS { net.socket().unwrap(); }
+        assert_eq!(net.socket(), Err(Status::NoBuffers));
+    }
+}